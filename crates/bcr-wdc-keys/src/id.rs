@@ -2,6 +2,7 @@
 // ----- extra library imports
 use cdk::nuts::nut02 as cdk02;
 // ----- local imports
+use crate::{Error, Result};
 
 /// rework of cdk02::Id as they do not export internal fields
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
@@ -19,6 +20,28 @@ impl KeysetID {
         bytes[1..].copy_from_slice(&self.id);
         bytes
     }
+
+    /// checked conversion from a cdk id, e.g. one coming from a client request.
+    /// only Version00 ids of BYTELEN bytes are supported
+    pub fn try_from_id(id: cdk02::Id) -> Result<Self> {
+        Self::try_from(id.to_bytes().as_slice())
+    }
+}
+
+impl std::convert::TryFrom<&[u8]> for KeysetID {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != Self::BYTELEN + 1 {
+            return Err(Error::KeysetIDLength(bytes.len()));
+        }
+        let version = cdk02::KeySetVersion::from_byte(&bytes[0])
+            .map_err(|_| Error::KeysetIDVersion(bytes[0]))?;
+        Ok(Self {
+            version,
+            id: bytes[1..].try_into().expect("length checked"),
+        })
+    }
 }
 
 impl std::cmp::PartialEq<cdk02::Id> for KeysetID {
@@ -27,15 +50,10 @@ impl std::cmp::PartialEq<cdk02::Id> for KeysetID {
     }
 }
 
+/// panics on ids `try_from_id` rejects, none of which cdk can build as of now
 impl std::convert::From<cdk02::Id> for KeysetID {
     fn from(id: cdk02::Id) -> Self {
-        let bb = id.to_bytes();
-        assert_eq!(bb.len(), Self::BYTELEN + 1);
-        assert_eq!(bb[0], cdk02::KeySetVersion::Version00.to_byte());
-        Self {
-            version: cdk02::KeySetVersion::Version00,
-            id: bb[1..].try_into().expect("cdk::KeysetID BYTELEN == 7"),
-        }
+        Self::try_from_id(id).expect("cdk::KeysetID is Version00, BYTELEN == 7")
     }
 }
 
@@ -50,3 +68,26 @@ impl std::fmt::Display for KeysetID {
         cdk02::Id::from(*self).fmt(f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_from_id_version00() {
+        let id = cdk02::Id::from_bytes(&[0, 1, 2, 3, 4, 5, 6, 7]).unwrap();
+        let kid = KeysetID::try_from_id(id).unwrap();
+        assert_eq!(kid.version, cdk02::KeySetVersion::Version00);
+        assert_eq!(kid.id, [1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(cdk02::Id::from(kid), id);
+    }
+
+    #[test]
+    fn test_try_from_bytes_rejects_unknown_version() {
+        // cdk cannot build an Id of a future version, its bytes are checked instead
+        let r = KeysetID::try_from([1_u8, 1, 2, 3, 4, 5, 6, 7].as_slice());
+        assert!(matches!(r, Err(Error::KeysetIDVersion(1))));
+        let r = KeysetID::try_from([0_u8; 9].as_slice());
+        assert!(matches!(r, Err(Error::KeysetIDLength(9))));
+    }
+}
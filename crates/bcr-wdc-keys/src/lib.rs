@@ -1,11 +1,13 @@
 // ----- standard library imports
+use std::collections::BTreeMap;
 // ----- extra library imports
-use anyhow::Result as AnyResult;
+use anyhow::{anyhow, Result as AnyResult};
 use async_trait::async_trait;
 use bitcoin::bip32 as btc32;
 use bitcoin::hashes::sha256::Hash as Sha256;
 use bitcoin::hashes::Hash;
 use cdk::nuts::nut00 as cdk00;
+use cdk::nuts::nut01 as cdk01;
 use cdk::nuts::nut02 as cdk02;
 use thiserror::Error;
 use uuid::Uuid;
@@ -26,8 +28,24 @@ pub enum Error {
     CdkDHKE(#[from] cdk::dhke::Error),
     #[error("invalid timestamp {0}")]
     TStamp(TStamp),
+    #[error("rotation index {0} does not fit in a keyset id")]
+    RotationIndex(u32),
+    #[error("keyset id {0} does not encode a valid date")]
+    KeysetIDDate(KeysetID),
+    #[error("no active keyset for maturity {0} within {1} rotations")]
+    RotationWalkExceeded(TStamp, u32),
+    #[error("keyset id version {0} not supported")]
+    KeysetIDVersion(u8),
+    #[error("keyset id of {0} bytes, expected {len}", len = KeysetID::BYTELEN + 1)]
+    KeysetIDLength(usize),
 }
 
+/// rotation indexes take the last 3 bytes of a date-based keyset id
+pub const MAX_ROTATION_IDX: u32 = 0x00FF_FFFF;
+/// default bound of the maturity keyset walks: rotation indexes stepped over before giving
+/// up, i.e. at most MAX_ROTATION_WALK + 1 lookups
+pub const MAX_ROTATION_WALK: u32 = 1000;
+
 pub fn generate_path_index_from_keysetid(kid: KeysetID) -> btc32::ChildNumber {
     const MAX_INDEX: u32 = 2_u32.pow(31) - 1;
     let ukid = std::cmp::min(u32::from(cdk02::Id::from(kid)), MAX_INDEX);
@@ -49,10 +67,23 @@ pub fn generate_path_index_from_id(id: Uuid) -> btc32::ChildNumber {
 // <keysetID_idx> check generate_path_index_from_keysetid
 // <Uuid> optional: check generate_path_idx_from_id
 pub fn generate_keyset_path(kid: KeysetID, id: Option<uuid::Uuid>) -> btc32::DerivationPath {
+    generate_keyset_path_in_era(kid, id, 0)
+}
+
+const PATH_PURPOSE_IDX: u32 = 129534;
+
+/// as generate_keyset_path, for keysets derived from the seed of era `era`:
+/// m/129372'/<129534 + era>'/<keysetID>'/<quoteID>'. era 0 paths are the legacy ones
+pub fn generate_keyset_path_in_era(
+    kid: KeysetID,
+    id: Option<uuid::Uuid>,
+    era: u32,
+) -> btc32::DerivationPath {
     let keyset_child = generate_path_index_from_keysetid(kid);
     let mut path = vec![
         btc32::ChildNumber::from_hardened_idx(129372).expect("129372 is a valid index"),
-        btc32::ChildNumber::from_hardened_idx(129534).expect("129534 is a valid index"),
+        btc32::ChildNumber::from_hardened_idx(PATH_PURPOSE_IDX + era)
+            .expect("era is a valid index"),
         keyset_child,
     ];
     if let Some(id) = id {
@@ -62,30 +93,58 @@ pub fn generate_keyset_path(kid: KeysetID, id: Option<uuid::Uuid>) -> btc32::Der
     btc32::DerivationPath::from(path.as_slice())
 }
 
+/// seed era a path from generate_keyset_path_in_era was derived in, None for other paths
+pub fn era_of_path(path: &btc32::DerivationPath) -> Option<u32> {
+    match path.as_ref() {
+        [btc32::ChildNumber::Hardened { index: 129372 }, btc32::ChildNumber::Hardened { index }, ..] => {
+            index.checked_sub(PATH_PURPOSE_IDX)
+        }
+        _ => None,
+    }
+}
+
 /// Generates a keyset id from a date and a rotation index
 /// id[0..4] = date in days from unix epoch
 /// id[4..7] = rotation index in big endian
-pub fn generate_keyset_id_from_date(date: TStamp, rotation_idx: u32) -> KeysetID {
-    let idx = (date - chrono::DateTime::UNIX_EPOCH).num_days() as u32;
+/// dates before the unix epoch and rotation indexes wider than 24 bits are rejected
+pub fn generate_keyset_id_from_date(date: TStamp, rotation_idx: u32) -> Result<KeysetID> {
+    let days = (date - chrono::DateTime::UNIX_EPOCH).num_days();
+    let days = u32::try_from(days).map_err(|_| Error::TStamp(date))?;
+    if rotation_idx > MAX_ROTATION_IDX {
+        return Err(Error::RotationIndex(rotation_idx));
+    }
     let mut kid = KeysetID {
         version: cdk02::KeySetVersion::Version00,
         id: Default::default(),
     };
-    kid.id[3..7].copy_from_slice(&rotation_idx.to_be_bytes());
-    kid.id[0..4].copy_from_slice(&idx.to_be_bytes());
-    kid
+    kid.id[0..4].copy_from_slice(&days.to_be_bytes());
+    kid.id[4..7].copy_from_slice(&rotation_idx.to_be_bytes()[1..]);
+    Ok(kid)
 }
 
-pub fn extract_date_from_id(id: &KeysetID) -> (TStamp, u32) {
+/// reverse of generate_keyset_id_from_date, the date is truncated to the day
+pub fn extract_date_from_id(id: &KeysetID) -> Result<(TStamp, u32)> {
     let mut u32_buf: [u8; 4] = Default::default();
     u32_buf.copy_from_slice(&id.id[0..4]);
-    let maturity = TStamp::from_timestamp(u32::from_be_bytes(u32_buf) as i64, 0)
-        .expect("datetime conversion from u64");
+    let days = u32::from_be_bytes(u32_buf);
+    let maturity = chrono::DateTime::UNIX_EPOCH
+        .checked_add_signed(chrono::TimeDelta::days(days as i64))
+        .ok_or(Error::KeysetIDDate(*id))?;
 
     u32_buf = Default::default();
     u32_buf[1..].copy_from_slice(&id.id[4..7]);
     let idx = u32::from_be_bytes(u32_buf);
-    (maturity, idx)
+    Ok((maturity, idx))
+}
+
+/// fails as a rotation walk bounded by `max_walk` would before reaching `kid`, for
+/// lookups that skip the walk. the walk stops at index `max_walk`, it never steps past it
+pub fn verify_rotation_walk(kid: &KeysetID, max_walk: u32) -> Result<()> {
+    let (maturity_date, rotation_idx) = extract_date_from_id(kid)?;
+    if rotation_idx > max_walk {
+        return Err(Error::RotationWalkExceeded(maturity_date, max_walk));
+    }
+    Ok(())
 }
 
 pub fn sign_with_keys(
@@ -107,6 +166,27 @@ pub fn sign_with_keys(
 }
 
 pub type KeysetEntry = (cdk::mint::MintKeySetInfo, cdk02::MintKeySet);
+/// extra data on a keyset, kept alongside the entry as MintKeySetInfo has no room for it
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct KeysetMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// proofs of the keyset must be P2PK-locked to, and signed by, this key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked_to: Option<cdk01::PublicKey>,
+}
+/// NUT-01 public view of a keyset, safe to hand out to wallets
+pub type PublicKeys = BTreeMap<cdk::Amount, cdk01::PublicKey>;
+
+pub fn public_keys(keyset: &cdk02::MintKeySet) -> PublicKeys {
+    keyset
+        .keys
+        .iter()
+        .map(|(amount, pair)| (*amount, pair.public_key))
+        .collect()
+}
 
 // ----- required traits
 #[async_trait]
@@ -119,6 +199,130 @@ pub trait Repository: Send + Sync {
         keyset: cdk02::MintKeySet,
         info: cdk::mint::MintKeySetInfo,
     ) -> AnyResult<()>;
+    /// infos of every stored keyset, in no particular order
+    async fn list_info(&self) -> AnyResult<Vec<cdk::mint::MintKeySetInfo>>;
+    async fn public_keys(&self, kid: &KeysetID) -> AnyResult<Option<PublicKeys>> {
+        Ok(self.keyset(kid).await?.as_ref().map(public_keys))
+    }
+    /// re-stores the keyset flagged as active, fails if unknown
+    async fn enable_keyset(&self, kid: &KeysetID) -> AnyResult<()> {
+        let (mut info, keyset) = self
+            .load(kid)
+            .await?
+            .ok_or_else(|| anyhow!("unknown keyset {}", kid))?;
+        info.active = true;
+        self.store(keyset, info).await
+    }
+    /// re-stores the keyset flagged as inactive, fails if unknown
+    async fn disable_keyset(&self, kid: &KeysetID) -> AnyResult<()> {
+        let (mut info, keyset) = self
+            .load(kid)
+            .await?
+            .ok_or_else(|| anyhow!("unknown keyset {}", kid))?;
+        info.active = false;
+        self.store(keyset, info).await
+    }
+    /// every stored keyset with its info, for backups and migrations between backends
+    async fn export_all(&self) -> AnyResult<Vec<KeysetEntry>> {
+        let mut entries = Vec::new();
+        for info in self.list_info().await? {
+            let kid = KeysetID::from(info.id);
+            let entry = self
+                .load(&kid)
+                .await?
+                .ok_or_else(|| anyhow!("keyset {} listed but not found", kid))?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+    /// stores entries as they are, nothing is stored if any info does not match its keyset
+    async fn import(&self, entries: Vec<KeysetEntry>) -> AnyResult<()> {
+        if let Some((info, keyset)) = entries
+            .iter()
+            .find(|(info, keyset)| KeysetID::from(info.id) != KeysetID::from(keyset.id))
+        {
+            return Err(anyhow!(
+                "keyset info {} does not match keyset {}",
+                info.id,
+                keyset.id
+            ));
+        }
+        for (info, keyset) in entries {
+            self.store(keyset, info).await?;
+        }
+        Ok(())
+    }
+    /// metadata of a stored keyset, empty by default. `None` if the keyset is unknown
+    async fn metadata(&self, kid: &KeysetID) -> AnyResult<Option<KeysetMetadata>> {
+        Ok(self.info(kid).await?.map(|_| KeysetMetadata::default()))
+    }
+    /// replaces the metadata of a stored keyset, unsupported by default
+    async fn set_metadata(&self, kid: &KeysetID, _metadata: KeysetMetadata) -> AnyResult<()> {
+        Err(anyhow!("keyset metadata not supported, keyset {}", kid))
+    }
+    /// sets or clears the label of a stored keyset, fails if unknown
+    async fn set_label(&self, kid: &KeysetID, label: Option<String>) -> AnyResult<()> {
+        let mut metadata = self
+            .metadata(kid)
+            .await?
+            .ok_or_else(|| anyhow!("unknown keyset {}", kid))?;
+        metadata.label = label;
+        self.set_metadata(kid, metadata).await
+    }
+    /// sets or clears the description of a stored keyset, fails if unknown
+    async fn set_description(&self, kid: &KeysetID, description: Option<String>) -> AnyResult<()> {
+        let mut metadata = self
+            .metadata(kid)
+            .await?
+            .ok_or_else(|| anyhow!("unknown keyset {}", kid))?;
+        metadata.description = description;
+        self.set_metadata(kid, metadata).await
+    }
+    /// as `list_info`, restricted to the keysets flagged active if `only_enabled`
+    async fn list_info_filtered(
+        &self,
+        only_enabled: bool,
+    ) -> AnyResult<Vec<cdk::mint::MintKeySetInfo>> {
+        let mut infos = self.list_info().await?;
+        if only_enabled {
+            infos.retain(|info| info.active);
+        }
+        Ok(infos)
+    }
+    /// info of the keyset derived from `path`, scanning every stored info
+    async fn find_by_path(
+        &self,
+        path: &btc32::DerivationPath,
+    ) -> AnyResult<Option<cdk::mint::MintKeySetInfo>> {
+        Ok(self
+            .list_info()
+            .await?
+            .into_iter()
+            .find(|info| info.derivation_path == *path))
+    }
+    /// id of the active keyset for `maturity_date`, by default walking its rotation indexes
+    /// up from 0 one `info` at a time. Backends able to look it up in one query should do so.
+    /// fails if the active keyset lies past rotation index `max_walk`
+    async fn active_for_maturity(
+        &self,
+        maturity_date: TStamp,
+        max_walk: u32,
+    ) -> AnyResult<Option<KeysetID>> {
+        let mut rotation_idx = 0;
+        loop {
+            let kid = generate_keyset_id_from_date(maturity_date, rotation_idx)?;
+            let Some(info) = self.info(&kid).await? else {
+                return Ok(None);
+            };
+            if info.active {
+                return Ok(Some(kid));
+            }
+            if rotation_idx >= max_walk {
+                return Err(Error::RotationWalkExceeded(maturity_date, max_walk).into());
+            }
+            rotation_idx += 1;
+        }
+    }
 }
 
 #[async_trait]
@@ -126,6 +330,11 @@ pub trait ActiveRepository: Repository {
     async fn info_active(&self) -> AnyResult<Option<cdk::mint::MintKeySetInfo>>;
     #[allow(dead_code)]
     async fn keyset_active(&self) -> AnyResult<Option<cdk02::MintKeySet>>;
+    /// points the active reference to `kid`, which must be already stored
+    async fn set_active(&self, kid: KeysetID) -> AnyResult<()>;
+    async fn active_public_keys(&self) -> AnyResult<Option<PublicKeys>> {
+        Ok(self.keyset_active().await?.as_ref().map(public_keys))
+    }
 }
 
 #[cfg(feature = "test-utils")]
@@ -147,11 +356,13 @@ pub mod test_utils {
         async fn keyset(&self, kid: &KeysetID) -> AnyResult<Option<cdk02::MintKeySet>>;
         async fn load(&self, kid: &KeysetID) -> AnyResult<Option<KeysetEntry>>;
         async fn store(&self, keyset: cdk02::MintKeySet, info: cdk::mint::MintKeySetInfo) -> AnyResult<()>;
+        async fn list_info(&self) -> AnyResult<Vec<cdk::mint::MintKeySetInfo>>;
         }
         #[async_trait]
         impl ActiveRepository for Repository {
             async fn info_active(&self) -> AnyResult<Option<cdk::mint::MintKeySetInfo>>;
             async fn keyset_active(&self) -> AnyResult<Option<cdk02::MintKeySet>>;
+            async fn set_active(&self, kid: KeysetID) -> AnyResult<()>;
         }
     }
     static SECPCTX: Lazy<bitcoin::secp256k1::Secp256k1<bitcoin::secp256k1::All>> =
@@ -166,6 +377,108 @@ pub mod test_utils {
 
     pub fn generate_keyset() -> cdk02::MintKeySet {
         let path = DerivationPath::from_str("m/0'/0").unwrap();
+        generate_keyset_with_path(path)
+    }
+
+    /// keysets of distinct paths have distinct keys and ids
+    pub fn generate_keyset_with_path(path: DerivationPath) -> cdk02::MintKeySet {
         cdk02::MintKeySet::generate_from_seed(&SECPCTX, &[], 10, cdk00::CurrencyUnit::Sat, path)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tstamp(rfc3339: &str) -> TStamp {
+        chrono::DateTime::parse_from_rfc3339(rfc3339)
+            .unwrap()
+            .to_utc()
+    }
+
+    #[test]
+    fn test_era_of_path() {
+        let kid = generate_keyset_id_from_date(tstamp("2021-01-01T00:00:00Z"), 0).unwrap();
+        let qid = Some(uuid::Uuid::from_u128(1));
+        assert_eq!(era_of_path(&generate_keyset_path(kid, qid)), Some(0));
+        assert_eq!(
+            era_of_path(&generate_keyset_path_in_era(kid, None, 3)),
+            Some(3)
+        );
+        assert_ne!(
+            generate_keyset_path_in_era(kid, qid, 1),
+            generate_keyset_path(kid, qid)
+        );
+        let other = btc32::DerivationPath::master().child(btc32::ChildNumber::from(0));
+        assert_eq!(era_of_path(&other), None);
+    }
+
+    #[test]
+    fn test_keyset_id_from_date_roundtrip() {
+        let dates = [
+            "1970-01-01T00:00:00Z",
+            "1970-01-02T00:00:00Z",
+            "2021-01-01T00:00:00Z",
+            "2038-01-19T00:00:00Z",
+            "9999-12-31T00:00:00Z",
+        ];
+        for date in dates {
+            let date = tstamp(date);
+            for idx in [0, 1, 255, 256, MAX_ROTATION_IDX] {
+                let kid = generate_keyset_id_from_date(date, idx).unwrap();
+                let (extracted, extracted_idx) = extract_date_from_id(&kid).unwrap();
+                assert_eq!(extracted, date);
+                assert_eq!(extracted_idx, idx);
+            }
+        }
+    }
+
+    #[test]
+    fn test_keyset_id_from_date_truncates_to_day() {
+        let kid = generate_keyset_id_from_date(tstamp("2021-01-01T23:59:59Z"), 3).unwrap();
+        let (extracted, _) = extract_date_from_id(&kid).unwrap();
+        assert_eq!(extracted, tstamp("2021-01-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn test_keyset_id_from_date_before_epoch() {
+        let date = tstamp("1969-12-31T00:00:00Z");
+        assert!(matches!(
+            generate_keyset_id_from_date(date, 0),
+            Err(Error::TStamp(_))
+        ));
+        let date = tstamp("1900-01-01T00:00:00Z");
+        assert!(matches!(
+            generate_keyset_id_from_date(date, 0),
+            Err(Error::TStamp(_))
+        ));
+    }
+
+    #[test]
+    fn test_keyset_id_from_date_rotation_overflow() {
+        let date = tstamp("2021-01-01T00:00:00Z");
+        assert!(matches!(
+            generate_keyset_id_from_date(date, MAX_ROTATION_IDX + 1),
+            Err(Error::RotationIndex(_))
+        ));
+    }
+
+    #[test]
+    fn test_keyset_id_from_date_unique() {
+        let first = generate_keyset_id_from_date(tstamp("2021-01-01T00:00:00Z"), 1).unwrap();
+        let second = generate_keyset_id_from_date(tstamp("2021-01-02T00:00:00Z"), 0).unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_extract_date_from_id_out_of_range() {
+        let kid = KeysetID {
+            version: cdk02::KeySetVersion::Version00,
+            id: [0xFF; 7],
+        };
+        assert!(matches!(
+            extract_date_from_id(&kid),
+            Err(Error::KeysetIDDate(_))
+        ));
+    }
+}
@@ -0,0 +1,18 @@
+// ----- standard library imports
+// ----- extra library imports
+use cdk::nuts::nut00 as cdk00;
+// ----- local imports
+
+///--------------------------- Mint debit eCash
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct MintRequest {
+    /// proof of the BTC payment, as handed out by the settlement backend
+    pub token: String,
+    pub amount: cdk::Amount,
+    pub outputs: Vec<cdk00::BlindedMessage>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct MintReply {
+    pub signatures: Vec<cdk00::BlindSignature>,
+}
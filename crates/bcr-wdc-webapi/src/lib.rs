@@ -1,5 +1,6 @@
 // ----- standard library imports
 // ----- extra library imports
 // ----- local modules
+pub mod debit;
 pub mod quotes;
 // ----- local imports
@@ -12,26 +12,70 @@ pub struct EnquireRequest {
     pub bill: String,
     pub node: String,
     pub outputs: Vec<cdk00::BlindedMessage>,
+    /// NUT-20: schnorr signature by the node's eBill key over bill, node and the
+    /// outputs' B_, length-prefixed. mandatory: requests without one are rejected
+    #[serde(default)]
+    pub signature: Option<cdk::secp256k1::schnorr::Signature>,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct EnquireReply {
     pub id: uuid::Uuid,
 }
 
 /// --------------------------- Look up quote
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "lowercase", tag = "status")]
 pub enum StatusReply {
     Pending,
     Declined,
+    Cancelled,
+    Offered {
+        discount: cdk::Amount,
+        expiration_date: TStamp,
+    },
     Accepted {
         signatures: Vec<cdk00::BlindSignature>,
         expiration_date: TStamp,
     },
+    Expired,
+}
+
+/// --------------------------- Look up quote signatures
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SignaturesReply {
+    pub signatures: Vec<cdk00::BlindSignature>,
+}
+
+/// --------------------------- Quote status webhook
+/// posted to the configured webhook url whenever a quote changes status
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct StatusChangeNotification {
+    pub id: uuid::Uuid,
+    pub bill: String,
+    pub node: String,
+    pub old: StatusReply,
+    pub new: StatusReply,
+}
+
+/// --------------------------- Cancel quote
+#[derive(serde::Deserialize)]
+pub struct CancelRequest {
+    pub node: String,
+    /// schnorr signature by the node's eBill key over the length-prefixed "cancel"
+    /// tag and the quote id. mandatory: requests without one are rejected
+    #[serde(default)]
+    pub signature: Option<cdk::secp256k1::schnorr::Signature>,
 }
 
 /// --------------------------- List quotes
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+pub struct ListParam {
+    pub since: Option<TStamp>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct ListReply {
     pub quotes: Vec<uuid::Uuid>,
@@ -48,18 +92,36 @@ pub enum InfoReply {
         submitted: chrono::DateTime<chrono::Utc>,
         suggested_expiration: chrono::DateTime<chrono::Utc>,
     },
+    Offered {
+        id: uuid::Uuid,
+        bill: String,
+        endorser: String,
+        discount: cdk::Amount,
+        expiry: chrono::DateTime<chrono::Utc>,
+    },
     Accepted {
         id: uuid::Uuid,
         bill: String,
         endorser: String,
         ttl: chrono::DateTime<chrono::Utc>,
         signatures: Vec<cdk00::BlindSignature>,
+        fee: cdk::Amount,
     },
     Declined {
         id: uuid::Uuid,
         bill: String,
         endorser: String,
     },
+    Cancelled {
+        id: uuid::Uuid,
+        bill: String,
+        endorser: String,
+    },
+    Expired {
+        id: uuid::Uuid,
+        bill: String,
+        endorser: String,
+    },
 }
 
 /// --------------------------- Resolve quote request
@@ -0,0 +1,30 @@
+#![allow(dead_code)]
+// ----- standard library imports
+// ----- extra library imports
+// ----- local imports
+use crate::TStamp;
+
+/// source of the current time, injected where time-dependent behavior must be testable
+pub trait Clock: Send + Sync {
+    fn now(&self) -> TStamp;
+}
+
+/// wall-clock time
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> TStamp {
+        chrono::Utc::now()
+    }
+}
+
+/// always the same instant, e.g. for deterministic tests
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub TStamp);
+
+impl Clock for FixedClock {
+    fn now(&self) -> TStamp {
+        self.0
+    }
+}
@@ -4,14 +4,23 @@ use axum::extract::{Json, Path, Query, State};
 use bcr_wdc_webapi::quotes as web_quotes;
 // ----- local imports
 use crate::credit::error::Result;
+use crate::credit::keys as creditkeys;
 use crate::credit::quotes;
 use crate::utils;
 use crate::TStamp;
 
 /// --------------------------- List quotes
+fn convert_to_page(params: &web_quotes::ListParam) -> quotes::Page {
+    let default = quotes::Page::default();
+    quotes::Page {
+        offset: params.offset.unwrap_or(default.offset),
+        limit: params.limit.unwrap_or(default.limit),
+    }
+}
+
 pub async fn list_pending_quotes<KG, QR>(
     State(ctrl): State<quotes::Service<KG, QR>>,
-    since: Option<Query<TStamp>>,
+    Query(params): Query<web_quotes::ListParam>,
 ) -> Result<Json<web_quotes::ListReply>>
 where
     KG: quotes::KeyFactory,
@@ -19,12 +28,14 @@ where
 {
     log::debug!("Received request to list pending quotes");
 
-    let quotes = ctrl.list_pendings(since.map(|q| q.0)).await?;
+    let page = convert_to_page(&params);
+    let quotes = ctrl.list_pendings(params.since, page).await?;
     Ok(Json(web_quotes::ListReply { quotes }))
 }
 
 pub async fn list_accepted_quotes<KG, QR>(
     State(ctrl): State<quotes::Service<KG, QR>>,
+    Query(params): Query<web_quotes::ListParam>,
 ) -> Result<Json<web_quotes::ListReply>>
 where
     KG: quotes::KeyFactory,
@@ -32,34 +43,57 @@ where
 {
     log::debug!("Received request to list accepted quotes");
 
-    let quotes = ctrl.list_accepteds(None).await?;
+    let page = convert_to_page(&params);
+    let quotes = ctrl.list_accepteds(params.since, page).await?;
     Ok(Json(web_quotes::ListReply { quotes }))
 }
 
 /// --------------------------- Look up request
-fn convert_to_info_reply(quote: quotes::Quote) -> web_quotes::InfoReply {
+fn convert_to_info_reply(quote: quotes::Quote, now: TStamp) -> web_quotes::InfoReply {
     match quote.status {
         quotes::QuoteStatus::Pending { .. } => web_quotes::InfoReply::Pending {
             id: quote.id,
             bill: quote.bill,
             endorser: quote.endorser,
             submitted: quote.submitted,
-            suggested_expiration: utils::calculate_default_expiration_date_for_quote(
-                chrono::Utc::now(),
-            ),
+            suggested_expiration: utils::calculate_default_expiration_date_for_quote(now),
         },
-        quotes::QuoteStatus::Accepted { signatures, ttl } => web_quotes::InfoReply::Accepted {
+        quotes::QuoteStatus::Offered {
+            discount, expiry, ..
+        } => web_quotes::InfoReply::Offered {
+            id: quote.id,
+            bill: quote.bill,
+            endorser: quote.endorser,
+            discount,
+            expiry,
+        },
+        quotes::QuoteStatus::Accepted {
+            signatures,
+            ttl,
+            fee,
+        } => web_quotes::InfoReply::Accepted {
             id: quote.id,
             bill: quote.bill.clone(),
             endorser: quote.endorser.clone(),
             ttl,
             signatures: signatures.clone(),
+            fee,
         },
         quotes::QuoteStatus::Declined => web_quotes::InfoReply::Declined {
             id: quote.id,
             bill: quote.bill,
             endorser: quote.endorser,
         },
+        quotes::QuoteStatus::Cancelled => web_quotes::InfoReply::Cancelled {
+            id: quote.id,
+            bill: quote.bill,
+            endorser: quote.endorser,
+        },
+        quotes::QuoteStatus::Expired => web_quotes::InfoReply::Expired {
+            id: quote.id,
+            bill: quote.bill,
+            endorser: quote.endorser,
+        },
     }
 }
 
@@ -73,8 +107,9 @@ where
 {
     log::debug!("Received mint quote lookup request for id: {}", id);
 
-    let quote = ctrl.lookup(id).await?;
-    let response = convert_to_info_reply(quote);
+    let now = ctrl.clock.now();
+    let quote = ctrl.lookup_at(id, now).await?;
+    let response = convert_to_info_reply(quote, now);
     Ok(Json(response))
 }
 
@@ -92,8 +127,246 @@ where
     match req {
         web_quotes::ResolveRequest::Decline => ctrl.decline(id).await?,
         web_quotes::ResolveRequest::Accept { discount, ttl } => {
-            ctrl.accept(id, discount, chrono::Utc::now(), ttl).await?
+            ctrl.accept(id, discount, ctrl.clock.now(), ttl).await?
         }
     }
     Ok(())
 }
+
+pub async fn decline_quote<KG, QR>(
+    State(ctrl): State<quotes::Service<KG, QR>>,
+    Path(id): Path<uuid::Uuid>,
+) -> Result<()>
+where
+    KG: quotes::KeyFactory,
+    QR: quotes::Repository,
+{
+    log::debug!("Received mint quote decline request for id: {}", id);
+
+    ctrl.decline(id).await?;
+    Ok(())
+}
+
+/// --------------------------- Endorse quote
+/// the bill of accepted quote `id` was endorsed to the mint: its keyset becomes swappable
+pub async fn endorse_quote<KG, QR, QK, EK>(
+    State(ctrl): State<quotes::Service<KG, QR>>,
+    State(endorsements): State<creditkeys::Endorsements<QK, EK>>,
+    Path(id): Path<uuid::Uuid>,
+) -> Result<()>
+where
+    KG: quotes::KeyFactory,
+    QR: quotes::Repository,
+    QK: creditkeys::QuoteBasedRepository,
+    EK: crate::keys::Repository,
+{
+    log::debug!("Received mint quote endorsement for id: {}", id);
+
+    let quote = ctrl.lookup(id).await?;
+    if !matches!(quote.status, quotes::QuoteStatus::Accepted { .. }) {
+        return Err(quotes::Error::NotAccepted(id).into());
+    }
+    endorsements.activate(&quote).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credit::quotes::test_utils as quotes_test;
+    use crate::persistence::inmemory;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use axum::routing::{get, post};
+    use axum::Router;
+    use mockall::predicate::*;
+    use tower::ServiceExt;
+
+    fn router(repo: quotes::MockRepository) -> Router<(), Body> {
+        let service = quotes_test::service(repo, quotes::MockRepository::new());
+        Router::new()
+            .route("/quotes/pending", get(list_pending_quotes))
+            .route("/quote/:id/decline", post(decline_quote))
+            .with_state(service)
+    }
+
+    #[tokio::test]
+    async fn test_list_pending_quotes() {
+        let id = uuid::Uuid::new_v4();
+        let mut repo = quotes::MockRepository::new();
+        repo.expect_list_pendings()
+            .with(eq(None), always())
+            .returning(move |_, _| Ok(vec![id]));
+
+        let request = Request::get("/quotes/pending").body(Body::empty()).unwrap();
+        let response = router(repo).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let reply: web_quotes::ListReply = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply.quotes, vec![id]);
+    }
+
+    #[tokio::test]
+    async fn test_decline_quote() {
+        let quote = quotes::Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            chrono::Utc::now(),
+        );
+        let id = quote.id;
+        let mut repo = quotes::MockRepository::new();
+        repo.expect_load()
+            .with(eq(id))
+            .returning(move |_| Ok(Some(quote.clone())));
+        repo.expect_update_if_pending()
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let request = Request::post(format!("/quote/{id}/decline"))
+            .body(Body::empty())
+            .unwrap();
+        let response = router(repo).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_decline_quote_already_resolved() {
+        let mut quote = quotes::Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            chrono::Utc::now(),
+        );
+        quote.decline().unwrap();
+        let id = quote.id;
+        let mut repo = quotes::MockRepository::new();
+        repo.expect_load()
+            .returning(move |_| Ok(Some(quote.clone())));
+
+        let request = Request::post(format!("/quote/{id}/decline"))
+            .body(Body::empty())
+            .unwrap();
+        let response = router(repo).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    type TestEndorsements =
+        creditkeys::Endorsements<inmemory::KeysetIDQuoteIDMap, inmemory::KeysetIDEntryMap>;
+
+    #[derive(Clone, axum::extract::FromRef)]
+    struct EndorseState {
+        quotes: quotes::Service<quotes_test::SharedKeyFactory, quotes_test::SharedRepository>,
+        endorsements: TestEndorsements,
+    }
+
+    fn endorse_router(
+        repo: quotes::MockRepository,
+        endorsements: TestEndorsements,
+    ) -> Router<(), Body> {
+        let state = EndorseState {
+            quotes: quotes_test::service(repo, quotes::MockRepository::new()),
+            endorsements,
+        };
+        Router::new()
+            .route("/quote/:id/endorse", post(endorse_quote))
+            .with_state(state)
+    }
+
+    #[tokio::test]
+    async fn test_endorse_quote() {
+        let mut quote = quotes::Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            chrono::Utc::now(),
+        );
+        quote.status = quotes::QuoteStatus::Accepted {
+            signatures: vec![],
+            ttl: chrono::Utc::now() + chrono::Duration::days(1),
+            fee: cdk::Amount::ZERO,
+        };
+        let id = quote.id;
+        let mut repo = quotes::MockRepository::new();
+        repo.expect_load()
+            .returning(move |_| Ok(Some(quote.clone())));
+        let kid = crate::keys::credit::generate_keyset_id_from_bill("billID", "endorserID");
+        let mut keyset = crate::keys::test_utils::generate_keyset();
+        keyset.id = kid.into();
+        let info = cdk::mint::MintKeySetInfo {
+            id: keyset.id,
+            unit: keyset.unit.clone(),
+            active: false,
+            valid_from: 0,
+            valid_to: None,
+            derivation_path: Default::default(),
+            derivation_path_index: None,
+            max_order: keyset.keys.len() as u8,
+            input_fee_ppk: 0,
+        };
+        let endorsements = TestEndorsements {
+            quote_keys: inmemory::KeysetIDQuoteIDMap::default(),
+            endorsed_keys: inmemory::KeysetIDEntryMap::default(),
+        };
+        creditkeys::QuoteBasedRepository::store(&endorsements.quote_keys, id, keyset, info)
+            .await
+            .unwrap();
+
+        let request = Request::post(format!("/quote/{id}/endorse"))
+            .body(Body::empty())
+            .unwrap();
+        let response = endorse_router(repo, endorsements.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let (info, _) = crate::keys::Repository::load(&endorsements.endorsed_keys, &kid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(info.active);
+    }
+
+    #[tokio::test]
+    async fn test_endorse_quote_not_accepted() {
+        let quote = quotes::Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            chrono::Utc::now(),
+        );
+        let id = quote.id;
+        let mut repo = quotes::MockRepository::new();
+        repo.expect_load()
+            .returning(move |_| Ok(Some(quote.clone())));
+        let endorsements = TestEndorsements {
+            quote_keys: inmemory::KeysetIDQuoteIDMap::default(),
+            endorsed_keys: inmemory::KeysetIDEntryMap::default(),
+        };
+
+        let request = Request::post(format!("/quote/{id}/endorse"))
+            .body(Body::empty())
+            .unwrap();
+        let response = endorse_router(repo, endorsements.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let kid = crate::keys::credit::generate_keyset_id_from_bill("billID", "endorserID");
+        let found = crate::keys::Repository::info(&endorsements.endorsed_keys, &kid)
+            .await
+            .unwrap();
+        assert!(found.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_list_pending_quotes_repository_error() {
+        let mut repo = quotes::MockRepository::new();
+        repo.expect_list_pendings()
+            .returning(|_, _| Err(anyhow::anyhow!("connection lost")));
+
+        let request = Request::get("/quotes/pending").body(Body::empty()).unwrap();
+        let response = router(repo).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,106 @@
+// ----- standard library imports
+// ----- extra library imports
+use anyhow::Result as AnyResult;
+use async_trait::async_trait;
+use cdk::nuts::nut01 as cdk01;
+use reqwest::StatusCode;
+// ----- local imports
+use crate::credit::quotes;
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+pub struct EbillConfig {
+    pub url: String,
+}
+
+#[derive(serde::Deserialize)]
+struct EndorserKeyReply {
+    public_key: cdk01::PublicKey,
+}
+
+/// looks up endorser keys at `{url}/v1/bill/{bill}/endorser/{endorser}`,
+/// a 404 means the eBill service does not know `endorser` on `bill`
+#[derive(Clone)]
+pub struct HttpEbillClient {
+    client: reqwest::Client,
+    cfg: EbillConfig,
+}
+
+impl HttpEbillClient {
+    pub fn new(cfg: EbillConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            cfg,
+        }
+    }
+}
+
+#[async_trait]
+impl quotes::EndorserKeys for HttpEbillClient {
+    async fn endorser_key(
+        &self,
+        bill: &str,
+        endorser: &str,
+    ) -> AnyResult<Option<cdk01::PublicKey>> {
+        let mut url = reqwest::Url::parse(&self.cfg.url)?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("eBill url cannot be a base: {}", self.cfg.url))?
+            .pop_if_empty()
+            .extend(["v1", "bill", bill, "endorser", endorser]);
+        let response = self.client.get(url).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let reply: EndorserKeyReply = response.error_for_status()?.json().await?;
+        Ok(Some(reply.public_key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credit::quotes::EndorserKeys;
+    use axum::extract::Path;
+    use axum::http::StatusCode;
+
+    async fn ebill_server(key: cdk01::PublicKey) -> String {
+        let router = axum::Router::new().route(
+            "/v1/bill/:bill/endorser/:endorser",
+            axum::routing::get(move |Path((bill, _)): Path<(String, String)>| async move {
+                if bill == "billID" {
+                    Ok(axum::Json(
+                        serde_json::json!({ "public_key": key.to_hex() }),
+                    ))
+                } else {
+                    Err(StatusCode::NOT_FOUND)
+                }
+            }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service());
+        tokio::spawn(server);
+        format!("http://{addr}/")
+    }
+
+    #[tokio::test]
+    async fn test_endorser_key_found() {
+        let key = cdk01::SecretKey::generate().public_key();
+        let url = ebill_server(key).await;
+        let client = HttpEbillClient::new(EbillConfig { url });
+
+        let found = client.endorser_key("billID", "endorserID").await.unwrap();
+        assert_eq!(found, Some(key));
+    }
+
+    #[tokio::test]
+    async fn test_endorser_key_unknown() {
+        let key = cdk01::SecretKey::generate().public_key();
+        let url = ebill_server(key).await;
+        let client = HttpEbillClient::new(EbillConfig { url });
+
+        let found = client.endorser_key("other", "endorserID").await.unwrap();
+        assert!(found.is_none());
+    }
+}
@@ -1,6 +1,7 @@
 // ----- standard library imports
 // ----- extra library imports
 use anyhow::Error as AnyError;
+use axum::http::StatusCode;
 use thiserror::Error;
 // ----- local modules
 // ----- local imports
@@ -21,8 +22,29 @@ pub enum Error {
     QuoteRepository(#[from] AnyError),
 }
 
+impl Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::Quote(quotes::Error::QuoteAlreadyResolved(_))
+            | Error::Quote(quotes::Error::NotAccepted(_))
+            | Error::Quote(quotes::Error::NoOffer(_))
+            | Error::Quote(quotes::Error::OfferExpired(_)) => StatusCode::CONFLICT,
+            Error::Quote(quotes::Error::UnknownQuoteID(_)) => StatusCode::NOT_FOUND,
+            Error::Quote(quotes::Error::InvalidAmount(_))
+            | Error::Quote(quotes::Error::TtlExceedsMax(..))
+            | Error::Quote(quotes::Error::FeeExceedsAmount(..))
+            | Error::Quote(quotes::Error::BlindsShortOfTarget(..)) => StatusCode::BAD_REQUEST,
+            Error::Quote(quotes::Error::NotEndorser(..))
+            | Error::Quote(quotes::Error::InvalidQuoteSignature) => StatusCode::FORBIDDEN,
+            Error::Quote(quotes::Error::RateLimited(_)) => StatusCode::TOO_MANY_REQUESTS,
+            Error::Quote(quotes::Error::EndorserKeys(_)) => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
 impl axum::response::IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
-        self.to_string().into_response()
+        (self.status_code(), self.to_string()).into_response()
     }
 }
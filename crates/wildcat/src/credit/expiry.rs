@@ -0,0 +1,164 @@
+// ----- standard library imports
+use std::sync::Arc;
+use std::time::Duration;
+// ----- extra library imports
+use anyhow::Result as AnyResult;
+// ----- local imports
+use crate::clock::{Clock, SystemClock};
+use crate::credit::quotes;
+use crate::TStamp;
+
+/// periodically moves accepted quotes past their ttl to expired
+#[derive(Clone)]
+pub struct QuoteExpiryWorker<Quotes> {
+    quotes: Quotes,
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl<Quotes> QuoteExpiryWorker<Quotes>
+where
+    Quotes: quotes::Repository,
+{
+    pub fn new(quotes: Quotes, interval: Duration) -> Self {
+        Self {
+            quotes,
+            interval,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// a single scan, returns how many quotes were expired.
+    /// quotes resolved meanwhile are left untouched, re-running is harmless
+    pub async fn run_once(&self, now: TStamp) -> AnyResult<usize> {
+        let accepted = self
+            .quotes
+            .list_accepteds(None, quotes::Page::default())
+            .await?;
+        let mut expired = 0;
+        for id in accepted {
+            let Some(mut quote) = self.quotes.load(id).await? else {
+                continue;
+            };
+            if quote.expire_if_stale(now) {
+                self.quotes.update_if_accepted(quote).await?;
+                expired += 1;
+            }
+        }
+        Ok(expired)
+    }
+
+    /// scans every `interval` until the task is dropped, failed scans are retried on
+    /// the next tick
+    pub async fn run(self) {
+        let mut ticker = tokio::time::interval(self.interval);
+        loop {
+            ticker.tick().await;
+            match self.run_once(self.clock.now()).await {
+                Ok(0) => {}
+                Ok(expired) => log::info!("{expired} accepted quotes expired"),
+                Err(e) => log::error!("quote expiry scan failed: {e}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::inmemory;
+    use mockall::predicate::*;
+
+    fn accepted_quote(ttl: TStamp) -> quotes::Quote {
+        let mut quote = quotes::Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            ttl - chrono::Duration::days(1),
+        );
+        quote.accept(vec![], ttl, cdk::Amount::ZERO).unwrap();
+        quote
+    }
+
+    #[tokio::test]
+    async fn test_run_once_expires_only_stale_quotes() {
+        let now = chrono::Utc::now();
+        let stale = accepted_quote(now - chrono::Duration::hours(1));
+        let live = accepted_quote(now + chrono::Duration::hours(1));
+        let (stale_id, live_id) = (stale.id, live.id);
+        let mut repo = quotes::MockRepository::new();
+        repo.expect_list_accepteds()
+            .returning(move |_, _| Ok(vec![stale_id, live_id]));
+        repo.expect_load()
+            .with(eq(stale_id))
+            .returning(move |_| Ok(Some(stale.clone())));
+        repo.expect_load()
+            .with(eq(live_id))
+            .returning(move |_| Ok(Some(live.clone())));
+        repo.expect_update_if_accepted()
+            .withf(move |q| q.id == stale_id && matches!(q.status, quotes::QuoteStatus::Expired))
+            .times(1)
+            .returning(|_| Ok(()));
+        let worker = QuoteExpiryWorker::new(repo, Duration::from_secs(60));
+
+        assert_eq!(worker.run_once(now).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_once_is_idempotent() {
+        let now = chrono::Utc::now();
+        let repo = inmemory::QuotesIDMap::default();
+        let stale = accepted_quote(now - chrono::Duration::hours(1));
+        let id = stale.id;
+        quotes::Repository::store(&repo, stale).await.unwrap();
+        let worker = QuoteExpiryWorker::new(repo.clone(), Duration::from_secs(60));
+
+        assert_eq!(worker.run_once(now).await.unwrap(), 1);
+        assert_eq!(worker.run_once(now).await.unwrap(), 0);
+        let quote = quotes::Repository::load(&repo, id).await.unwrap().unwrap();
+        assert!(matches!(quote.status, quotes::QuoteStatus::Expired));
+    }
+
+    #[tokio::test]
+    async fn test_run_scans_at_clock_time() {
+        let now = chrono::DateTime::parse_from_rfc3339("2030-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let repo = inmemory::QuotesIDMap::default();
+        let stale = accepted_quote(now - chrono::Duration::hours(1));
+        let live = accepted_quote(now + chrono::Duration::hours(1));
+        let (stale_id, live_id) = (stale.id, live.id);
+        quotes::Repository::store(&repo, stale).await.unwrap();
+        quotes::Repository::store(&repo, live).await.unwrap();
+        let worker = QuoteExpiryWorker::new(repo.clone(), Duration::from_secs(60))
+            .with_clock(Arc::new(crate::clock::FixedClock(now)));
+
+        // the first tick is immediate
+        let handle = tokio::spawn(worker.run());
+        let expired = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let stale = quotes::Repository::load(&repo, stale_id)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                if matches!(stale.status, quotes::QuoteStatus::Expired) {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        handle.abort();
+        assert!(expired.is_ok());
+        let live = quotes::Repository::load(&repo, live_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(live.status, quotes::QuoteStatus::Accepted { .. }));
+    }
+}
@@ -0,0 +1,53 @@
+// ----- standard library imports
+// ----- extra library imports
+use cdk::Amount;
+// ----- local imports
+
+/// fees the mint keeps out of every bill it signs for:
+/// `base_ppk` parts per thousand of the bill amount, plus `flat`
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+pub struct FeePolicy {
+    pub base_ppk: u64,
+    pub flat: Amount,
+}
+
+/// rounds down the proportional part, never exceeds `bill_amount`
+pub fn fee_for(bill_amount: Amount, policy: &FeePolicy) -> Amount {
+    let bill = u64::from(bill_amount);
+    let base = u128::from(bill) * u128::from(policy.base_ppk) / 1000;
+    let fee = u64::try_from(base)
+        .unwrap_or(u64::MAX)
+        .saturating_add(u64::from(policy.flat));
+    Amount::from(fee.min(bill))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fee_for_zero_policy() {
+        let policy = FeePolicy::default();
+        assert_eq!(fee_for(Amount::from(1000), &policy), Amount::ZERO);
+    }
+
+    #[test]
+    fn test_fee_for_flat_only() {
+        let policy = FeePolicy {
+            base_ppk: 0,
+            flat: Amount::from(3),
+        };
+        assert_eq!(fee_for(Amount::from(1000), &policy), Amount::from(3));
+        assert_eq!(fee_for(Amount::from(2), &policy), Amount::from(2));
+    }
+
+    #[test]
+    fn test_fee_for_base_and_flat() {
+        let policy = FeePolicy {
+            base_ppk: 5,
+            flat: Amount::from(2),
+        };
+        // 1999 * 5 / 1000 = 9.995, rounded down
+        assert_eq!(fee_for(Amount::from(1999), &policy), Amount::from(11));
+    }
+}
@@ -12,7 +12,9 @@ use thiserror::Error;
 use uuid::Uuid;
 // ----- local modules
 // ----- local imports
-use crate::credit::quotes::KeyFactory;
+use crate::clock::{Clock, SystemClock};
+use crate::credit::quotes::{self, KeyFactory};
+use crate::health::HealthCheck;
 use crate::swap;
 use crate::TStamp;
 
@@ -23,6 +25,16 @@ pub enum Error {
     CdkNut01(#[from] cdk01::Error),
     #[error("repository error {0}")]
     Repository(#[from] AnyError),
+    #[error("keys error {0}")]
+    Keys(#[from] keys::Error),
+    #[error("keyset id {0} already in use for another bill and endorser")]
+    KeysetIdCollision(KeysetID),
+    #[error("no keyset generated for keyset id {0}")]
+    UnknownKeyset(KeysetID),
+    #[error("bill maturity date {0} is not in the future")]
+    MaturityInPast(TStamp),
+    #[error("no seed for era {0}")]
+    UnknownEra(u32),
 }
 
 // ---------- required traits
@@ -30,36 +42,279 @@ pub enum Error {
 #[async_trait]
 pub trait QuoteBasedRepository: Send + Sync {
     async fn load(&self, kid: &KeysetID, qid: Uuid) -> AnyResult<Option<keys::KeysetEntry>>;
+    /// the keyset stored under `kid`, whatever the quote
+    async fn search_by_kid(&self, kid: &KeysetID) -> AnyResult<Option<keys::KeysetEntry>>;
+    /// every keyset stored under the id derived from `(bill, node)`, whatever the quote
+    async fn load_by_bill(&self, bill: &str, node: &str) -> AnyResult<Vec<keys::KeysetEntry>>;
+    /// every keyset stored for quote `qid`, whatever the keyset id
+    async fn load_by_quote(&self, qid: Uuid) -> AnyResult<Vec<keys::KeysetEntry>>;
+    /// fails if an entry with different contents is already stored for `(keyset.id, qid)`
     async fn store(
         &self,
         qid: Uuid,
         keyset: cdk02::MintKeySet,
         info: cdk::mint::MintKeySetInfo,
     ) -> AnyResult<()>;
+    /// the (bill, endorser) the keysets stored under `kid` were generated for, if recorded
+    async fn owner(&self, kid: &KeysetID) -> AnyResult<Option<(String, String)>>;
+    async fn set_owner(&self, kid: &KeysetID, bill: &str, endorser: &str) -> AnyResult<()>;
+}
+
+/// maturity keysets, with the face value issued under each of them
+#[async_trait]
+pub trait MaturityKeyRepository: keys::Repository {
+    async fn record_issued(&self, kid: &KeysetID, amount: cdk::Amount) -> AnyResult<()>;
+    async fn issued(&self, kid: &KeysetID) -> AnyResult<cdk::Amount>;
+    /// total face value issued against `maturity_date`, over all its rotation indices.
+    /// fails if a keyset is stored at index `max_walk`, the walk never steps past it.
+    /// see `keys::MAX_ROTATION_WALK`
+    async fn issued_amount(&self, maturity_date: TStamp, max_walk: u32) -> AnyResult<cdk::Amount> {
+        let mut total = cdk::Amount::ZERO;
+        let mut rotation_idx = 0;
+        loop {
+            let kid = keys::generate_keyset_id_from_date(maturity_date, rotation_idx)?;
+            if self.info(&kid).await?.is_none() {
+                return Ok(total);
+            }
+            total += self.issued(&kid).await?;
+            if rotation_idx >= max_walk {
+                return Err(keys::Error::RotationWalkExceeded(maturity_date, max_walk).into());
+            }
+            rotation_idx += 1;
+        }
+    }
+    /// highest rotation index stored for `maturity_date`, None if it has no keyset.
+    /// rotation indices may have gaps, every stored info is scanned
+    async fn current_rotation(&self, maturity_date: TStamp) -> AnyResult<Option<u32>> {
+        let (date, _) =
+            keys::extract_date_from_id(&keys::generate_keyset_id_from_date(maturity_date, 0)?)?;
+        let mut current = None;
+        for info in self.list_info().await? {
+            let Some((stored_date, rotation_idx)) = maturity_date_of(&KeysetID::from(info.id))
+            else {
+                continue;
+            };
+            if stored_date == date {
+                current = current.max(Some(rotation_idx));
+            }
+        }
+        Ok(current)
+    }
 }
 
 // ---------- Keys Factory
 #[derive(Clone)]
 pub struct Factory<QuoteKeys, MaturityKeys> {
     ctx: bitcoin::secp256k1::Secp256k1<bitcoin::secp256k1::All>,
-    xpriv: btc32::Xpriv,
+    // one master key per seed era, new keysets are derived from the last one
+    xprivs: Vec<btc32::Xpriv>,
+    network: bitcoin::Network,
     quote_keys: QuoteKeys,
     maturing_keys: MaturityKeys,
     unit: cdk00::CurrencyUnit,
+    max_order: u8,
+    input_fee_ppk: u64,
+    max_rotation_walk: u32,
+    clock: std::sync::Arc<dyn Clock>,
 }
 
 impl<QuoteKeys, MaturityKeys> Factory<QuoteKeys, MaturityKeys> {
-    pub const MAX_ORDER: u8 = 20;
+    pub const DEFAULT_MAX_ORDER: u8 = 20;
     pub const CURRENCY_UNIT: &'static str = "crsat";
 
-    pub fn new(seed: &[u8], quote_keys: QuoteKeys, maturing_keys: MaturityKeys) -> Self {
+    /// keysets generated by this factory are all in `unit`, e.g. `CURRENCY_UNIT`.
+    /// `network` only tags the master xpriv for serialization: the derived keys are
+    /// the same on every network, so switching it does not invalidate stored keysets
+    pub fn new(
+        seed: &[u8],
+        network: bitcoin::Network,
+        unit: cdk00::CurrencyUnit,
+        quote_keys: QuoteKeys,
+        maturing_keys: MaturityKeys,
+    ) -> Self {
         Self {
             ctx: bitcoin::secp256k1::Secp256k1::new(),
-            xpriv: btc32::Xpriv::new_master(bitcoin::Network::Bitcoin, seed).expect("bitcoin FAIL"),
+            xprivs: vec![btc32::Xpriv::new_master(network, seed).expect("bitcoin FAIL")],
+            network,
             quote_keys,
             maturing_keys,
-            unit: cdk00::CurrencyUnit::Custom(String::from(Self::CURRENCY_UNIT)),
+            unit,
+            max_order: Self::DEFAULT_MAX_ORDER,
+            input_fee_ppk: 0,
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
+            clock: std::sync::Arc::new(SystemClock),
+        }
+    }
+
+    pub fn network(&self) -> bitcoin::Network {
+        self.network
+    }
+
+    /// seed era new keysets are derived in
+    pub fn era(&self) -> u32 {
+        (self.xprivs.len() - 1) as u32
+    }
+
+    /// re-keying, e.g. after a seed compromise: keysets generated from now on are derived
+    /// from `seed`, stored ones keep signing and verifying as they are. maturity keysets
+    /// move to the new seed at their next rotation. returns the new era
+    pub fn add_seed_era(&mut self, seed: &[u8]) -> u32 {
+        let xpriv = btc32::Xpriv::new_master(self.network, seed).expect("bitcoin FAIL");
+        self.xprivs.push(xpriv);
+        self.era()
+    }
+
+    fn xpriv(&self) -> btc32::Xpriv {
+        *self.xprivs.last().expect("at least the initial seed")
+    }
+
+    fn xpriv_of_era(&self, era: u32) -> Result<btc32::Xpriv> {
+        self.xprivs
+            .get(era as usize)
+            .copied()
+            .ok_or(Error::UnknownEra(era))
+    }
+
+    /// keysets generated from now on offer denominations 2^0 .. 2^(max_order - 1)
+    pub fn with_max_order(mut self, max_order: u8) -> Self {
+        self.max_order = max_order;
+        self
+    }
+
+    /// keysets generated from now on charge `input_fee_ppk` per spent proof, see NUT-02
+    pub fn with_input_fee_ppk(mut self, input_fee_ppk: u64) -> Self {
+        self.input_fee_ppk = input_fee_ppk;
+        self
+    }
+
+    /// inactive rotations of a maturity date skipped looking for its active keyset
+    /// before giving up, `keys::MAX_ROTATION_WALK` by default
+    pub fn with_max_rotation_walk(mut self, max_rotation_walk: u32) -> Self {
+        self.max_rotation_walk = max_rotation_walk;
+        self
+    }
+
+    /// time source of the maturity keysets' valid_from
+    pub fn with_clock(mut self, clock: std::sync::Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+impl<QuoteKeys, MaturityKeys> Factory<QuoteKeys, MaturityKeys>
+where
+    MaturityKeys: keys::Repository,
+{
+    // m/129372'/129534'/<keysetID>'/<rotateID>'
+    fn generate_maturity_keyset(
+        &self,
+        maturity_date: TStamp,
+        rotation_idx: u32,
+    ) -> Result<(cdk02::MintKeySet, cdk::mint::MintKeySetInfo)> {
+        self.generate_maturity_keyset_in_era(maturity_date, rotation_idx, self.era())
+    }
+
+    // m/129372'/<129534 + era>'/<keysetID>'/<rotateID>'
+    fn generate_maturity_keyset_in_era(
+        &self,
+        maturity_date: TStamp,
+        rotation_idx: u32,
+        era: u32,
+    ) -> Result<(cdk02::MintKeySet, cdk::mint::MintKeySetInfo)> {
+        let xpriv = self.xpriv_of_era(era)?;
+        let kid = keys::generate_keyset_id_from_date(maturity_date, rotation_idx)?;
+        let path = keys::generate_keyset_path_in_era(kid, None, era);
+        let rotate_child =
+            btc32::ChildNumber::from_hardened_idx(rotation_idx).expect("rotate index is valid");
+        let indexed_path = path.child(rotate_child);
+        let mut keyset = cdk02::MintKeySet::generate_from_xpriv(
+            &self.ctx,
+            xpriv,
+            self.max_order,
+            self.unit.clone(),
+            indexed_path,
+        );
+        keyset.id = kid.into();
+        let info = cdk::mint::MintKeySetInfo {
+            id: keyset.id,
+            unit: self.unit.clone(),
+            active: true,
+            valid_from: self.clock.now().timestamp() as u64,
+            valid_to: Some(maturity_date.timestamp() as u64),
+            derivation_path: path,
+            derivation_path_index: Some(rotation_idx),
+            max_order: self.max_order,
+            input_fee_ppk: self.input_fee_ppk,
+        };
+        Ok((keyset, info))
+    }
+
+    /// regenerates the maturity keyset `kid`, derived in seed era `era`, from the seeds
+    /// alone, e.g. for disaster recovery: maturity date and rotation index are both
+    /// encoded in the id. the id has no room left for the era, which is found in the
+    /// stored derivation path (see `keys::era_of_path`) or tried one by one
+    pub fn rederive_maturity(&self, kid: &KeysetID, era: u32) -> Result<cdk02::MintKeySet> {
+        let (maturity_date, rotation_idx) = keys::extract_date_from_id(kid)?;
+        let (keyset, _) = self.generate_maturity_keyset_in_era(maturity_date, rotation_idx, era)?;
+        Ok(keyset)
+    }
+
+    /// stores the rotation 0 keyset of each maturity date ahead of its first quote,
+    /// dates that already have one are skipped. returns the ids of the keysets created
+    pub async fn pregenerate_maturity(&self, dates: &[TStamp]) -> AnyResult<Vec<KeysetID>> {
+        let mut created = Vec::with_capacity(dates.len());
+        for maturity_date in dates {
+            let kid = keys::generate_keyset_id_from_date(*maturity_date, 0)?;
+            if self.maturing_keys.info(&kid).await?.is_some() {
+                continue;
+            }
+            let (keyset, info) = self.generate_maturity_keyset(*maturity_date, 0)?;
+            self.maturing_keys.store(keyset, info).await?;
+            tracing::debug!(maturity_keyset = %kid, "maturity keyset pregenerated");
+            created.push(kid);
         }
+        Ok(created)
+    }
+
+    async fn load_active_maturity_keyset(
+        &self,
+        maturity_date: TStamp,
+    ) -> AnyResult<(u32, keys::KeysetEntry)> {
+        let mut rotation_idx = 0;
+        loop {
+            let kid = keys::generate_keyset_id_from_date(maturity_date, rotation_idx)?;
+            let Some(entry) = self.maturing_keys.load(&kid).await? else {
+                return Err(anyhow::anyhow!(
+                    "no active maturity keyset for {}",
+                    maturity_date
+                ));
+            };
+            if entry.0.active {
+                return Ok((rotation_idx, entry));
+            }
+            if rotation_idx >= self.max_rotation_walk {
+                return Err(keys::Error::RotationWalkExceeded(
+                    maturity_date,
+                    self.max_rotation_walk,
+                )
+                .into());
+            }
+            rotation_idx += 1;
+        }
+    }
+
+    /// deactivates the current maturity keyset for `maturity_date` and activates the next
+    /// rotation index, returns the id of the new keyset
+    pub async fn rotate_maturity_keyset(&self, maturity_date: TStamp) -> AnyResult<KeysetID> {
+        let (rotation_idx, (mut info, keyset)) =
+            self.load_active_maturity_keyset(maturity_date).await?;
+        info.active = false;
+        self.maturing_keys.store(keyset, info).await?;
+
+        let (keyset, info) = self.generate_maturity_keyset(maturity_date, rotation_idx + 1)?;
+        let kid = KeysetID::from(keyset.id);
+        self.maturing_keys.store(keyset, info).await?;
+        Ok(kid)
     }
 }
 
@@ -67,19 +322,46 @@ impl<QuoteKeys, MaturityKeys> Factory<QuoteKeys, MaturityKeys> {
 impl<QuoteKeys, MaturityKeys> KeyFactory for Factory<QuoteKeys, MaturityKeys>
 where
     QuoteKeys: QuoteBasedRepository,
-    MaturityKeys: keys::Repository,
+    MaturityKeys: MaturityKeyRepository,
 {
+    #[tracing::instrument(
+        skip_all,
+        fields(keyset = %keysetid, quote = %quote, maturity = %bill_maturity_date),
+        err(level = "warn", Display)
+    )]
     async fn generate(
         &self,
         keysetid: KeysetID,
+        bill: &str,
+        endorser: &str,
         quote: uuid::Uuid,
         bill_maturity_date: TStamp,
+        now: TStamp,
     ) -> AnyResult<cdk02::MintKeySet> {
-        let path = keys::generate_keyset_path(keysetid, Some(quote));
+        if bill_maturity_date <= now {
+            return Err(Error::MaturityInPast(bill_maturity_date).into());
+        }
+        // derivation is deterministic, a stored keyset is returned untouched
+        if let Some((_, keyset)) = self.quote_keys.load(&keysetid, quote).await? {
+            tracing::debug!("keyset already generated");
+            return Ok(keyset);
+        }
+        // ids are truncated hashes: another (bill, endorser) may already own this one.
+        // the same pair quoting again gets a keyset of its own, under the new quote
+        match self.quote_keys.owner(&keysetid).await? {
+            Some((owner_bill, owner_endorser))
+                if owner_bill != bill || owner_endorser != endorser =>
+            {
+                return Err(Error::KeysetIdCollision(keysetid).into());
+            }
+            Some(_) => {}
+            None => self.quote_keys.set_owner(&keysetid, bill, endorser).await?,
+        }
+        let path = keys::generate_keyset_path_in_era(keysetid, Some(quote), self.era());
         let keys = cdk02::MintKeySet::generate_from_xpriv(
             &self.ctx,
-            self.xpriv,
-            Self::MAX_ORDER,
+            self.xpriv(),
+            self.max_order,
             self.unit.clone(),
             path.clone(),
         )
@@ -89,62 +371,74 @@ where
             id: keysetid.into(),
             unit: self.unit.clone(),
             active: false,
-            valid_from: chrono::Utc::now().timestamp() as u64,
+            valid_from: now.timestamp() as u64,
             valid_to: Some(bill_maturity_date.timestamp() as u64),
             derivation_path: path,
             derivation_path_index: None,
-            max_order: Self::MAX_ORDER,
-            input_fee_ppk: 0,
+            max_order: self.max_order,
+            input_fee_ppk: self.input_fee_ppk,
         };
         let set = cdk02::MintKeySet {
             id: keysetid.into(),
             keys,
             unit: self.unit.clone(),
         };
-        self.quote_keys.store(quote, set.clone(), info).await?;
 
-        let kid = keys::generate_keyset_id_from_date(bill_maturity_date, 0);
-        if self.maturing_keys.info(&kid).await?.is_some() {
-            return Ok(set);
+        // the maturity keyset goes first: left alone by a failing quote keyset store it is
+        // just pregenerated, whereas a quote keyset without it could never be redeemed
+        let kid = keys::generate_keyset_id_from_date(bill_maturity_date, 0)?;
+        if self.maturing_keys.info(&kid).await?.is_none() {
+            let (keyset, info) = self.generate_maturity_keyset(bill_maturity_date, 0)?;
+            self.maturing_keys.store(keyset, info).await?;
+            tracing::debug!(maturity_keyset = %kid, "maturity keyset stored");
         }
 
-        let kid = keys::generate_keyset_id_from_date(bill_maturity_date, 0);
-        let path = keys::generate_keyset_path(kid, None);
-        // adding <rotate_idx> starts from zero
-        let rotate_child =
-            btc32::ChildNumber::from_hardened_idx(0).expect("rotate index 0 is valid");
-        let indexed_path = path.child(rotate_child);
-        let mut keyset = cdk02::MintKeySet::generate_from_xpriv(
-            &self.ctx,
-            self.xpriv,
-            Self::MAX_ORDER,
-            self.unit.clone(),
-            indexed_path,
-        );
-        keyset.id = kid.into();
-        let info = cdk::mint::MintKeySetInfo {
-            id: keyset.id,
-            unit: self.unit.clone(),
-            active: true,
-            valid_from: chrono::Utc::now().timestamp() as u64,
-            valid_to: Some(bill_maturity_date.timestamp() as u64),
-            derivation_path: path,
-            derivation_path_index: Some(0),
-            max_order: Self::MAX_ORDER,
-            input_fee_ppk: 0,
-        };
-        self.maturing_keys.store(keyset.clone(), info).await?;
+        self.quote_keys.store(quote, set.clone(), info).await?;
+        tracing::debug!("quote keyset stored");
 
         Ok(set)
     }
+
+    async fn record_issued(&self, maturity_date: TStamp, amount: cdk::Amount) -> AnyResult<()> {
+        let (_, (info, _)) = self.load_active_maturity_keyset(maturity_date).await?;
+        self.maturing_keys
+            .record_issued(&KeysetID::from(info.id), amount)
+            .await
+    }
 }
 
 // ---------- Swap Keys Repository
-#[derive(Default, Clone)]
+#[derive(Clone)]
 pub struct SwapRepository<KeysRepo, ActiveRepo> {
     pub endorsed_keys: KeysRepo,
     pub maturity_keys: KeysRepo,
     pub debit_keys: ActiveRepo,
+    /// bound of the maturity keyset walks, `keys::MAX_ROTATION_WALK` by default
+    pub max_rotation_walk: u32,
+}
+
+// maturity date of a stored keyset, a keyset missing it is corrupted
+pub(crate) fn maturity_of(info: &cdk::mint::MintKeySetInfo) -> swap::KeysRepoResult<TStamp> {
+    let kid = KeysetID::from(info.id);
+    let corrupt = |reason: &str| swap::KeysRepoError::Corrupt(kid, String::from(reason));
+    let valid_to = info
+        .valid_to
+        .ok_or_else(|| corrupt("valid_to field not set"))?;
+    let maturity = i64::try_from(valid_to)
+        .ok()
+        .and_then(|valid_to| TStamp::from_timestamp(valid_to, 0))
+        .ok_or_else(|| corrupt("valid_to is not a valid datetime"))?;
+    Ok(maturity)
+}
+
+// the first occurrence of an id wins, i.e. endorsed over maturity over debit keysets
+fn dedup_and_sort(mut infos: Vec<cdk::mint::MintKeySetInfo>) -> Vec<cdk::mint::MintKeySetInfo> {
+    let mut seen = std::collections::HashSet::with_capacity(infos.len());
+    infos.retain(|info| seen.insert(info.id));
+    infos.sort_by(|lhs, rhs| {
+        (&lhs.unit, lhs.valid_from, lhs.id).cmp(&(&rhs.unit, rhs.valid_from, rhs.id))
+    });
+    infos
 }
 
 impl<KeysRepo, ActiveRepo> SwapRepository<KeysRepo, ActiveRepo>
@@ -155,72 +449,102 @@ where
     async fn find_maturity_keys_from_maturity_date(
         &self,
         maturity_date: TStamp,
-        mut rotation_idx: u32,
-    ) -> Result<Option<KeysetID>> {
-        let mut kid = keys::generate_keyset_id_from_date(maturity_date, rotation_idx);
-        while let Some(info) = self.maturity_keys.info(&kid).await? {
-            if info.active {
-                return Ok(Some(kid));
-            }
-            rotation_idx += 1;
-            kid = keys::generate_keyset_id_from_date(maturity_date, rotation_idx)
-        }
-        Ok(None)
+    ) -> swap::KeysRepoResult<Option<KeysetID>> {
+        self.maturity_keys
+            .active_for_maturity(maturity_date, self.max_rotation_walk)
+            .await
+            .map_err(|e| match e.downcast_ref::<keys::Error>() {
+                Some(keys::Error::RotationWalkExceeded(date, max)) => {
+                    swap::KeysRepoError::RotationWalkExceeded(*date, *max)
+                }
+                _ => swap::KeysRepoError::Backend(e),
+            })
     }
 
-    async fn find_maturity_keys_from_id(&self, kid: &KeysetID) -> Result<Option<KeysetID>> {
+    /// seed era keyset `kid` was derived in, see `Factory::add_seed_era`.
+    /// None for unknown keysets and for debit ones, which have no era
+    pub async fn seed_era(&self, kid: &KeysetID) -> swap::KeysRepoResult<Option<u32>> {
+        let info = match self.endorsed_keys.info(kid).await? {
+            Some(info) => Some(info),
+            None => self.maturity_keys.info(kid).await?,
+        };
+        Ok(info.and_then(|info| keys::era_of_path(&info.derivation_path)))
+    }
+
+    async fn find_maturity_keys_from_id(
+        &self,
+        kid: &KeysetID,
+    ) -> swap::KeysRepoResult<Option<KeysetID>> {
         if let Some(info) = self.maturity_keys.info(kid).await? {
             if info.active {
                 return Ok(Some(*kid));
             }
-            let valid_to = info.valid_to.expect("valid_to field not set") as i64;
-            let maturity =
-                TStamp::from_timestamp(valid_to, 0).expect("datetime conversion from u64");
-            let rotation_index = info
-                .derivation_path_index
-                .expect("derivation_path_index not set");
-            return self
-                .find_maturity_keys_from_maturity_date(maturity, rotation_index + 1)
-                .await;
+            let maturity = maturity_of(&info)?;
+            return self.find_maturity_keys_from_maturity_date(maturity).await;
         }
         Ok(None)
     }
 }
 
+#[async_trait]
+impl<KeysRepo, ActiveRepo> HealthCheck for SwapRepository<KeysRepo, ActiveRepo>
+where
+    KeysRepo: HealthCheck,
+    ActiveRepo: HealthCheck,
+{
+    async fn ready(&self) -> bool {
+        self.endorsed_keys.ready().await
+            && self.maturity_keys.ready().await
+            && self.debit_keys.ready().await
+    }
+}
+
 #[async_trait]
 impl<KeysRepo, ActiveRepo> swap::KeysRepository for SwapRepository<KeysRepo, ActiveRepo>
 where
     KeysRepo: keys::Repository,
     ActiveRepo: keys::ActiveRepository,
 {
-    async fn keyset(&self, id: &KeysetID) -> AnyResult<Option<cdk02::MintKeySet>> {
+    async fn keyset(&self, id: &KeysetID) -> swap::KeysRepoResult<Option<cdk02::MintKeySet>> {
         if let Some(keyset) = self.endorsed_keys.keyset(id).await? {
             return Ok(Some(keyset));
         }
         if let Some(keyset) = self.maturity_keys.keyset(id).await? {
             return Ok(Some(keyset));
         }
-        self.debit_keys.keyset(id).await
+        Ok(self.debit_keys.keyset(id).await?)
     }
-    async fn info(&self, id: &KeysetID) -> AnyResult<Option<cdk::mint::MintKeySetInfo>> {
+    async fn info(&self, id: &KeysetID) -> swap::KeysRepoResult<Option<cdk::mint::MintKeySetInfo>> {
         if let Some(info) = self.endorsed_keys.info(id).await? {
             return Ok(Some(info));
         }
         if let Some(info) = self.maturity_keys.info(id).await? {
             return Ok(Some(info));
         }
-        self.debit_keys.info(id).await
+        Ok(self.debit_keys.info(id).await?)
+    }
+    // endorsed keysets are spendable once enabled, maturity and debit ones always are
+    async fn spendable(&self, kid: &KeysetID) -> swap::KeysRepoResult<bool> {
+        if let Some(info) = self.endorsed_keys.info(kid).await? {
+            return Ok(info.active);
+        }
+        if self.maturity_keys.info(kid).await?.is_some() {
+            return Ok(true);
+        }
+        if self.debit_keys.info(kid).await?.is_some() {
+            return Ok(true);
+        }
+        Err(swap::KeysRepoError::NotFound(*kid))
     }
     // in case keyset id is inactive, returns the proper replacement for it
-    async fn replacing_id(&self, kid: &KeysetID) -> AnyResult<Option<KeysetID>> {
+    async fn replacing_id(&self, kid: &KeysetID) -> swap::KeysRepoResult<Option<KeysetID>> {
         if let Some(info) = self.endorsed_keys.info(kid).await? {
-            let valid_to = info.valid_to.expect("valid_to field not set") as i64;
-            let maturity =
-                TStamp::from_timestamp(valid_to, 0).expect("datetime conversion from u64");
-            if let Some(id) = self
-                .find_maturity_keys_from_maturity_date(maturity, 0)
-                .await?
-            {
+            // quote keysets are redeemable only once enabled by the endorsement
+            if !info.active {
+                return Err(swap::KeysRepoError::Disabled(*kid));
+            }
+            let maturity = maturity_of(&info)?;
+            if let Some(id) = self.find_maturity_keys_from_maturity_date(maturity).await? {
                 return Ok(Some(id));
             }
         }
@@ -235,52 +559,1599 @@ where
             .map(KeysetID::from);
         Ok(kid)
     }
-}
+    async fn list_info(&self) -> swap::KeysRepoResult<Vec<cdk::mint::MintKeySetInfo>> {
+        let mut infos = self.endorsed_keys.list_info().await?;
+        infos.extend(self.maturity_keys.list_info().await?);
+        infos.extend(self.debit_keys.list_info().await?);
+        Ok(dedup_and_sort(infos))
+    }
+    // quote keysets until enabled by the endorsement and debit keysets but the active one
+    // are left out, maturity keysets are always spendable
+    async fn list_info_filtered(
+        &self,
+        only_enabled: bool,
+    ) -> swap::KeysRepoResult<Vec<cdk::mint::MintKeySetInfo>> {
+        let mut infos = self.endorsed_keys.list_info_filtered(only_enabled).await?;
+        infos.extend(self.maturity_keys.list_info().await?);
+        infos.extend(self.debit_keys.list_info_filtered(only_enabled).await?);
+        Ok(dedup_and_sort(infos))
+    }
+    // only endorsed keysets may be locked, to the endorser
+    async fn locked_to(&self, kid: &KeysetID) -> swap::KeysRepoResult<Option<cdk01::PublicKey>> {
+        let metadata = self.endorsed_keys.metadata(kid).await?;
+        Ok(metadata.and_then(|metadata| metadata.locked_to))
+    }
+}
+
+// ---------- maturity lookup
+/// maturity date and rotation index encoded in a maturity keyset id, None if they do
+/// not decode to a valid date.
+/// quote keysets (see `keys::credit::generate_keyset_id_from_bill`) cannot be told apart
+/// from maturity ones by their bytes: callers must only pass maturity keyset ids,
+/// otherwise the returned date is meaningless
+pub fn maturity_date_of(kid: &KeysetID) -> Option<(TStamp, u32)> {
+    keys::extract_date_from_id(kid).ok()
+}
+
+// ---------- endorsement activation
+/// moves the quote keyset of `(bill, endorser)` into the endorsed keys, activated
+pub async fn activate_endorsed_keyset<QuoteKeys, EndorsedKeys>(
+    quote_keys: &QuoteKeys,
+    endorsed_keys: &EndorsedKeys,
+    bill: &str,
+    endorser: &str,
+) -> Result<KeysetID>
+where
+    QuoteKeys: QuoteBasedRepository,
+    EndorsedKeys: keys::Repository,
+{
+    let kid = keys::credit::generate_keyset_id_from_bill(bill, endorser);
+    let (mut info, keyset) = quote_keys
+        .search_by_kid(&kid)
+        .await?
+        .ok_or(Error::UnknownKeyset(kid))?;
+    info.active = true;
+    endorsed_keys.store(keyset, info).await?;
+    Ok(kid)
+}
+
+/// as `activate_endorsed_keyset` for the bill of `quote`, carrying over its lock if any
+pub async fn activate_endorsed_quote<QuoteKeys, EndorsedKeys>(
+    quote_keys: &QuoteKeys,
+    endorsed_keys: &EndorsedKeys,
+    quote: &quotes::Quote,
+) -> Result<KeysetID>
+where
+    QuoteKeys: QuoteBasedRepository,
+    EndorsedKeys: keys::Repository,
+{
+    let kid =
+        activate_endorsed_keyset(quote_keys, endorsed_keys, &quote.bill, &quote.endorser).await?;
+    if let Some(lock) = quote.locked_to {
+        let mut metadata = endorsed_keys
+            .metadata(&kid)
+            .await?
+            .ok_or(Error::UnknownKeyset(kid))?;
+        metadata.locked_to = Some(lock);
+        endorsed_keys.set_metadata(&kid, metadata).await?;
+    }
+    Ok(kid)
+}
+
+/// the quote and endorsed keys, for activating the keyset of an accepted quote once its
+/// bill is endorsed to the mint: swaps only accept endorsed keysets
+#[derive(Clone)]
+pub struct Endorsements<QuoteKeys, EndorsedKeys> {
+    pub quote_keys: QuoteKeys,
+    pub endorsed_keys: EndorsedKeys,
+}
+
+impl<QuoteKeys, EndorsedKeys> Endorsements<QuoteKeys, EndorsedKeys>
+where
+    QuoteKeys: QuoteBasedRepository,
+    EndorsedKeys: keys::Repository,
+{
+    pub async fn activate(&self, quote: &quotes::Quote) -> Result<KeysetID> {
+        activate_endorsed_quote(&self.quote_keys, &self.endorsed_keys, quote).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::keys::test_utils as keys_test;
+    use crate::persistence::inmemory;
+    use crate::swap::KeysRepository;
+    use crate::utils::tests as utils;
+    use mockall::predicate::*;
+    use std::str::FromStr;
+
+    fn crsat() -> cdk00::CurrencyUnit {
+        cdk00::CurrencyUnit::Custom(String::from("crsat"))
+    }
+
+    mockall::mock! {
+        MaturityKeys {}
+        #[async_trait]
+        impl keys::Repository for MaturityKeys {
+        async fn info(&self, kid: &KeysetID) -> AnyResult<Option<cdk::mint::MintKeySetInfo>>;
+        async fn keyset(&self, kid: &KeysetID) -> AnyResult<Option<cdk02::MintKeySet>>;
+        async fn load(&self, kid: &KeysetID) -> AnyResult<Option<keys::KeysetEntry>>;
+        async fn store(&self, keyset: cdk02::MintKeySet, info: cdk::mint::MintKeySetInfo) -> AnyResult<()>;
+        async fn list_info(&self) -> AnyResult<Vec<cdk::mint::MintKeySetInfo>>;
+        }
+        #[async_trait]
+        impl MaturityKeyRepository for MaturityKeys {
+        async fn record_issued(&self, kid: &KeysetID, amount: cdk::Amount) -> AnyResult<()>;
+        async fn issued(&self, kid: &KeysetID) -> AnyResult<cdk::Amount>;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keys_factory_generate() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+
+        let keyid = KeysetID::from(cdk02::Id::from_bytes(&[0u8; 8]).unwrap());
+        let quote = uuid::Uuid::from_u128(0);
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+
+        let mut maturitykeys_repo = MockMaturityKeys::new();
+        maturitykeys_repo.expect_info().returning(|_| Ok(None));
+        maturitykeys_repo.expect_store().returning(|_, _| Ok(()));
+        let mut quotekeys_repo = MockQuoteBasedRepository::new();
+        quotekeys_repo.expect_owner().returning(|_| Ok(None));
+        quotekeys_repo
+            .expect_set_owner()
+            .returning(|_, _, _| Ok(()));
+        quotekeys_repo.expect_load().returning(|_, _| Ok(None));
+        quotekeys_repo
+            .expect_search_by_kid()
+            .returning(|_| Ok(None));
+        quotekeys_repo
+            .expect_store()
+            .with(eq(quote), always(), always())
+            .returning(|_, _, _| Ok(()));
+        //quotekeys_repo.expect_store().returning(|_, _| Ok(()));
+
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            quotekeys_repo,
+            maturitykeys_repo,
+        );
+
+        let keyset = factory
+            .generate(keyid, "billID", "endorserID", quote, maturity, now)
+            .await
+            .unwrap();
+        // m/129372'/129534'/0'/927402239'/0'
+        let key = &keyset.keys[&cdk::Amount::from(1_u64)];
+        assert_eq!(
+            key.public_key.to_hex(),
+            "03287106d3d2f1df660f7c7764e39e98051bca0c95feb9604336e9744de88eac68"
+        );
+        // m/129372'/129534'/0'/927402239'/5'
+        let key = &keyset.keys[&cdk::Amount::from(32_u64)];
+        assert_eq!(
+            key.public_key.to_hex(),
+            "03c5b66986d15100d1c0b342e012da7a954c7040c13d514ebc3b282ffa3a54651f"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keys_factory_network_does_not_change_keys() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+        let kid = keys_test::generate_random_keysetid();
+        let qid = uuid::Uuid::from_u128(0);
+        let mut keysets = Vec::new();
+        for network in [bitcoin::Network::Bitcoin, bitcoin::Network::Testnet] {
+            let factory = Factory::new(
+                &seed,
+                network,
+                crsat(),
+                inmemory::KeysetIDQuoteIDMap::default(),
+                inmemory::KeysetIDEntryMapWithActive::default(),
+            );
+            assert_eq!(factory.network(), network);
+            keysets.push(
+                factory
+                    .generate(kid, "billID", "endorserID", qid, maturity, now)
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        assert_eq!(keysets[0].id, keysets[1].id);
+        assert_eq!(
+            keys::public_keys(&keysets[0]),
+            keys::public_keys(&keysets[1])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keys_factory_maturity_public_keys() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+        let maturity_keys = inmemory::KeysetIDEntryMapWithActive::default();
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            maturity_keys.clone(),
+        );
+        factory
+            .generate(
+                keys_test::generate_random_keysetid(),
+                "billID",
+                "endorserID",
+                uuid::Uuid::from_u128(0),
+                maturity,
+                now,
+            )
+            .await
+            .unwrap();
+
+        let kid = keys::generate_keyset_id_from_date(maturity, 0).unwrap();
+        let pks = keys::Repository::public_keys(&maturity_keys, &kid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(pks.len(), usize::from(Factory::<(), ()>::DEFAULT_MAX_ORDER));
+        let active = keys::ActiveRepository::active_public_keys(&maturity_keys)
+            .await
+            .unwrap();
+        assert_eq!(active.as_ref(), Some(&pks));
+
+        let keyset = keys::Repository::keyset(&maturity_keys, &kid)
+            .await
+            .unwrap()
+            .unwrap();
+        let serialized = serde_json::to_string(&pks).unwrap();
+        for (amount, pair) in keyset.keys.iter() {
+            assert_eq!(pks[amount], pair.public_key);
+            assert!(!serialized.contains(&pair.secret_key.to_secret_hex()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keys_factory_generate_twice() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+        let kid = keys_test::generate_random_keysetid();
+        let quote = uuid::Uuid::from_u128(0);
+
+        let stored: std::sync::Arc<std::sync::Mutex<Option<keys::KeysetEntry>>> =
+            Default::default();
+        let mut quotekeys_repo = MockQuoteBasedRepository::new();
+        quotekeys_repo.expect_owner().returning(|_| Ok(None));
+        quotekeys_repo
+            .expect_set_owner()
+            .returning(|_, _, _| Ok(()));
+        let loaded = stored.clone();
+        quotekeys_repo
+            .expect_load()
+            .with(eq(kid), eq(quote))
+            .returning(move |_, _| Ok(loaded.lock().unwrap().clone()));
+        quotekeys_repo
+            .expect_search_by_kid()
+            .returning(|_| Ok(None));
+        let store = stored.clone();
+        quotekeys_repo
+            .expect_store()
+            .times(1)
+            .returning(move |_, keyset, info| {
+                *store.lock().unwrap() = Some((info, keyset));
+                Ok(())
+            });
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            quotekeys_repo,
+            inmemory::KeysetIDEntryMap::default(),
+        );
+
+        let keyset = factory
+            .generate(kid, "billID", "endorserID", quote, maturity, now)
+            .await
+            .unwrap();
+        let valid_from = stored.lock().unwrap().as_ref().unwrap().0.valid_from;
+        let again = factory
+            .generate(kid, "billID", "endorserID", quote, maturity, now)
+            .await
+            .unwrap();
+        assert_eq!(keyset, again);
+        let (info, _) = stored.lock().unwrap().clone().unwrap();
+        assert_eq!(info.valid_from, valid_from);
+    }
+
+    #[tokio::test]
+    async fn test_keys_factory_unit() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+        let crusd = cdk00::CurrencyUnit::Custom(String::from("crusd"));
+        let maturity_keys = inmemory::KeysetIDEntryMap::default();
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crusd.clone(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            maturity_keys.clone(),
+        );
+        let keyset = factory
+            .generate(
+                keys_test::generate_random_keysetid(),
+                "billID",
+                "endorserID",
+                uuid::Uuid::from_u128(0),
+                maturity,
+                now,
+            )
+            .await
+            .unwrap();
+        assert_eq!(keyset.unit, crusd);
+        let kid = keys::generate_keyset_id_from_date(maturity, 0).unwrap();
+        let info = keys::Repository::info(&maturity_keys, &kid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.unit, crusd);
+    }
+
+    #[tokio::test]
+    async fn test_keys_factory_generate_maturity_just_past() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let now = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            inmemory::KeysetIDEntryMap::default(),
+        );
+        let kid = keys_test::generate_random_keysetid();
+        let quote = uuid::Uuid::from_u128(0);
+
+        for maturity in [now, now - chrono::Duration::seconds(1)] {
+            let e = factory
+                .generate(kid, "billID", "endorserID", quote, maturity, now)
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                e.downcast_ref::<Error>(),
+                Some(Error::MaturityInPast(date)) if *date == maturity
+            ));
+        }
+        assert!(factory
+            .quote_keys
+            .load(&kid, quote)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_keys_factory_generate_maturity_just_future() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let now = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let maturity = now + chrono::Duration::seconds(1);
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            inmemory::KeysetIDEntryMap::default(),
+        );
+        let kid = keys_test::generate_random_keysetid();
+        let quote = uuid::Uuid::from_u128(0);
+
+        factory
+            .generate(kid, "billID", "endorserID", quote, maturity, now)
+            .await
+            .unwrap();
+        let (info, _) = factory.quote_keys.load(&kid, quote).await.unwrap().unwrap();
+        assert_eq!(info.valid_from, now.timestamp() as u64);
+        assert_eq!(info.valid_to, Some(maturity.timestamp() as u64));
+    }
+
+    #[tokio::test]
+    async fn test_keys_factory_generate_keyset_id_collision() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            inmemory::KeysetIDEntryMap::default(),
+        );
+        // two (bill, endorser) pairs forced onto the same truncated id
+        let kid = keys_test::generate_random_keysetid();
+        let qid = uuid::Uuid::from_u128(0);
+
+        let keyset = factory
+            .generate(kid, "billA", "endorserA", qid, maturity, now)
+            .await
+            .unwrap();
+        let e = factory
+            .generate(
+                kid,
+                "billB",
+                "endorserB",
+                uuid::Uuid::from_u128(1),
+                maturity,
+                now,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<Error>(),
+            Some(Error::KeysetIdCollision(id)) if *id == kid
+        ));
+        let stored = factory.quote_keys.load(&kid, qid).await.unwrap();
+        assert_eq!(stored.map(|(_, keyset)| keyset), Some(keyset));
+    }
+
+    #[tokio::test]
+    async fn test_keys_factory_generate_requote_same_bill_endorser() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            inmemory::KeysetIDEntryMap::default(),
+        );
+        let kid = keys::credit::generate_keyset_id_from_bill("bill", "endorser");
+        let first = uuid::Uuid::from_u128(0);
+        let second = uuid::Uuid::from_u128(1);
+
+        let keyset = factory
+            .generate(kid, "bill", "endorser", first, maturity, now)
+            .await
+            .unwrap();
+        let again = factory
+            .generate(kid, "bill", "endorser", first, maturity, now)
+            .await
+            .unwrap();
+        assert_eq!(keyset, again);
+        let requoted = factory
+            .generate(kid, "bill", "endorser", second, maturity, now)
+            .await
+            .unwrap();
+        assert_eq!(keyset.id, requoted.id);
+        assert_ne!(keyset, requoted);
+
+        let stored = factory.quote_keys.load(&kid, second).await.unwrap();
+        assert_eq!(stored.map(|(_, keyset)| keyset), Some(requoted));
+        let by_bill = factory
+            .quote_keys
+            .load_by_bill("bill", "endorser")
+            .await
+            .unwrap();
+        assert_eq!(by_bill.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_activate_endorsed_keyset() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            inmemory::KeysetIDEntryMap::default(),
+        );
+        let endorsed = inmemory::KeysetIDEntryMap::default();
+        let kid = keys::credit::generate_keyset_id_from_bill("bill", "endorser");
+        let keyset = factory
+            .generate(
+                kid,
+                "billID",
+                "endorserID",
+                uuid::Uuid::from_u128(0),
+                maturity,
+                now,
+            )
+            .await
+            .unwrap();
+
+        let e = activate_endorsed_keyset(&factory.quote_keys, &endorsed, "bill", "other")
+            .await
+            .unwrap_err();
+        assert!(matches!(e, Error::UnknownKeyset(_)));
+
+        let activated =
+            activate_endorsed_keyset(&factory.quote_keys, &endorsed, "bill", "endorser")
+                .await
+                .unwrap();
+        assert_eq!(activated, kid);
+        let (info, stored) = keys::Repository::load(&endorsed, &kid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(info.active);
+        assert_eq!(stored, keyset);
+    }
+
+    #[tokio::test]
+    async fn test_keys_factory_issued_amount_across_rotations() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+        let other_maturity = maturity + chrono::Duration::days(1);
+        let maturity_keys = inmemory::KeysetIDEntryMap::default();
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            maturity_keys.clone(),
+        );
+        for (idx, date) in [maturity, other_maturity].into_iter().enumerate() {
+            factory
+                .generate(
+                    keys_test::generate_random_keysetid(),
+                    "billID",
+                    "endorserID",
+                    uuid::Uuid::from_u128(idx as u128),
+                    date,
+                    now,
+                )
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            maturity_keys
+                .issued_amount(maturity, keys::MAX_ROTATION_WALK)
+                .await
+                .unwrap(),
+            cdk::Amount::ZERO
+        );
+
+        factory
+            .record_issued(maturity, cdk::Amount::from(8_u64))
+            .await
+            .unwrap();
+        factory.rotate_maturity_keyset(maturity).await.unwrap();
+        factory
+            .record_issued(maturity, cdk::Amount::from(4_u64))
+            .await
+            .unwrap();
+        factory
+            .record_issued(other_maturity, cdk::Amount::from(1_u64))
+            .await
+            .unwrap();
+
+        let kid0 = keys::generate_keyset_id_from_date(maturity, 0).unwrap();
+        let kid1 = keys::generate_keyset_id_from_date(maturity, 1).unwrap();
+        assert_eq!(
+            maturity_keys.issued(&kid0).await.unwrap(),
+            cdk::Amount::from(8_u64)
+        );
+        assert_eq!(
+            maturity_keys.issued(&kid1).await.unwrap(),
+            cdk::Amount::from(4_u64)
+        );
+        assert_eq!(
+            maturity_keys
+                .issued_amount(maturity, keys::MAX_ROTATION_WALK)
+                .await
+                .unwrap(),
+            cdk::Amount::from(12_u64)
+        );
+        assert_eq!(
+            maturity_keys
+                .issued_amount(other_maturity, keys::MAX_ROTATION_WALK)
+                .await
+                .unwrap(),
+            cdk::Amount::from(1_u64)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keys_factory_generate_maturity_before_epoch() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("1969-12-31T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+        let maturity_keys = inmemory::KeysetIDEntryMap::default();
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            maturity_keys.clone(),
+        );
+        let r = factory
+            .generate(
+                keys_test::generate_random_keysetid(),
+                "billID",
+                "endorserID",
+                uuid::Uuid::new_v4(),
+                maturity,
+                now,
+            )
+            .await;
+        assert!(matches!(
+            r.unwrap_err().downcast_ref::<keys::Error>(),
+            Some(keys::Error::TStamp(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_keys_factory_seed_era() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let new_seed =
+            bip39::Mnemonic::from_str("zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong")
+                .unwrap()
+                .to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+        let maturity_keys = inmemory::KeysetIDEntryMap::default();
+        let mut factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            maturity_keys.clone(),
+        );
+        let swap_repo = SwapRepository {
+            endorsed_keys: inmemory::KeysetIDEntryMap::default(),
+            maturity_keys: maturity_keys.clone(),
+            debit_keys: inmemory::KeysetIDEntryMapWithActive::default(),
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
+        };
+        factory
+            .generate(
+                keys_test::generate_random_keysetid(),
+                "billID",
+                "endorserID",
+                uuid::Uuid::new_v4(),
+                maturity,
+                now,
+            )
+            .await
+            .unwrap();
+        let kid0 = keys::generate_keyset_id_from_date(maturity, 0).unwrap();
+        let keyset0 = keys::Repository::keyset(&maturity_keys, &kid0)
+            .await
+            .unwrap()
+            .unwrap();
+        let proofs = utils::generate_proofs(&keyset0, &[cdk::Amount::from(8_u64)]);
+
+        assert_eq!(factory.add_seed_era(&new_seed), 1);
+        let kid1 = factory.rotate_maturity_keyset(maturity).await.unwrap();
+        assert_eq!(swap_repo.seed_era(&kid0).await.unwrap(), Some(0));
+        assert_eq!(swap_repo.seed_era(&kid1).await.unwrap(), Some(1));
+        // the same rotation derived from the new seed has other keys
+        let keyset1 = keys::Repository::keyset(&maturity_keys, &kid1)
+            .await
+            .unwrap()
+            .unwrap();
+        let (old_era1, _) = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            inmemory::KeysetIDEntryMap::default(),
+        )
+        .generate_maturity_keyset(maturity, 1)
+        .unwrap();
+        assert_ne!(keys::public_keys(&keyset1), keys::public_keys(&old_era1));
+
+        // eCash signed in era 0 still verifies
+        let swaps = crate::swap::Service {
+            keys: swap_repo,
+            proofs: inmemory::ProofMap::default(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: std::sync::Arc::new(crate::clock::SystemClock),
+        };
+        let verified = swaps
+            .verify_proofs_signatures(&proofs, crate::swap::KeysetValidity::Ignore)
+            .await
+            .unwrap();
+        assert!(verified);
+    }
+
+    #[tokio::test]
+    async fn test_maturity_keys_current_rotation() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let other_maturity = maturity + chrono::Duration::days(1);
+        let maturity_keys = inmemory::KeysetIDEntryMap::default();
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            maturity_keys.clone(),
+        );
+        assert_eq!(
+            maturity_keys.current_rotation(maturity).await.unwrap(),
+            None
+        );
+
+        // rotation 1 is missing, e.g. pruned
+        for (date, rotation_idx) in [(maturity, 0), (maturity, 2), (other_maturity, 5)] {
+            let (keyset, info) = factory
+                .generate_maturity_keyset(date, rotation_idx)
+                .unwrap();
+            keys::Repository::store(&maturity_keys, keyset, info)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            maturity_keys.current_rotation(maturity).await.unwrap(),
+            Some(2)
+        );
+        assert_eq!(
+            maturity_keys
+                .current_rotation(other_maturity)
+                .await
+                .unwrap(),
+            Some(5)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keys_factory_rotate_maturity_keyset() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+        let maturity_keys = inmemory::KeysetIDEntryMap::default();
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            maturity_keys.clone(),
+        );
+        let swap_repo = SwapRepository {
+            endorsed_keys: inmemory::KeysetIDEntryMap::default(),
+            maturity_keys: maturity_keys.clone(),
+            debit_keys: inmemory::KeysetIDEntryMapWithActive::default(),
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
+        };
+        factory
+            .generate(
+                keys_test::generate_random_keysetid(),
+                "billID",
+                "endorserID",
+                uuid::Uuid::new_v4(),
+                maturity,
+                now,
+            )
+            .await
+            .unwrap();
+        let kid0 = keys::generate_keyset_id_from_date(maturity, 0).unwrap();
+        assert_eq!(swap_repo.replacing_id(&kid0).await.unwrap(), Some(kid0));
+
+        let kid1 = factory.rotate_maturity_keyset(maturity).await.unwrap();
+        assert_eq!(
+            kid1,
+            keys::generate_keyset_id_from_date(maturity, 1).unwrap()
+        );
+        let info0 = keys::Repository::info(&maturity_keys, &kid0)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!info0.active);
+        let info1 = keys::Repository::info(&maturity_keys, &kid1)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(info1.active);
+        assert_eq!(info1.derivation_path_index, Some(1));
+        assert_eq!(swap_repo.replacing_id(&kid0).await.unwrap(), Some(kid1));
+
+        let kid2 = factory.rotate_maturity_keyset(maturity).await.unwrap();
+        assert_eq!(swap_repo.replacing_id(&kid0).await.unwrap(), Some(kid2));
+        assert_eq!(swap_repo.replacing_id(&kid1).await.unwrap(), Some(kid2));
+    }
+
+    #[tokio::test]
+    async fn test_keys_factory_generate_maturity_store_fails() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+        let kid = keys_test::generate_random_keysetid();
+        let quote = uuid::Uuid::new_v4();
+
+        let mut maturity_repo = MockMaturityKeys::new();
+        maturity_repo.expect_info().returning(|_| Ok(None));
+        maturity_repo
+            .expect_store()
+            .times(1)
+            .returning(|_, _| Err(anyhow::anyhow!("connection lost")));
+        let quote_keys = inmemory::KeysetIDQuoteIDMap::default();
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            quote_keys.clone(),
+            maturity_repo,
+        );
+
+        assert!(factory
+            .generate(kid, "billID", "endorserID", quote, maturity, now)
+            .await
+            .is_err());
+        assert!(quote_keys.load(&kid, quote).await.unwrap().is_none());
+        assert!(quote_keys.search_by_kid(&kid).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_keys_factory_pregenerate_maturity() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let first = chrono::DateTime::parse_from_rfc3339("2021-01-31T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let second = chrono::DateTime::parse_from_rfc3339("2021-02-28T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = first - chrono::Duration::days(30);
+
+        let stored: std::sync::Arc<
+            std::sync::Mutex<std::collections::HashMap<KeysetID, cdk::mint::MintKeySetInfo>>,
+        > = Default::default();
+        let mut maturity_repo = MockMaturityKeys::new();
+        let loaded = stored.clone();
+        maturity_repo
+            .expect_info()
+            .returning(move |kid| Ok(loaded.lock().unwrap().get(kid).cloned()));
+        let store = stored.clone();
+        maturity_repo
+            .expect_store()
+            .times(2)
+            .returning(move |_, info| {
+                store.lock().unwrap().insert(KeysetID::from(info.id), info);
+                Ok(())
+            });
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            maturity_repo,
+        );
+
+        let created = factory
+            .pregenerate_maturity(&[first, second])
+            .await
+            .unwrap();
+        let kid_first = keys::generate_keyset_id_from_date(first, 0).unwrap();
+        let kid_second = keys::generate_keyset_id_from_date(second, 0).unwrap();
+        assert_eq!(created, vec![kid_first, kid_second]);
+        assert!(factory
+            .pregenerate_maturity(&[first])
+            .await
+            .unwrap()
+            .is_empty());
+
+        // the quote keyset is new, the maturity keyset is not stored again
+        factory
+            .generate(
+                keys_test::generate_random_keysetid(),
+                "billID",
+                "endorserID",
+                uuid::Uuid::new_v4(),
+                first,
+                now,
+            )
+            .await
+            .unwrap();
+        assert_eq!(stored.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_swap_locked_quote_keyset() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+        let maturity_keys = inmemory::KeysetIDEntryMap::default();
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            maturity_keys.clone(),
+        );
+        let endorser = cdk01::SecretKey::generate();
+        let mut quote =
+            quotes::Quote::new(String::from("bill"), String::from("endorser"), vec![], now);
+        quote.lock_to(endorser.public_key()).unwrap();
+        let kid = keys::credit::generate_keyset_id_from_bill("bill", "endorser");
+        let keyset = factory
+            .generate(kid, "billID", "endorserID", quote.id, maturity, now)
+            .await
+            .unwrap();
+        let endorsed_keys = inmemory::KeysetIDEntryMap::default();
+        activate_endorsed_quote(&factory.quote_keys, &endorsed_keys, &quote)
+            .await
+            .unwrap();
+        let swaps = swap::Service {
+            keys: SwapRepository {
+                endorsed_keys,
+                maturity_keys: maturity_keys.clone(),
+                debit_keys: inmemory::KeysetIDEntryMapWithActive::default(),
+                max_rotation_walk: keys::MAX_ROTATION_WALK,
+            },
+            proofs: inmemory::ProofMap::default(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: std::sync::Arc::new(crate::clock::SystemClock),
+        };
+        assert_eq!(
+            swaps.keys.locked_to(&kid).await.unwrap(),
+            Some(endorser.public_key())
+        );
+        let maturity_keyset = keys::Repository::keyset(
+            &maturity_keys,
+            &keys::generate_keyset_id_from_date(maturity, 0).unwrap(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let locked_proof = |key: &cdk01::SecretKey| {
+            let conditions = cdk::nuts::SpendingConditions::new_p2pk(key.public_key(), None);
+            let secret = cdk::nuts::nut10::Secret::from(conditions)
+                .try_into()
+                .unwrap();
+            let mut proof =
+                utils::generate_proof_with_secret(&keyset, cdk::Amount::from(8_u64), secret);
+            proof.sign_p2pk(key.clone()).unwrap();
+            proof
+        };
+        let outputs = || -> Vec<cdk00::BlindedMessage> {
+            utils::generate_blinds(&maturity_keyset, &[cdk::Amount::from(8_u64)])
+                .into_iter()
+                .map(|b| b.0)
+                .collect()
+        };
+
+        // held by someone else, whether unlocked or locked to their own key
+        let unlocked = utils::generate_proofs(&keyset, &[cdk::Amount::from(8_u64)]);
+        let r = swaps.swap(&unlocked, &outputs(), None).await;
+        assert!(
+            matches!(r, Err(swap::Error::LockedToKey(id, key)) if id == kid && key == endorser.public_key())
+        );
+        let holder = cdk01::SecretKey::generate();
+        let r = swaps.swap(&[locked_proof(&holder)], &outputs(), None).await;
+        assert!(matches!(r, Err(swap::Error::LockedToKey(id, _)) if id == kid));
+
+        let signatures = swaps
+            .swap(&[locked_proof(&endorser)], &outputs(), None)
+            .await
+            .unwrap();
+        assert_eq!(signatures.len(), 1);
+        assert_eq!(signatures[0].keyset_id, maturity_keyset.id);
+    }
+
+    #[tokio::test]
+    async fn test_swap_disabled_quote_keyset_until_enabled() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+        let quote_keys = inmemory::KeysetIDQuoteIDMap::default();
+        let maturity_keys = inmemory::KeysetIDEntryMap::default();
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            quote_keys.clone(),
+            maturity_keys.clone(),
+        );
+        let kid = keys_test::generate_random_keysetid();
+        let keyset = factory
+            .generate(
+                kid,
+                "billID",
+                "endorserID",
+                uuid::Uuid::new_v4(),
+                maturity,
+                now,
+            )
+            .await
+            .unwrap();
+        // endorsed as generated, still disabled
+        let endorsed_keys = inmemory::KeysetIDEntryMap::default();
+        let (info, _) = quote_keys.search_by_kid(&kid).await.unwrap().unwrap();
+        assert!(!info.active);
+        keys::Repository::store(&endorsed_keys, keyset.clone(), info)
+            .await
+            .unwrap();
+        let swaps = swap::Service {
+            keys: SwapRepository {
+                endorsed_keys: endorsed_keys.clone(),
+                maturity_keys: maturity_keys.clone(),
+                debit_keys: inmemory::KeysetIDEntryMapWithActive::default(),
+                max_rotation_walk: keys::MAX_ROTATION_WALK,
+            },
+            proofs: inmemory::ProofMap::default(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: std::sync::Arc::new(crate::clock::SystemClock),
+        };
+        let amounts = [cdk::Amount::from(8_u64), cdk::Amount::from(2_u64)];
+        let inputs = utils::generate_proofs(&keyset, &amounts);
+        let maturity_keyset = keys::Repository::keyset(
+            &maturity_keys,
+            &keys::generate_keyset_id_from_date(maturity, 0).unwrap(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let outputs: Vec<_> = utils::generate_blinds(&maturity_keyset, &amounts)
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(matches!(r, Err(swap::Error::UnspendableKeyset(id)) if id == kid));
+        // nor is it replaced by the maturity keyset
+        let r = swaps.keys.replacing_id(&kid).await;
+        assert!(matches!(r, Err(swap::KeysRepoError::Disabled(id)) if id == kid));
+
+        keys::Repository::enable_keyset(&endorsed_keys, &kid)
+            .await
+            .unwrap();
+        let signatures = swaps.swap(&inputs, &outputs, None).await.unwrap();
+        assert_eq!(signatures.len(), 2);
+        assert!(signatures
+            .iter()
+            .all(|signature| signature.keyset_id == maturity_keyset.id));
+    }
+
+    #[tokio::test]
+    async fn test_swap_inactive_maturity_keyset_is_replaced() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+        let maturity_keys = inmemory::KeysetIDEntryMap::default();
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            maturity_keys.clone(),
+        );
+        factory
+            .generate(
+                keys_test::generate_random_keysetid(),
+                "billID",
+                "endorserID",
+                uuid::Uuid::new_v4(),
+                maturity,
+                now,
+            )
+            .await
+            .unwrap();
+        let kid0 = keys::generate_keyset_id_from_date(maturity, 0).unwrap();
+        let keyset0 = keys::Repository::keyset(&maturity_keys, &kid0)
+            .await
+            .unwrap()
+            .unwrap();
+        let kid1 = factory.rotate_maturity_keyset(maturity).await.unwrap();
+        let keyset1 = keys::Repository::keyset(&maturity_keys, &kid1)
+            .await
+            .unwrap()
+            .unwrap();
+        let swaps = swap::Service {
+            keys: SwapRepository {
+                endorsed_keys: inmemory::KeysetIDEntryMap::default(),
+                maturity_keys,
+                debit_keys: inmemory::KeysetIDEntryMapWithActive::default(),
+                max_rotation_walk: keys::MAX_ROTATION_WALK,
+            },
+            proofs: inmemory::ProofMap::default(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: std::sync::Arc::new(crate::clock::SystemClock),
+        };
+        let amounts = [cdk::Amount::from(4_u64), cdk::Amount::from(1_u64)];
+        let inputs = utils::generate_proofs(&keyset0, &amounts);
+        let outputs: Vec<_> = utils::generate_blinds(&keyset1, &amounts)
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+
+        assert!(swaps.keys.spendable(&kid0).await.unwrap());
+        let signatures = swaps.swap(&inputs, &outputs, None).await.unwrap();
+        assert!(signatures
+            .iter()
+            .all(|signature| KeysetID::from(signature.keyset_id) == kid1));
+    }
+
+    #[tokio::test]
+    async fn test_keys_factory_input_fee_ppk() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+        let quote_keys = inmemory::KeysetIDQuoteIDMap::default();
+        let maturity_keys = inmemory::KeysetIDEntryMap::default();
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            quote_keys.clone(),
+            maturity_keys.clone(),
+        )
+        .with_input_fee_ppk(100);
+        let quote_kid = keys_test::generate_random_keysetid();
+        factory
+            .generate(
+                quote_kid,
+                "billID",
+                "endorserID",
+                uuid::Uuid::new_v4(),
+                maturity,
+                now,
+            )
+            .await
+            .unwrap();
+        let (quote_info, _) = QuoteBasedRepository::search_by_kid(&quote_keys, &quote_kid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(quote_info.input_fee_ppk, 100);
+        let kid = keys::generate_keyset_id_from_date(maturity, 0).unwrap();
+        let info = keys::Repository::info(&maturity_keys, &kid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.input_fee_ppk, 100);
+    }
+
+    #[test]
+    fn test_rederive_maturity() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let new_factory = || {
+            Factory::new(
+                &seed,
+                bitcoin::Network::Bitcoin,
+                crsat(),
+                inmemory::KeysetIDQuoteIDMap::default(),
+                inmemory::KeysetIDEntryMap::default(),
+            )
+        };
+        let (keyset, _) = new_factory().generate_maturity_keyset(maturity, 2).unwrap();
+        let kid = keys::generate_keyset_id_from_date(maturity, 2).unwrap();
+
+        // nothing stored, only the seed survives
+        let rederived = new_factory().rederive_maturity(&kid, 0).unwrap();
+        assert_eq!(rederived.id, keyset.id);
+        assert_eq!(keys::public_keys(&rederived), keys::public_keys(&keyset));
+
+        // keysets of an older era are still derived from their own seed
+        let new_seed = bip39::Mnemonic::from_str(
+            "legal winner thank year wave sausage worth useful legal winner thank yellow",
+        )
+        .unwrap()
+        .to_seed("");
+        let mut factory = new_factory();
+        assert_eq!(factory.add_seed_era(&new_seed), 1);
+        let rederived = factory.rederive_maturity(&kid, 0).unwrap();
+        assert_eq!(keys::public_keys(&rederived), keys::public_keys(&keyset));
+        let (era1, _) = factory.generate_maturity_keyset(maturity, 2).unwrap();
+        let rederived = factory.rederive_maturity(&kid, 1).unwrap();
+        assert_eq!(keys::public_keys(&rederived), keys::public_keys(&era1));
+        assert_ne!(keys::public_keys(&era1), keys::public_keys(&keyset));
+        assert!(matches!(
+            factory.rederive_maturity(&kid, 2),
+            Err(Error::UnknownEra(2))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_maturity_keyset_find_by_path() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let maturity_keys = inmemory::KeysetIDEntryMap::default();
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            maturity_keys.clone(),
+        );
+        let (keyset, info) = factory.generate_maturity_keyset(maturity, 0).unwrap();
+        keys::Repository::store(&maturity_keys, keyset, info.clone())
+            .await
+            .unwrap();
+
+        let kid = keys::generate_keyset_id_from_date(maturity, 0).unwrap();
+        let path = keys::generate_keyset_path(kid, None);
+        let found = keys::Repository::find_by_path(&maturity_keys, &path)
+            .await
+            .unwrap();
+        assert_eq!(found, Some(info));
+
+        let other = keys::generate_keyset_path(keys_test::generate_random_keysetid(), None);
+        let found = keys::Repository::find_by_path(&maturity_keys, &other)
+            .await
+            .unwrap();
+        assert!(found.is_none());
+    }
 
-#[cfg(test)]
-mod tests {
+    #[tokio::test]
+    async fn test_keys_factory_valid_from_follows_clock() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+        let maturity_keys = inmemory::KeysetIDEntryMap::default();
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            maturity_keys.clone(),
+        )
+        .with_clock(std::sync::Arc::new(crate::clock::FixedClock(now)));
+        factory
+            .generate(
+                keys_test::generate_random_keysetid(),
+                "billID",
+                "endorserID",
+                uuid::Uuid::new_v4(),
+                maturity,
+                now,
+            )
+            .await
+            .unwrap();
+        let kid = keys::generate_keyset_id_from_date(maturity, 0).unwrap();
+        let info = keys::Repository::info(&maturity_keys, &kid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.valid_from, now.timestamp() as u64);
+    }
 
-    use super::*;
-    use crate::keys::test_utils as keys_test;
-    use crate::swap::KeysRepository;
-    use mockall::predicate::*;
-    use std::str::FromStr;
+    #[tokio::test]
+    async fn test_keys_factory_max_order_bounds_swap_outputs() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let now = maturity - chrono::Duration::days(30);
+        let quote_keys = inmemory::KeysetIDQuoteIDMap::default();
+        let maturity_keys = inmemory::KeysetIDEntryMap::default();
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            quote_keys.clone(),
+            maturity_keys.clone(),
+        )
+        .with_max_order(8);
+        let quote_kid = keys_test::generate_random_keysetid();
+        let quote_keyset = factory
+            .generate(
+                quote_kid,
+                "billID",
+                "endorserID",
+                uuid::Uuid::new_v4(),
+                maturity,
+                now,
+            )
+            .await
+            .unwrap();
+        assert_eq!(quote_keyset.keys.len(), 8);
+        let (quote_info, _) = QuoteBasedRepository::search_by_kid(&quote_keys, &quote_kid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(quote_info.max_order, 8);
+        let kid = keys::generate_keyset_id_from_date(maturity, 0).unwrap();
+        let (info, keyset) = keys::Repository::load(&maturity_keys, &kid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.max_order, 8);
+
+        let swaps = swap::Service {
+            keys: SwapRepository {
+                endorsed_keys: inmemory::KeysetIDEntryMap::default(),
+                maturity_keys,
+                debit_keys: inmemory::KeysetIDEntryMapWithActive::default(),
+                max_rotation_walk: keys::MAX_ROTATION_WALK,
+            },
+            proofs: inmemory::ProofMap::default(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: std::sync::Arc::new(crate::clock::SystemClock),
+        };
+        let inputs = utils::generate_proofs(&keyset, &[cdk::Amount::from(128_u64); 4]);
+        let outputs = vec![cdk00::BlindedMessage::new(
+            cdk::Amount::from(512_u64),
+            keyset.id,
+            utils::publics()[0],
+        )];
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(matches!(
+            r,
+            Err(swap::Error::UnknownAmountForKeyset(id, amount))
+                if id == kid && amount == cdk::Amount::from(512_u64)
+        ));
+    }
 
     #[tokio::test]
-    async fn test_keys_factory_generate() {
+    async fn test_keys_factory_rotate_unknown_maturity() {
         let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            inmemory::KeysetIDEntryMap::default(),
+        );
+        assert!(factory.rotate_maturity_keyset(maturity).await.is_err());
+    }
 
-        let keyid = KeysetID::from(cdk02::Id::from_bytes(&[0u8; 8]).unwrap());
-        let quote = uuid::Uuid::from_u128(0);
+    #[tokio::test]
+    async fn test_keys_factory_max_rotation_walk() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
         let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
             .unwrap()
             .to_utc();
+        let maturity_keys = inmemory::KeysetIDEntryMap::default();
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            maturity_keys.clone(),
+        )
+        .with_max_rotation_walk(1);
+        factory.pregenerate_maturity(&[maturity]).await.unwrap();
+        factory.rotate_maturity_keyset(maturity).await.unwrap();
+        factory.rotate_maturity_keyset(maturity).await.unwrap();
+
+        // rotation 2 is active, only 1 inactive rotation may be skipped to reach it
+        let e = factory.rotate_maturity_keyset(maturity).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<keys::Error>(),
+            Some(keys::Error::RotationWalkExceeded(date, 1)) if *date == maturity
+        ));
+        // with rotation 2 stored, the sum must step past index 2 to reach its end
+        for max_walk in [1, 2] {
+            let e = maturity_keys
+                .issued_amount(maturity, max_walk)
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                e.downcast_ref::<keys::Error>(),
+                Some(keys::Error::RotationWalkExceeded(_, max)) if *max == max_walk
+            ));
+        }
+        assert_eq!(
+            maturity_keys.issued_amount(maturity, 3).await.unwrap(),
+            cdk::Amount::ZERO
+        );
+        // the walk reaches index 2 with a bound of 2, not 1
+        let factory = factory.with_max_rotation_walk(2);
+        factory.rotate_maturity_keyset(maturity).await.unwrap();
+    }
 
-        let mut maturitykeys_repo = keys_test::MockRepository::new();
-        maturitykeys_repo.expect_info().returning(|_| Ok(None));
-        maturitykeys_repo.expect_store().returning(|_, _| Ok(()));
-        let mut quotekeys_repo = MockQuoteBasedRepository::new();
-        quotekeys_repo
-            .expect_store()
-            .with(eq(quote), always(), always())
-            .returning(|_, _, _| Ok(()));
-        //quotekeys_repo.expect_store().returning(|_, _| Ok(()));
+    #[tokio::test]
+    async fn test_swaprepository_list_info() {
+        let mut quote_repo = keys_test::MockRepository::new();
+        let mut maturing_repo = keys_test::MockRepository::new();
+        let mut debit_repo = keys_test::MockRepository::new();
 
-        let factory = Factory::new(&seed, quotekeys_repo, maturitykeys_repo);
+        let mut infos: Vec<_> = (0..4)
+            .map(|_| cdk::mint::MintKeySetInfo {
+                active: false,
+                derivation_path: Default::default(),
+                derivation_path_index: Default::default(),
+                id: keys_test::generate_random_keysetid().into(),
+                input_fee_ppk: Default::default(),
+                max_order: Default::default(),
+                unit: Default::default(),
+                valid_from: Default::default(),
+                valid_to: Default::default(),
+            })
+            .collect();
+        // same unit and valid_from, listed by id
+        infos.sort_by_key(|info| info.id);
 
-        let keyset = factory.generate(keyid, quote, maturity).await.unwrap();
-        // m/129372'/129534'/0'/927402239'/0'
-        let key = &keyset.keys[&cdk::Amount::from(1_u64)];
-        assert_eq!(
-            key.public_key.to_hex(),
-            "03287106d3d2f1df660f7c7764e39e98051bca0c95feb9604336e9744de88eac68"
-        );
-        // m/129372'/129534'/0'/927402239'/5'
-        let key = &keyset.keys[&cdk::Amount::from(32_u64)];
-        assert_eq!(
-            key.public_key.to_hex(),
-            "03c5b66986d15100d1c0b342e012da7a954c7040c13d514ebc3b282ffa3a54651f"
-        );
+        let endorsed = vec![infos[0].clone()];
+        quote_repo
+            .expect_list_info()
+            .returning(move || Ok(endorsed.clone()));
+        let maturing = vec![infos[1].clone(), infos[2].clone()];
+        maturing_repo
+            .expect_list_info()
+            .returning(move || Ok(maturing.clone()));
+        // infos[2] is reported twice, it must be listed once
+        let debit = vec![infos[2].clone(), infos[3].clone()];
+        debit_repo
+            .expect_list_info()
+            .returning(move || Ok(debit.clone()));
+
+        let swap_repo = SwapRepository {
+            endorsed_keys: quote_repo,
+            maturity_keys: maturing_repo,
+            debit_keys: debit_repo,
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
+        };
+
+        let result = swap_repo.list_info().await.unwrap();
+        assert_eq!(result, infos);
+    }
+
+    #[tokio::test]
+    async fn test_swaprepository_list_info_filtered() {
+        let mut quote_repo = keys_test::MockRepository::new();
+        let mut maturing_repo = keys_test::MockRepository::new();
+        let mut debit_repo = keys_test::MockRepository::new();
+
+        let mut ids: Vec<cdk02::Id> = (0..6)
+            .map(|_| keys_test::generate_random_keysetid().into())
+            .collect();
+        ids.sort();
+        let infos: Vec<_> = ids
+            .into_iter()
+            .zip([true, false, true, false, true, false])
+            .map(|(id, active)| cdk::mint::MintKeySetInfo {
+                active,
+                derivation_path: Default::default(),
+                derivation_path_index: Default::default(),
+                id,
+                input_fee_ppk: Default::default(),
+                max_order: Default::default(),
+                unit: Default::default(),
+                valid_from: Default::default(),
+                valid_to: Default::default(),
+            })
+            .collect();
+
+        // infos[1] is a quote keyset still disabled, infos[5] a retired debit keyset
+        let endorsed = vec![infos[0].clone(), infos[1].clone()];
+        quote_repo
+            .expect_list_info()
+            .returning(move || Ok(endorsed.clone()));
+        let maturing = vec![infos[2].clone(), infos[3].clone()];
+        maturing_repo
+            .expect_list_info()
+            .returning(move || Ok(maturing.clone()));
+        let debit = vec![infos[4].clone(), infos[5].clone()];
+        debit_repo
+            .expect_list_info()
+            .returning(move || Ok(debit.clone()));
+
+        let swap_repo = SwapRepository {
+            endorsed_keys: quote_repo,
+            maturity_keys: maturing_repo,
+            debit_keys: debit_repo,
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
+        };
+
+        let enabled = swap_repo.list_info_filtered(true).await.unwrap();
+        let expected = vec![
+            infos[0].clone(),
+            infos[2].clone(),
+            infos[3].clone(),
+            infos[4].clone(),
+        ];
+        assert_eq!(enabled, expected);
+        let all = swap_repo.list_info_filtered(false).await.unwrap();
+        assert_eq!(all, infos);
+    }
+
+    #[tokio::test]
+    async fn test_swaprepository_list_info_order_and_precedence() {
+        let mut quote_repo = keys_test::MockRepository::new();
+        let mut maturing_repo = keys_test::MockRepository::new();
+        let mut debit_repo = keys_test::MockRepository::new();
+
+        let info = |unit: cdk00::CurrencyUnit, valid_from: u64| cdk::mint::MintKeySetInfo {
+            active: true,
+            derivation_path: Default::default(),
+            derivation_path_index: Default::default(),
+            id: keys_test::generate_random_keysetid().into(),
+            input_fee_ppk: Default::default(),
+            max_order: Default::default(),
+            unit,
+            valid_from,
+            valid_to: Default::default(),
+        };
+        let late_sat = info(cdk00::CurrencyUnit::Sat, 20);
+        let early_sat = info(cdk00::CurrencyUnit::Sat, 10);
+        let early_crsat = info(crsat(), 10);
+        // the maturity copy of the endorsed keyset must be dropped
+        let mut shadowed = late_sat.clone();
+        shadowed.active = false;
+        shadowed.valid_from = 0;
+
+        let endorsed = vec![late_sat.clone()];
+        quote_repo
+            .expect_list_info()
+            .returning(move || Ok(endorsed.clone()));
+        let maturing = vec![early_crsat.clone(), shadowed];
+        maturing_repo
+            .expect_list_info()
+            .returning(move || Ok(maturing.clone()));
+        let debit = vec![early_sat.clone()];
+        debit_repo
+            .expect_list_info()
+            .returning(move || Ok(debit.clone()));
+
+        let swap_repo = SwapRepository {
+            endorsed_keys: quote_repo,
+            maturity_keys: maturing_repo,
+            debit_keys: debit_repo,
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
+        };
+
+        let first = swap_repo.list_info().await.unwrap();
+        // CurrencyUnit::Sat sorts before custom units
+        assert_eq!(first, vec![early_sat, late_sat, early_crsat]);
+        assert_eq!(swap_repo.list_info().await.unwrap(), first);
     }
 
     #[tokio::test]
@@ -320,6 +2191,7 @@ mod tests {
             endorsed_keys: quote_repo,
             maturity_keys: maturing_repo,
             debit_keys: debit_repo,
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
         };
 
         let result = swap_repo.info(&kid).await.unwrap();
@@ -359,6 +2231,7 @@ mod tests {
             endorsed_keys: quote_repo,
             maturity_keys: maturing_repo,
             debit_keys: debit_repo,
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
         };
 
         let result = swap_repo.info(&kid).await.unwrap();
@@ -394,6 +2267,7 @@ mod tests {
             endorsed_keys: quote_repo,
             maturity_keys: maturing_repo,
             debit_keys: debit_repo,
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
         };
 
         let result = swap_repo.info(&kid).await.unwrap();
@@ -431,6 +2305,7 @@ mod tests {
             endorsed_keys: quote_repo,
             maturity_keys: maturing_repo,
             debit_keys: debit_repo,
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
         };
 
         let result = swap_repo.keyset(&kid).await.unwrap();
@@ -464,6 +2339,7 @@ mod tests {
             endorsed_keys: quote_repo,
             maturity_keys: maturing_repo,
             debit_keys: debit_repo,
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
         };
 
         let result = swap_repo.keyset(&kid).await.unwrap();
@@ -493,6 +2369,7 @@ mod tests {
             endorsed_keys: quote_repo,
             maturity_keys: maturing_repo,
             debit_keys: debit_repo,
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
         };
 
         let result = swap_repo.keyset(&kid).await.unwrap();
@@ -534,6 +2411,7 @@ mod tests {
             endorsed_keys: quote_repo,
             maturity_keys: maturing_repo,
             debit_keys: debit_repo,
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
         };
 
         let result = swap_repo.replacing_id(&in_kid).await.unwrap();
@@ -573,6 +2451,7 @@ mod tests {
             endorsed_keys: quote_repo,
             maturity_keys: maturing_repo,
             debit_keys: debit_repo,
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
         };
 
         let result = swap_repo.replacing_id(&kid).await.unwrap();
@@ -585,11 +2464,12 @@ mod tests {
         let mut maturing_repo = keys_test::MockRepository::new();
         let debit_repo = keys_test::MockRepository::new();
 
-        let in_kid = keys_test::generate_random_keysetid();
         let maturity_date =
             chrono::NaiveDateTime::parse_from_str("2026-01-01 00:00:00", "%Y-%m-%d %H:%M:%S")
                 .unwrap()
                 .and_utc();
+        // the first rotation of the maturity date
+        let in_kid = keys::generate_keyset_id_from_date(maturity_date, 0).unwrap();
 
         quote_repo
             .expect_info()
@@ -611,7 +2491,7 @@ mod tests {
                     valid_to: Some(maturity_date.timestamp() as u64),
                 }))
             });
-        let maturity_kid = keys::generate_keyset_id_from_date(maturity_date, 1);
+        let maturity_kid = keys::generate_keyset_id_from_date(maturity_date, 1).unwrap();
         maturing_repo
             .expect_info()
             .with(eq(maturity_kid))
@@ -633,6 +2513,7 @@ mod tests {
             endorsed_keys: quote_repo,
             maturity_keys: maturing_repo,
             debit_keys: debit_repo,
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
         };
 
         let result = swap_repo.replacing_id(&in_kid).await.unwrap();
@@ -645,11 +2526,12 @@ mod tests {
         let mut maturing_repo = keys_test::MockRepository::new();
         let mut debit_repo = keys_test::MockRepository::new();
 
-        let in_kid = keys_test::generate_random_keysetid();
         let maturity_date =
             chrono::NaiveDateTime::parse_from_str("2026-01-01 00:00:00", "%Y-%m-%d %H:%M:%S")
                 .unwrap()
                 .and_utc();
+        // the first rotation of the maturity date
+        let in_kid = keys::generate_keyset_id_from_date(maturity_date, 0).unwrap();
 
         quote_repo
             .expect_info()
@@ -671,7 +2553,7 @@ mod tests {
                     valid_to: Some(maturity_date.timestamp() as u64),
                 }))
             });
-        let maturity_kid = keys::generate_keyset_id_from_date(maturity_date, 1);
+        let maturity_kid = keys::generate_keyset_id_from_date(maturity_date, 1).unwrap();
         maturing_repo
             .expect_info()
             .with(eq(maturity_kid))
@@ -695,6 +2577,7 @@ mod tests {
             endorsed_keys: quote_repo,
             maturity_keys: maturing_repo,
             debit_keys: debit_repo,
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
         };
 
         let result = swap_repo.replacing_id(&in_kid).await.unwrap();
@@ -718,7 +2601,7 @@ mod tests {
             .with(eq(in_kid))
             .returning(move |_| {
                 Ok(Some(cdk::mint::MintKeySetInfo {
-                    active: false,
+                    active: true,
                     derivation_path: Default::default(),
                     derivation_path_index: Some(0),
                     id: in_kid.into(),
@@ -729,7 +2612,7 @@ mod tests {
                     valid_to: Some(maturity_date.timestamp() as u64),
                 }))
             });
-        let maturity_kid = keys::generate_keyset_id_from_date(maturity_date, 0);
+        let maturity_kid = keys::generate_keyset_id_from_date(maturity_date, 0).unwrap();
         maturing_repo
             .expect_info()
             .with(eq(maturity_kid))
@@ -751,9 +2634,327 @@ mod tests {
             endorsed_keys: quote_repo,
             maturity_keys: maturing_repo,
             debit_keys: debit_repo,
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
         };
 
         let result = swap_repo.replacing_id(&in_kid).await.unwrap();
         assert_eq!(result, Some(maturity_kid));
     }
+
+    #[tokio::test]
+    async fn test_swaprepository_spendable_unknown() {
+        let mut quote_repo = keys_test::MockRepository::new();
+        let mut maturing_repo = keys_test::MockRepository::new();
+        let mut debit_repo = keys_test::MockRepository::new();
+        quote_repo.expect_info().returning(|_| Ok(None));
+        maturing_repo.expect_info().returning(|_| Ok(None));
+        debit_repo.expect_info().returning(|_| Ok(None));
+        let swap_repo = SwapRepository {
+            endorsed_keys: quote_repo,
+            maturity_keys: maturing_repo,
+            debit_keys: debit_repo,
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
+        };
+
+        let kid = keys_test::generate_random_keysetid();
+        let r = swap_repo.spendable(&kid).await;
+        assert!(matches!(r, Err(swap::KeysRepoError::NotFound(id)) if id == kid));
+    }
+
+    #[tokio::test]
+    async fn test_swaprepository_backend_error() {
+        let mut quote_repo = keys_test::MockRepository::new();
+        quote_repo
+            .expect_info()
+            .returning(|_| Err(anyhow::anyhow!("connection lost")));
+        let swap_repo = SwapRepository {
+            endorsed_keys: quote_repo,
+            maturity_keys: keys_test::MockRepository::new(),
+            debit_keys: keys_test::MockRepository::new(),
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
+        };
+
+        let kid = keys_test::generate_random_keysetid();
+        let r = swap_repo.spendable(&kid).await;
+        assert!(matches!(r, Err(swap::KeysRepoError::Backend(_))));
+        let r = swap_repo.replacing_id(&kid).await;
+        assert!(matches!(r, Err(swap::KeysRepoError::Backend(_))));
+    }
+
+    #[tokio::test]
+    async fn test_swaprepository_replacing_keys_quote_without_maturity() {
+        let mut quote_repo = keys_test::MockRepository::new();
+        let in_kid = keys_test::generate_random_keysetid();
+        quote_repo
+            .expect_info()
+            .with(eq(in_kid))
+            .returning(move |_| {
+                Ok(Some(cdk::mint::MintKeySetInfo {
+                    active: true,
+                    derivation_path: Default::default(),
+                    derivation_path_index: None,
+                    id: in_kid.into(),
+                    input_fee_ppk: Default::default(),
+                    max_order: Default::default(),
+                    unit: Default::default(),
+                    valid_from: Default::default(),
+                    valid_to: None,
+                }))
+            });
+        let swap_repo = SwapRepository {
+            endorsed_keys: quote_repo,
+            maturity_keys: keys_test::MockRepository::new(),
+            debit_keys: keys_test::MockRepository::new(),
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
+        };
+
+        let r = swap_repo.replacing_id(&in_kid).await;
+        assert!(matches!(r, Err(swap::KeysRepoError::Corrupt(id, _)) if id == in_kid));
+    }
+
+    fn corrupt_info(
+        kid: KeysetID,
+        active: bool,
+        valid_to: Option<u64>,
+    ) -> cdk::mint::MintKeySetInfo {
+        cdk::mint::MintKeySetInfo {
+            active,
+            derivation_path: Default::default(),
+            derivation_path_index: Some(0),
+            id: kid.into(),
+            input_fee_ppk: Default::default(),
+            max_order: Default::default(),
+            unit: Default::default(),
+            valid_from: Default::default(),
+            valid_to,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_swaprepository_replacing_keys_quote_maturity_out_of_range() {
+        let mut quote_repo = keys_test::MockRepository::new();
+        let in_kid = keys_test::generate_random_keysetid();
+        quote_repo
+            .expect_info()
+            .with(eq(in_kid))
+            .returning(move |_| Ok(Some(corrupt_info(in_kid, true, Some(u64::MAX)))));
+        let swap_repo = SwapRepository {
+            endorsed_keys: quote_repo,
+            maturity_keys: keys_test::MockRepository::new(),
+            debit_keys: keys_test::MockRepository::new(),
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
+        };
+
+        let r = swap_repo.replacing_id(&in_kid).await;
+        assert!(matches!(r, Err(swap::KeysRepoError::Corrupt(id, _)) if id == in_kid));
+    }
+
+    #[tokio::test]
+    async fn test_swaprepository_replacing_keys_corrupt_maturity_keyset() {
+        for valid_to in [None, Some(u64::MAX)] {
+            let mut endorsed_repo = keys_test::MockRepository::new();
+            endorsed_repo.expect_info().returning(|_| Ok(None));
+            let mut maturity_repo = keys_test::MockRepository::new();
+            let in_kid = keys_test::generate_random_keysetid();
+            maturity_repo
+                .expect_info()
+                .with(eq(in_kid))
+                .returning(move |_| Ok(Some(corrupt_info(in_kid, false, valid_to))));
+            let swap_repo = SwapRepository {
+                endorsed_keys: endorsed_repo,
+                maturity_keys: maturity_repo,
+                debit_keys: keys_test::MockRepository::new(),
+                max_rotation_walk: keys::MAX_ROTATION_WALK,
+            };
+
+            let r = swap_repo.replacing_id(&in_kid).await;
+            assert!(matches!(r, Err(swap::KeysRepoError::Corrupt(id, _)) if id == in_kid));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_swaprepository_replacing_keys_rotation_walk_exceeded() {
+        let maturity = chrono::DateTime::parse_from_rfc3339("2030-06-15T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let mut quote_repo = keys_test::MockRepository::new();
+        let in_kid = keys_test::generate_random_keysetid();
+        let valid_to = Some(maturity.timestamp() as u64);
+        quote_repo
+            .expect_info()
+            .with(eq(in_kid))
+            .returning(move |_| Ok(Some(corrupt_info(in_kid, true, valid_to))));
+        // every rotation of the maturity date exists and is inactive: indexes 0 to 5 are
+        // looked up, the configured bound, not keys::MAX_ROTATION_WALK, stops the walk
+        let mut maturity_repo = keys_test::MockRepository::new();
+        maturity_repo
+            .expect_info()
+            .times(6)
+            .returning(move |kid| Ok(Some(corrupt_info(*kid, false, valid_to))));
+        let swap_repo = SwapRepository {
+            endorsed_keys: quote_repo,
+            maturity_keys: maturity_repo,
+            debit_keys: keys_test::MockRepository::new(),
+            max_rotation_walk: 5,
+        };
+
+        let r = swap_repo.replacing_id(&in_kid).await;
+        assert!(matches!(
+            r,
+            Err(swap::KeysRepoError::RotationWalkExceeded(date, 5))
+                if date == maturity
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_swaprepository_rotation_walk_boundary() {
+        let maturity = chrono::DateTime::parse_from_rfc3339("2030-06-15T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let valid_to = Some(maturity.timestamp() as u64);
+        // rotations 0 to 4 are inactive, 5 is the active one
+        let active_kid = keys::generate_keyset_id_from_date(maturity, 5).unwrap();
+        for (max_walk, found) in [(4, false), (5, true)] {
+            let mut maturity_repo = keys_test::MockRepository::new();
+            maturity_repo
+                .expect_info()
+                .times(max_walk as usize + 1)
+                .returning(move |kid| Ok(Some(corrupt_info(*kid, *kid == active_kid, valid_to))));
+            let swap_repo = SwapRepository {
+                endorsed_keys: keys_test::MockRepository::new(),
+                maturity_keys: maturity_repo,
+                debit_keys: keys_test::MockRepository::new(),
+                max_rotation_walk: max_walk,
+            };
+
+            let r = swap_repo
+                .find_maturity_keys_from_maturity_date(maturity)
+                .await;
+            if found {
+                assert_eq!(r.unwrap(), Some(active_kid));
+            } else {
+                assert!(matches!(
+                    r,
+                    Err(swap::KeysRepoError::RotationWalkExceeded(_, max)) if max == max_walk
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_maturity_date_of_roundtrip() {
+        let maturity = chrono::DateTime::parse_from_rfc3339("2030-06-15T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        for idx in [0, 1, 1_000] {
+            let kid = keys::generate_keyset_id_from_date(maturity, idx).unwrap();
+            assert_eq!(maturity_date_of(&kid), Some((maturity, idx)));
+        }
+        // the date is truncated to the day
+        let kid =
+            keys::generate_keyset_id_from_date(maturity + chrono::Duration::hours(13), 2).unwrap();
+        assert_eq!(maturity_date_of(&kid), Some((maturity, 2)));
+    }
+
+    #[test]
+    fn test_maturity_date_of_out_of_range() {
+        let kid = KeysetID::from(cdk02::Id::from_str("00ffffffffffffff").unwrap());
+        assert_eq!(maturity_date_of(&kid), None);
+    }
+
+    #[tokio::test]
+    async fn test_liabilities_quote_minted_then_melted() {
+        let seed = bip39::Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap().to_seed("");
+        let now = chrono::Utc::now();
+        let maturity_keys = inmemory::KeysetIDEntryMap::default();
+        let factory = Factory::new(
+            &seed,
+            bitcoin::Network::Bitcoin,
+            crsat(),
+            inmemory::KeysetIDQuoteIDMap::default(),
+            maturity_keys.clone(),
+        );
+        let amounts = [64_u64, 32].map(cdk::Amount::from);
+        let blinds = utils::generate_blinds(&keys_test::generate_keyset(), &amounts);
+        let quote = quotes::Quote::new(
+            String::from("bill"),
+            String::from("endorser"),
+            blinds.iter().map(|b| b.0.clone()).collect(),
+            now,
+        );
+        let qid = quote.id;
+        let quotes_repo = inmemory::QuotesIDMap::default();
+        quotes::Repository::store(&quotes_repo, quote)
+            .await
+            .unwrap();
+        let ledger = inmemory::ProofMap::default();
+        let service = quotes::Service {
+            keys_gen: factory.clone(),
+            quotes_gen: quotes::Factory {
+                quotes: quotes_repo.clone(),
+            }
+            .into(),
+            quotes: quotes_repo,
+            observers: vec![],
+            issued: std::sync::Arc::new(inmemory::IssuedSignaturesMap::default()),
+            ledger: std::sync::Arc::new(ledger.clone()),
+            fees: Default::default(),
+            ttl: Default::default(),
+            stats: Default::default(),
+            clock: std::sync::Arc::new(crate::clock::SystemClock),
+            endorser_keys: std::sync::Arc::new(quotes::test_utils::FixedEndorserKey(
+                quotes::test_utils::endorser_secret().public_key(),
+            )),
+        };
+
+        service
+            .accept(qid, rust_decimal::Decimal::from(96), now, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            ledger.total_liabilities().unwrap(),
+            cdk::Amount::from(96_u64)
+        );
+
+        let kid = keys::credit::generate_keyset_id_from_bill("bill", "endorser");
+        let (_, keyset) = factory.quote_keys.load(&kid, qid).await.unwrap().unwrap();
+        let quotes::QuoteStatus::Accepted { signatures, .. } =
+            service.lookup(qid).await.unwrap().status
+        else {
+            panic!("quote not accepted");
+        };
+        let proofs: Vec<cdk00::Proof> = signatures
+            .iter()
+            .zip(&blinds)
+            .map(|(signature, (_, secret, r))| {
+                let pk = keyset.keys.get(&signature.amount).unwrap().public_key;
+                let c = cdk::dhke::unblind_message(&signature.c, r, &pk).unwrap();
+                cdk00::Proof::new(signature.amount, signature.keyset_id, secret.clone(), c)
+            })
+            .collect();
+        let endorsed_keys = inmemory::KeysetIDEntryMap::default();
+        activate_endorsed_keyset(&factory.quote_keys, &endorsed_keys, "bill", "endorser")
+            .await
+            .unwrap();
+        let swaps = swap::Service {
+            keys: SwapRepository {
+                endorsed_keys,
+                maturity_keys,
+                debit_keys: inmemory::KeysetIDEntryMapWithActive::default(),
+                max_rotation_walk: keys::MAX_ROTATION_WALK,
+            },
+            proofs: ledger.clone(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: std::sync::Arc::new(crate::clock::SystemClock),
+        };
+        swaps
+            .melt(&proofs[..1], cdk::Amount::from(64_u64))
+            .await
+            .unwrap();
+        assert_eq!(
+            ledger.total_liabilities().unwrap(),
+            cdk::Amount::from(32_u64)
+        );
+    }
 }
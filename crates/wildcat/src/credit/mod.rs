@@ -2,8 +2,12 @@
 // ----- extra library imports
 // ----- local modules
 pub mod admin;
+pub mod ebill;
 pub mod error;
+pub mod expiry;
+pub mod fees;
 pub mod keys;
 pub mod quotes;
 pub mod web;
+pub mod webhook;
 // ----- local imports
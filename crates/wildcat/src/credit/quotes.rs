@@ -1,17 +1,25 @@
 // ----- standard library imports
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 // ----- extra library imports
 use anyhow::{Error as AnyError, Result as AnyResult};
 use async_trait::async_trait;
 use bcr_wdc_keys as keys;
 use bcr_wdc_keys::KeysetID;
 use cdk::nuts::nut00 as cdk00;
+use cdk::nuts::nut01 as cdk01;
 use cdk::nuts::nut02 as cdk02;
+use cdk::secp256k1::schnorr::Signature;
 use rust_decimal::{prelude::ToPrimitive, Decimal};
 use thiserror::Error;
 use uuid::Uuid;
 // ----- local modules
 // ----- local imports
+use crate::clock::Clock;
+use crate::credit::fees::{fee_for, FeePolicy};
 use crate::keys::{sign_with_keys, Result as KeyResult};
+use crate::stats::DenominationStats;
+use crate::swap;
 use crate::utils;
 use crate::TStamp;
 
@@ -29,31 +37,85 @@ pub enum Error {
 
     #[error("Quote has been already resolved: {0}")]
     QuoteAlreadyResolved(uuid::Uuid),
+    #[error("Quote {0} has no pending offer")]
+    NoOffer(uuid::Uuid),
+    #[error("Offer for quote {0} has expired")]
+    OfferExpired(uuid::Uuid),
     #[error("unknown quote id {0}")]
     UnknownQuoteID(uuid::Uuid),
     #[error("Invalid amount: {0}")]
     InvalidAmount(rust_decimal::Decimal),
+    #[error("Quote {0} was not submitted by {1}")]
+    NotEndorser(uuid::Uuid, String),
+    #[error("Too many quotes requested by {0}")]
+    RateLimited(String),
+    #[error("Requested ttl {0} is past the maximum {1}")]
+    TtlExceedsMax(TStamp, TStamp),
+    #[error("Quote {0} is not accepted")]
+    NotAccepted(uuid::Uuid),
+    #[error("Invalid quote request signature")]
+    InvalidQuoteSignature,
+    #[error("eBill service error {0}")]
+    EndorserKeys(AnyError),
+    #[error("Fee {0} exceeds the amount {1} to sign")]
+    FeeExceedsAmount(cdk::Amount, cdk::Amount),
+    #[error("Blinds cover {1} of the {0} to sign")]
+    BlindsShortOfTarget(cdk::Amount, cdk::Amount),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase", tag = "status")]
 pub enum QuoteStatus {
     Pending {
         blinds: Vec<cdk00::BlindedMessage>,
     },
     Declined,
+    // withdrawn by the endorser before being resolved
+    Cancelled,
+    // the mint proposes `discount` as the amount to sign, valid until `expiry`
+    Offered {
+        blinds: Vec<cdk00::BlindedMessage>,
+        discount: cdk::Amount,
+        expiry: TStamp,
+    },
+    // `fee` is the portion of the bill kept by the mint, on top of `signatures`
     Accepted {
         signatures: Vec<cdk00::BlindSignature>,
         ttl: TStamp,
+        #[serde(default)]
+        fee: cdk::Amount,
     },
+    // accepted, but signatures not redeemed before ttl
+    Expired,
+}
+
+pub const DEFAULT_MATURITY_DAYS: i64 = 30;
+
+/// the bill of exchange as decrypted by the mint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecryptedBill {
+    pub id: String,
+    pub maturity: TStamp,
+    pub amount: cdk::Amount,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Quote {
     pub status: QuoteStatus,
     pub id: Uuid,
     pub bill: String,
     pub endorser: String,
     pub submitted: TStamp,
+    // maturity date of the bill, unknown for quotes created from its id only
+    #[serde(default)]
+    pub maturity: Option<TStamp>,
+    // face value of the bill, unknown for quotes created from its id only
+    #[serde(default)]
+    pub amount: Option<cdk::Amount>,
+    // the credit eCash signed for this quote can only be swapped with a P2PK signature
+    // by this key, usually the endorser's
+    #[serde(default)]
+    pub locked_to: Option<cdk01::PublicKey>,
 }
 
 impl Quote {
@@ -69,11 +131,43 @@ impl Quote {
             bill,
             endorser,
             submitted,
+            maturity: None,
+            amount: None,
+            locked_to: None,
+        }
+    }
+
+    pub fn from_bill(
+        bill: DecryptedBill,
+        endorser: String,
+        blinds: Vec<cdk00::BlindedMessage>,
+        submitted: TStamp,
+    ) -> Self {
+        Self {
+            maturity: Some(bill.maturity),
+            amount: Some(bill.amount),
+            ..Self::new(bill.id, endorser, blinds, submitted)
+        }
+    }
+
+    /// the bill maturity date, `DEFAULT_MATURITY_DAYS` from `now` when unknown
+    fn maturity_or_default(&self, now: TStamp) -> TStamp {
+        self.maturity
+            .unwrap_or(now + chrono::Duration::days(DEFAULT_MATURITY_DAYS))
+    }
+
+    /// the lock must be set before the blinds are signed
+    pub fn lock_to(&mut self, key: cdk01::PublicKey) -> Result<()> {
+        if let QuoteStatus::Pending { .. } | QuoteStatus::Offered { .. } = self.status {
+            self.locked_to = Some(key);
+            Ok(())
+        } else {
+            Err(Error::QuoteAlreadyResolved(self.id))
         }
     }
 
     pub fn decline(&mut self) -> Result<()> {
-        if let QuoteStatus::Pending { .. } = self.status {
+        if let QuoteStatus::Pending { .. } | QuoteStatus::Offered { .. } = self.status {
             self.status = QuoteStatus::Declined;
             Ok(())
         } else {
@@ -81,14 +175,116 @@ impl Quote {
         }
     }
 
-    pub fn accept(&mut self, signatures: Vec<cdk00::BlindSignature>, ttl: TStamp) -> Result<()> {
+    pub fn cancel(&mut self) -> Result<()> {
+        if let QuoteStatus::Pending { .. } | QuoteStatus::Offered { .. } = self.status {
+            self.status = QuoteStatus::Cancelled;
+            Ok(())
+        } else {
+            Err(Error::QuoteAlreadyResolved(self.id))
+        }
+    }
+
+    pub fn offer(&mut self, discount: cdk::Amount, expiry: TStamp) -> Result<()> {
+        let QuoteStatus::Pending { ref mut blinds } = self.status else {
+            return Err(Error::QuoteAlreadyResolved(self.id));
+        };
+
+        let blinds = std::mem::take(blinds);
+        self.status = QuoteStatus::Offered {
+            blinds,
+            discount,
+            expiry,
+        };
+        Ok(())
+    }
+
+    /// the ttl is the offer expiry, capped at `policy.max` from `now`
+    pub fn accept_offer(
+        &mut self,
+        signatures: Vec<cdk00::BlindSignature>,
+        now: TStamp,
+        policy: &TtlPolicy,
+        fee: cdk::Amount,
+    ) -> Result<()> {
+        let QuoteStatus::Offered { expiry, .. } = self.status else {
+            return Err(Error::NoOffer(self.id));
+        };
+
+        self.status = QuoteStatus::Accepted {
+            signatures,
+            ttl: expiry.min(now + policy.max),
+            fee,
+        };
+        Ok(())
+    }
+
+    pub fn accept(
+        &mut self,
+        signatures: Vec<cdk00::BlindSignature>,
+        ttl: TStamp,
+        fee: cdk::Amount,
+    ) -> Result<()> {
         let QuoteStatus::Pending { .. } = self.status else {
             return Err(Error::QuoteAlreadyResolved(self.id));
         };
 
-        self.status = QuoteStatus::Accepted { signatures, ttl };
+        self.status = QuoteStatus::Accepted {
+            signatures,
+            ttl,
+            fee,
+        };
         Ok(())
     }
+
+    /// as `accept`, the ttl is `policy.default` from `now` unless requested.
+    /// a requested ttl past `policy.max` from `now` is rejected
+    pub fn accept_with_policy(
+        &mut self,
+        signatures: Vec<cdk00::BlindSignature>,
+        requested: Option<TStamp>,
+        now: TStamp,
+        policy: &TtlPolicy,
+        fee: cdk::Amount,
+    ) -> Result<()> {
+        let ttl = policy.resolve(requested, now)?;
+        self.accept(signatures, ttl, fee)
+    }
+
+    /// only accepted quotes within their ttl can be redeemed
+    pub fn is_redeemable(&self, now: TStamp) -> bool {
+        match self.status {
+            QuoteStatus::Accepted { ttl, .. } => now <= ttl,
+            _ => false,
+        }
+    }
+
+    /// moves an accepted quote past its ttl to Expired, returns true if it did
+    pub fn expire_if_stale(&mut self, now: TStamp) -> bool {
+        let QuoteStatus::Accepted { ttl, .. } = self.status else {
+            return false;
+        };
+        if ttl < now {
+            self.status = QuoteStatus::Expired;
+            return true;
+        }
+        false
+    }
+}
+
+/// a window over a listing, results are ordered by submission date, newest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            limit: usize::MAX,
+        }
+    }
 }
 
 // ---------- required traits
@@ -96,21 +292,87 @@ impl Quote {
 #[async_trait]
 pub trait Repository: Send + Sync {
     async fn load(&self, id: uuid::Uuid) -> AnyResult<Option<Quote>>;
+    /// batch version of `load`, in the order of `ids`. unknown ids are left out.
+    /// backends able to fetch several quotes in one round-trip should override it
+    async fn load_many(&self, ids: &[Uuid]) -> AnyResult<Vec<Quote>> {
+        let mut quotes = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(quote) = self.load(*id).await? {
+                quotes.push(quote);
+            }
+        }
+        Ok(quotes)
+    }
     async fn update_if_pending(&self, quote: Quote) -> AnyResult<()>;
-    async fn list_pendings(&self, since: Option<TStamp>) -> AnyResult<Vec<Uuid>>;
-    async fn list_accepteds(&self, since: Option<TStamp>) -> AnyResult<Vec<Uuid>>;
+    async fn update_if_offered(&self, quote: Quote) -> AnyResult<()>;
+    /// an accepted quote may only move on to expired
+    async fn update_if_accepted(&self, quote: Quote) -> AnyResult<()>;
+    async fn list_pendings(&self, since: Option<TStamp>, page: Page) -> AnyResult<Vec<Uuid>>;
+    /// pending quotes submitted before `older_than`, oldest first.
+    /// backends able to filter on submission time should override it
+    async fn list_stale_pendings(&self, older_than: TStamp) -> AnyResult<Vec<Uuid>> {
+        let pendings = self.list_pendings(None, Page::default()).await?;
+        let mut stale: Vec<Quote> = self
+            .load_many(&pendings)
+            .await?
+            .into_iter()
+            .filter(|quote| quote.submitted < older_than)
+            .collect();
+        stale.sort_by(|a, b| a.submitted.cmp(&b.submitted).then(a.id.cmp(&b.id)));
+        Ok(stale.into_iter().map(|quote| quote.id).collect())
+    }
+    async fn list_offered(&self, since: Option<TStamp>, page: Page) -> AnyResult<Vec<Uuid>>;
+    async fn list_accepteds(&self, since: Option<TStamp>, page: Page) -> AnyResult<Vec<Uuid>>;
     async fn search_by_bill(&self, bill: &str, endorser: &str) -> AnyResult<Option<Quote>>;
+    /// all quotes submitted by `endorser`, newest first
+    async fn search_by_endorser(&self, endorser: &str) -> AnyResult<Vec<Quote>>;
     async fn store(&self, quote: Quote) -> AnyResult<()>;
+    /// removes declined, cancelled and expired quotes submitted before `before`
+    /// and accepted quotes whose ttl is past `before`, returns how many were removed.
+    /// pending and offered quotes are kept regardless of their age
+    async fn purge_expired(&self, before: TStamp) -> AnyResult<usize>;
 }
 
+#[cfg_attr(test, mockall::automock)]
 #[async_trait]
 pub trait KeyFactory: Send + Sync {
+    /// fails if `maturity_date` is not after `now`
     async fn generate(
         &self,
         kid: KeysetID,
+        bill: &str,
+        endorser: &str,
         qid: Uuid,
         maturity_date: TStamp,
+        now: TStamp,
     ) -> AnyResult<cdk02::MintKeySet>;
+    /// accounts `amount` as issued against `maturity_date`
+    async fn record_issued(&self, maturity_date: TStamp, amount: cdk::Amount) -> AnyResult<()>;
+}
+
+/// notified once a quote status change has been stored
+#[async_trait]
+pub trait QuoteObserver: Send + Sync {
+    async fn on_status_change(&self, quote: &Quote, old: &QuoteStatus);
+}
+
+/// the eBill service, holder of the keys endorsers sign their requests with
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait EndorserKeys: Send + Sync {
+    /// the eBill-derived public key of `endorser` on `bill`, None if unknown
+    async fn endorser_key(&self, bill: &str, endorser: &str)
+        -> AnyResult<Option<cdk01::PublicKey>>;
+}
+
+/// durable record of the blind signatures issued for each quote, e.g. for NUT-09
+/// restore or audit once the quote itself is gone
+#[async_trait]
+pub trait IssuedSignatureRepository: Send + Sync {
+    /// appends `signatures` to the ones issued for quote `qid`
+    async fn record(&self, qid: Uuid, signatures: &[cdk00::BlindSignature]) -> AnyResult<()>;
+    /// every signature issued for quote `qid`, in issuance order
+    async fn by_quote(&self, qid: Uuid) -> AnyResult<Vec<cdk00::BlindSignature>>;
 }
 
 // ---------- Factory
@@ -120,6 +382,117 @@ pub struct Factory<Quotes> {
 }
 
 impl<Quotes> Factory<Quotes>
+where
+    Quotes: Repository,
+{
+    // the quote already standing for bill and endorser, if it can be handed out again
+    async fn existing(
+        &self,
+        bill: &str,
+        endorser: &str,
+        submitted: TStamp,
+    ) -> AnyResult<Option<uuid::Uuid>> {
+        let Some(mut quote) = self.quotes.search_by_bill(bill, endorser).await? else {
+            return Ok(None);
+        };
+        quote.expire_if_stale(submitted);
+        if let QuoteStatus::Expired | QuoteStatus::Cancelled = quote.status {
+            return Ok(None);
+        }
+        Ok(Some(quote.id))
+    }
+
+    async fn create(&self, quote: Quote) -> AnyResult<uuid::Uuid> {
+        let id = quote.id;
+        self.quotes.store(quote).await?;
+        Ok(id)
+    }
+}
+
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+pub struct RateLimit {
+    pub max_quotes: usize,
+    pub window_secs: u64,
+}
+
+impl RateLimit {
+    pub const UNLIMITED: Self = Self {
+        max_quotes: usize::MAX,
+        window_secs: 0,
+    };
+}
+
+/// how long the signatures of an accepted quote stay redeemable
+#[derive(Clone, Copy, Debug)]
+pub struct TtlPolicy {
+    pub default: chrono::Duration,
+    pub max: chrono::Duration,
+}
+
+impl TtlPolicy {
+    /// the ttl of signatures issued at `now`: `default` from `now` unless requested.
+    /// a requested ttl past `max` from `now` is rejected
+    pub fn resolve(&self, requested: Option<TStamp>, now: TStamp) -> Result<TStamp> {
+        let max = now + self.max;
+        let ttl = requested.unwrap_or(now + self.default);
+        if ttl > max {
+            return Err(Error::TtlExceedsMax(ttl, max));
+        }
+        Ok(ttl)
+    }
+}
+
+impl Default for TtlPolicy {
+    fn default() -> Self {
+        Self {
+            default: chrono::Duration::days(2),
+            max: chrono::Duration::days(7),
+        }
+    }
+}
+
+/// caps the new quotes an endorser may request within a sliding window.
+/// handing out an existing quote again is free
+#[derive(Clone)]
+pub struct RateLimitedQuoteFactory<Quotes> {
+    pub inner: Factory<Quotes>,
+    pub limit: RateLimit,
+    requests: Arc<Mutex<HashMap<String, Vec<TStamp>>>>,
+}
+
+impl<Quotes> RateLimitedQuoteFactory<Quotes> {
+    pub fn new(inner: Factory<Quotes>, limit: RateLimit) -> Self {
+        Self {
+            inner,
+            limit,
+            requests: Default::default(),
+        }
+    }
+
+    // drops the requests out of the window, then records `now` if still under the limit
+    fn admit(&self, endorser: &str, now: TStamp) -> bool {
+        let window_start = now - chrono::Duration::seconds(self.limit.window_secs as i64);
+        let mut requests = self.requests.lock().unwrap();
+        requests.retain(|_, tstamps| {
+            tstamps.retain(|tstamp| *tstamp > window_start);
+            !tstamps.is_empty()
+        });
+        let tstamps = requests.entry(String::from(endorser)).or_default();
+        if tstamps.len() >= self.limit.max_quotes {
+            return false;
+        }
+        tstamps.push(now);
+        true
+    }
+}
+
+impl<Quotes> From<Factory<Quotes>> for RateLimitedQuoteFactory<Quotes> {
+    fn from(inner: Factory<Quotes>) -> Self {
+        Self::new(inner, RateLimit::UNLIMITED)
+    }
+}
+
+impl<Quotes> RateLimitedQuoteFactory<Quotes>
 where
     Quotes: Repository,
 {
@@ -129,63 +502,226 @@ where
         endorser: String,
         blinds: Vec<cdk00::BlindedMessage>,
         submitted: TStamp,
-    ) -> AnyResult<uuid::Uuid> {
-        let Some(quote) = self.quotes.search_by_bill(&bill, &endorser).await? else {
-            let quote = Quote::new(bill, endorser, blinds, submitted);
-            let id = quote.id;
-            self.quotes.store(quote).await?;
+        signature: (cdk01::PublicKey, Signature),
+    ) -> Result<uuid::Uuid> {
+        let quote = Quote::new(bill, endorser, blinds, submitted);
+        let (pubkey, sig) = signature;
+        verify_quote_signature(&quote, &pubkey, &sig)?;
+        if let Some(id) = self
+            .inner
+            .existing(&quote.bill, &quote.endorser, submitted)
+            .await?
+        {
             return Ok(id);
-        };
-
-        if let QuoteStatus::Accepted { ttl, .. } = quote.status {
-            if ttl < submitted {
-                let new = Quote::new(bill, endorser, blinds, submitted);
-                let id = new.id;
-                self.quotes.store(new).await?;
-                return Ok(id);
-            }
         }
-        Ok(quote.id)
+        if !self.admit(&quote.endorser, submitted) {
+            return Err(Error::RateLimited(quote.endorser));
+        }
+        let id = self.inner.create(quote).await?;
+        Ok(id)
+    }
+}
+
+/// the message a NUT-20 signed quote request commits to: bill and endorser, each
+/// prefixed by its length, then the count of outputs and their blinded secrets.
+/// lengths are u32 big endian, blinded secrets are 33 bytes each
+pub fn request_message(bill: &str, endorser: &str, blinds: &[cdk00::BlindedMessage]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(12 + bill.len() + endorser.len() + 33 * blinds.len());
+    for field in [bill.as_bytes(), endorser.as_bytes()] {
+        msg.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        msg.extend_from_slice(field);
+    }
+    msg.extend_from_slice(&(blinds.len() as u32).to_be_bytes());
+    for blind in blinds {
+        msg.extend_from_slice(&blind.blinded_secret.to_bytes());
     }
+    msg
+}
+
+/// the message a cancel request commits to: the length-prefixed "cancel" tag, then
+/// the quote id, so it cannot be replayed as a quote request signature
+pub fn cancel_message(id: uuid::Uuid) -> Vec<u8> {
+    const TAG: &[u8] = b"cancel";
+    let mut msg = Vec::with_capacity(4 + TAG.len() + 16);
+    msg.extend_from_slice(&(TAG.len() as u32).to_be_bytes());
+    msg.extend_from_slice(TAG);
+    msg.extend_from_slice(id.as_bytes());
+    msg
+}
+
+fn quote_request_message(quote: &Quote) -> Option<Vec<u8>> {
+    let QuoteStatus::Pending { blinds } = &quote.status else {
+        return None;
+    };
+    Some(request_message(&quote.bill, &quote.endorser, blinds))
+}
+
+/// checks `sig` is the endorser's schnorr signature over the pending quote request,
+/// so only the holder of `pubkey` can claim the signatures
+pub fn verify_quote_signature(
+    quote: &Quote,
+    pubkey: &cdk01::PublicKey,
+    sig: &Signature,
+) -> Result<()> {
+    let msg = quote_request_message(quote).ok_or(Error::InvalidQuoteSignature)?;
+    pubkey
+        .verify(&msg, sig)
+        .map_err(|_| Error::InvalidQuoteSignature)
+}
+
+/// the blinds signed for `amount` and the fee kept by the mint out of it, computed on
+/// the bill face value when known. the signed blinds and the fee add up to `amount`
+/// exactly, or nothing is signed
+fn select_blinds_after_fee<'a>(
+    amount: cdk::Amount,
+    bill_amount: Option<cdk::Amount>,
+    fees: &FeePolicy,
+    blinds: &'a mut [cdk00::BlindedMessage],
+) -> Result<(&'a [cdk00::BlindedMessage], cdk::Amount)> {
+    let fee = fee_for(bill_amount.unwrap_or(amount), fees);
+    if fee > amount {
+        return Err(Error::FeeExceedsAmount(fee, amount));
+    }
+    let target = amount - fee;
+    let selected = utils::select_blinds_to_target(target, blinds);
+    let covered = selected
+        .iter()
+        .fold(cdk::Amount::ZERO, |total, blind| total + blind.amount);
+    if covered != target {
+        return Err(Error::BlindsShortOfTarget(target, covered));
+    }
+    Ok((selected, fee))
 }
 
 // ---------- Service
 #[derive(Clone)]
 pub struct Service<KeysGen, QuotesRepo> {
     pub keys_gen: KeysGen,
-    pub quotes_gen: Factory<QuotesRepo>,
+    pub quotes_gen: RateLimitedQuoteFactory<QuotesRepo>,
     pub quotes: QuotesRepo,
+    pub observers: Vec<Arc<dyn QuoteObserver>>,
+    pub issued: Arc<dyn IssuedSignatureRepository>,
+    /// the ledger of every output issued by the mint, quotes included
+    pub ledger: Arc<dyn swap::ProofRepository>,
+    pub fees: FeePolicy,
+    pub ttl: TtlPolicy,
+    pub stats: DenominationStats,
+    /// the current time of the requests handled on behalf of the service
+    pub clock: Arc<dyn Clock>,
+    pub endorser_keys: Arc<dyn EndorserKeys>,
 }
 
 impl<KeysGen, QuotesRepo> Service<KeysGen, QuotesRepo>
 where
     QuotesRepo: Repository,
 {
+    async fn notify(&self, quote: &Quote, old: &QuoteStatus) {
+        for observer in &self.observers {
+            observer.on_status_change(quote, old).await;
+        }
+    }
+
     pub async fn lookup(&self, id: uuid::Uuid) -> Result<Quote> {
         self.quotes.load(id).await?.ok_or(Error::UnknownQuoteID(id))
     }
 
+    /// as lookup, but an accepted quote past its ttl is reported as expired
+    pub async fn lookup_at(&self, id: uuid::Uuid, now: TStamp) -> Result<Quote> {
+        let mut quote = self.lookup(id).await?;
+        quote.expire_if_stale(now);
+        Ok(quote)
+    }
+
+    /// the blind signatures of an accepted quote, None in any other state (expired included)
+    pub async fn get_signatures(
+        &self,
+        id: uuid::Uuid,
+    ) -> Result<Option<Vec<cdk00::BlindSignature>>> {
+        let quote = self.lookup_at(id, self.clock.now()).await?;
+        match quote.status {
+            QuoteStatus::Accepted { signatures, .. } => Ok(Some(signatures)),
+            _ => Ok(None),
+        }
+    }
+
     pub async fn decline(&self, id: uuid::Uuid) -> Result<()> {
         let old = self.quotes.load(id).await?;
         if old.is_none() {
             return Err(Error::UnknownQuoteID(id));
         }
         let mut quote = old.unwrap();
+        let previous = quote.status.clone();
         quote.decline()?;
-        self.quotes.update_if_pending(quote).await?;
+        if let QuoteStatus::Offered { .. } = previous {
+            self.quotes.update_if_offered(quote.clone()).await?;
+        } else {
+            self.quotes.update_if_pending(quote.clone()).await?;
+        }
+        self.notify(&quote, &previous).await;
+        Ok(())
+    }
+
+    /// the endorser withdraws its own quote
+    /// `signature` is the endorser's over `cancel_message(id)`, checked against its eBill key
+    pub async fn cancel(
+        &self,
+        id: uuid::Uuid,
+        endorser: &str,
+        signature: &Signature,
+    ) -> Result<()> {
+        let mut quote = self.lookup(id).await?;
+        if quote.endorser != endorser {
+            return Err(Error::NotEndorser(id, String::from(endorser)));
+        }
+        let pubkey = self.endorser_key(&quote.bill, &quote.endorser).await?;
+        pubkey
+            .verify(&cancel_message(id), signature)
+            .map_err(|_| Error::InvalidQuoteSignature)?;
+        let previous = quote.status.clone();
+        quote.cancel()?;
+        if let QuoteStatus::Offered { .. } = previous {
+            self.quotes.update_if_offered(quote.clone()).await?;
+        } else {
+            self.quotes.update_if_pending(quote.clone()).await?;
+        }
+        self.notify(&quote, &previous).await;
+        Ok(())
+    }
+
+    pub async fn offer(&self, id: uuid::Uuid, discount: cdk::Amount, expiry: TStamp) -> Result<()> {
+        let mut quote = self.lookup(id).await?;
+        let previous = quote.status.clone();
+        quote.offer(discount, expiry)?;
+        self.quotes.update_if_pending(quote.clone()).await?;
+        self.notify(&quote, &previous).await;
         Ok(())
     }
 
-    pub async fn list_pendings(&self, since: Option<TStamp>) -> Result<Vec<uuid::Uuid>> {
+    pub async fn list_offered(&self, since: Option<TStamp>, page: Page) -> Result<Vec<uuid::Uuid>> {
+        self.quotes
+            .list_offered(since, page)
+            .await
+            .map_err(Error::Repository)
+    }
+
+    pub async fn list_pendings(
+        &self,
+        since: Option<TStamp>,
+        page: Page,
+    ) -> Result<Vec<uuid::Uuid>> {
         self.quotes
-            .list_pendings(since)
+            .list_pendings(since, page)
             .await
             .map_err(Error::Repository)
     }
 
-    pub async fn list_accepteds(&self, since: Option<TStamp>) -> Result<Vec<uuid::Uuid>> {
+    pub async fn list_accepteds(
+        &self,
+        since: Option<TStamp>,
+        page: Page,
+    ) -> Result<Vec<uuid::Uuid>> {
         self.quotes
-            .list_accepteds(since)
+            .list_accepteds(since, page)
             .await
             .map_err(Error::Repository)
     }
@@ -196,11 +732,22 @@ where
         endorser: String,
         tstamp: TStamp,
         blinds: Vec<cdk00::BlindedMessage>,
+        signature: Signature,
     ) -> Result<uuid::Uuid> {
+        let pubkey = self.endorser_key(&bill, &endorser).await?;
         self.quotes_gen
-            .generate(bill, endorser, blinds, tstamp)
+            .generate(bill, endorser, blinds, tstamp, (pubkey, signature))
             .await
-            .map_err(Error::from)
+    }
+
+    /// the key `endorser` signs its requests on `bill` with, as known to the eBill service.
+    /// an endorser it does not know cannot sign
+    async fn endorser_key(&self, bill: &str, endorser: &str) -> Result<cdk01::PublicKey> {
+        self.endorser_keys
+            .endorser_key(bill, endorser)
+            .await
+            .map_err(Error::EndorserKeys)?
+            .ok_or(Error::InvalidQuoteSignature)
     }
 }
 
@@ -219,31 +766,228 @@ where
         let discounted_amount =
             cdk::Amount::from(discount.to_u64().ok_or(Error::InvalidAmount(discount))?);
 
+        // nothing gets generated nor signed for a ttl out of policy
+        let ttl = self.ttl.resolve(ttl, now)?;
         let mut quote = self.lookup(id).await?;
         let qid = quote.id;
         let kid = keys::credit::generate_keyset_id_from_bill(&quote.bill, &quote.endorser);
+        let maturity_date = quote.maturity_or_default(now);
         let QuoteStatus::Pending { ref mut blinds } = quote.status else {
             return Err(Error::QuoteAlreadyResolved(qid));
         };
 
-        let selected_blinds = utils::select_blinds_to_target(discounted_amount, blinds);
-        log::warn!("WARNING: we are leaving fees on the table, ... but we don't know how much (eBill data missing)");
+        let (selected_blinds, fee) =
+            select_blinds_after_fee(discounted_amount, quote.amount, &self.fees, blinds)?;
+
+        let keyset = self
+            .keys_gen
+            .generate(kid, &quote.bill, &quote.endorser, qid, maturity_date, now)
+            .await?;
+
+        let signatures = selected_blinds
+            .iter()
+            .map(|blind| sign_with_keys(&keyset, blind))
+            .collect::<KeyResult<Vec<cdk00::BlindSignature>>>()?;
+        let outputs = swap::issued_outputs(selected_blinds.iter(), &signatures);
+        let issued = signatures
+            .iter()
+            .fold(cdk::Amount::ZERO, |total, sig| total + sig.amount);
+        let previous = quote.status.clone();
+        quote.accept(signatures.clone(), ttl, fee)?;
+        self.quotes.update_if_pending(quote.clone()).await?;
+        // only signatures handed out count, a lost update race hands out none
+        self.stats.record(&signatures);
+        self.keys_gen.record_issued(maturity_date, issued).await?;
+        self.issued.record(qid, &signatures).await?;
+        self.ledger.record_issued(&outputs).await?;
+        self.notify(&quote, &previous).await;
+        Ok(())
+    }
+
+    /// the user agreed on the offered discount: sign the blinds up to it
+    pub async fn accept_offer(&self, id: uuid::Uuid, now: TStamp) -> Result<()> {
+        let mut quote = self.lookup(id).await?;
+        let qid = quote.id;
+        let kid = keys::credit::generate_keyset_id_from_bill(&quote.bill, &quote.endorser);
+        let maturity_date = quote.maturity_or_default(now);
+        let QuoteStatus::Offered {
+            ref mut blinds,
+            discount,
+            expiry,
+        } = quote.status
+        else {
+            return Err(Error::NoOffer(qid));
+        };
+        if expiry < now {
+            return Err(Error::OfferExpired(qid));
+        }
 
-        // TODO! maturity date should come from the eBill
-        let maturity_date = now + chrono::Duration::days(30);
-        let keyset = self.keys_gen.generate(kid, qid, maturity_date).await?;
+        let (selected_blinds, fee) =
+            select_blinds_after_fee(discount, quote.amount, &self.fees, blinds)?;
+        let keyset = self
+            .keys_gen
+            .generate(kid, &quote.bill, &quote.endorser, qid, maturity_date, now)
+            .await?;
 
         let signatures = selected_blinds
             .iter()
             .map(|blind| sign_with_keys(&keyset, blind))
             .collect::<KeyResult<Vec<cdk00::BlindSignature>>>()?;
-        let expiration = ttl.unwrap_or(utils::calculate_default_expiration_date_for_quote(now));
-        quote.accept(signatures, expiration)?;
-        self.quotes.update_if_pending(quote).await?;
+        let outputs = swap::issued_outputs(selected_blinds.iter(), &signatures);
+        let issued = signatures
+            .iter()
+            .fold(cdk::Amount::ZERO, |total, sig| total + sig.amount);
+        let previous = quote.status.clone();
+        quote.accept_offer(signatures.clone(), now, &self.ttl, fee)?;
+        self.quotes.update_if_offered(quote.clone()).await?;
+        // only signatures handed out count, a lost update race hands out none
+        self.stats.record(&signatures);
+        self.keys_gen.record_issued(maturity_date, issued).await?;
+        self.issued.record(qid, &signatures).await?;
+        self.ledger.record_issued(&outputs).await?;
+        self.notify(&quote, &previous).await;
         Ok(())
     }
 }
 
+#[cfg(test)]
+pub mod test_utils {
+    use super::*;
+    use std::sync::Arc;
+
+    // axum states must be Clone, mocks are not: share them behind an Arc
+    #[derive(Clone)]
+    pub struct SharedRepository(pub Arc<MockRepository>);
+
+    impl From<MockRepository> for SharedRepository {
+        fn from(repo: MockRepository) -> Self {
+            Self(Arc::new(repo))
+        }
+    }
+
+    #[async_trait]
+    impl Repository for SharedRepository {
+        async fn load(&self, id: uuid::Uuid) -> AnyResult<Option<Quote>> {
+            self.0.load(id).await
+        }
+        async fn update_if_pending(&self, quote: Quote) -> AnyResult<()> {
+            self.0.update_if_pending(quote).await
+        }
+        async fn update_if_offered(&self, quote: Quote) -> AnyResult<()> {
+            self.0.update_if_offered(quote).await
+        }
+        async fn update_if_accepted(&self, quote: Quote) -> AnyResult<()> {
+            self.0.update_if_accepted(quote).await
+        }
+        async fn list_pendings(&self, since: Option<TStamp>, page: Page) -> AnyResult<Vec<Uuid>> {
+            self.0.list_pendings(since, page).await
+        }
+        async fn list_offered(&self, since: Option<TStamp>, page: Page) -> AnyResult<Vec<Uuid>> {
+            self.0.list_offered(since, page).await
+        }
+        async fn list_accepteds(&self, since: Option<TStamp>, page: Page) -> AnyResult<Vec<Uuid>> {
+            self.0.list_accepteds(since, page).await
+        }
+        async fn search_by_bill(&self, bill: &str, endorser: &str) -> AnyResult<Option<Quote>> {
+            self.0.search_by_bill(bill, endorser).await
+        }
+        async fn search_by_endorser(&self, endorser: &str) -> AnyResult<Vec<Quote>> {
+            self.0.search_by_endorser(endorser).await
+        }
+        async fn store(&self, quote: Quote) -> AnyResult<()> {
+            self.0.store(quote).await
+        }
+        async fn purge_expired(&self, before: TStamp) -> AnyResult<usize> {
+            self.0.purge_expired(before).await
+        }
+    }
+
+    #[derive(Clone)]
+    pub struct SharedKeyFactory(pub Arc<MockKeyFactory>);
+
+    impl Default for SharedKeyFactory {
+        fn default() -> Self {
+            Self(Arc::new(MockKeyFactory::new()))
+        }
+    }
+
+    #[async_trait]
+    impl KeyFactory for SharedKeyFactory {
+        async fn generate(
+            &self,
+            kid: KeysetID,
+            bill: &str,
+            endorser: &str,
+            qid: Uuid,
+            maturity_date: TStamp,
+            now: TStamp,
+        ) -> AnyResult<cdk02::MintKeySet> {
+            self.0
+                .generate(kid, bill, endorser, qid, maturity_date, now)
+                .await
+        }
+        async fn record_issued(&self, maturity_date: TStamp, amount: cdk::Amount) -> AnyResult<()> {
+            self.0.record_issued(maturity_date, amount).await
+        }
+    }
+
+    /// every endorser holds the same key
+    pub struct FixedEndorserKey(pub cdk01::PublicKey);
+
+    #[async_trait]
+    impl EndorserKeys for FixedEndorserKey {
+        async fn endorser_key(
+            &self,
+            _bill: &str,
+            _endorser: &str,
+        ) -> AnyResult<Option<cdk01::PublicKey>> {
+            Ok(Some(self.0))
+        }
+    }
+
+    pub fn endorser_secret() -> cdk01::SecretKey {
+        cdk01::SecretKey::from_hex(
+            "0000000000000000000000000000000000000000000000000000000000000001",
+        )
+        .unwrap()
+    }
+
+    /// the request signed by `endorser_secret`
+    pub fn sign_request(
+        bill: &str,
+        endorser: &str,
+        blinds: &[cdk00::BlindedMessage],
+    ) -> (cdk01::PublicKey, Signature) {
+        let secret = endorser_secret();
+        let sig = secret
+            .sign(&request_message(bill, endorser, blinds))
+            .unwrap();
+        (secret.public_key(), sig)
+    }
+
+    pub fn service(
+        repo: MockRepository,
+        gen_repo: MockRepository,
+    ) -> Service<SharedKeyFactory, SharedRepository> {
+        Service {
+            keys_gen: SharedKeyFactory::default(),
+            quotes_gen: Factory {
+                quotes: gen_repo.into(),
+            }
+            .into(),
+            quotes: repo.into(),
+            observers: vec![],
+            issued: Arc::new(crate::persistence::inmemory::IssuedSignaturesMap::default()),
+            ledger: Arc::new(crate::persistence::inmemory::ProofMap::default()),
+            fees: FeePolicy::default(),
+            ttl: TtlPolicy::default(),
+            stats: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+            endorser_keys: Arc::new(FixedEndorserKey(endorser_secret().public_key())),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -256,13 +1000,14 @@ mod tests {
         repo.expect_search_by_bill().returning(|_, _| Ok(None));
         repo.expect_store().returning(|_| Ok(()));
 
-        let factory = Factory { quotes: repo };
+        let factory = RateLimitedQuoteFactory::from(Factory { quotes: repo });
         let test = factory
             .generate(
                 String::from("billID"),
                 String::from("endorserID"),
                 vec![],
                 chrono::Utc::now(),
+                test_utils::sign_request("billID", "endorserID", &[]),
             )
             .await;
         assert!(test.is_ok());
@@ -283,17 +1028,21 @@ mod tests {
                     bill: String::from(bill_id),
                     endorser: String::from(endorser_id),
                     submitted: chrono::Utc::now(),
+                    maturity: None,
+                    amount: None,
+                    locked_to: None,
                 }))
             });
         repo.expect_store().returning(|_| Ok(()));
 
-        let factory = Factory { quotes: repo };
+        let factory = RateLimitedQuoteFactory::from(Factory { quotes: repo });
         let test_id = factory
             .generate(
                 String::from(bill_id),
                 String::from(endorser_id),
                 vec![],
                 chrono::Utc::now(),
+                test_utils::sign_request(bill_id, endorser_id, &[]),
             )
             .await;
         assert!(test_id.is_ok());
@@ -315,17 +1064,21 @@ mod tests {
                     bill: String::from(bill_id),
                     endorser: String::from(endorser_id),
                     submitted: chrono::Utc::now(),
+                    maturity: None,
+                    amount: None,
+                    locked_to: None,
                 }))
             });
         repo.expect_store().returning(|_| Ok(()));
 
-        let factory = Factory { quotes: repo };
+        let factory = RateLimitedQuoteFactory::from(Factory { quotes: repo });
         let test_id = factory
             .generate(
                 String::from(bill_id),
                 String::from(endorser_id),
                 vec![],
                 chrono::Utc::now(),
+                test_utils::sign_request(bill_id, endorser_id, &[]),
             )
             .await;
         assert!(test_id.is_ok());
@@ -345,22 +1098,27 @@ mod tests {
                     status: QuoteStatus::Accepted {
                         signatures: vec![],
                         ttl: chrono::Utc::now() + chrono::Duration::days(1),
+                        fee: cdk::Amount::ZERO,
                     },
                     id,
                     bill: String::from(bill_id),
                     endorser: String::from(endorser_id),
                     submitted: chrono::Utc::now(),
+                    maturity: None,
+                    amount: None,
+                    locked_to: None,
                 }))
             });
         repo.expect_store().returning(|_| Ok(()));
 
-        let factory = Factory { quotes: repo };
+        let factory = RateLimitedQuoteFactory::from(Factory { quotes: repo });
         let test_id = factory
             .generate(
                 String::from(bill_id),
                 String::from(endorser_id),
                 vec![],
                 chrono::Utc::now(),
+                test_utils::sign_request(bill_id, endorser_id, &[]),
             )
             .await;
         assert!(test_id.is_ok());
@@ -380,25 +1138,1152 @@ mod tests {
                     status: QuoteStatus::Accepted {
                         signatures: vec![],
                         ttl: chrono::Utc::now(),
+                        fee: cdk::Amount::ZERO,
                     },
                     id,
                     bill: String::from(bill_id),
                     endorser: String::from(endorser_id),
                     submitted: chrono::Utc::now(),
+                    maturity: None,
+                    amount: None,
+                    locked_to: None,
                 }))
             });
         repo.expect_store().returning(|_| Ok(()));
 
-        let factory = Factory { quotes: repo };
+        let factory = RateLimitedQuoteFactory::from(Factory { quotes: repo });
         let test_id = factory
             .generate(
                 String::from(bill_id),
                 String::from(endorser_id),
                 vec![],
                 chrono::Utc::now() + chrono::Duration::seconds(1),
+                test_utils::sign_request(bill_id, endorser_id, &[]),
             )
             .await;
         assert!(test_id.is_ok());
         assert_ne!(id, test_id.unwrap());
     }
+
+    fn accepted_quote(ttl: TStamp) -> Quote {
+        let mut quote = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            chrono::Utc::now(),
+        );
+        quote.accept(vec![], ttl, cdk::Amount::ZERO).unwrap();
+        quote
+    }
+
+    #[test]
+    fn test_quote_accept_with_policy_default_ttl() {
+        let now = chrono::Utc::now();
+        let policy = TtlPolicy::default();
+        let mut quote = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            now,
+        );
+        quote
+            .accept_with_policy(vec![], None, now, &policy, cdk::Amount::ZERO)
+            .unwrap();
+        assert!(
+            matches!(quote.status, QuoteStatus::Accepted { ttl, .. } if ttl == now + policy.default)
+        );
+    }
+
+    #[test]
+    fn test_quote_accept_with_policy_over_max_rejected() {
+        let now = chrono::Utc::now();
+        let policy = TtlPolicy {
+            default: chrono::Duration::days(1),
+            max: chrono::Duration::days(3),
+        };
+        let mut quote = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            now,
+        );
+        let too_far = now + chrono::Duration::days(4);
+        let r = quote.accept_with_policy(vec![], Some(too_far), now, &policy, cdk::Amount::ZERO);
+        assert!(
+            matches!(r, Err(Error::TtlExceedsMax(ttl, max)) if ttl == too_far && max == now + policy.max)
+        );
+        assert!(matches!(quote.status, QuoteStatus::Pending { .. }));
+
+        let at_max = now + policy.max;
+        quote
+            .accept_with_policy(vec![], Some(at_max), now, &policy, cdk::Amount::ZERO)
+            .unwrap();
+        assert!(matches!(quote.status, QuoteStatus::Accepted { ttl, .. } if ttl == at_max));
+    }
+
+    #[test]
+    fn test_quote_is_redeemable_at_ttl() {
+        let ttl = chrono::Utc::now();
+        let quote = accepted_quote(ttl);
+        assert!(quote.is_redeemable(ttl - chrono::Duration::seconds(1)));
+        assert!(quote.is_redeemable(ttl));
+        assert!(!quote.is_redeemable(ttl + chrono::Duration::seconds(1)));
+    }
+
+    #[test]
+    fn test_quote_is_redeemable_not_accepted() {
+        let now = chrono::Utc::now();
+        let mut quote = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            now,
+        );
+        assert!(!quote.is_redeemable(now));
+        quote.decline().unwrap();
+        assert!(!quote.is_redeemable(now));
+    }
+
+    #[test]
+    fn test_quote_expire_if_stale() {
+        let ttl = chrono::Utc::now();
+        let mut quote = accepted_quote(ttl);
+        assert!(!quote.expire_if_stale(ttl));
+        assert!(matches!(quote.status, QuoteStatus::Accepted { .. }));
+
+        assert!(quote.expire_if_stale(ttl + chrono::Duration::seconds(1)));
+        assert!(matches!(quote.status, QuoteStatus::Expired));
+        assert!(!quote.is_redeemable(ttl));
+        // expired is final, e.g. it cannot be accepted again
+        assert!(!quote.expire_if_stale(ttl + chrono::Duration::seconds(1)));
+        assert!(quote.accept(vec![], ttl, cdk::Amount::ZERO).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_new_quote_request_quote_accepted_at_ttl() {
+        let ttl = chrono::Utc::now();
+        let quote = accepted_quote(ttl);
+        let id = quote.id;
+        let mut repo = MockRepository::new();
+        repo.expect_search_by_bill()
+            .returning(move |_, _| Ok(Some(quote.clone())));
+
+        let factory = RateLimitedQuoteFactory::from(Factory { quotes: repo });
+        let test_id = factory
+            .generate(
+                String::from("billID"),
+                String::from("endorserID"),
+                vec![],
+                ttl,
+                test_utils::sign_request("billID", "endorserID", &[]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(id, test_id);
+    }
+
+    #[tokio::test]
+    async fn test_new_quote_request_quote_expired() {
+        let mut quote = accepted_quote(chrono::Utc::now());
+        quote.status = QuoteStatus::Expired;
+        let id = quote.id;
+        let mut repo = MockRepository::new();
+        repo.expect_search_by_bill()
+            .returning(move |_, _| Ok(Some(quote.clone())));
+        repo.expect_store().times(1).returning(|_| Ok(()));
+
+        let factory = RateLimitedQuoteFactory::from(Factory { quotes: repo });
+        let test_id = factory
+            .generate(
+                String::from("billID"),
+                String::from("endorserID"),
+                vec![],
+                chrono::Utc::now(),
+                test_utils::sign_request("billID", "endorserID", &[]),
+            )
+            .await
+            .unwrap();
+        assert_ne!(id, test_id);
+    }
+
+    #[test]
+    fn test_quote_offer_then_accept() {
+        let now = chrono::Utc::now();
+        let mut quote = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            now,
+        );
+        assert!(quote
+            .accept_offer(vec![], now, &TtlPolicy::default(), cdk::Amount::ZERO)
+            .is_err());
+        quote.offer(cdk::Amount::from(10_u64), now).unwrap();
+        assert!(matches!(
+            quote.status,
+            QuoteStatus::Offered { discount, expiry, .. }
+                if discount == cdk::Amount::from(10_u64) && expiry == now
+        ));
+        // an offered quote is no longer pending
+        assert!(quote.offer(cdk::Amount::from(5_u64), now).is_err());
+        assert!(quote.accept(vec![], now, cdk::Amount::ZERO).is_err());
+
+        quote
+            .accept_offer(vec![], now, &TtlPolicy::default(), cdk::Amount::ZERO)
+            .unwrap();
+        assert!(matches!(quote.status, QuoteStatus::Accepted { ttl, .. } if ttl == now));
+        assert!(quote
+            .accept_offer(vec![], now, &TtlPolicy::default(), cdk::Amount::ZERO)
+            .is_err());
+    }
+
+    #[test]
+    fn test_quote_offer_then_decline() {
+        let now = chrono::Utc::now();
+        let mut quote = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            now,
+        );
+        quote.offer(cdk::Amount::from(10_u64), now).unwrap();
+        quote.decline().unwrap();
+        assert!(matches!(quote.status, QuoteStatus::Declined));
+        assert!(quote
+            .accept_offer(vec![], now, &TtlPolicy::default(), cdk::Amount::ZERO)
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_service_decline_offered() {
+        let now = chrono::Utc::now();
+        let mut quote = accepted_quote(now);
+        quote.status = QuoteStatus::Offered {
+            blinds: vec![],
+            discount: cdk::Amount::from(10_u64),
+            expiry: now,
+        };
+        let id = quote.id;
+        let mut repo = MockRepository::new();
+        repo.expect_load()
+            .with(eq(id))
+            .returning(move |_| Ok(Some(quote.clone())));
+        repo.expect_update_if_offered()
+            .withf(|q| matches!(q.status, QuoteStatus::Declined))
+            .times(1)
+            .returning(|_| Ok(()));
+        let service = Service {
+            keys_gen: (),
+            quotes_gen: Factory {
+                quotes: MockRepository::new(),
+            }
+            .into(),
+            quotes: repo,
+            observers: vec![],
+            issued: Arc::new(crate::persistence::inmemory::IssuedSignaturesMap::default()),
+            ledger: Arc::new(crate::persistence::inmemory::ProofMap::default()),
+            fees: FeePolicy::default(),
+            ttl: TtlPolicy::default(),
+            stats: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+            endorser_keys: Arc::new(test_utils::FixedEndorserKey(
+                test_utils::endorser_secret().public_key(),
+            )),
+        };
+
+        service.decline(id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_service_get_signatures() {
+        let now = chrono::Utc::now();
+        let keyset = crate::keys::test_utils::generate_keyset();
+        let blinds: Vec<_> =
+            crate::utils::tests::generate_blinds(&keyset, &[cdk::Amount::from(8_u64)])
+                .into_iter()
+                .map(|(blind, _, _)| blind)
+                .collect();
+        let signatures: Vec<_> = blinds
+            .iter()
+            .map(|blind| sign_with_keys(&keyset, blind).unwrap())
+            .collect();
+        let mut accepted = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            blinds.clone(),
+            now,
+        );
+        accepted
+            .accept(
+                signatures.clone(),
+                now + chrono::Duration::days(1),
+                cdk::Amount::ZERO,
+            )
+            .unwrap();
+        let pending = Quote::new(
+            String::from("otherBillID"),
+            String::from("endorserID"),
+            blinds,
+            now,
+        );
+        let (accepted_id, pending_id) = (accepted.id, pending.id);
+        let mut repo = MockRepository::new();
+        repo.expect_load()
+            .with(eq(accepted_id))
+            .returning(move |_| Ok(Some(accepted.clone())));
+        repo.expect_load()
+            .with(eq(pending_id))
+            .returning(move |_| Ok(Some(pending.clone())));
+        let service = Service {
+            keys_gen: (),
+            quotes_gen: Factory {
+                quotes: MockRepository::new(),
+            }
+            .into(),
+            quotes: repo,
+            observers: vec![],
+            issued: Arc::new(crate::persistence::inmemory::IssuedSignaturesMap::default()),
+            ledger: Arc::new(crate::persistence::inmemory::ProofMap::default()),
+            fees: FeePolicy::default(),
+            ttl: TtlPolicy::default(),
+            stats: Default::default(),
+            clock: Arc::new(crate::clock::FixedClock(now)),
+            endorser_keys: Arc::new(test_utils::FixedEndorserKey(
+                test_utils::endorser_secret().public_key(),
+            )),
+        };
+
+        let found = service.get_signatures(accepted_id).await.unwrap();
+        assert_eq!(found, Some(signatures));
+        let found = service.get_signatures(pending_id).await.unwrap();
+        assert_eq!(found, None);
+    }
+
+    #[test]
+    fn test_quote_cancel_pending() {
+        let mut quote = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            chrono::Utc::now(),
+        );
+        quote.cancel().unwrap();
+        assert!(matches!(quote.status, QuoteStatus::Cancelled));
+        assert!(quote
+            .offer(cdk::Amount::from(10_u64), chrono::Utc::now())
+            .is_err());
+    }
+
+    #[test]
+    fn test_quote_cancel_accepted() {
+        let mut quote = accepted_quote(chrono::Utc::now());
+        let id = quote.id;
+        let r = quote.cancel();
+        assert!(matches!(r, Err(Error::QuoteAlreadyResolved(qid)) if qid == id));
+        assert!(matches!(quote.status, QuoteStatus::Accepted { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_service_cancel_only_by_endorser() {
+        let quote = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            chrono::Utc::now(),
+        );
+        let id = quote.id;
+        let mut repo = MockRepository::new();
+        repo.expect_load()
+            .with(eq(id))
+            .returning(move |_| Ok(Some(quote.clone())));
+        repo.expect_update_if_pending()
+            .withf(|q| matches!(q.status, QuoteStatus::Cancelled))
+            .times(1)
+            .returning(|_| Ok(()));
+        let service = Service {
+            keys_gen: (),
+            quotes_gen: Factory {
+                quotes: MockRepository::new(),
+            }
+            .into(),
+            quotes: repo,
+            observers: vec![],
+            issued: Arc::new(crate::persistence::inmemory::IssuedSignaturesMap::default()),
+            ledger: Arc::new(crate::persistence::inmemory::ProofMap::default()),
+            fees: FeePolicy::default(),
+            ttl: TtlPolicy::default(),
+            stats: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+            endorser_keys: Arc::new(test_utils::FixedEndorserKey(
+                test_utils::endorser_secret().public_key(),
+            )),
+        };
+
+        let sig = test_utils::endorser_secret()
+            .sign(&cancel_message(id))
+            .unwrap();
+        let r = service.cancel(id, "otherID", &sig).await;
+        assert!(matches!(r, Err(Error::NotEndorser(qid, _)) if qid == id));
+        // signed by someone else
+        let other = cdk01::SecretKey::generate()
+            .sign(&cancel_message(id))
+            .unwrap();
+        let r = service.cancel(id, "endorserID", &other).await;
+        assert!(matches!(r, Err(Error::InvalidQuoteSignature)));
+        // a signature over another quote
+        let replayed = test_utils::endorser_secret()
+            .sign(&cancel_message(Uuid::new_v4()))
+            .unwrap();
+        let r = service.cancel(id, "endorserID", &replayed).await;
+        assert!(matches!(r, Err(Error::InvalidQuoteSignature)));
+        service.cancel(id, "endorserID", &sig).await.unwrap();
+    }
+
+    #[derive(Default)]
+    struct RecordingObserver(std::sync::Mutex<Vec<(Uuid, QuoteStatus, QuoteStatus)>>);
+
+    #[async_trait]
+    impl QuoteObserver for RecordingObserver {
+        async fn on_status_change(&self, quote: &Quote, old: &QuoteStatus) {
+            let mut calls = self.0.lock().unwrap();
+            calls.push((quote.id, old.clone(), quote.status.clone()));
+        }
+    }
+
+    fn observed_service<KG>(
+        keys_gen: KG,
+        repo: MockRepository,
+    ) -> (Service<KG, MockRepository>, Arc<RecordingObserver>) {
+        let observer = Arc::new(RecordingObserver::default());
+        let service = Service {
+            keys_gen,
+            quotes_gen: Factory {
+                quotes: MockRepository::new(),
+            }
+            .into(),
+            quotes: repo,
+            observers: vec![observer.clone()],
+            issued: Arc::new(crate::persistence::inmemory::IssuedSignaturesMap::default()),
+            ledger: Arc::new(crate::persistence::inmemory::ProofMap::default()),
+            fees: FeePolicy::default(),
+            ttl: TtlPolicy::default(),
+            stats: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+            endorser_keys: Arc::new(test_utils::FixedEndorserKey(
+                test_utils::endorser_secret().public_key(),
+            )),
+        };
+        (service, observer)
+    }
+
+    #[tokio::test]
+    async fn test_service_observers_decline_and_offer() {
+        let now = chrono::Utc::now();
+        let pending = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            now,
+        );
+        let pending_id = pending.id;
+        let declined = {
+            let mut quote = pending.clone();
+            quote.id = Uuid::new_v4();
+            quote.decline().unwrap();
+            quote
+        };
+        let declined_id = declined.id;
+        let mut repo = MockRepository::new();
+        repo.expect_load()
+            .with(eq(pending_id))
+            .returning(move |_| Ok(Some(pending.clone())));
+        repo.expect_load()
+            .with(eq(declined_id))
+            .returning(move |_| Ok(Some(declined.clone())));
+        repo.expect_update_if_pending().returning(|_| Ok(()));
+        let (service, observer) = observed_service((), repo);
+
+        service.decline(pending_id).await.unwrap();
+        service
+            .offer(pending_id, cdk::Amount::from(10_u64), now)
+            .await
+            .unwrap();
+        // no-ops: nothing changes, nothing is reported
+        assert!(service.decline(declined_id).await.is_err());
+        assert!(service
+            .offer(declined_id, cdk::Amount::from(10_u64), now)
+            .await
+            .is_err());
+
+        let calls = observer.0.lock().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert!(matches!(
+            calls[0],
+            (id, QuoteStatus::Pending { .. }, QuoteStatus::Declined) if id == pending_id
+        ));
+        assert!(matches!(
+            calls[1],
+            (id, QuoteStatus::Pending { .. }, QuoteStatus::Offered { .. }) if id == pending_id
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_service_observers_accept() {
+        let now = chrono::Utc::now();
+        let pending = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            now,
+        );
+        let id = pending.id;
+        let mut repo = MockRepository::new();
+        repo.expect_load()
+            .with(eq(id))
+            .returning(move |_| Ok(Some(pending.clone())));
+        repo.expect_update_if_pending()
+            .times(1)
+            .returning(|_| Ok(()));
+        let mut keys_gen = MockKeyFactory::new();
+        keys_gen
+            .expect_generate()
+            .returning(|_, _, _, _, _, _| Ok(keys::test_utils::generate_keyset()));
+        keys_gen.expect_record_issued().returning(|_, _| Ok(()));
+        let (service, observer) = observed_service(keys_gen, repo);
+
+        service.accept(id, Decimal::ZERO, now, None).await.unwrap();
+        // a failing accept does not report
+        let r = service.accept(id, Decimal::NEGATIVE_ONE, now, None).await;
+        assert!(matches!(r, Err(Error::InvalidAmount(_))));
+
+        let calls = observer.0.lock().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert!(matches!(
+            calls[0],
+            (qid, QuoteStatus::Pending { .. }, QuoteStatus::Accepted { .. }) if qid == id
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_service_accept_records_fee_apart() {
+        let now = chrono::Utc::now();
+        let keyset = keys::test_utils::generate_keyset();
+        let amounts = [64_u64, 32, 4].map(cdk::Amount::from);
+        let blinds = utils::tests::generate_blinds(&keyset, &amounts)
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+        let pending = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            blinds,
+            now,
+        );
+        let id = pending.id;
+        let mut repo = MockRepository::new();
+        repo.expect_load()
+            .with(eq(id))
+            .returning(move |_| Ok(Some(pending.clone())));
+        repo.expect_update_if_pending()
+            .withf(|quote| match &quote.status {
+                QuoteStatus::Accepted {
+                    signatures, fee, ..
+                } => {
+                    let signed = signatures
+                        .iter()
+                        .fold(cdk::Amount::ZERO, |total, s| total + s.amount);
+                    signed == cdk::Amount::from(96) && signed + *fee == cdk::Amount::from(100)
+                }
+                _ => false,
+            })
+            .times(1)
+            .returning(|_| Ok(()));
+        let mut keys_gen = MockKeyFactory::new();
+        keys_gen
+            .expect_generate()
+            .returning(move |_, _, _, _, _, _| Ok(keyset.clone()));
+        keys_gen
+            .expect_record_issued()
+            .with(always(), eq(cdk::Amount::from(96)))
+            .returning(|_, _| Ok(()));
+        let (mut service, _) = observed_service(keys_gen, repo);
+        service.fees = FeePolicy {
+            base_ppk: 0,
+            flat: cdk::Amount::from(4),
+        };
+        let issued = crate::persistence::inmemory::IssuedSignaturesMap::default();
+        service.issued = Arc::new(issued.clone());
+
+        service
+            .accept(id, Decimal::from(100), now, None)
+            .await
+            .unwrap();
+        let amounts: Vec<_> = issued
+            .by_quote(id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|signature| signature.amount)
+            .collect();
+        assert_eq!(amounts, [64_u64, 32].map(cdk::Amount::from));
+    }
+
+    #[tokio::test]
+    async fn test_service_accept_fee_on_bill_amount() {
+        let now = chrono::Utc::now();
+        let keyset = keys::test_utils::generate_keyset();
+        let amounts = [64_u64, 32].map(cdk::Amount::from);
+        let blinds = utils::tests::generate_blinds(&keyset, &amounts)
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+        let bill = DecryptedBill {
+            id: String::from("billID"),
+            maturity: now + chrono::Duration::days(90),
+            amount: cdk::Amount::from(1000),
+        };
+        let pending = Quote::from_bill(bill, String::from("endorserID"), blinds, now);
+        let id = pending.id;
+        let mut repo = MockRepository::new();
+        repo.expect_load()
+            .returning(move |_| Ok(Some(pending.clone())));
+        // 1% of the 1000 face value, not of the 106 discounted amount
+        repo.expect_update_if_pending()
+            .withf(|quote| {
+                matches!(quote.status, QuoteStatus::Accepted { fee, .. } if fee == cdk::Amount::from(10))
+            })
+            .times(1)
+            .returning(|_| Ok(()));
+        let mut keys_gen = MockKeyFactory::new();
+        keys_gen
+            .expect_generate()
+            .returning(move |_, _, _, _, _, _| Ok(keyset.clone()));
+        keys_gen
+            .expect_record_issued()
+            .with(always(), eq(cdk::Amount::from(96)))
+            .returning(|_, _| Ok(()));
+        let (mut service, _) = observed_service(keys_gen, repo);
+        service.fees = FeePolicy {
+            base_ppk: 10,
+            flat: cdk::Amount::ZERO,
+        };
+
+        service
+            .accept(id, Decimal::from(106), now, None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_service_accept_blinds_short_of_target() {
+        let now = chrono::Utc::now();
+        let keyset = keys::test_utils::generate_keyset();
+        let amounts = [64_u64, 32, 4].map(cdk::Amount::from);
+        let blinds = utils::tests::generate_blinds(&keyset, &amounts)
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+        let pending = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            blinds,
+            now,
+        );
+        let id = pending.id;
+        let mut repo = MockRepository::new();
+        repo.expect_load()
+            .returning(move |_| Ok(Some(pending.clone())));
+        // no expectation on generate nor on the update: nothing gets signed
+        let (mut service, _) = observed_service(MockKeyFactory::new(), repo);
+        service.fees = FeePolicy {
+            base_ppk: 0,
+            flat: cdk::Amount::from(10),
+        };
+
+        // 64 fits in the 80 left after the fee, 32 does not
+        let r = service.accept(id, Decimal::from(90), now, None).await;
+        assert!(matches!(
+            r,
+            Err(Error::BlindsShortOfTarget(target, covered))
+                if target == cdk::Amount::from(80) && covered == cdk::Amount::from(64)
+        ));
+        // the fee on a 1000 face value exceeds a discounted amount of 5
+        let r = select_blinds_after_fee(
+            cdk::Amount::from(5),
+            Some(cdk::Amount::from(1000)),
+            &service.fees,
+            &mut [],
+        );
+        assert!(matches!(r, Err(Error::FeeExceedsAmount(..))));
+    }
+
+    #[tokio::test]
+    async fn test_service_accept_ttl_over_max_rejected_before_signing() {
+        let now = chrono::Utc::now();
+        let pending = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            now,
+        );
+        let id = pending.id;
+        let mut repo = MockRepository::new();
+        repo.expect_load()
+            .returning(move |_| Ok(Some(pending.clone())));
+        // no expectation on generate nor on the update: neither may run
+        let (service, observer) = observed_service(MockKeyFactory::new(), repo);
+
+        let too_far = now + TtlPolicy::default().max + chrono::Duration::seconds(1);
+        let r = service.accept(id, Decimal::ZERO, now, Some(too_far)).await;
+        assert!(matches!(r, Err(Error::TtlExceedsMax(..))));
+        assert!(observer.0.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_service_accept_failed_update_not_counted() {
+        let now = chrono::Utc::now();
+        let keyset = keys::test_utils::generate_keyset();
+        let amounts = [64_u64, 32].map(cdk::Amount::from);
+        let blinds = utils::tests::generate_blinds(&keyset, &amounts)
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+        let pending = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            blinds,
+            now,
+        );
+        let id = pending.id;
+        let mut repo = MockRepository::new();
+        repo.expect_load()
+            .returning(move |_| Ok(Some(pending.clone())));
+        repo.expect_update_if_pending()
+            .returning(|_| Err(anyhow::anyhow!("quote resolved concurrently")));
+        let mut keys_gen = MockKeyFactory::new();
+        keys_gen
+            .expect_generate()
+            .returning(move |_, _, _, _, _, _| Ok(keyset.clone()));
+        let (service, _) = observed_service(keys_gen, repo);
+
+        let r = service.accept(id, Decimal::from(96), now, None).await;
+        assert!(r.is_err());
+        assert!(service.stats.histogram().is_empty());
+    }
+
+    #[test]
+    fn test_quote_accept_offer_ttl_capped_at_max() {
+        let now = chrono::Utc::now();
+        let policy = TtlPolicy::default();
+        let mut quote = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            now,
+        );
+        quote
+            .offer(cdk::Amount::from(10_u64), now + chrono::Duration::days(30))
+            .unwrap();
+
+        quote
+            .accept_offer(vec![], now, &policy, cdk::Amount::ZERO)
+            .unwrap();
+        assert!(
+            matches!(quote.status, QuoteStatus::Accepted { ttl, .. } if ttl == now + policy.max)
+        );
+    }
+
+    #[test]
+    fn test_quote_from_bill() {
+        let now = chrono::Utc::now();
+        let bill = DecryptedBill {
+            id: String::from("billID"),
+            maturity: now + chrono::Duration::days(90),
+            amount: cdk::Amount::from(1000),
+        };
+        let quote = Quote::from_bill(bill.clone(), String::from("endorserID"), vec![], now);
+        assert_eq!(quote.bill, bill.id);
+        assert_eq!(quote.endorser, "endorserID");
+        assert_eq!(quote.submitted, now);
+        assert_eq!(quote.maturity, Some(bill.maturity));
+        assert!(matches!(quote.status, QuoteStatus::Pending { ref blinds } if blinds.is_empty()));
+        assert!(Quote::new(bill.id, String::from("endorserID"), vec![], now)
+            .maturity
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_service_accept_uses_bill_maturity() {
+        let now = chrono::Utc::now();
+        let maturity = now + chrono::Duration::days(90);
+        let keyset = keys::test_utils::generate_keyset();
+        let blinds = utils::tests::generate_blinds(&keyset, &[cdk::Amount::from(8)])
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+        let bill = DecryptedBill {
+            id: String::from("billID"),
+            maturity,
+            amount: cdk::Amount::from(8),
+        };
+        let pending = Quote::from_bill(bill, String::from("endorserID"), blinds, now);
+        let id = pending.id;
+        let mut repo = MockRepository::new();
+        repo.expect_load()
+            .with(eq(id))
+            .returning(move |_| Ok(Some(pending.clone())));
+        repo.expect_update_if_pending().returning(|_| Ok(()));
+        let mut keys_gen = MockKeyFactory::new();
+        keys_gen
+            .expect_generate()
+            .with(always(), always(), always(), eq(id), eq(maturity), eq(now))
+            .times(1)
+            .returning(move |_, _, _, _, _, _| Ok(keyset.clone()));
+        keys_gen
+            .expect_record_issued()
+            .with(eq(maturity), always())
+            .returning(|_, _| Ok(()));
+        let (service, _) = observed_service(keys_gen, repo);
+
+        service
+            .accept(id, Decimal::from(8), now, None)
+            .await
+            .unwrap();
+    }
+
+    fn rate_limited(
+        max_quotes: usize,
+    ) -> RateLimitedQuoteFactory<crate::persistence::inmemory::QuotesIDMap> {
+        let factory = Factory {
+            quotes: crate::persistence::inmemory::QuotesIDMap::default(),
+        };
+        let limit = RateLimit {
+            max_quotes,
+            window_secs: 60,
+        };
+        RateLimitedQuoteFactory::new(factory, limit)
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_factory_under_limit() {
+        let factory = rate_limited(2);
+        let now = chrono::Utc::now();
+
+        for bill in ["bill1", "bill2"] {
+            let r = factory
+                .generate(
+                    String::from(bill),
+                    String::from("endorserID"),
+                    vec![],
+                    now,
+                    test_utils::sign_request(bill, "endorserID", &[]),
+                )
+                .await;
+            assert!(r.is_ok());
+        }
+        // another endorser has its own budget
+        let r = factory
+            .generate(
+                String::from("bill3"),
+                String::from("other"),
+                vec![],
+                now,
+                test_utils::sign_request("bill3", "other", &[]),
+            )
+            .await;
+        assert!(r.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_factory_at_limit() {
+        let factory = rate_limited(1);
+        let now = chrono::Utc::now();
+        let id = factory
+            .generate(
+                String::from("bill1"),
+                String::from("endorserID"),
+                vec![],
+                now,
+                test_utils::sign_request("bill1", "endorserID", &[]),
+            )
+            .await
+            .unwrap();
+
+        let r = factory
+            .generate(
+                String::from("bill2"),
+                String::from("endorserID"),
+                vec![],
+                now,
+                test_utils::sign_request("bill2", "endorserID", &[]),
+            )
+            .await;
+        assert!(matches!(r, Err(Error::RateLimited(endorser)) if endorser == "endorserID"));
+        // the standing quote is handed out again, no new work done
+        let again = factory
+            .generate(
+                String::from("bill1"),
+                String::from("endorserID"),
+                vec![],
+                now,
+                test_utils::sign_request("bill1", "endorserID", &[]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(again, id);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_factory_window_reset() {
+        let factory = rate_limited(1);
+        let now = chrono::Utc::now();
+        factory
+            .generate(
+                String::from("bill1"),
+                String::from("endorserID"),
+                vec![],
+                now,
+                test_utils::sign_request("bill1", "endorserID", &[]),
+            )
+            .await
+            .unwrap();
+        let r = factory
+            .generate(
+                String::from("bill2"),
+                String::from("endorserID"),
+                vec![],
+                now + chrono::Duration::seconds(59),
+                test_utils::sign_request("bill2", "endorserID", &[]),
+            )
+            .await;
+        assert!(r.is_err());
+
+        let r = factory
+            .generate(
+                String::from("bill2"),
+                String::from("endorserID"),
+                vec![],
+                now + chrono::Duration::seconds(60),
+                test_utils::sign_request("bill2", "endorserID", &[]),
+            )
+            .await;
+        assert!(r.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_generate_signed_quote_request() {
+        let factory = rate_limited(10);
+        let now = chrono::Utc::now();
+        let keyset = crate::keys::test_utils::generate_keyset();
+        let amounts = [cdk::Amount::from(8_u64), cdk::Amount::from(2_u64)];
+        let blinds: Vec<_> = crate::utils::tests::generate_blinds(&keyset, &amounts)
+            .into_iter()
+            .map(|(blind, _, _)| blind)
+            .collect();
+        let endorser_sk = cdk01::SecretKey::generate();
+        let endorser = endorser_sk.public_key();
+        let msg = request_message("billID", &endorser.to_hex(), &blinds);
+        let sig = endorser_sk.sign(&msg).unwrap();
+
+        // tampered outputs
+        let r = factory
+            .generate(
+                String::from("billID"),
+                endorser.to_hex(),
+                blinds[..1].to_vec(),
+                now,
+                (endorser, sig),
+            )
+            .await;
+        assert!(matches!(r, Err(Error::InvalidQuoteSignature)));
+        // signed by someone else
+        let other = cdk01::SecretKey::generate().public_key();
+        let r = factory
+            .generate(
+                String::from("billID"),
+                endorser.to_hex(),
+                blinds.clone(),
+                now,
+                (other, sig),
+            )
+            .await;
+        assert!(matches!(r, Err(Error::InvalidQuoteSignature)));
+
+        let r = factory
+            .generate(
+                String::from("billID"),
+                endorser.to_hex(),
+                blinds,
+                now,
+                (endorser, sig),
+            )
+            .await;
+        assert!(r.is_ok());
+    }
+
+    #[test]
+    fn test_request_message_field_boundaries() {
+        // the same concatenation, split differently between bill and endorser
+        assert_ne!(
+            request_message("billA", "B", &[]),
+            request_message("bill", "AB", &[])
+        );
+        assert_ne!(request_message("", "x", &[]), request_message("x", "", &[]));
+    }
+
+    #[tokio::test]
+    async fn test_enquire_unknown_endorser_rejected() {
+        let mut service = test_utils::service(MockRepository::new(), MockRepository::new());
+        let mut keys = MockEndorserKeys::new();
+        keys.expect_endorser_key().returning(|_, _| Ok(None));
+        service.endorser_keys = Arc::new(keys);
+        let (_, sig) = test_utils::sign_request("billID", "endorserID", &[]);
+        let r = service
+            .enquire(
+                String::from("billID"),
+                String::from("endorserID"),
+                chrono::Utc::now(),
+                vec![],
+                sig,
+            )
+            .await;
+        assert!(matches!(r, Err(Error::InvalidQuoteSignature)));
+    }
+
+    #[tokio::test]
+    async fn test_enquire_signed_by_other_key_rejected() {
+        let service = test_utils::service(MockRepository::new(), MockRepository::new());
+        let msg = request_message("billID", "endorserID", &[]);
+        let sig = cdk01::SecretKey::generate().sign(&msg).unwrap();
+        let r = service
+            .enquire(
+                String::from("billID"),
+                String::from("endorserID"),
+                chrono::Utc::now(),
+                vec![],
+                sig,
+            )
+            .await;
+        assert!(matches!(r, Err(Error::InvalidQuoteSignature)));
+    }
+
+    fn roundtrip(quote: &Quote) -> Quote {
+        let json = serde_json::to_string(quote).unwrap();
+        let back: Quote = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&back).unwrap(), json);
+        back
+    }
+
+    #[test]
+    fn test_quote_serde_roundtrip_pending() {
+        let keyset = keys::test_utils::generate_keyset();
+        let blinds: Vec<_> = utils::tests::generate_blinds(&keyset, &[cdk::Amount::from(8)])
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+        let quote = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            blinds.clone(),
+            chrono::Utc::now(),
+        );
+
+        let back = roundtrip(&quote);
+        assert_eq!(back.id, quote.id);
+        assert_eq!(back.submitted, quote.submitted);
+        assert!(matches!(back.status, QuoteStatus::Pending { blinds: b } if b == blinds));
+    }
+
+    #[test]
+    fn test_quote_serde_roundtrip_declined() {
+        let mut quote = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            chrono::Utc::now(),
+        );
+        quote.decline().unwrap();
+
+        let back = roundtrip(&quote);
+        assert!(matches!(back.status, QuoteStatus::Declined));
+    }
+
+    #[test]
+    fn test_quote_serde_roundtrip_accepted() {
+        let keyset = keys::test_utils::generate_keyset();
+        let signatures: Vec<_> = utils::tests::generate_blinds(&keyset, &[cdk::Amount::from(8)])
+            .into_iter()
+            .map(|b| sign_with_keys(&keyset, &b.0).unwrap())
+            .collect();
+        let ttl = chrono::Utc::now();
+        let mut quote = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            chrono::Utc::now(),
+        );
+        quote
+            .accept(signatures.clone(), ttl, cdk::Amount::from(3))
+            .unwrap();
+
+        let back = roundtrip(&quote);
+        assert!(matches!(
+            back.status,
+            QuoteStatus::Accepted { signatures: s, ttl: t, fee }
+                if s == signatures && t == ttl && fee == cdk::Amount::from(3)
+        ));
+    }
+
+    #[test]
+    fn test_quote_serde_roundtrip_offered() {
+        let expiry = chrono::Utc::now();
+        let mut quote = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            chrono::Utc::now(),
+        );
+        quote.offer(cdk::Amount::from(42), expiry).unwrap();
+
+        let back = roundtrip(&quote);
+        assert!(matches!(
+            back.status,
+            QuoteStatus::Offered { discount, expiry: e, .. }
+                if discount == cdk::Amount::from(42) && e == expiry
+        ));
+    }
+
+    #[test]
+    fn test_quote_serde_roundtrip_cancelled_and_expired() {
+        let mut quote = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            chrono::Utc::now(),
+        );
+        quote.cancel().unwrap();
+        assert!(matches!(roundtrip(&quote).status, QuoteStatus::Cancelled));
+
+        quote.status = QuoteStatus::Expired;
+        assert!(matches!(roundtrip(&quote).status, QuoteStatus::Expired));
+    }
+
+    #[test]
+    fn test_quote_serde_timestamps_rfc3339() {
+        let submitted = chrono::DateTime::parse_from_rfc3339("2025-01-02T03:04:05Z")
+            .unwrap()
+            .to_utc();
+        let mut quote = Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            submitted,
+        );
+        quote.accept(vec![], submitted, cdk::Amount::ZERO).unwrap();
+
+        let json = serde_json::to_value(&quote).unwrap();
+        assert_eq!(json["submitted"], "2025-01-02T03:04:05Z");
+        assert_eq!(json["status"]["status"], "accepted");
+        assert_eq!(json["status"]["ttl"], "2025-01-02T03:04:05Z");
+    }
 }
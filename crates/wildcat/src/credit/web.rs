@@ -21,21 +21,33 @@ where
         req.node
     );
 
+    // the key it is checked against comes from the eBill, not from the request
+    let signature = req.signature.ok_or(quotes::Error::InvalidQuoteSignature)?;
     let id = ctrl
-        .enquire(req.bill, req.node, chrono::Utc::now(), req.outputs)
+        .enquire(req.bill, req.node, ctrl.clock.now(), req.outputs, signature)
         .await?;
     Ok(Json(web_quotes::EnquireReply { id }))
 }
 
 /// --------------------------- Look up quote
-fn convert_to_enquire_reply(quote: quotes::Quote) -> web_quotes::StatusReply {
-    match quote.status {
+pub fn convert_to_status_reply(status: quotes::QuoteStatus) -> web_quotes::StatusReply {
+    match status {
         quotes::QuoteStatus::Pending { .. } => web_quotes::StatusReply::Pending,
         quotes::QuoteStatus::Declined => web_quotes::StatusReply::Declined,
-        quotes::QuoteStatus::Accepted { signatures, ttl } => web_quotes::StatusReply::Accepted {
+        quotes::QuoteStatus::Cancelled => web_quotes::StatusReply::Cancelled,
+        quotes::QuoteStatus::Offered {
+            discount, expiry, ..
+        } => web_quotes::StatusReply::Offered {
+            discount,
+            expiration_date: expiry,
+        },
+        quotes::QuoteStatus::Accepted {
+            signatures, ttl, ..
+        } => web_quotes::StatusReply::Accepted {
             signatures,
             expiration_date: ttl,
         },
+        quotes::QuoteStatus::Expired => web_quotes::StatusReply::Expired,
     }
 }
 
@@ -49,6 +61,286 @@ where
 {
     log::debug!("Received mint quote lookup request for id: {}", id);
 
-    let quote = ctrl.lookup(id).await?;
-    Ok(Json(convert_to_enquire_reply(quote)))
+    let quote = ctrl.lookup_at(id, ctrl.clock.now()).await?;
+    Ok(Json(convert_to_status_reply(quote.status)))
+}
+
+/// --------------------------- Look up quote signatures
+pub async fn lookup_signatures<KG, QR>(
+    State(ctrl): State<quotes::Service<KG, QR>>,
+    Path(id): Path<uuid::Uuid>,
+) -> Result<Json<web_quotes::SignaturesReply>>
+where
+    KG: quotes::KeyFactory,
+    QR: quotes::Repository,
+{
+    log::debug!("Received mint quote signatures request for id: {}", id);
+
+    let signatures = ctrl
+        .get_signatures(id)
+        .await?
+        .ok_or(quotes::Error::NotAccepted(id))?;
+    Ok(Json(web_quotes::SignaturesReply { signatures }))
+}
+
+/// --------------------------- Cancel quote
+pub async fn cancel_quote<KG, QR>(
+    State(ctrl): State<quotes::Service<KG, QR>>,
+    Path(id): Path<uuid::Uuid>,
+    Json(req): Json<web_quotes::CancelRequest>,
+) -> Result<()>
+where
+    KG: quotes::KeyFactory,
+    QR: quotes::Repository,
+{
+    log::debug!(
+        "Received mint quote cancel request for id: {}, from node: {}",
+        id,
+        req.node
+    );
+
+    let signature = req.signature.ok_or(quotes::Error::InvalidQuoteSignature)?;
+    ctrl.cancel(id, &req.node, &signature).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credit::quotes::test_utils as quotes_test;
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use axum::routing::{get, post};
+    use axum::Router;
+    use tower::ServiceExt;
+
+    fn router(repo: quotes::MockRepository, gen_repo: quotes::MockRepository) -> Router<(), Body> {
+        let service = quotes_test::service(repo, gen_repo);
+        Router::new()
+            .route("/quote", post(enquire_quote))
+            .route("/quote/:id", get(lookup_quote))
+            .route("/quote/:id/cancel", post(cancel_quote))
+            .route("/quote/:id/signatures", get(lookup_signatures))
+            .with_state(service)
+    }
+
+    #[tokio::test]
+    async fn test_enquire_quote() {
+        let mut gen_repo = quotes::MockRepository::new();
+        gen_repo.expect_search_by_bill().returning(|_, _| Ok(None));
+        gen_repo.expect_store().times(1).returning(|_| Ok(()));
+
+        let (_, signature) = quotes_test::sign_request("billID", "endorserID", &[]);
+        let body = serde_json::json!({
+            "bill": "billID",
+            "node": "endorserID",
+            "outputs": [],
+            "signature": signature,
+        });
+        let request = Request::post("/quote")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router(quotes::MockRepository::new(), gen_repo)
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let reply: web_quotes::EnquireReply = serde_json::from_slice(&body).unwrap();
+        assert!(!reply.id.is_nil());
+    }
+
+    #[tokio::test]
+    async fn test_enquire_quote_unsigned() {
+        let body = serde_json::json!({"bill": "billID", "node": "endorserID", "outputs": []});
+        let request = Request::post("/quote")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router(quotes::MockRepository::new(), quotes::MockRepository::new())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_lookup_quote_pending() {
+        let quote = quotes::Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            chrono::Utc::now(),
+        );
+        let id = quote.id;
+        let mut repo = quotes::MockRepository::new();
+        repo.expect_load()
+            .returning(move |_| Ok(Some(quote.clone())));
+
+        let request = Request::get(format!("/quote/{id}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = router(repo, quotes::MockRepository::new())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let reply: web_quotes::StatusReply = serde_json::from_slice(&body).unwrap();
+        assert!(matches!(reply, web_quotes::StatusReply::Pending));
+    }
+
+    #[tokio::test]
+    async fn test_lookup_signatures_not_accepted() {
+        let quote = quotes::Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            chrono::Utc::now(),
+        );
+        let id = quote.id;
+        let mut repo = quotes::MockRepository::new();
+        repo.expect_load()
+            .returning(move |_| Ok(Some(quote.clone())));
+
+        let request = Request::get(format!("/quote/{id}/signatures"))
+            .body(Body::empty())
+            .unwrap();
+        let response = router(repo, quotes::MockRepository::new())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_lookup_quote_unknown() {
+        let mut repo = quotes::MockRepository::new();
+        repo.expect_load().returning(|_| Ok(None));
+
+        let request = Request::get(format!("/quote/{}", uuid::Uuid::new_v4()))
+            .body(Body::empty())
+            .unwrap();
+        let response = router(repo, quotes::MockRepository::new())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_quote_not_endorser() {
+        let quote = quotes::Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            chrono::Utc::now(),
+        );
+        let id = quote.id;
+        let mut repo = quotes::MockRepository::new();
+        repo.expect_load()
+            .returning(move |_| Ok(Some(quote.clone())));
+        repo.expect_update_if_pending().never();
+
+        let signature = quotes_test::endorser_secret()
+            .sign(&quotes::cancel_message(id))
+            .unwrap();
+        let body = serde_json::json!({"node": "otherID", "signature": signature});
+        let request = Request::post(format!("/quote/{id}/cancel"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router(repo, quotes::MockRepository::new())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_quote_unsigned() {
+        let quote = quotes::Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            chrono::Utc::now(),
+        );
+        let id = quote.id;
+        let mut repo = quotes::MockRepository::new();
+        repo.expect_load()
+            .returning(move |_| Ok(Some(quote.clone())));
+        repo.expect_update_if_pending().never();
+
+        let body = serde_json::json!({"node": "endorserID"});
+        let request = Request::post(format!("/quote/{id}/cancel"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router(repo, quotes::MockRepository::new())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_quote_wrongly_signed() {
+        let quote = quotes::Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            chrono::Utc::now(),
+        );
+        let id = quote.id;
+        let mut repo = quotes::MockRepository::new();
+        repo.expect_load()
+            .returning(move |_| Ok(Some(quote.clone())));
+        repo.expect_update_if_pending().never();
+
+        let signature = cdk::nuts::nut01::SecretKey::generate()
+            .sign(&quotes::cancel_message(id))
+            .unwrap();
+        let body = serde_json::json!({"node": "endorserID", "signature": signature});
+        let request = Request::post(format!("/quote/{id}/cancel"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router(repo, quotes::MockRepository::new())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_quote_signed() {
+        let quote = quotes::Quote::new(
+            String::from("billID"),
+            String::from("endorserID"),
+            vec![],
+            chrono::Utc::now(),
+        );
+        let id = quote.id;
+        let mut repo = quotes::MockRepository::new();
+        repo.expect_load()
+            .returning(move |_| Ok(Some(quote.clone())));
+        repo.expect_update_if_pending()
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let signature = quotes_test::endorser_secret()
+            .sign(&quotes::cancel_message(id))
+            .unwrap();
+        let body = serde_json::json!({"node": "endorserID", "signature": signature});
+        let request = Request::post(format!("/quote/{id}/cancel"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router(repo, quotes::MockRepository::new())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
 }
@@ -0,0 +1,157 @@
+// ----- standard library imports
+// ----- extra library imports
+use anyhow::{anyhow, Result as AnyResult};
+use async_trait::async_trait;
+use bcr_wdc_webapi::quotes as web_quotes;
+// ----- local imports
+use crate::credit::quotes;
+use crate::credit::web::convert_to_status_reply;
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default = "default_max_retries")]
+    pub max_retries: usize,
+    #[serde(default = "default_retry_delay_ms")]
+    pub retry_delay_ms: u64,
+}
+
+fn default_max_retries() -> usize {
+    3
+}
+
+fn default_retry_delay_ms() -> u64 {
+    500
+}
+
+/// POSTs a `StatusChangeNotification` to `url` on every quote status change.
+/// Delivery happens in the background, server errors are retried with a linear backoff
+#[derive(Clone)]
+pub struct HttpWebhookObserver {
+    client: reqwest::Client,
+    cfg: WebhookConfig,
+}
+
+impl HttpWebhookObserver {
+    pub fn new(cfg: WebhookConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            cfg,
+        }
+    }
+
+    async fn deliver(&self, notification: &web_quotes::StatusChangeNotification) -> AnyResult<()> {
+        let mut attempt = 0;
+        loop {
+            let response = self
+                .client
+                .post(&self.cfg.url)
+                .json(notification)
+                .send()
+                .await?;
+            let status = response.status();
+            if !status.is_server_error() {
+                return response.error_for_status().map(|_| ()).map_err(Into::into);
+            }
+            if attempt >= self.cfg.max_retries {
+                return Err(anyhow!(
+                    "webhook gave up after {} attempts: {status}",
+                    attempt + 1
+                ));
+            }
+            attempt += 1;
+            let delay = self.cfg.retry_delay_ms * attempt as u64;
+            tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+        }
+    }
+}
+
+#[async_trait]
+impl quotes::QuoteObserver for HttpWebhookObserver {
+    async fn on_status_change(&self, quote: &quotes::Quote, old: &quotes::QuoteStatus) {
+        let notification = web_quotes::StatusChangeNotification {
+            id: quote.id,
+            bill: quote.bill.clone(),
+            node: quote.endorser.clone(),
+            old: convert_to_status_reply(old.clone()),
+            new: convert_to_status_reply(quote.status.clone()),
+        };
+        // quote handlers should not wait on the integrator
+        let observer = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = observer.deliver(&notification).await {
+                log::error!("webhook for quote {} failed: {e}", notification.id);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // answers `failures` times with 503, then with 200
+    async fn flaky_server(failures: usize) -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let router = axum::Router::new().route(
+            "/hook",
+            axum::routing::post(move |_: axum::Json<web_quotes::StatusChangeNotification>| {
+                let counter = counter.clone();
+                async move {
+                    if counter.fetch_add(1, Ordering::SeqCst) < failures {
+                        StatusCode::SERVICE_UNAVAILABLE
+                    } else {
+                        StatusCode::OK
+                    }
+                }
+            }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service());
+        tokio::spawn(server);
+        (format!("http://{addr}/hook"), hits)
+    }
+
+    fn notification() -> web_quotes::StatusChangeNotification {
+        web_quotes::StatusChangeNotification {
+            id: uuid::Uuid::new_v4(),
+            bill: String::from("billID"),
+            node: String::from("endorserID"),
+            old: web_quotes::StatusReply::Pending,
+            new: web_quotes::StatusReply::Declined,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deliver_retries_on_server_error() {
+        let (url, hits) = flaky_server(2).await;
+        let observer = HttpWebhookObserver::new(WebhookConfig {
+            url,
+            max_retries: 3,
+            retry_delay_ms: 1,
+        });
+
+        observer.deliver(&notification()).await.unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_deliver_gives_up_after_max_retries() {
+        let (url, hits) = flaky_server(usize::MAX).await;
+        let observer = HttpWebhookObserver::new(WebhookConfig {
+            url,
+            max_retries: 2,
+            retry_delay_ms: 1,
+        });
+
+        assert!(observer.deliver(&notification()).await.is_err());
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,314 @@
+// ----- standard library imports
+use std::sync::Arc;
+// ----- extra library imports
+use anyhow::Result as AnyResult;
+use async_trait::async_trait;
+use cdk::nuts::nut00 as cdk00;
+use cdk::nuts::nut02 as cdk02;
+use cdk::Amount;
+use thiserror::Error;
+// ----- local imports
+use crate::keys::{ActiveRepository, KeysetID};
+use crate::stats::DenominationStats;
+use crate::swap;
+
+// ----- error
+pub type Result<T> = std::result::Result<T, Error>;
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Keyset Repository error: {0}")]
+    KeysetRepository(anyhow::Error),
+    #[error("Proof Repository error: {0}")]
+    ProofRepository(anyhow::Error),
+    #[error("Payment token Repository error: {0}")]
+    PaymentTokenRepository(anyhow::Error),
+    #[error("Payment backend error: {0}")]
+    PaymentBackend(anyhow::Error),
+    #[error("DHKE error: {0}")]
+    CdkDhke(#[from] cdk::dhke::Error),
+    #[error("cdk::nut12 error: {0}")]
+    CDKNUT12(#[from] cdk::nuts::nut12::Error),
+
+    #[error("No active debit keyset")]
+    NoActiveKeyset,
+    #[error("Payment of {0} not received")]
+    Unpaid(Amount),
+    #[error("Payment token already redeemed")]
+    TokenAlreadyRedeemed,
+    #[error("Outputs sum to {0}, expected {1}")]
+    AmountMismatch(Amount, Amount),
+    #[error("Unsupported keyset id {0}")]
+    UnsupportedKeysetID(cdk02::Id),
+    #[error("Output for keyset {0}, expected the active keyset {1}")]
+    InactiveKeyset(KeysetID, KeysetID),
+    #[error("Unknown amount {1} for keyset {0}")]
+    UnknownAmountForKeyset(KeysetID, Amount),
+    #[error("Amounts overflow")]
+    AmountOverflow,
+}
+
+// ----- required traits
+/// settlement backend, e.g. on-chain or lightning, vouching for incoming payments
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait PaymentProof: Send + Sync {
+    /// whether `token` proves at least `amount` has been received
+    async fn is_paid(&self, token: &str, amount: Amount) -> AnyResult<bool>;
+}
+
+/// the payment tokens already turned into eCash, each one may mint only once
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait PaymentTokenRepository: Send + Sync {
+    /// marks `token` as redeemed in a single atomic step, false if it already was
+    async fn consume(&self, token: &str) -> AnyResult<bool>;
+}
+
+// ---------- Service
+/// mints debit eCash against BTC payments
+#[derive(Clone)]
+pub struct MintService<ActiveKeys, Payments> {
+    pub keys: ActiveKeys,
+    pub payments: Payments,
+    pub stats: DenominationStats,
+    /// the ledger of every output issued by the mint, swaps included
+    pub ledger: Arc<dyn swap::ProofRepository>,
+    pub tokens: Arc<dyn PaymentTokenRepository>,
+}
+
+impl<ActiveKeys, Payments> MintService<ActiveKeys, Payments>
+where
+    ActiveKeys: ActiveRepository,
+    Payments: PaymentProof,
+{
+    /// signs `outputs` with the active debit keyset once `token` proves `amount` was paid.
+    /// outputs must add up to `amount` exactly
+    pub async fn mint(
+        &self,
+        token: &str,
+        amount: Amount,
+        outputs: &[cdk00::BlindedMessage],
+    ) -> Result<Vec<cdk00::BlindSignature>> {
+        let total = Amount::try_sum(outputs.iter().map(|output| output.amount))
+            .map_err(|_| Error::AmountOverflow)?;
+        if total != amount {
+            return Err(Error::AmountMismatch(total, amount));
+        }
+        let keyset = self
+            .keys
+            .keyset_active()
+            .await
+            .map_err(Error::KeysetRepository)?
+            .ok_or(Error::NoActiveKeyset)?;
+        let active = KeysetID::from(keyset.id);
+        // every output is checked before asking the payment backend
+        for output in outputs {
+            if output.keyset_id != keyset.id {
+                let kid = KeysetID::try_from_id(output.keyset_id)
+                    .map_err(|_| Error::UnsupportedKeysetID(output.keyset_id))?;
+                return Err(Error::InactiveKeyset(kid, active));
+            }
+            if !keyset.keys.contains_key(&output.amount) {
+                return Err(Error::UnknownAmountForKeyset(active, output.amount));
+            }
+        }
+        let paid = self
+            .payments
+            .is_paid(token, amount)
+            .await
+            .map_err(Error::PaymentBackend)?;
+        if !paid {
+            return Err(Error::Unpaid(amount));
+        }
+        // a concurrent mint with the same token loses here, before anything is signed
+        let fresh = self
+            .tokens
+            .consume(token)
+            .await
+            .map_err(Error::PaymentTokenRepository)?;
+        if !fresh {
+            return Err(Error::TokenAlreadyRedeemed);
+        }
+        let signatures = outputs
+            .iter()
+            .map(|output| sign_output(&keyset, output))
+            .collect::<Result<Vec<_>>>()?;
+        self.ledger
+            .record_issued(&swap::issued_outputs(outputs.iter(), &signatures))
+            .await
+            .map_err(Error::ProofRepository)?;
+        self.stats.record(&signatures);
+        Ok(signatures)
+    }
+}
+
+fn sign_output(
+    keys: &cdk02::MintKeySet,
+    output: &cdk00::BlindedMessage,
+) -> Result<cdk00::BlindSignature> {
+    let keypair = keys
+        .keys
+        .get(&output.amount)
+        .ok_or(Error::UnknownAmountForKeyset(
+            KeysetID::from(keys.id),
+            output.amount,
+        ))?;
+    let c = cdk::dhke::sign_message(&keypair.secret_key, &output.blinded_secret)?;
+    let signature = cdk00::BlindSignature::new(
+        output.amount,
+        c,
+        keys.id,
+        &output.blinded_secret,
+        keypair.secret_key.clone(),
+    )?;
+    Ok(signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::test_utils as keys_test;
+    use crate::utils::tests as utils;
+    use mockall::predicate::*;
+
+    fn active_keys(keyset: &cdk02::MintKeySet) -> keys_test::MockRepository {
+        let mut repo = keys_test::MockRepository::new();
+        let keyset = keyset.clone();
+        repo.expect_keyset_active()
+            .returning(move || Ok(Some(keyset.clone())));
+        repo
+    }
+
+    #[tokio::test]
+    async fn test_mint_paid_signs_outputs() {
+        let keyset = keys_test::generate_keyset();
+        let amounts = [Amount::from(8), Amount::from(2)];
+        let outputs: Vec<_> = utils::generate_blinds(&keyset, &amounts)
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+        let mut payments = MockPaymentProof::new();
+        payments
+            .expect_is_paid()
+            .with(eq("paid"), eq(Amount::from(10)))
+            .returning(|_, _| Ok(true));
+        let ledger = crate::persistence::inmemory::ProofMap::default();
+        let service = MintService {
+            keys: active_keys(&keyset),
+            payments,
+            stats: Default::default(),
+            ledger: Arc::new(ledger.clone()),
+            tokens: Arc::new(crate::persistence::inmemory::PaymentTokenSet::default()),
+        };
+
+        let signatures = service
+            .mint("paid", Amount::from(10), &outputs)
+            .await
+            .unwrap();
+        assert_eq!(signatures.len(), 2);
+        assert!(utils::verify_signatures_data(
+            &keyset,
+            outputs.iter().cloned().zip(signatures.iter().cloned())
+        ));
+        for (output, signature) in outputs.iter().zip(&signatures) {
+            assert!(utils::verify_dleq(&keyset, output, signature));
+        }
+        let histogram = service.stats.histogram();
+        assert_eq!(histogram.get(&Amount::from(8)), Some(&1));
+        assert_eq!(histogram.get(&Amount::from(2)), Some(&1));
+        assert_eq!(ledger.total_liabilities().unwrap(), Amount::from(10));
+    }
+
+    #[tokio::test]
+    async fn test_mint_unpaid_rejected() {
+        let keyset = keys_test::generate_keyset();
+        let outputs: Vec<_> = utils::generate_blinds(&keyset, &[Amount::from(8)])
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+        let mut payments = MockPaymentProof::new();
+        payments.expect_is_paid().returning(|_, _| Ok(false));
+        let service = MintService {
+            keys: active_keys(&keyset),
+            payments,
+            stats: Default::default(),
+            ledger: Arc::new(crate::persistence::inmemory::ProofMap::default()),
+            tokens: Arc::new(crate::persistence::inmemory::PaymentTokenSet::default()),
+        };
+
+        let r = service.mint("unpaid", Amount::from(8), &outputs).await;
+        assert!(matches!(r, Err(Error::Unpaid(amount)) if amount == Amount::from(8)));
+    }
+
+    #[tokio::test]
+    async fn test_mint_amount_mismatch_skips_payment_check() {
+        let keyset = keys_test::generate_keyset();
+        let outputs: Vec<_> = utils::generate_blinds(&keyset, &[Amount::from(8)])
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+        let mut payments = MockPaymentProof::new();
+        payments.expect_is_paid().never();
+        let service = MintService {
+            keys: active_keys(&keyset),
+            payments,
+            stats: Default::default(),
+            ledger: Arc::new(crate::persistence::inmemory::ProofMap::default()),
+            tokens: Arc::new(crate::persistence::inmemory::PaymentTokenSet::default()),
+        };
+
+        let r = service.mint("paid", Amount::from(16), &outputs).await;
+        assert!(matches!(r, Err(Error::AmountMismatch(_, _))));
+    }
+
+    #[tokio::test]
+    async fn test_mint_same_token_twice_rejected() {
+        let keyset = keys_test::generate_keyset();
+        let mut payments = MockPaymentProof::new();
+        payments.expect_is_paid().returning(|_, _| Ok(true));
+        let service = MintService {
+            keys: active_keys(&keyset),
+            payments,
+            stats: Default::default(),
+            ledger: Arc::new(crate::persistence::inmemory::ProofMap::default()),
+            tokens: Arc::new(crate::persistence::inmemory::PaymentTokenSet::default()),
+        };
+        let blinds = |amount: u64| -> Vec<cdk00::BlindedMessage> {
+            utils::generate_blinds(&keyset, &[Amount::from(amount)])
+                .into_iter()
+                .map(|b| b.0)
+                .collect()
+        };
+
+        service
+            .mint("paid", Amount::from(8), &blinds(8))
+            .await
+            .unwrap();
+        let r = service.mint("paid", Amount::from(8), &blinds(8)).await;
+        assert!(matches!(r, Err(Error::TokenAlreadyRedeemed)));
+        assert_eq!(service.stats.histogram().get(&Amount::from(8)), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn test_mint_unpaid_keeps_token_unused() {
+        let keyset = keys_test::generate_keyset();
+        let outputs: Vec<_> = utils::generate_blinds(&keyset, &[Amount::from(8)])
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+        let mut payments = MockPaymentProof::new();
+        payments.expect_is_paid().returning(|_, _| Ok(false));
+        let mut tokens = MockPaymentTokenRepository::new();
+        tokens.expect_consume().never();
+        let service = MintService {
+            keys: active_keys(&keyset),
+            payments,
+            stats: Default::default(),
+            ledger: Arc::new(crate::persistence::inmemory::ProofMap::default()),
+            tokens: Arc::new(tokens),
+        };
+
+        let r = service.mint("unpaid", Amount::from(8), &outputs).await;
+        assert!(matches!(r, Err(Error::Unpaid(_))));
+    }
+}
@@ -0,0 +1,7 @@
+// ----- standard library imports
+// ----- extra library imports
+// ----- local modules
+pub mod mint;
+pub mod payments;
+pub mod web;
+// ----- local imports
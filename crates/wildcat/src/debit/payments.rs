@@ -0,0 +1,97 @@
+// ----- standard library imports
+// ----- extra library imports
+use anyhow::Result as AnyResult;
+use async_trait::async_trait;
+use cdk::Amount;
+use reqwest::StatusCode;
+// ----- local imports
+use crate::debit::mint;
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+pub struct PaymentsConfig {
+    pub url: String,
+}
+
+#[derive(serde::Deserialize)]
+struct PaymentReply {
+    amount: Amount,
+    settled: bool,
+}
+
+/// asks the settlement backend about `{url}/v1/payment/{token}`, a token it does not
+/// know proves nothing
+#[derive(Clone)]
+pub struct HttpPaymentBackend {
+    client: reqwest::Client,
+    cfg: PaymentsConfig,
+}
+
+impl HttpPaymentBackend {
+    pub fn new(cfg: PaymentsConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            cfg,
+        }
+    }
+}
+
+#[async_trait]
+impl mint::PaymentProof for HttpPaymentBackend {
+    async fn is_paid(&self, token: &str, amount: Amount) -> AnyResult<bool> {
+        let mut url = reqwest::Url::parse(&self.cfg.url)?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("payments url cannot be a base: {}", self.cfg.url))?
+            .pop_if_empty()
+            .extend(["v1", "payment", token]);
+        let response = self.client.get(url).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        let reply: PaymentReply = response.error_for_status()?.json().await?;
+        Ok(reply.settled && reply.amount >= amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debit::mint::PaymentProof;
+    use axum::extract::Path;
+    use axum::http::StatusCode;
+
+    // "settled" paid 10, "pending" 10 not settled yet, anything else is unknown
+    async fn payments_server() -> String {
+        let router = axum::Router::new().route(
+            "/v1/payment/:token",
+            axum::routing::get(|Path(token): Path<String>| async move {
+                match token.as_str() {
+                    "settled" => Ok(axum::Json(
+                        serde_json::json!({ "amount": 10, "settled": true }),
+                    )),
+                    "pending" => Ok(axum::Json(
+                        serde_json::json!({ "amount": 10, "settled": false }),
+                    )),
+                    _ => Err(StatusCode::NOT_FOUND),
+                }
+            }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service());
+        tokio::spawn(server);
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn test_is_paid() {
+        let url = payments_server().await;
+        let backend = HttpPaymentBackend::new(PaymentsConfig { url });
+
+        assert!(backend.is_paid("settled", Amount::from(10)).await.unwrap());
+        assert!(!backend.is_paid("settled", Amount::from(16)).await.unwrap());
+        assert!(!backend.is_paid("pending", Amount::from(10)).await.unwrap());
+        assert!(!backend.is_paid("unknown", Amount::from(1)).await.unwrap());
+    }
+}
@@ -0,0 +1,142 @@
+// ----- standard library imports
+// ----- extra library imports
+use axum::extract::{Json, State};
+use axum::http::StatusCode;
+use bcr_wdc_webapi::debit as web_debit;
+// ----- local imports
+use crate::debit::mint;
+use crate::keys::ActiveRepository;
+
+impl mint::Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            mint::Error::Unpaid(_) => StatusCode::PAYMENT_REQUIRED,
+            mint::Error::TokenAlreadyRedeemed => StatusCode::CONFLICT,
+            mint::Error::AmountMismatch(..)
+            | mint::Error::UnsupportedKeysetID(_)
+            | mint::Error::InactiveKeyset(..)
+            | mint::Error::UnknownAmountForKeyset(..)
+            | mint::Error::AmountOverflow => StatusCode::BAD_REQUEST,
+            mint::Error::PaymentBackend(_) => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl axum::response::IntoResponse for mint::Error {
+    fn into_response(self) -> axum::response::Response {
+        (self.status_code(), self.to_string()).into_response()
+    }
+}
+
+pub async fn mint_tokens<AK, PP>(
+    State(ctrl): State<mint::MintService<AK, PP>>,
+    Json(req): Json<web_debit::MintRequest>,
+) -> mint::Result<Json<web_debit::MintReply>>
+where
+    AK: ActiveRepository,
+    PP: mint::PaymentProof,
+{
+    log::debug!(
+        "Received mint request of {} on {} outputs",
+        req.amount,
+        req.outputs.len()
+    );
+
+    let signatures = ctrl.mint(&req.token, req.amount, &req.outputs).await?;
+    Ok(Json(web_debit::MintReply { signatures }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::test_utils as keys_test;
+    use crate::persistence::inmemory;
+    use crate::utils::tests as utils;
+    use axum::body::Body;
+    use axum::http::{header, Request};
+    use axum::routing::post;
+    use axum::Router;
+    use cdk::Amount;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    #[derive(Clone)]
+    struct Paid(bool);
+
+    #[async_trait::async_trait]
+    impl mint::PaymentProof for Paid {
+        async fn is_paid(&self, _token: &str, _amount: Amount) -> anyhow::Result<bool> {
+            Ok(self.0)
+        }
+    }
+
+    async fn router(paid: bool) -> (Router<(), Body>, cdk::nuts::nut02::MintKeySet) {
+        let keyset = keys_test::generate_keyset();
+        let info = cdk::mint::MintKeySetInfo {
+            id: keyset.id,
+            unit: keyset.unit.clone(),
+            active: true,
+            valid_from: 0,
+            valid_to: None,
+            derivation_path: Default::default(),
+            derivation_path_index: None,
+            max_order: keyset.keys.len() as u8,
+            input_fee_ppk: 0,
+        };
+        let keys = inmemory::KeysetIDEntryMapWithActive::default();
+        crate::keys::Repository::store(&keys, keyset.clone(), info)
+            .await
+            .unwrap();
+        let service = mint::MintService {
+            keys,
+            payments: Paid(paid),
+            stats: Default::default(),
+            ledger: Arc::new(inmemory::ProofMap::default()),
+            tokens: Arc::new(inmemory::PaymentTokenSet::default()),
+        };
+        let router = Router::new()
+            .route("/mint", post(mint_tokens))
+            .with_state(service);
+        (router, keyset)
+    }
+
+    fn mint_request(keyset: &cdk::nuts::nut02::MintKeySet) -> Request<Body> {
+        let outputs: Vec<_> = utils::generate_blinds(keyset, &[Amount::from(8)])
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+        let body = web_debit::MintRequest {
+            token: String::from("token"),
+            amount: Amount::from(8),
+            outputs,
+        };
+        Request::post("/mint")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(&body).unwrap()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mint_tokens() {
+        let (router, keyset) = router(true).await;
+
+        let response = router.clone().oneshot(mint_request(&keyset)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let reply: web_debit::MintReply = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply.signatures.len(), 1);
+
+        // the same token again
+        let response = router.oneshot(mint_request(&keyset)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_mint_tokens_unpaid() {
+        let (router, keyset) = router(false).await;
+
+        let response = router.oneshot(mint_request(&keyset)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+    }
+}
@@ -0,0 +1,25 @@
+// ----- standard library imports
+use std::collections::BTreeMap;
+// ----- extra library imports
+use async_trait::async_trait;
+// ----- local imports
+
+/// reachability of a backend, e.g. for a k8s readiness probe
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait HealthCheck: Send + Sync {
+    async fn ready(&self) -> bool;
+}
+
+/// readiness of each dependency of a service, by name
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct HealthReport {
+    pub dependencies: BTreeMap<String, bool>,
+}
+
+impl HealthReport {
+    /// ready once every dependency is
+    pub fn ready(&self) -> bool {
+        self.dependencies.values().all(|ready| *ready)
+    }
+}
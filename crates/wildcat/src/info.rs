@@ -0,0 +1,233 @@
+#![allow(dead_code)]
+// ----- standard library imports
+// ----- extra library imports
+use anyhow::Result as AnyResult;
+use bcr_wdc_keys as keys;
+use cdk::nuts::nut00 as cdk00;
+use cdk::nuts::nut02 as cdk02;
+use cdk::nuts::nut04 as cdk04;
+use cdk::nuts::nut05 as cdk05;
+use cdk::nuts::nut06 as cdk06;
+// ----- local modules
+// ----- local imports
+
+/// NUT-06 mint info, along with the units the mint deals in and its active keysets
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MintInfo {
+    #[serde(flatten)]
+    pub info: cdk06::MintInfo,
+    pub units: Vec<cdk00::CurrencyUnit>,
+    pub keysets: Vec<cdk02::Id>,
+}
+
+pub struct MintInfoBuilder {
+    info: cdk06::MintInfo,
+    units: Vec<cdk00::CurrencyUnit>,
+}
+
+impl MintInfoBuilder {
+    pub const CREDIT_UNIT: &'static str = "crsat";
+
+    // NUT-01, NUT-02 and NUT-03 (swap) are mandatory, hence not listed.
+    // minting and melting go through the credit quotes, not NUT-04/NUT-05
+    fn supported_nuts() -> cdk06::Nuts {
+        cdk06::Nuts::new()
+            .nut04(cdk04::Settings {
+                methods: Vec::new(),
+                disabled: true,
+            })
+            .nut05(cdk05::Settings {
+                methods: Vec::new(),
+                disabled: true,
+            })
+            .nut07(true)
+            .nut12(true)
+    }
+
+    pub fn new(name: impl Into<String>) -> Self {
+        let version = cdk06::MintVersion::new(
+            String::from(env!("CARGO_PKG_NAME")),
+            String::from(env!("CARGO_PKG_VERSION")),
+        );
+        let info = cdk06::MintInfo::new()
+            .name(name)
+            .version(version)
+            .nuts(Self::supported_nuts());
+        Self {
+            info,
+            units: Vec::new(),
+        }
+    }
+
+    /// as `new`, advertising the credit unit
+    pub fn credit(name: impl Into<String>) -> Self {
+        Self::new(name).unit(cdk00::CurrencyUnit::Custom(String::from(Self::CREDIT_UNIT)))
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.info = self.info.description(description);
+        self
+    }
+
+    pub fn contact(mut self, method: impl Into<String>, info: impl Into<String>) -> Self {
+        let mut contacts = self.info.contact.take().unwrap_or_default();
+        contacts.push(cdk06::ContactInfo::new(method.into(), info.into()));
+        self.info = self.info.contact_info(contacts);
+        self
+    }
+
+    pub fn unit(mut self, unit: cdk00::CurrencyUnit) -> Self {
+        if !self.units.contains(&unit) {
+            self.units.push(unit);
+        }
+        self
+    }
+
+    /// collects the keysets flagged active in `keys`
+    pub async fn build<Keys>(self, keys: &Keys) -> AnyResult<MintInfo>
+    where
+        Keys: keys::ActiveRepository,
+    {
+        let mut keysets: Vec<cdk02::Id> = keys
+            .list_info()
+            .await?
+            .into_iter()
+            .filter(|info| info.active)
+            .map(|info| info.id)
+            .collect();
+        keysets.sort();
+        Ok(MintInfo {
+            info: self.info,
+            units: self.units,
+            keysets,
+        })
+    }
+}
+
+/// NUT-02 keyset info, along with the operator label and description if any
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct KeysetListing {
+    #[serde(flatten)]
+    pub info: cdk02::KeySetInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// NUT-02 listing of every stored keyset, or only the enabled ones, sorted by id
+pub async fn list_keysets<Keys>(keys: &Keys, only_enabled: bool) -> AnyResult<Vec<KeysetListing>>
+where
+    Keys: keys::Repository,
+{
+    let mut listing = Vec::new();
+    for info in keys.list_info_filtered(only_enabled).await? {
+        let metadata = keys
+            .metadata(&keys::KeysetID::from(info.id))
+            .await?
+            .unwrap_or_default();
+        listing.push(KeysetListing {
+            info: cdk02::KeySetInfo::from(info),
+            label: metadata.label,
+            description: metadata.description,
+        });
+    }
+    listing.sort_by_key(|entry| entry.info.id);
+    Ok(listing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::inmemory;
+    use keys::Repository;
+    use std::str::FromStr;
+
+    fn generate_entry(path: &str, active: bool, valid_from: u64) -> keys::KeysetEntry {
+        let path = bitcoin::bip32::DerivationPath::from_str(path).unwrap();
+        let keyset = cdk02::MintKeySet::generate_from_seed(
+            &bitcoin::secp256k1::Secp256k1::new(),
+            &[],
+            4,
+            cdk00::CurrencyUnit::Sat,
+            path.clone(),
+        );
+        let info = cdk::mint::MintKeySetInfo {
+            id: keyset.id,
+            unit: keyset.unit.clone(),
+            active,
+            valid_from,
+            valid_to: None,
+            derivation_path: path,
+            derivation_path_index: None,
+            max_order: 4,
+            input_fee_ppk: 0,
+        };
+        (info, keyset)
+    }
+
+    #[tokio::test]
+    async fn test_build_credit_info() {
+        let repo = inmemory::KeysetIDEntryMapWithActive::default();
+        let (active, keyset) = generate_entry("m/0'/0'", true, 1);
+        repo.store(keyset, active.clone()).await.unwrap();
+        let (inactive, keyset) = generate_entry("m/0'/1'", false, 0);
+        repo.store(keyset, inactive).await.unwrap();
+
+        let info = MintInfoBuilder::credit("wildcat")
+            .description("credit mint")
+            .contact("email", "mint@example.com")
+            .build(&repo)
+            .await
+            .unwrap();
+        assert_eq!(info.keysets, vec![active.id]);
+        assert_eq!(
+            info.units,
+            vec![cdk00::CurrencyUnit::Custom(String::from("crsat"))]
+        );
+        assert_eq!(info.info.name.as_deref(), Some("wildcat"));
+        assert_eq!(info.info.contact.as_ref().map(Vec::len), Some(1));
+        assert!(info.info.nuts.nut04.disabled);
+        assert!(info.info.nuts.nut05.disabled);
+
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["keysets"][0], serde_json::json!(active.id.to_string()));
+        assert_eq!(json["nuts"]["7"]["supported"], serde_json::json!(true));
+        assert_eq!(json["nuts"]["12"]["supported"], serde_json::json!(true));
+    }
+
+    #[tokio::test]
+    async fn test_list_keysets_with_label() {
+        let repo = inmemory::KeysetIDEntryMapWithActive::default();
+        let (labelled, keyset) = generate_entry("m/0'/0'", true, 1);
+        repo.store(keyset, labelled.clone()).await.unwrap();
+        let (plain, keyset) = generate_entry("m/0'/1'", false, 0);
+        repo.store(keyset, plain.clone()).await.unwrap();
+        repo.set_label(
+            &keys::KeysetID::from(labelled.id),
+            Some(String::from("main")),
+        )
+        .await
+        .unwrap();
+
+        let listing = list_keysets(&repo, false).await.unwrap();
+        assert_eq!(listing.len(), 2);
+        let json = serde_json::to_value(&listing).unwrap();
+        let labelled_json = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|entry| entry["id"] == serde_json::json!(labelled.id.to_string()))
+            .unwrap();
+        assert_eq!(labelled_json["label"], serde_json::json!("main"));
+        assert_eq!(labelled_json["active"], serde_json::json!(true));
+        assert!(labelled_json.get("description").is_none());
+        let plain_json = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|entry| entry["id"] == serde_json::json!(plain.id.to_string()))
+            .unwrap();
+        assert!(plain_json.get("label").is_none());
+    }
+}
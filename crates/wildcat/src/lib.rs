@@ -6,10 +6,18 @@ use axum::Router;
 use bcr_wdc_keys as keys;
 // ----- local modules
 //mod credit;
+mod clock;
 mod credit;
+mod debit;
+mod health;
+mod info;
+mod nostr;
 mod persistence;
+mod redemption;
+mod stats;
 mod swap;
 mod utils;
+mod ws;
 // ----- local imports
 
 type TStamp = chrono::DateTime<chrono::Utc>;
@@ -27,21 +35,68 @@ pub type ProdQuotingService = credit::quotes::Service<ProdCreditKeysFactory, Pro
 pub type ProdCreditKeysRepository =
     crate::credit::keys::SwapRepository<ProdKeysRepository, ProdActiveKeysRepository>;
 pub type ProdSwapService = swap::Service<ProdCreditKeysRepository, ProdProofRepository>;
+pub type ProdRedemptionService = redemption::RedemptionService<
+    ProdKeysRepository,
+    ProdActiveKeysRepository,
+    ProdProofRepository,
+>;
+pub type ProdMintService =
+    debit::mint::MintService<ProdActiveKeysRepository, debit::payments::HttpPaymentBackend>;
+pub type ProdEndorsements = credit::keys::Endorsements<ProdQuoteKeysRepository, ProdKeysRepository>;
 
 #[derive(Clone, Debug, Default, serde::Deserialize)]
 pub struct AppConfig {
     dbs: persistence::surreal::DBConfig,
+    webhook: Option<credit::webhook::WebhookConfig>,
+    // where the endorsers' keys checking quote request signatures are looked up
+    ebill: credit::ebill::EbillConfig,
+    // the settlement backend vouching for the BTC payments debit eCash is minted against
+    payments: debit::payments::PaymentsConfig,
+    // largest denomination of the generated keysets is 2^(max_order - 1)
+    keys_max_order: Option<u8>,
+    // NUT-02 fee, per thousand inputs, of the generated keysets. 0 if unset
+    keys_input_fee_ppk: Option<u64>,
+    // rotation indexes of a maturity date stepped over before giving up, by the keys
+    // factory and the swap lookups alike. 1000 if unset
+    keys_max_rotation_walk: Option<u32>,
+    // seconds between two scans for accepted quotes past their ttl
+    quote_expiry_interval_secs: Option<u64>,
+    fees: Option<credit::fees::FeePolicy>,
+    // network the master xpriv is tagged with, bitcoin if unset
+    network: Option<bitcoin::Network>,
+    // new quotes an endorser may request per window, unlimited if unset
+    quote_rate_limit: Option<credit::quotes::RateLimit>,
+    // inputs and outputs a single swap may carry, 1000 each if unset
+    swap_limits: Option<swap::SwapLimits>,
 }
 
 #[derive(Clone, FromRef)]
 pub struct AppController {
     quote: ProdQuotingService,
     swap: ProdSwapService,
+    redemption: ProdRedemptionService,
+    endorsements: ProdEndorsements,
+    mint: ProdMintService,
 }
 
 impl AppController {
+    const DEFAULT_QUOTE_EXPIRY_INTERVAL_SECS: u64 = 300;
+
     pub async fn new(mint_seed: &[u8], cfg: AppConfig) -> Self {
-        let AppConfig { dbs, .. } = cfg;
+        let AppConfig {
+            dbs,
+            webhook,
+            ebill,
+            payments,
+            keys_max_order,
+            keys_input_fee_ppk,
+            keys_max_rotation_walk,
+            quote_expiry_interval_secs,
+            fees,
+            network,
+            quote_rate_limit,
+            swap_limits,
+        } = cfg;
         let persistence::surreal::DBConfig {
             quotes,
             quotes_keys,
@@ -70,40 +125,110 @@ impl AppController {
             .await
             .expect("DB connection to proofs failed");
 
+        let max_rotation_walk = keys_max_rotation_walk.unwrap_or(keys::MAX_ROTATION_WALK);
+        // a single time source for the credit mint, the swaps and the expiry worker
+        let clock: std::sync::Arc<dyn clock::Clock> = std::sync::Arc::new(clock::SystemClock);
         let keys_factory = ProdCreditKeysFactory::new(
             mint_seed,
-            quote_keys_repository,
+            network.unwrap_or(bitcoin::Network::Bitcoin),
+            cdk::nuts::CurrencyUnit::Custom(String::from(ProdCreditKeysFactory::CURRENCY_UNIT)),
+            quote_keys_repository.clone(),
             maturity_keys_repository.clone(),
+        )
+        .with_max_order(keys_max_order.unwrap_or(ProdCreditKeysFactory::DEFAULT_MAX_ORDER))
+        .with_input_fee_ppk(keys_input_fee_ppk.unwrap_or_default())
+        .with_max_rotation_walk(max_rotation_walk)
+        .with_clock(clock.clone());
+        log::info!("credit keys derived for network {}", keys_factory.network());
+        let expiry_interval = std::time::Duration::from_secs(
+            quote_expiry_interval_secs.unwrap_or(Self::DEFAULT_QUOTE_EXPIRY_INTERVAL_SECS),
         );
+        let expiry_worker =
+            credit::expiry::QuoteExpiryWorker::new(quotes_repository.clone(), expiry_interval)
+                .with_clock(clock.clone());
+        tokio::spawn(expiry_worker.run());
         let quotes_factory = ProdQuoteFactory {
             quotes: quotes_repository.clone(),
         };
-        let quoting_service = ProdQuotingService {
+        // a single histogram over the credit mint, the debit mint and the swaps
+        let stats = stats::DenominationStats::default();
+        let mut quoting_service = ProdQuotingService {
             keys_gen: keys_factory,
-            quotes_gen: quotes_factory,
+            quotes_gen: credit::quotes::RateLimitedQuoteFactory::new(
+                quotes_factory,
+                quote_rate_limit.unwrap_or(credit::quotes::RateLimit::UNLIMITED),
+            ),
             quotes: quotes_repository,
+            observers: Vec::new(),
+            issued: std::sync::Arc::new(persistence::inmemory::IssuedSignaturesMap::default()),
+            ledger: std::sync::Arc::new(proofs_repo.clone()),
+            fees: fees.unwrap_or_default(),
+            ttl: credit::quotes::TtlPolicy::default(),
+            stats: stats.clone(),
+            clock: clock.clone(),
+            endorser_keys: std::sync::Arc::new(credit::ebill::HttpEbillClient::new(ebill)),
         };
+        if let Some(webhook) = webhook {
+            let observer = credit::webhook::HttpWebhookObserver::new(webhook);
+            quoting_service
+                .observers
+                .push(std::sync::Arc::new(observer));
+        }
 
+        let endorsements = ProdEndorsements {
+            quote_keys: quote_keys_repository,
+            endorsed_keys: endorsed_keys_repository.clone(),
+        };
+        let mint = ProdMintService {
+            keys: debit_keys_repository.clone(),
+            payments: debit::payments::HttpPaymentBackend::new(payments),
+            stats: stats.clone(),
+            ledger: std::sync::Arc::new(proofs_repo.clone()),
+            tokens: std::sync::Arc::new(proofs_repo.clone()),
+        };
         let credit_keys_for_swaps = ProdCreditKeysRepository {
             debit_keys: debit_keys_repository,
             endorsed_keys: endorsed_keys_repository,
             maturity_keys: maturity_keys_repository,
+            max_rotation_walk,
         };
         let swaps = ProdSwapService {
             keys: credit_keys_for_swaps,
             proofs: proofs_repo,
+            stats,
+            limits: swap_limits.unwrap_or_default(),
+            clock,
+        };
+        // redemption spends through the swap checks and ledger
+        let redemption = ProdRedemptionService {
+            swap: swaps.clone(),
         };
         Self {
             quote: quoting_service,
             swap: swaps,
+            redemption,
+            endorsements,
+            mint,
         }
     }
 }
 pub fn credit_routes(ctrl: AppController) -> Router {
     Router::new()
         .route("/v1/swap", post(swap::web::swap_tokens))
+        .route("/v1/restore", post(swap::web::restore))
+        .route("/v1/redeem", post(redemption::redeem_tokens))
+        .route("/debit/v1/mint", post(debit::web::mint_tokens))
+        .route("/health/ready", get(swap::web::ready))
         .route("/credit/v1/mint/quote", post(credit::web::enquire_quote))
         .route("/credit/v1/mint/quote/:id", get(credit::web::lookup_quote))
+        .route(
+            "/credit/v1/mint/quote/:id/signatures",
+            get(credit::web::lookup_signatures),
+        )
+        .route(
+            "/credit/v1/mint/quote/:id/cancel",
+            post(credit::web::cancel_quote),
+        )
         .route(
             "/admin/credit/v1/quote/pending",
             get(credit::admin::list_pending_quotes),
@@ -120,5 +245,13 @@ pub fn credit_routes(ctrl: AppController) -> Router {
             "/admin/credit/v1/quote/:id",
             post(credit::admin::resolve_quote),
         )
+        .route(
+            "/admin/credit/v1/quote/:id/decline",
+            post(credit::admin::decline_quote),
+        )
+        .route(
+            "/admin/credit/v1/quote/:id/endorse",
+            post(credit::admin::endorse_quote),
+        )
         .with_state(ctrl)
 }
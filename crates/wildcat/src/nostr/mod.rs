@@ -0,0 +1,258 @@
+#![allow(dead_code)]
+// ----- standard library imports
+use std::future::Future;
+use std::str::FromStr;
+// ----- extra library imports
+use anyhow::Result as AnyResult;
+use bitcoin::hashes::sha256::Hash as Sha256;
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1;
+use serde::Deserialize;
+use thiserror::Error;
+// ----- local modules
+// ----- local imports
+
+pub type Result<T> = std::result::Result<T, Error>;
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("json error {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("secp256k1 error {0}")]
+    Secp256k1(#[from] secp256k1::Error),
+    #[error("event id {0} does not match its content")]
+    EventId(String),
+    #[error("unexpected event kind {0}")]
+    Kind(u16),
+    #[cfg(feature = "nostr")]
+    #[error("relay error {0}")]
+    Relay(#[from] Box<tokio_tungstenite::tungstenite::Error>),
+}
+
+#[cfg(feature = "nostr")]
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        Self::Relay(Box::new(e))
+    }
+}
+
+/// event kind the eBill service publishes when a bill is endorsed to the mint
+pub const ENDORSEMENT_KIND: u16 = 7_373;
+const SUBSCRIPTION_ID: &str = "wildcat-endorsements";
+
+/// NIP-01 event
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Event {
+    pub id: String,
+    pub pubkey: String,
+    pub created_at: u64,
+    pub kind: u16,
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+    pub sig: String,
+}
+
+impl Event {
+    /// NIP-01 id: sha256 of the serialized [0, pubkey, created_at, kind, tags, content]
+    pub fn compute_id(&self) -> Result<String> {
+        let serialized = serde_json::to_string(&(
+            0,
+            &self.pubkey,
+            self.created_at,
+            self.kind,
+            &self.tags,
+            &self.content,
+        ))?;
+        Ok(Sha256::hash(serialized.as_bytes()).to_string())
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        if self.compute_id()? != self.id {
+            return Err(Error::EventId(self.id.clone()));
+        }
+        let pubkey = secp256k1::XOnlyPublicKey::from_str(&self.pubkey)?;
+        let sig = secp256k1::schnorr::Signature::from_str(&self.sig)?;
+        let digest = Sha256::from_str(&self.id).map_err(|_| Error::EventId(self.id.clone()))?;
+        let msg = secp256k1::Message::from_digest(digest.to_byte_array());
+        secp256k1::Secp256k1::verification_only().verify_schnorr(&sig, &msg, &pubkey)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct EndorsementEvent {
+    pub bill: String,
+    pub endorser: String,
+}
+
+impl TryFrom<&Event> for EndorsementEvent {
+    type Error = Error;
+    fn try_from(event: &Event) -> Result<Self> {
+        if event.kind != ENDORSEMENT_KIND {
+            return Err(Error::Kind(event.kind));
+        }
+        event.verify()?;
+        Ok(serde_json::from_str(&event.content)?)
+    }
+}
+
+/// parses a relay message, only `["EVENT", <subscription>, <event>]` carries an endorsement
+pub fn parse_relay_message(message: &str) -> Result<Option<EndorsementEvent>> {
+    let value: serde_json::Value = serde_json::from_str(message)?;
+    let Some([tag, _, event]) = value.as_array().map(Vec::as_slice) else {
+        return Ok(None);
+    };
+    if tag.as_str() != Some("EVENT") {
+        return Ok(None);
+    }
+    let event = Event::deserialize(event)?;
+    EndorsementEvent::try_from(&event).map(Some)
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct ListenerConfig {
+    pub relay: String,
+    /// hex x-only public key of the mint, endorsements are expected to `p`-tag it
+    pub mint_pubkey: String,
+}
+
+pub struct NostrEndorsementListener {
+    cfg: ListenerConfig,
+}
+
+impl NostrEndorsementListener {
+    pub fn new(cfg: ListenerConfig) -> Self {
+        Self { cfg }
+    }
+
+    pub fn subscription(&self) -> String {
+        serde_json::json!([
+            "REQ",
+            SUBSCRIPTION_ID,
+            {"kinds": [ENDORSEMENT_KIND], "#p": [self.cfg.mint_pubkey]}
+        ])
+        .to_string()
+    }
+
+    /// hands every endorsement found in `message` to `on_endorsement`,
+    /// malformed messages are skipped with a warning
+    pub async fn handle_message<F, Fut>(message: &str, on_endorsement: &F)
+    where
+        F: Fn(EndorsementEvent) -> Fut,
+        Fut: Future<Output = AnyResult<()>>,
+    {
+        let endorsement = match parse_relay_message(message) {
+            Ok(Some(endorsement)) => endorsement,
+            Ok(None) => return,
+            Err(e) => {
+                log::warn!("skipping malformed nostr message: {e}");
+                return;
+            }
+        };
+        let bill = endorsement.bill.clone();
+        if let Err(e) = on_endorsement(endorsement).await {
+            log::warn!("endorsement of bill {bill} not processed: {e}");
+        }
+    }
+
+    /// subscribes to the relay and processes endorsements until the connection drops
+    #[cfg(feature = "nostr")]
+    pub async fn listen<F, Fut>(&self, on_endorsement: F) -> Result<()>
+    where
+        F: Fn(EndorsementEvent) -> Fut,
+        Fut: Future<Output = AnyResult<()>>,
+    {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::Message;
+
+        let (mut stream, _) = tokio_tungstenite::connect_async(&self.cfg.relay).await?;
+        stream.send(Message::Text(self.subscription())).await?;
+        while let Some(message) = stream.next().await {
+            match message? {
+                Message::Text(text) => Self::handle_message(&text, &on_endorsement).await,
+                Message::Ping(payload) => stream.send(Message::Pong(payload)).await?,
+                Message::Close(_) => break,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn signed_event(kind: u16, content: &str) -> Event {
+        let ctx = secp256k1::Secp256k1::new();
+        let keypair = secp256k1::Keypair::from_seckey_slice(&ctx, &[0x11; 32]).unwrap();
+        let (pubkey, _) = keypair.x_only_public_key();
+        let mut event = Event {
+            id: String::new(),
+            pubkey: pubkey.to_string(),
+            created_at: 1_700_000_000,
+            kind,
+            tags: vec![vec![String::from("p"), String::from("mint")]],
+            content: String::from(content),
+            sig: String::new(),
+        };
+        event.id = event.compute_id().unwrap();
+        let digest = Sha256::from_str(&event.id).unwrap();
+        let msg = secp256k1::Message::from_digest(digest.to_byte_array());
+        event.sig = ctx.sign_schnorr_no_aux_rand(&msg, &keypair).to_string();
+        event
+    }
+
+    fn relay_message(event: &Event) -> String {
+        serde_json::json!(["EVENT", SUBSCRIPTION_ID, event]).to_string()
+    }
+
+    fn recording_callback(
+        bills: Arc<Mutex<Vec<String>>>,
+    ) -> impl Fn(EndorsementEvent) -> std::future::Ready<AnyResult<()>> {
+        move |endorsement| {
+            bills.lock().unwrap().push(endorsement.bill);
+            std::future::ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_fires_callback() {
+        let event = signed_event(
+            ENDORSEMENT_KIND,
+            r#"{"bill":"bill_id","endorser":"endorser_id"}"#,
+        );
+        let bills: Arc<Mutex<Vec<String>>> = Default::default();
+        let callback = recording_callback(bills.clone());
+
+        NostrEndorsementListener::handle_message(&relay_message(&event), &callback).await;
+        assert_eq!(*bills.lock().unwrap(), vec![String::from("bill_id")]);
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_skips_malformed() {
+        let bills: Arc<Mutex<Vec<String>>> = Default::default();
+        let callback = recording_callback(bills.clone());
+
+        let no_bill = signed_event(ENDORSEMENT_KIND, r#"{"endorser":"endorser_id"}"#);
+        NostrEndorsementListener::handle_message(&relay_message(&no_bill), &callback).await;
+        let wrong_kind = signed_event(1, r#"{"bill":"bill_id","endorser":"endorser_id"}"#);
+        NostrEndorsementListener::handle_message(&relay_message(&wrong_kind), &callback).await;
+        let mut tampered = signed_event(
+            ENDORSEMENT_KIND,
+            r#"{"bill":"bill_id","endorser":"endorser_id"}"#,
+        );
+        tampered.content = String::from(r#"{"bill":"other_bill","endorser":"endorser_id"}"#);
+        NostrEndorsementListener::handle_message(&relay_message(&tampered), &callback).await;
+        NostrEndorsementListener::handle_message("not json", &callback).await;
+        NostrEndorsementListener::handle_message(r#"["EOSE","sub"]"#, &callback).await;
+
+        assert!(bills.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_relay_message_ignores_other_messages() {
+        let parsed = parse_relay_message(r#"["NOTICE","sub","hello"]"#).unwrap();
+        assert!(parsed.is_none());
+    }
+}
@@ -1,20 +1,27 @@
+#![allow(dead_code)]
 // ----- standard library imports
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, RwLock};
 // ----- extra library imports
-use anyhow::Result as AnyResult;
+use anyhow::{anyhow, Result as AnyResult};
 use async_trait::async_trait;
+use bitcoin::hashes::sha256::Hash as Sha256;
+use bitcoin::hashes::{Hash, HashEngine};
 use cdk::nuts::nut00 as cdk00;
 use cdk::nuts::nut01 as cdk01;
 use cdk::nuts::nut02 as cdk02;
 use cdk::nuts::nut07 as cdk07;
+use tokio::sync::broadcast;
 use uuid::Uuid;
 // ----- local modules
 // ----- local imports
 use crate::credit::{keys as creditkeys, quotes};
+use crate::debit;
+use crate::health::HealthCheck;
 use crate::keys;
 use crate::keys::{KeysetEntry, KeysetID, Repository};
 use crate::swap;
+use crate::ws;
 use crate::TStamp;
 
 #[derive(Default, Clone)]
@@ -33,6 +40,19 @@ impl quotes::Repository for QuotesIDMap {
             .map(|(_, q)| q.clone()))
     }
 
+    async fn search_by_endorser(&self, endorser: &str) -> AnyResult<Vec<quotes::Quote>> {
+        let mut found: Vec<quotes::Quote> = self
+            .quotes
+            .read()
+            .unwrap()
+            .values()
+            .filter(|quote| quote.endorser == endorser)
+            .cloned()
+            .collect();
+        found.sort_by(|a, b| b.submitted.cmp(&a.submitted).then(a.id.cmp(&b.id)));
+        Ok(found)
+    }
+
     async fn store(&self, quote: quotes::Quote) -> AnyResult<()> {
         self.quotes.write().unwrap().insert(quote.id, quote);
         Ok(())
@@ -41,6 +61,14 @@ impl quotes::Repository for QuotesIDMap {
         Ok(self.quotes.read().unwrap().get(&id).cloned())
     }
 
+    async fn load_many(&self, ids: &[Uuid]) -> AnyResult<Vec<quotes::Quote>> {
+        let reader = self.quotes.read().unwrap();
+        Ok(ids
+            .iter()
+            .filter_map(|id| reader.get(id).cloned())
+            .collect())
+    }
+
     async fn update_if_pending(&self, new: quotes::Quote) -> AnyResult<()> {
         let id = new.id;
         let mut m = self.quotes.write().unwrap();
@@ -55,28 +83,138 @@ impl quotes::Repository for QuotesIDMap {
         Ok(())
     }
 
-    async fn list_pendings(&self, since: Option<TStamp>) -> AnyResult<Vec<Uuid>> {
-        let a = self
-            .quotes
-            .read()
-            .unwrap()
-            .iter()
-            .filter(|(_, q)| matches!(q.status, quotes::QuoteStatus::Pending { .. }))
-            .filter(|(_, q)| q.submitted >= since.unwrap_or_default())
-            .map(|(id, _)| *id)
+    async fn update_if_offered(&self, new: quotes::Quote) -> AnyResult<()> {
+        let mut m = self.quotes.write().unwrap();
+        if let Some(old) = m.get_mut(&new.id) {
+            if matches!(old.status, quotes::QuoteStatus::Offered { .. }) {
+                *old = new;
+            }
+        }
+        Ok(())
+    }
+
+    async fn update_if_accepted(&self, new: quotes::Quote) -> AnyResult<()> {
+        if !matches!(new.status, quotes::QuoteStatus::Expired) {
+            return Err(anyhow!("accepted quotes can only expire"));
+        }
+        let mut m = self.quotes.write().unwrap();
+        if let Some(old) = m.get_mut(&new.id) {
+            if matches!(old.status, quotes::QuoteStatus::Accepted { .. }) {
+                *old = new;
+            }
+        }
+        Ok(())
+    }
+
+    async fn list_pendings(
+        &self,
+        since: Option<TStamp>,
+        page: quotes::Page,
+    ) -> AnyResult<Vec<Uuid>> {
+        Ok(self.list_by(
+            |status| matches!(status, quotes::QuoteStatus::Pending { .. }),
+            since,
+            page,
+        ))
+    }
+    async fn list_stale_pendings(&self, older_than: TStamp) -> AnyResult<Vec<Uuid>> {
+        let reader = self.quotes.read().unwrap();
+        let mut stale: Vec<&quotes::Quote> = reader
+            .values()
+            .filter(|quote| matches!(quote.status, quotes::QuoteStatus::Pending { .. }))
+            .filter(|quote| quote.submitted < older_than)
             .collect();
-        Ok(a)
+        stale.sort_by(|a, b| a.submitted.cmp(&b.submitted).then(a.id.cmp(&b.id)));
+        Ok(stale.into_iter().map(|quote| quote.id).collect())
     }
-    async fn list_accepteds(&self, _since: Option<TStamp>) -> AnyResult<Vec<Uuid>> {
-        let a = self
-            .quotes
-            .read()
-            .unwrap()
-            .iter()
-            .filter(|(_, q)| matches!(q.status, quotes::QuoteStatus::Accepted { .. }))
-            .map(|(id, _)| *id)
+    async fn list_offered(
+        &self,
+        since: Option<TStamp>,
+        page: quotes::Page,
+    ) -> AnyResult<Vec<Uuid>> {
+        Ok(self.list_by(
+            |status| matches!(status, quotes::QuoteStatus::Offered { .. }),
+            since,
+            page,
+        ))
+    }
+    async fn list_accepteds(
+        &self,
+        since: Option<TStamp>,
+        page: quotes::Page,
+    ) -> AnyResult<Vec<Uuid>> {
+        Ok(self.list_by(
+            |status| matches!(status, quotes::QuoteStatus::Accepted { .. }),
+            since,
+            page,
+        ))
+    }
+
+    async fn purge_expired(&self, before: TStamp) -> AnyResult<usize> {
+        let mut m = self.quotes.write().unwrap();
+        let count = m.len();
+        m.retain(|_, quote| match quote.status {
+            quotes::QuoteStatus::Pending { .. } | quotes::QuoteStatus::Offered { .. } => true,
+            quotes::QuoteStatus::Accepted { ttl, .. } => ttl >= before,
+            quotes::QuoteStatus::Declined
+            | quotes::QuoteStatus::Cancelled
+            | quotes::QuoteStatus::Expired => quote.submitted >= before,
+        });
+        Ok(count - m.len())
+    }
+}
+
+impl QuotesIDMap {
+    // newest first, ties broken by id so that pages are stable across calls
+    fn list_by(
+        &self,
+        filter: impl Fn(&quotes::QuoteStatus) -> bool,
+        since: Option<TStamp>,
+        page: quotes::Page,
+    ) -> Vec<Uuid> {
+        let reader = self.quotes.read().unwrap();
+        let mut selected: Vec<&quotes::Quote> = reader
+            .values()
+            .filter(|q| filter(&q.status))
+            .filter(|q| q.submitted >= since.unwrap_or_default())
             .collect();
-        Ok(a)
+        selected.sort_by(|a, b| b.submitted.cmp(&a.submitted).then(a.id.cmp(&b.id)));
+        selected
+            .into_iter()
+            .skip(page.offset)
+            .take(page.limit)
+            .map(|q| q.id)
+            .collect()
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct IssuedSignaturesMap {
+    signatures: Arc<RwLock<HashMap<Uuid, Vec<cdk00::BlindSignature>>>>,
+}
+
+#[async_trait]
+impl quotes::IssuedSignatureRepository for IssuedSignaturesMap {
+    async fn record(&self, qid: Uuid, signatures: &[cdk00::BlindSignature]) -> AnyResult<()> {
+        let mut writer = self.signatures.write().unwrap();
+        writer.entry(qid).or_default().extend_from_slice(signatures);
+        Ok(())
+    }
+    async fn by_quote(&self, qid: Uuid) -> AnyResult<Vec<cdk00::BlindSignature>> {
+        let reader = self.signatures.read().unwrap();
+        Ok(reader.get(&qid).cloned().unwrap_or_default())
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct PaymentTokenSet {
+    tokens: Arc<RwLock<HashSet<String>>>,
+}
+
+#[async_trait]
+impl debit::mint::PaymentTokenRepository for PaymentTokenSet {
+    async fn consume(&self, token: &str) -> AnyResult<bool> {
+        Ok(self.tokens.write().unwrap().insert(token.to_owned()))
     }
 }
 
@@ -85,6 +223,7 @@ type QuoteKeysIndex = (KeysetID, Uuid);
 #[derive(Default, Clone)]
 pub struct KeysetIDQuoteIDMap {
     keys: Arc<RwLock<HashMap<QuoteKeysIndex, KeysetEntry>>>,
+    owners: Arc<RwLock<HashMap<KeysetID, (String, String)>>>,
 }
 
 #[async_trait]
@@ -95,22 +234,81 @@ impl creditkeys::QuoteBasedRepository for KeysetIDQuoteIDMap {
         keyset: cdk02::MintKeySet,
         info: cdk::mint::MintKeySetInfo,
     ) -> AnyResult<()> {
-        self.keys
-            .write()
-            .unwrap()
-            .insert((KeysetID::from(keyset.id), qid), (info, keyset));
-        Ok(())
+        let mapkey = (KeysetID::from(keyset.id), qid);
+        let entry = (info, keyset);
+        let mut writer = self.keys.write().unwrap();
+        match writer.get(&mapkey) {
+            Some(existing) if *existing != entry => Err(anyhow!(
+                "keyset {} for quote {} already stored",
+                mapkey.0,
+                qid
+            )),
+            Some(_) => Ok(()),
+            None => {
+                writer.insert(mapkey, entry);
+                Ok(())
+            }
+        }
     }
 
     async fn load(&self, kid: &keys::KeysetID, qid: Uuid) -> AnyResult<Option<keys::KeysetEntry>> {
-        let mapkey = (kid.clone(), qid);
+        let mapkey = (*kid, qid);
         Ok(self.keys.read().unwrap().get(&mapkey).cloned())
     }
+
+    async fn search_by_kid(&self, kid: &keys::KeysetID) -> AnyResult<Option<keys::KeysetEntry>> {
+        let reader = self.keys.read().unwrap();
+        let found = reader
+            .iter()
+            .find(|((k, _), _)| k == kid)
+            .map(|(_, entry)| entry.clone());
+        Ok(found)
+    }
+
+    async fn load_by_bill(&self, bill: &str, node: &str) -> AnyResult<Vec<keys::KeysetEntry>> {
+        let kid = keys::credit::generate_keyset_id_from_bill(bill, node);
+        let reader = self.keys.read().unwrap();
+        let found = reader
+            .iter()
+            .filter(|((k, _), _)| *k == kid)
+            .map(|(_, entry)| entry.clone())
+            .collect();
+        Ok(found)
+    }
+
+    async fn load_by_quote(&self, qid: Uuid) -> AnyResult<Vec<keys::KeysetEntry>> {
+        let reader = self.keys.read().unwrap();
+        let found = reader
+            .iter()
+            .filter(|((_, q), _)| *q == qid)
+            .map(|(_, entry)| entry.clone())
+            .collect();
+        Ok(found)
+    }
+
+    async fn owner(&self, kid: &keys::KeysetID) -> AnyResult<Option<(String, String)>> {
+        Ok(self.owners.read().unwrap().get(kid).cloned())
+    }
+
+    async fn set_owner(&self, kid: &keys::KeysetID, bill: &str, endorser: &str) -> AnyResult<()> {
+        let owner = (String::from(bill), String::from(endorser));
+        self.owners.write().unwrap().insert(*kid, owner);
+        Ok(())
+    }
 }
 
 #[derive(Default, Clone)]
 pub struct KeysetIDEntryMap {
     keys: Arc<RwLock<HashMap<KeysetID, KeysetEntry>>>,
+    issued: Arc<RwLock<HashMap<KeysetID, cdk::Amount>>>,
+    metadata: Arc<RwLock<HashMap<KeysetID, keys::KeysetMetadata>>>,
+}
+
+#[async_trait]
+impl HealthCheck for KeysetIDEntryMap {
+    async fn ready(&self) -> bool {
+        true
+    }
 }
 
 #[async_trait]
@@ -148,47 +346,348 @@ impl keys::Repository for KeysetIDEntryMap {
             .insert(KeysetID::from(keyset.id), (info, keyset));
         Ok(())
     }
+    async fn list_info(&self) -> AnyResult<Vec<cdk::mint::MintKeySetInfo>> {
+        let infos = self
+            .keys
+            .read()
+            .unwrap()
+            .values()
+            .map(|(info, _)| info.clone())
+            .collect();
+        Ok(infos)
+    }
+    async fn metadata(&self, kid: &KeysetID) -> AnyResult<Option<keys::KeysetMetadata>> {
+        if !self.keys.read().unwrap().contains_key(kid) {
+            return Ok(None);
+        }
+        let metadata = self.metadata.read().unwrap().get(kid).cloned();
+        Ok(Some(metadata.unwrap_or_default()))
+    }
+    async fn set_metadata(&self, kid: &KeysetID, metadata: keys::KeysetMetadata) -> AnyResult<()> {
+        if !self.keys.read().unwrap().contains_key(kid) {
+            return Err(anyhow!("unknown keyset {}", kid));
+        }
+        // kept apart from the entry, re-storing the keyset leaves it untouched
+        self.metadata.write().unwrap().insert(*kid, metadata);
+        Ok(())
+    }
+    // date-based ids share the date prefix and sort by rotation index
+    async fn active_for_maturity(
+        &self,
+        maturity_date: TStamp,
+        max_walk: u32,
+    ) -> AnyResult<Option<KeysetID>> {
+        let first = keys::generate_keyset_id_from_date(maturity_date, 0)?;
+        let newest = self
+            .keys
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(kid, (info, _))| info.active && kid.id[0..4] == first.id[0..4])
+            .map(|(kid, _)| *kid)
+            .max_by_key(|kid| kid.id);
+        if let Some(kid) = &newest {
+            keys::verify_rotation_walk(kid, max_walk)?;
+        }
+        Ok(newest)
+    }
 }
 
-#[derive(Default, Clone)]
+#[derive(Clone)]
 pub struct ProofMap {
     proofs: Arc<RwLock<HashMap<cdk01::PublicKey, cdk07::ProofState>>>,
+    issued: Arc<RwLock<HashMap<cdk01::PublicKey, cdk00::BlindedMessage>>>,
+    // face value of the spent proofs, by Y
+    spent_amounts: Arc<RwLock<HashMap<cdk01::PublicKey, cdk::Amount>>>,
+    // every state change, for NUT-17 subscribers
+    events: broadcast::Sender<cdk07::ProofState>,
+}
+
+impl Default for ProofMap {
+    fn default() -> Self {
+        let (events, _) = broadcast::channel(Self::EVENTS_CAPACITY);
+        Self {
+            proofs: Default::default(),
+            issued: Default::default(),
+            spent_amounts: Default::default(),
+            events,
+        }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for ProofMap {
+    async fn ready(&self) -> bool {
+        true
+    }
 }
 
 #[async_trait()]
 impl swap::ProofRepository for ProofMap {
     async fn spend(&self, tokens: &[cdk00::Proof]) -> AnyResult<()> {
+        let ys = hash_tokens(tokens)?;
+        let mut unique = HashSet::with_capacity(ys.len());
+        if !ys.iter().all(|y| unique.insert(*y)) {
+            return Err(swap::Error::DuplicateInputs.into());
+        }
+        // state check and insertion under the same lock, all or nothing
         let mut writer = self.proofs.write().unwrap();
-        for token in tokens {
-            let y = cdk::dhke::hash_to_curve(&token.secret.to_bytes())?;
-            let proofstate = cdk07::ProofState {
-                y,
-                state: cdk07::State::Spent,
-                witness: None,
-            };
-            writer.insert(y, proofstate);
+        let already_spent = ys
+            .iter()
+            .any(|y| matches!(writer.get(y), Some(ps) if ps.state == cdk07::State::Spent));
+        if already_spent {
+            return Err(swap::Error::ProofsAlreadySpent.into());
+        }
+        let mut amounts = self.spent_amounts.write().unwrap();
+        for (y, token) in ys.into_iter().zip(tokens) {
+            writer.insert(y, proof_state(y, cdk07::State::Spent));
+            amounts.insert(y, token.amount);
+            self.publish(proof_state(y, cdk07::State::Spent));
         }
         Ok(())
     }
 
     async fn get_state(&self, tokens: &[cdk00::Proof]) -> AnyResult<Vec<cdk07::State>> {
-        let mut states: Vec<cdk07::State> = Vec::new();
+        let ys = hash_tokens(tokens)?;
         let reader = self.proofs.read().unwrap();
-        for token in tokens {
-            let y = cdk::dhke::hash_to_curve(&token.secret.to_bytes())?;
-            let state = reader.get(&y).map_or(cdk07::State::Unspent, |x| x.state);
-            states.push(state);
+        let states = ys
+            .iter()
+            .map(|y| reader.get(y).map_or(cdk07::State::Unspent, |x| x.state))
+            .collect();
+        Ok(states)
+    }
+
+    async fn reserve(&self, tokens: &[cdk00::Proof]) -> AnyResult<()> {
+        let ys = hash_tokens(tokens)?;
+        let mut writer = self.proofs.write().unwrap();
+        for y in &ys {
+            match writer.get(y).map(|ps| ps.state) {
+                Some(cdk07::State::Spent) => return Err(swap::Error::ProofsAlreadySpent.into()),
+                Some(cdk07::State::Pending) => return Err(swap::Error::ProofsPending.into()),
+                _ => {}
+            }
+        }
+        for y in ys {
+            writer.insert(y, proof_state(y, cdk07::State::Pending));
+            self.publish(proof_state(y, cdk07::State::Pending));
+        }
+        Ok(())
+    }
+
+    async fn release(&self, tokens: &[cdk00::Proof]) -> AnyResult<()> {
+        let ys = hash_tokens(tokens)?;
+        let mut writer = self.proofs.write().unwrap();
+        for y in ys {
+            if matches!(writer.get(&y), Some(ps) if ps.state == cdk07::State::Pending) {
+                writer.remove(&y);
+                self.publish(proof_state(y, cdk07::State::Unspent));
+            }
+        }
+        Ok(())
+    }
+
+    async fn record_issued(&self, outputs: &[cdk00::BlindedMessage]) -> AnyResult<()> {
+        let mut writer = self.issued.write().unwrap();
+        for output in outputs {
+            writer.insert(output.blinded_secret, output.clone());
+        }
+        Ok(())
+    }
+
+    async fn issued(
+        &self,
+        blinded_secrets: &[cdk01::PublicKey],
+    ) -> AnyResult<Vec<Option<cdk00::BlindedMessage>>> {
+        let reader = self.issued.read().unwrap();
+        let issued = blinded_secrets
+            .iter()
+            .map(|blinded_secret| reader.get(blinded_secret).cloned())
+            .collect();
+        Ok(issued)
+    }
+}
+
+impl ProofMap {
+    const EVENTS_CAPACITY: usize = 256;
+
+    // nobody listening is not an error
+    fn publish(&self, state: cdk07::ProofState) {
+        let _ = self.events.send(state);
+    }
+
+    /// audit commitment over the spent proofs: merkle root of their Ys sorted by
+    /// compressed bytes, replicas with the same spent set agree on it whatever the
+    /// order of the spends. pending proofs are left out, the empty set commits to zeros
+    pub fn spent_root(&self) -> AnyResult<[u8; 32]> {
+        let mut ys: Vec<[u8; 33]> = self
+            .proofs
+            .read()
+            .unwrap()
+            .values()
+            .filter(|ps| ps.state == cdk07::State::Spent)
+            .map(|ps| ps.y.to_bytes())
+            .collect();
+        ys.sort_unstable();
+        Ok(merkle_root(&ys))
+    }
+
+    /// face value of the eCash still in circulation: issued outputs minus spent proofs.
+    /// swaps, quotes and debit mints all record their outputs through `record_issued`
+    pub fn total_liabilities(&self) -> AnyResult<cdk::Amount> {
+        let issued = self
+            .issued
+            .read()
+            .unwrap()
+            .values()
+            .try_fold(cdk::Amount::ZERO, |total, output| {
+                total.checked_add(output.amount)
+            })
+            .ok_or_else(|| anyhow!("issued amount overflow"))?;
+        let spent = self
+            .spent_amounts
+            .read()
+            .unwrap()
+            .values()
+            .try_fold(cdk::Amount::ZERO, |total, amount| {
+                total.checked_add(*amount)
+            })
+            .ok_or_else(|| anyhow!("spent amount overflow"))?;
+        issued
+            .checked_sub(spent)
+            .ok_or_else(|| anyhow!("spent {} exceeds issued {}", spent, issued))
+    }
+
+    /// every tracked proof with its state, sorted by Y. never-seen proofs are unspent and
+    /// not listed
+    pub fn snapshot(&self) -> Vec<(cdk01::PublicKey, cdk07::ProofState)> {
+        let mut entries: Vec<_> = self
+            .proofs
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(y, ps)| (*y, ps.clone()))
+            .collect();
+        entries.sort_unstable_by_key(|(y, _)| y.to_bytes());
+        entries
+    }
+
+    /// merges a snapshot into the current states: on conflict Spent wins over Pending,
+    /// which wins over Unspent, so spends happened meanwhile are not lost
+    pub fn restore(&self, entries: Vec<(cdk01::PublicKey, cdk07::ProofState)>) {
+        fn rank(state: cdk07::State) -> u8 {
+            match state {
+                cdk07::State::Spent => 2,
+                cdk07::State::Pending => 1,
+                _ => 0,
+            }
+        }
+        let mut writer = self.proofs.write().unwrap();
+        for (y, ps) in entries {
+            let current = writer.get(&y).map_or(cdk07::State::Unspent, |ps| ps.state);
+            if rank(ps.state) > rank(current) {
+                writer.insert(y, proof_state(y, ps.state));
+                self.publish(proof_state(y, ps.state));
+            }
         }
+    }
+}
+
+// leaves and nodes are tagged apart, an odd node is carried up as is
+fn merkle_root(leaves: &[[u8; 33]]) -> [u8; 32] {
+    let mut level: Vec<Sha256> = leaves
+        .iter()
+        .map(|leaf| {
+            let mut engine = Sha256::engine();
+            engine.input(&[0x00]);
+            engine.input(leaf);
+            Sha256::from_engine(engine)
+        })
+        .collect();
+    if level.is_empty() {
+        return [0u8; 32];
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    let mut engine = Sha256::engine();
+                    engine.input(&[0x01]);
+                    engine.input(left.as_byte_array());
+                    engine.input(right.as_byte_array());
+                    Sha256::from_engine(engine)
+                }
+                [single] => *single,
+                _ => unreachable!("chunks of 2"),
+            })
+            .collect();
+    }
+    level[0].to_byte_array()
+}
+
+#[async_trait]
+impl ws::ProofStateFeed for ProofMap {
+    fn subscribe(&self) -> broadcast::Receiver<cdk07::ProofState> {
+        self.events.subscribe()
+    }
+
+    async fn states(&self, ys: &[cdk01::PublicKey]) -> AnyResult<Vec<cdk07::ProofState>> {
+        let reader = self.proofs.read().unwrap();
+        let states = ys
+            .iter()
+            .map(|y| {
+                reader
+                    .get(y)
+                    .cloned()
+                    .unwrap_or_else(|| proof_state(*y, cdk07::State::Unspent))
+            })
+            .collect();
         Ok(states)
     }
 }
 
+fn hash_tokens(tokens: &[cdk00::Proof]) -> AnyResult<Vec<cdk01::PublicKey>> {
+    let mut ys: Vec<cdk01::PublicKey> = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        swap::verify_secret(&token.secret)?;
+        ys.push(cdk::dhke::hash_to_curve(&token.secret.to_bytes())?);
+    }
+    Ok(ys)
+}
+
+fn proof_state(y: cdk01::PublicKey, state: cdk07::State) -> cdk07::ProofState {
+    cdk07::ProofState {
+        y,
+        state,
+        witness: None,
+    }
+}
+
+#[async_trait]
+impl creditkeys::MaturityKeyRepository for KeysetIDEntryMap {
+    async fn record_issued(&self, kid: &KeysetID, amount: cdk::Amount) -> AnyResult<()> {
+        *self.issued.write().unwrap().entry(*kid).or_default() += amount;
+        Ok(())
+    }
+    async fn issued(&self, kid: &KeysetID) -> AnyResult<cdk::Amount> {
+        let issued = self.issued.read().unwrap().get(kid).copied();
+        Ok(issued.unwrap_or_default())
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct KeysetIDEntryMapWithActive {
     keys: KeysetIDEntryMap,
     active: Arc<RwLock<Option<KeysetID>>>,
 }
 
+#[async_trait]
+impl HealthCheck for KeysetIDEntryMapWithActive {
+    async fn ready(&self) -> bool {
+        true
+    }
+}
+
 #[async_trait]
 impl keys::Repository for KeysetIDEntryMapWithActive {
     async fn info(&self, kid: &KeysetID) -> AnyResult<Option<cdk::mint::MintKeySetInfo>> {
@@ -208,10 +707,57 @@ impl keys::Repository for KeysetIDEntryMapWithActive {
         keyset: cdk02::MintKeySet,
         info: cdk::mint::MintKeySetInfo,
     ) -> AnyResult<()> {
-        if info.active {
-            *self.active.write().unwrap() = Some(KeysetID::from(keyset.id));
+        let kid = KeysetID::from(keyset.id);
+        let current = *self.active.read().unwrap();
+        // keysets may be loaded in any order, the newest active one wins
+        let newer = match current {
+            Some(current) if current != kid => self
+                .keys
+                .info(&current)
+                .await?
+                .is_none_or(|current| current.valid_from < info.valid_from),
+            _ => true,
+        };
+        let activate = info.active && newer;
+        let deactivate = !info.active && current == Some(kid);
+        self.keys.store(keyset, info).await?;
+        if activate {
+            *self.active.write().unwrap() = Some(kid);
+        } else if deactivate {
+            // re-stored as inactive: drop the pointer if it was the active one
+            *self.active.write().unwrap() = None;
         }
-        self.keys.store(keyset, info).await
+        Ok(())
+    }
+
+    async fn list_info(&self) -> AnyResult<Vec<cdk::mint::MintKeySetInfo>> {
+        self.keys.list_info().await
+    }
+
+    async fn metadata(&self, kid: &KeysetID) -> AnyResult<Option<keys::KeysetMetadata>> {
+        self.keys.metadata(kid).await
+    }
+
+    async fn set_metadata(&self, kid: &KeysetID, metadata: keys::KeysetMetadata) -> AnyResult<()> {
+        self.keys.set_metadata(kid, metadata).await
+    }
+
+    async fn active_for_maturity(
+        &self,
+        maturity_date: TStamp,
+        max_walk: u32,
+    ) -> AnyResult<Option<KeysetID>> {
+        self.keys.active_for_maturity(maturity_date, max_walk).await
+    }
+}
+
+#[async_trait]
+impl creditkeys::MaturityKeyRepository for KeysetIDEntryMapWithActive {
+    async fn record_issued(&self, kid: &KeysetID, amount: cdk::Amount) -> AnyResult<()> {
+        self.keys.record_issued(kid, amount).await
+    }
+    async fn issued(&self, kid: &KeysetID) -> AnyResult<cdk::Amount> {
+        self.keys.issued(kid).await
     }
 }
 
@@ -232,4 +778,757 @@ impl keys::ActiveRepository for KeysetIDEntryMapWithActive {
         }
         Ok(None)
     }
+
+    async fn set_active(&self, kid: KeysetID) -> AnyResult<()> {
+        if self.keys.info(&kid).await?.is_none() {
+            return Err(anyhow!("unknown keyset {}", kid));
+        }
+        *self.active.write().unwrap() = Some(kid);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credit::quotes::IssuedSignatureRepository;
+    use crate::keys::test_utils as keys_test;
+    use crate::swap::ProofRepository;
+    use crate::utils::tests as utils;
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn test_issued_signatures_by_quote() {
+        let repo = IssuedSignaturesMap::default();
+        let keyset = keys_test::generate_keyset();
+        let signatures: Vec<_> = utils::generate_blinds(&keyset, &[8, 4, 2].map(cdk::Amount::from))
+            .into_iter()
+            .map(|(blind, _, _)| crate::keys::sign_with_keys(&keyset, &blind).unwrap())
+            .collect();
+        let (qid, other) = (Uuid::new_v4(), Uuid::new_v4());
+
+        repo.record(qid, &signatures[0..2]).await.unwrap();
+        repo.record(other, &signatures[2..]).await.unwrap();
+        repo.record(qid, &signatures[2..]).await.unwrap();
+        assert_eq!(repo.by_quote(qid).await.unwrap(), signatures);
+        assert_eq!(repo.by_quote(other).await.unwrap(), signatures[2..]);
+        assert!(repo.by_quote(Uuid::new_v4()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_proofmap_spend_rejects_already_spent() {
+        let proofmap = ProofMap::default();
+        let keyset = keys_test::generate_keyset();
+        let proofs = utils::generate_proofs(
+            &keyset,
+            &[cdk::Amount::from(8_u64), cdk::Amount::from(4_u64)],
+        );
+        proofmap.spend(&proofs[0..1]).await.unwrap();
+
+        let e = proofmap.spend(&proofs).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<swap::Error>(),
+            Some(swap::Error::ProofsAlreadySpent)
+        ));
+        // the batch is rolled back as a whole
+        let states = proofmap.get_state(&proofs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Spent, cdk07::State::Unspent]);
+    }
+
+    #[tokio::test]
+    async fn test_proofmap_spend_rejects_duplicates_in_batch() {
+        let proofmap = ProofMap::default();
+        let keyset = keys_test::generate_keyset();
+        let proofs = utils::generate_proofs(&keyset, &[cdk::Amount::from(8_u64)]);
+        let batch = vec![proofs[0].clone(), proofs[0].clone()];
+
+        let e = proofmap.spend(&batch).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<swap::Error>(),
+            Some(swap::Error::DuplicateInputs)
+        ));
+        let states = proofmap.get_state(&proofs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Unspent]);
+    }
+
+    #[tokio::test]
+    async fn test_proofmap_malformed_secret() {
+        let proofmap = ProofMap::default();
+        let keyset = keys_test::generate_keyset();
+        let mut proofs = utils::generate_proofs(&keyset, &[cdk::Amount::from(8_u64)]);
+        proofs[0].secret = cdk::secret::Secret::new("");
+
+        let e = proofmap.spend(&proofs).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<swap::Error>(),
+            Some(swap::Error::MalformedSecret(_))
+        ));
+        proofs[0].secret = cdk::secret::Secret::new("a".repeat(4096));
+        let e = proofmap.get_state(&proofs).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<swap::Error>(),
+            Some(swap::Error::MalformedSecret(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_proofmap_total_liabilities() {
+        let proofmap = ProofMap::default();
+        let keyset = keys_test::generate_keyset();
+        let amounts = [64_u64, 32, 4].map(cdk::Amount::from);
+        let outputs: Vec<_> = utils::generate_blinds(&keyset, &amounts)
+            .into_iter()
+            .map(|(blind, _, _)| blind)
+            .collect();
+        proofmap.record_issued(&outputs).await.unwrap();
+        assert_eq!(
+            proofmap.total_liabilities().unwrap(),
+            cdk::Amount::from(100_u64)
+        );
+
+        let amounts = [16_u64, 8, 4, 2].map(cdk::Amount::from);
+        let proofs = utils::generate_proofs(&keyset, &amounts);
+        proofmap.spend(&proofs).await.unwrap();
+        assert_eq!(
+            proofmap.total_liabilities().unwrap(),
+            cdk::Amount::from(70_u64)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proofmap_spent_root_is_order_independent() {
+        let keyset = keys_test::generate_keyset();
+        let amounts = [1, 2, 4, 8, 16].map(cdk::Amount::from);
+        let proofs = utils::generate_proofs(&keyset, &amounts);
+        let mut reversed = proofs.clone();
+        reversed.reverse();
+
+        let first = ProofMap::default();
+        for proof in &proofs {
+            first.spend(std::slice::from_ref(proof)).await.unwrap();
+        }
+        let second = ProofMap::default();
+        second.spend(&reversed[..2]).await.unwrap();
+        second.spend(&reversed[2..]).await.unwrap();
+
+        assert_eq!(first.spent_root().unwrap(), second.spent_root().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_proofmap_spent_root_changes_on_spend() {
+        let proofmap = ProofMap::default();
+        let keyset = keys_test::generate_keyset();
+        let proofs = utils::generate_proofs(
+            &keyset,
+            &[cdk::Amount::from(8_u64), cdk::Amount::from(4_u64)],
+        );
+        let empty = proofmap.spent_root().unwrap();
+        assert_eq!(empty, [0u8; 32]);
+
+        proofmap.spend(&proofs[0..1]).await.unwrap();
+        let one = proofmap.spent_root().unwrap();
+        assert_ne!(one, empty);
+        // pending proofs are not committed to
+        proofmap.reserve(&proofs[1..]).await.unwrap();
+        assert_eq!(proofmap.spent_root().unwrap(), one);
+        proofmap.spend(&proofs[1..]).await.unwrap();
+        assert_ne!(proofmap.spent_root().unwrap(), one);
+    }
+
+    #[tokio::test]
+    async fn test_proofmap_snapshot_restore() {
+        let proofmap = ProofMap::default();
+        let keyset = keys_test::generate_keyset();
+        let proofs = utils::generate_proofs(
+            &keyset,
+            &[cdk::Amount::from(8_u64), cdk::Amount::from(4_u64)],
+        );
+        proofmap.spend(&proofs[0..1]).await.unwrap();
+        let snapshot = proofmap.snapshot();
+        let serialized = serde_json::to_string(&snapshot).unwrap();
+
+        let fresh = ProofMap::default();
+        fresh.spend(&proofs[1..]).await.unwrap();
+        fresh.restore(serde_json::from_str(&serialized).unwrap());
+        let states = fresh.get_state(&proofs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Spent, cdk07::State::Spent]);
+
+        // a stale unspent entry does not revive a spent proof
+        let y = cdk::dhke::hash_to_curve(&proofs[1].secret.to_bytes()).unwrap();
+        fresh.restore(vec![(y, proof_state(y, cdk07::State::Unspent))]);
+        let states = fresh.get_state(&proofs[1..]).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Spent]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_proofmap_concurrent_spend() {
+        let proofmap = ProofMap::default();
+        let keyset = keys_test::generate_keyset();
+        let proofs = utils::generate_proofs(
+            &keyset,
+            &[cdk::Amount::from(8_u64), cdk::Amount::from(4_u64)],
+        );
+
+        let (p1, p2) = (proofmap.clone(), proofmap.clone());
+        let (i1, i2) = (proofs.clone(), proofs.clone());
+        let h1 = tokio::spawn(async move { p1.spend(&i1).await });
+        let h2 = tokio::spawn(async move { p2.spend(&i2).await });
+        let r1 = h1.await.unwrap();
+        let r2 = h2.await.unwrap();
+        assert!(r1.is_ok() ^ r2.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_quotesidmap_list_pendings_paginated() {
+        let quotemap = QuotesIDMap::default();
+        let now = chrono::Utc::now();
+        let mut expected = Vec::new();
+        for i in 0..5 {
+            let quote = quotes::Quote::new(
+                format!("bill{i}"),
+                String::from("endorser"),
+                vec![],
+                now - chrono::Duration::hours(i),
+            );
+            expected.push(quote.id);
+            quotes::Repository::store(&quotemap, quote).await.unwrap();
+        }
+
+        let page = quotes::Page {
+            offset: 1,
+            limit: 3,
+        };
+        let listed = quotes::Repository::list_pendings(&quotemap, None, page)
+            .await
+            .unwrap();
+        assert_eq!(listed, expected[1..4]);
+        let page = quotes::Page {
+            offset: 5,
+            limit: 3,
+        };
+        let listed = quotes::Repository::list_pendings(&quotemap, None, page)
+            .await
+            .unwrap();
+        assert!(listed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_quotesidmap_list_stale_pendings() {
+        let quotemap = QuotesIDMap::default();
+        let now = chrono::Utc::now();
+        let mut pendings = Vec::new();
+        // submitted 0, 1, .. 4 days ago
+        for days in 0..5 {
+            let quote = quotes::Quote::new(
+                format!("bill{days}"),
+                String::from("endorser"),
+                vec![],
+                now - chrono::Duration::days(days),
+            );
+            pendings.push(quote.id);
+            quotes::Repository::store(&quotemap, quote).await.unwrap();
+        }
+        let mut declined = quotes::Quote::new(
+            String::from("declined"),
+            String::from("endorser"),
+            vec![],
+            now - chrono::Duration::days(10),
+        );
+        declined.decline().unwrap();
+        quotes::Repository::store(&quotemap, declined)
+            .await
+            .unwrap();
+
+        let older_than = now - chrono::Duration::hours(36);
+        let stale = quotes::Repository::list_stale_pendings(&quotemap, older_than)
+            .await
+            .unwrap();
+        assert_eq!(stale, vec![pendings[4], pendings[3], pendings[2]]);
+    }
+
+    #[tokio::test]
+    async fn test_quotesidmap_purge_expired() {
+        let quotemap = QuotesIDMap::default();
+        let now = chrono::Utc::now();
+        let old = now - chrono::Duration::days(30);
+        let pending = quotes::Quote::new(String::from("bill1"), String::from("e"), vec![], old);
+        let mut declined =
+            quotes::Quote::new(String::from("bill2"), String::from("e"), vec![], old);
+        declined.decline().unwrap();
+        let mut recent = quotes::Quote::new(String::from("bill3"), String::from("e"), vec![], now);
+        recent.decline().unwrap();
+        let mut accepted =
+            quotes::Quote::new(String::from("bill4"), String::from("e"), vec![], old);
+        accepted.accept(vec![], old, cdk::Amount::ZERO).unwrap();
+        let mut redeemable =
+            quotes::Quote::new(String::from("bill5"), String::from("e"), vec![], old);
+        redeemable
+            .accept(vec![], now + chrono::Duration::days(1), cdk::Amount::ZERO)
+            .unwrap();
+        for quote in [&pending, &declined, &recent, &accepted, &redeemable] {
+            quotes::Repository::store(&quotemap, quote.clone())
+                .await
+                .unwrap();
+        }
+
+        let before = now - chrono::Duration::days(1);
+        let purged = quotes::Repository::purge_expired(&quotemap, before)
+            .await
+            .unwrap();
+        assert_eq!(purged, 2);
+        for gone in [&declined, &accepted] {
+            let loaded = quotes::Repository::load(&quotemap, gone.id).await.unwrap();
+            assert!(loaded.is_none());
+        }
+        for kept in [&pending, &recent, &redeemable] {
+            let loaded = quotes::Repository::load(&quotemap, kept.id).await.unwrap();
+            assert!(loaded.is_some());
+        }
+        let purged = quotes::Repository::purge_expired(&quotemap, before)
+            .await
+            .unwrap();
+        assert_eq!(purged, 0);
+    }
+
+    #[tokio::test]
+    async fn test_quotesidmap_load_many() {
+        let quotemap = QuotesIDMap::default();
+        let now = chrono::Utc::now();
+        let mut ids = Vec::new();
+        for bill in ["bill1", "bill2", "bill3"] {
+            let quote =
+                quotes::Quote::new(String::from(bill), String::from("endorserID"), vec![], now);
+            ids.push(quote.id);
+            quotes::Repository::store(&quotemap, quote).await.unwrap();
+        }
+        let unknown = Uuid::new_v4();
+
+        let requested = [ids[2], unknown, ids[0], ids[1]];
+        let found = quotes::Repository::load_many(&quotemap, &requested)
+            .await
+            .unwrap();
+        let found: Vec<Uuid> = found.iter().map(|q| q.id).collect();
+        assert_eq!(found, vec![ids[2], ids[0], ids[1]]);
+    }
+
+    #[tokio::test]
+    async fn test_quotesidmap_search_by_endorser() {
+        let quotemap = QuotesIDMap::default();
+        let now = chrono::Utc::now();
+        let old = quotes::Quote::new(
+            String::from("bill1"),
+            String::from("endorser"),
+            vec![],
+            now - chrono::Duration::days(1),
+        );
+        let new = quotes::Quote::new(String::from("bill2"), String::from("endorser"), vec![], now);
+        let other = quotes::Quote::new(String::from("bill1"), String::from("other"), vec![], now);
+        quotes::Repository::store(&quotemap, old.clone())
+            .await
+            .unwrap();
+        quotes::Repository::store(&quotemap, new.clone())
+            .await
+            .unwrap();
+        quotes::Repository::store(&quotemap, other).await.unwrap();
+
+        let found = quotes::Repository::search_by_endorser(&quotemap, "endorser")
+            .await
+            .unwrap();
+        let ids: Vec<_> = found.iter().map(|q| q.id).collect();
+        assert_eq!(ids, vec![new.id, old.id]);
+        let found = quotes::Repository::search_by_endorser(&quotemap, "nobody")
+            .await
+            .unwrap();
+        assert!(found.is_empty());
+    }
+
+    fn generate_active_entry(path: &str, valid_from: u64) -> KeysetEntry {
+        let path = bitcoin::bip32::DerivationPath::from_str(path).unwrap();
+        let keyset = cdk02::MintKeySet::generate_from_seed(
+            &bitcoin::secp256k1::Secp256k1::new(),
+            &[],
+            4,
+            cdk00::CurrencyUnit::Sat,
+            path.clone(),
+        );
+        let info = cdk::mint::MintKeySetInfo {
+            id: keyset.id,
+            unit: keyset.unit.clone(),
+            active: true,
+            valid_from,
+            valid_to: None,
+            derivation_path: path,
+            derivation_path_index: None,
+            max_order: 4,
+            input_fee_ppk: 0,
+        };
+        (info, keyset)
+    }
+
+    #[tokio::test]
+    async fn test_keysetidentrymapwithactive_newest_wins() {
+        let repo = KeysetIDEntryMapWithActive::default();
+        let (old_info, old_keyset) = generate_active_entry("m/0'/0'", 1);
+        let (new_info, new_keyset) = generate_active_entry("m/0'/1'", 2);
+        // loaded out of order, e.g. after a restart
+        keys::Repository::store(&repo, new_keyset, new_info.clone())
+            .await
+            .unwrap();
+        keys::Repository::store(&repo, old_keyset, old_info.clone())
+            .await
+            .unwrap();
+        let active = keys::ActiveRepository::info_active(&repo).await.unwrap();
+        assert_eq!(active, Some(new_info));
+
+        keys::ActiveRepository::set_active(&repo, KeysetID::from(old_info.id))
+            .await
+            .unwrap();
+        let active = keys::ActiveRepository::info_active(&repo).await.unwrap();
+        assert_eq!(active, Some(old_info));
+
+        let unknown = keys_test::generate_random_keysetid();
+        assert!(keys::ActiveRepository::set_active(&repo, unknown)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_keysetidentrymapwithactive_list_info() {
+        let repo = KeysetIDEntryMapWithActive::default();
+        let (first, keyset) = generate_active_entry("m/0'/0'", 1);
+        keys::Repository::store(&repo, keyset, first.clone())
+            .await
+            .unwrap();
+        let (second, keyset) = generate_active_entry("m/0'/1'", 2);
+        keys::Repository::store(&repo, keyset, second.clone())
+            .await
+            .unwrap();
+
+        let mut infos = keys::Repository::list_info(&repo).await.unwrap();
+        infos.sort_by_key(|info| info.valid_from);
+        assert_eq!(infos, vec![first, second]);
+    }
+
+    #[tokio::test]
+    async fn test_keysetidentrymap_export_import() {
+        let source = KeysetIDEntryMap::default();
+        let mut kids = Vec::new();
+        for (path, valid_from) in [("m/0'/0'", 1), ("m/0'/1'", 2)] {
+            let (info, keyset) = generate_active_entry(path, valid_from);
+            kids.push(KeysetID::from(keyset.id));
+            keys::Repository::store(&source, keyset, info)
+                .await
+                .unwrap();
+        }
+
+        let exported = keys::Repository::export_all(&source).await.unwrap();
+        assert_eq!(exported.len(), 2);
+        // through JSON, as a backup file would
+        let json = serde_json::to_string(&exported).unwrap();
+        let entries: Vec<KeysetEntry> = serde_json::from_str(&json).unwrap();
+        let target = KeysetIDEntryMap::default();
+        keys::Repository::import(&target, entries).await.unwrap();
+
+        for kid in kids {
+            let original = keys::Repository::load(&source, &kid).await.unwrap();
+            let imported = keys::Repository::load(&target, &kid).await.unwrap();
+            assert!(imported.is_some());
+            assert_eq!(original, imported);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keysetidentrymap_import_rejects_mismatched_id() {
+        let (good_info, good_keyset) = generate_active_entry("m/0'/0'", 1);
+        let (mut bad_info, bad_keyset) = generate_active_entry("m/0'/1'", 2);
+        bad_info.id = good_keyset.id;
+        let repo = KeysetIDEntryMap::default();
+
+        let entries = vec![(good_info, good_keyset.clone()), (bad_info, bad_keyset)];
+        assert!(keys::Repository::import(&repo, entries).await.is_err());
+        let kid = KeysetID::from(good_keyset.id);
+        assert!(keys::Repository::load(&repo, &kid).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_keysetidentrymap_label_survives_store_and_load() {
+        let repo = KeysetIDEntryMapWithActive::default();
+        let (info, keyset) = generate_active_entry("m/0'/0'", 1);
+        let kid = KeysetID::from(keyset.id);
+        keys::Repository::store(&repo, keyset.clone(), info.clone())
+            .await
+            .unwrap();
+        let metadata = keys::Repository::metadata(&repo, &kid).await.unwrap();
+        assert_eq!(metadata, Some(keys::KeysetMetadata::default()));
+
+        keys::Repository::set_label(&repo, &kid, Some(String::from("2025 Q1")))
+            .await
+            .unwrap();
+        keys::Repository::set_description(&repo, &kid, Some(String::from("first rotation")))
+            .await
+            .unwrap();
+        // re-stored, e.g. on deactivation
+        keys::Repository::disable_keyset(&repo, &kid).await.unwrap();
+
+        let (loaded, _) = keys::Repository::load(&repo, &kid).await.unwrap().unwrap();
+        assert!(!loaded.active);
+        let metadata = keys::Repository::metadata(&repo, &kid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(metadata.label.as_deref(), Some("2025 Q1"));
+        assert_eq!(metadata.description.as_deref(), Some("first rotation"));
+
+        keys::Repository::set_label(&repo, &kid, None)
+            .await
+            .unwrap();
+        let metadata = keys::Repository::metadata(&repo, &kid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(metadata.label, None);
+        assert_eq!(metadata.description.as_deref(), Some("first rotation"));
+    }
+
+    fn generate_maturity_entry(maturity: TStamp, rotation_idx: u32, active: bool) -> KeysetEntry {
+        let kid = keys::generate_keyset_id_from_date(maturity, rotation_idx).unwrap();
+        let (mut info, mut keyset) = generate_active_entry("m/0'/0'", 0);
+        keyset.id = kid.into();
+        info.id = kid.into();
+        info.active = active;
+        info.valid_to = Some(maturity.timestamp() as u64);
+        info.derivation_path_index = Some(rotation_idx);
+        (info, keyset)
+    }
+
+    // relies on the default, rotation walking, active_for_maturity
+    struct WalkingRepository(KeysetIDEntryMap);
+
+    #[async_trait]
+    impl keys::Repository for WalkingRepository {
+        async fn info(&self, kid: &KeysetID) -> AnyResult<Option<cdk::mint::MintKeySetInfo>> {
+            self.0.info(kid).await
+        }
+        async fn keyset(&self, kid: &KeysetID) -> AnyResult<Option<cdk02::MintKeySet>> {
+            self.0.keyset(kid).await
+        }
+        async fn load(&self, kid: &KeysetID) -> AnyResult<Option<KeysetEntry>> {
+            self.0.load(kid).await
+        }
+        async fn store(
+            &self,
+            keyset: cdk02::MintKeySet,
+            info: cdk::mint::MintKeySetInfo,
+        ) -> AnyResult<()> {
+            self.0.store(keyset, info).await
+        }
+        async fn list_info(&self) -> AnyResult<Vec<cdk::mint::MintKeySetInfo>> {
+            self.0.list_info().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keysetidentrymap_active_for_maturity_matches_walk() {
+        let maturity = chrono::DateTime::parse_from_rfc3339("2030-06-15T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let other = maturity + chrono::Duration::days(1);
+        let repo = KeysetIDEntryMap::default();
+        let walking = WalkingRepository(repo.clone());
+        for (date, idx, active) in [
+            (maturity, 0, false),
+            (maturity, 1, false),
+            (maturity, 2, true),
+            (other, 0, false),
+        ] {
+            let (info, keyset) = generate_maturity_entry(date, idx, active);
+            repo.store(keyset, info).await.unwrap();
+        }
+
+        let expected = keys::generate_keyset_id_from_date(maturity, 2).unwrap();
+        let found = repo
+            .active_for_maturity(maturity, keys::MAX_ROTATION_WALK)
+            .await
+            .unwrap();
+        assert_eq!(found, Some(expected));
+        assert_eq!(
+            walking
+                .active_for_maturity(maturity, keys::MAX_ROTATION_WALK)
+                .await
+                .unwrap(),
+            found
+        );
+
+        // rotation 2 is out of reach of a walk stopping at index 1
+        for e in [
+            repo.active_for_maturity(maturity, 1).await.unwrap_err(),
+            walking.active_for_maturity(maturity, 1).await.unwrap_err(),
+        ] {
+            assert!(matches!(
+                e.downcast_ref::<keys::Error>(),
+                Some(keys::Error::RotationWalkExceeded(date, 1)) if *date == maturity
+            ));
+        }
+        assert_eq!(
+            walking.active_for_maturity(maturity, 2).await.unwrap(),
+            found
+        );
+        assert_eq!(repo.active_for_maturity(maturity, 2).await.unwrap(), found);
+
+        // only inactive rotations
+        assert_eq!(
+            repo.active_for_maturity(other, keys::MAX_ROTATION_WALK)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            walking
+                .active_for_maturity(other, keys::MAX_ROTATION_WALK)
+                .await
+                .unwrap(),
+            None
+        );
+        // nothing stored for the date
+        let unknown = other + chrono::Duration::days(1);
+        assert_eq!(
+            repo.active_for_maturity(unknown, keys::MAX_ROTATION_WALK)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            walking
+                .active_for_maturity(unknown, keys::MAX_ROTATION_WALK)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keysetidentrymap_label_unknown_keyset() {
+        let repo = KeysetIDEntryMap::default();
+        let kid = keys_test::generate_random_keysetid();
+        assert!(keys::Repository::metadata(&repo, &kid)
+            .await
+            .unwrap()
+            .is_none());
+        let r = keys::Repository::set_label(&repo, &kid, Some(String::from("label"))).await;
+        assert!(r.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_keysetidquoteidmap_store_rejects_overwrite() {
+        let repo = KeysetIDQuoteIDMap::default();
+        let (info, keyset) = generate_active_entry("m/0'/0'", 1);
+        let kid = KeysetID::from(keyset.id);
+        let qid = Uuid::new_v4();
+        creditkeys::QuoteBasedRepository::store(&repo, qid, keyset.clone(), info.clone())
+            .await
+            .unwrap();
+        // same contents: a no-op
+        creditkeys::QuoteBasedRepository::store(&repo, qid, keyset.clone(), info.clone())
+            .await
+            .unwrap();
+
+        let mut other = info.clone();
+        other.valid_from = 2;
+        let r = creditkeys::QuoteBasedRepository::store(&repo, qid, keyset, other).await;
+        assert!(r.is_err());
+        let stored = creditkeys::QuoteBasedRepository::search_by_kid(&repo, &kid)
+            .await
+            .unwrap();
+        assert_eq!(stored.map(|(info, _)| info), Some(info));
+    }
+
+    fn generate_bill_entry(bill: &str, node: &str, path: &str) -> KeysetEntry {
+        let kid = keys::credit::generate_keyset_id_from_bill(bill, node);
+        let (mut info, mut keyset) = generate_active_entry(path, 1);
+        info.id = kid.into();
+        keyset.id = kid.into();
+        (info, keyset)
+    }
+
+    #[tokio::test]
+    async fn test_keysetidquoteidmap_load_by_bill() {
+        let repo = KeysetIDQuoteIDMap::default();
+        let first = generate_bill_entry("billID", "endorserID", "m/0'/0'");
+        let second = generate_bill_entry("billID", "endorserID", "m/0'/1'");
+        let other = generate_bill_entry("otherBillID", "endorserID", "m/0'/2'");
+        for entry in [&first, &second, &other] {
+            let (info, keyset) = entry.clone();
+            creditkeys::QuoteBasedRepository::store(&repo, Uuid::new_v4(), keyset, info)
+                .await
+                .unwrap();
+        }
+
+        let mut found =
+            creditkeys::QuoteBasedRepository::load_by_bill(&repo, "billID", "endorserID")
+                .await
+                .unwrap();
+        found.sort_by(|a, b| a.0.derivation_path.cmp(&b.0.derivation_path));
+        assert_eq!(found, vec![first, second]);
+        let found = creditkeys::QuoteBasedRepository::load_by_bill(&repo, "billID", "otherID")
+            .await
+            .unwrap();
+        assert!(found.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_keysetidquoteidmap_load_by_quote() {
+        let repo = KeysetIDQuoteIDMap::default();
+        let qid = Uuid::new_v4();
+        let first = generate_active_entry("m/0'/0'", 1);
+        let second = generate_active_entry("m/0'/1'", 1);
+        assert_ne!(first.1.id, second.1.id);
+        for (info, keyset) in [first.clone(), second.clone()] {
+            creditkeys::QuoteBasedRepository::store(&repo, qid, keyset, info)
+                .await
+                .unwrap();
+        }
+        let (info, keyset) = generate_active_entry("m/0'/2'", 1);
+        creditkeys::QuoteBasedRepository::store(&repo, Uuid::new_v4(), keyset, info)
+            .await
+            .unwrap();
+
+        let mut found = creditkeys::QuoteBasedRepository::load_by_quote(&repo, qid)
+            .await
+            .unwrap();
+        found.sort_by(|a, b| a.0.derivation_path.cmp(&b.0.derivation_path));
+        assert_eq!(found, vec![first, second]);
+        let found = creditkeys::QuoteBasedRepository::load_by_quote(&repo, Uuid::new_v4())
+            .await
+            .unwrap();
+        assert!(found.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_proofmap_reserve_release() {
+        let proofmap = ProofMap::default();
+        let keyset = keys_test::generate_keyset();
+        let proofs = utils::generate_proofs(
+            &keyset,
+            &[cdk::Amount::from(8_u64), cdk::Amount::from(4_u64)],
+        );
+        proofmap.reserve(&proofs[0..1]).await.unwrap();
+        let e = proofmap.reserve(&proofs).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<swap::Error>(),
+            Some(swap::Error::ProofsPending)
+        ));
+        let states = proofmap.get_state(&proofs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Pending, cdk07::State::Unspent]);
+
+        proofmap.release(&proofs).await.unwrap();
+        let states = proofmap.get_state(&proofs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Unspent, cdk07::State::Unspent]);
+
+        proofmap.reserve(&proofs).await.unwrap();
+        proofmap.spend(&proofs).await.unwrap();
+        // release does not resurrect spent proofs
+        proofmap.release(&proofs).await.unwrap();
+        let states = proofmap.get_state(&proofs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Spent, cdk07::State::Spent]);
+    }
 }
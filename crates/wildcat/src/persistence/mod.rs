@@ -2,5 +2,10 @@
 // ----- extra library imports
 // ----- local modules
 pub mod inmemory;
+pub mod postgres;
+#[cfg(feature = "redis")]
+pub mod redis;
+pub mod sqlite;
 pub mod surreal;
+pub mod versioned;
 // ----- local imports
@@ -0,0 +1,387 @@
+#![allow(dead_code)]
+// ----- standard library imports
+// ----- extra library imports
+use anyhow::Result as AnyResult;
+use async_trait::async_trait;
+use cdk::nuts::nut02 as cdk02;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::Row;
+// ----- local modules
+// ----- local imports
+use crate::health::HealthCheck;
+use crate::keys;
+use crate::persistence::postgres::ConnectionConfig;
+use crate::persistence::versioned;
+use crate::TStamp;
+
+// ----- keys repository with active keyset reference
+// info and keyset are stored serialized as versioned JSON text, keyed by KeysetID.
+// the active keyset pointer lives in a single-row table (`singleton` primary key constrained
+// to TRUE), so every instance sharing the DB agrees on one active keyset.
+#[derive(Debug, Clone)]
+pub struct KeysDB {
+    pool: PgPool,
+    table: String,
+}
+
+impl KeysDB {
+    pub async fn new(cfg: ConnectionConfig) -> sqlx::Result<Self> {
+        let pool = PgPoolOptions::new().connect(&cfg.connection).await?;
+        let db = Self {
+            pool,
+            table: cfg.table,
+        };
+        db.migrate().await?;
+        Ok(db)
+    }
+
+    async fn migrate(&self) -> sqlx::Result<()> {
+        let table = &self.table;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {table} (
+                kid TEXT PRIMARY KEY NOT NULL,
+                info TEXT NOT NULL,
+                keyset TEXT NOT NULL
+            )"
+        ))
+        .execute(&self.pool)
+        .await?;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {table}_active (
+                singleton BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (singleton),
+                kid TEXT NOT NULL REFERENCES {table} (kid)
+            )"
+        ))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn load_info(&self, kid: &str) -> AnyResult<Option<cdk::mint::MintKeySetInfo>> {
+        let row = sqlx::query(&format!("SELECT info FROM {} WHERE kid = $1", self.table))
+            .bind(kid)
+            .fetch_optional(&self.pool)
+            .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let info: String = row.try_get("info")?;
+        Ok(Some(versioned::from_json(&info)?))
+    }
+
+    async fn load_keyset(&self, kid: &str) -> AnyResult<Option<keys::KeysetEntry>> {
+        let row = sqlx::query(&format!(
+            "SELECT info, keyset FROM {} WHERE kid = $1",
+            self.table
+        ))
+        .bind(kid)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let info: String = row.try_get("info")?;
+        let keyset: String = row.try_get("keyset")?;
+        Ok(Some((
+            versioned::from_json(&info)?,
+            versioned::from_json(&keyset)?,
+        )))
+    }
+
+    async fn active_kid(&self) -> AnyResult<Option<String>> {
+        let row = sqlx::query(&format!("SELECT kid FROM {}_active", self.table))
+            .fetch_optional(&self.pool)
+            .await?;
+        row.map(|r| r.try_get("kid").map_err(Into::into))
+            .transpose()
+    }
+}
+
+#[async_trait]
+impl HealthCheck for KeysDB {
+    async fn ready(&self) -> bool {
+        sqlx::query("SELECT 1").execute(&self.pool).await.is_ok()
+    }
+}
+
+#[async_trait]
+impl keys::Repository for KeysDB {
+    async fn info(&self, kid: &keys::KeysetID) -> AnyResult<Option<cdk::mint::MintKeySetInfo>> {
+        self.load_info(&kid.to_string()).await
+    }
+
+    async fn keyset(&self, kid: &keys::KeysetID) -> AnyResult<Option<cdk02::MintKeySet>> {
+        self.load_keyset(&kid.to_string())
+            .await
+            .map(|res| res.map(|(_, keyset)| keyset))
+    }
+
+    async fn load(&self, kid: &keys::KeysetID) -> AnyResult<Option<keys::KeysetEntry>> {
+        self.load_keyset(&kid.to_string()).await
+    }
+
+    async fn store(
+        &self,
+        keyset: cdk02::MintKeySet,
+        info: cdk::mint::MintKeySetInfo,
+    ) -> AnyResult<()> {
+        let kid = keyset.id.to_string();
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(&format!(
+            "INSERT INTO {} (kid, info, keyset) VALUES ($1, $2, $3)
+            ON CONFLICT (kid) DO UPDATE SET info = EXCLUDED.info, keyset = EXCLUDED.keyset",
+            self.table
+        ))
+        .bind(&kid)
+        .bind(versioned::to_json(&info)?)
+        .bind(versioned::to_json(&keyset)?)
+        .execute(&mut *tx)
+        .await?;
+        if !info.active {
+            // re-stored as inactive: drop the pointer if it was the active one
+            sqlx::query(&format!("DELETE FROM {}_active WHERE kid = $1", self.table))
+                .bind(&kid)
+                .execute(&mut *tx)
+                .await?;
+        }
+        if info.active {
+            sqlx::query(&format!(
+                "INSERT INTO {}_active (singleton, kid) VALUES (TRUE, $1)
+                ON CONFLICT (singleton) DO UPDATE SET kid = EXCLUDED.kid",
+                self.table
+            ))
+            .bind(&kid)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn list_info(&self) -> AnyResult<Vec<cdk::mint::MintKeySetInfo>> {
+        let rows = sqlx::query(&format!("SELECT info FROM {}", self.table))
+            .fetch_all(&self.pool)
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                let info: String = row.try_get("info")?;
+                Ok(versioned::from_json(&info)?)
+            })
+            .collect()
+    }
+
+    // date-based ids share the hex prefix of their date and sort by rotation index
+    async fn active_for_maturity(
+        &self,
+        maturity_date: TStamp,
+        max_walk: u32,
+    ) -> AnyResult<Option<keys::KeysetID>> {
+        let first = keys::generate_keyset_id_from_date(maturity_date, 0)?.to_string();
+        let row = sqlx::query(&format!(
+            "SELECT info FROM {} WHERE kid LIKE $1
+            AND (COALESCE(info::jsonb -> 'inner', info::jsonb) ->> 'active')::boolean
+            ORDER BY kid DESC LIMIT 1",
+            self.table
+        ))
+        .bind(format!("{}%", &first[..10]))
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let info: String = row.try_get("info")?;
+        let info: cdk::mint::MintKeySetInfo = versioned::from_json(&info)?;
+        let kid = keys::KeysetID::from(info.id);
+        keys::verify_rotation_walk(&kid, max_walk)?;
+        Ok(Some(kid))
+    }
+}
+
+#[async_trait]
+impl keys::ActiveRepository for KeysDB {
+    async fn info_active(&self) -> AnyResult<Option<cdk::mint::MintKeySetInfo>> {
+        let Some(kid) = self.active_kid().await? else {
+            return Ok(None);
+        };
+        self.load_info(&kid).await
+    }
+
+    async fn keyset_active(&self) -> AnyResult<Option<cdk02::MintKeySet>> {
+        let Some(kid) = self.active_kid().await? else {
+            return Ok(None);
+        };
+        self.load_keyset(&kid)
+            .await
+            .map(|res| res.map(|(_, keyset)| keyset))
+    }
+
+    async fn set_active(&self, kid: keys::KeysetID) -> AnyResult<()> {
+        // the foreign key rejects unknown keysets
+        sqlx::query(&format!(
+            "INSERT INTO {}_active (singleton, kid) VALUES (TRUE, $1)
+            ON CONFLICT (singleton) DO UPDATE SET kid = EXCLUDED.kid",
+            self.table
+        ))
+        .bind(kid.to_string())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::test_utils as keys_test;
+    use crate::keys::{ActiveRepository, Repository};
+    use bitcoin::bip32::DerivationPath;
+    use cdk::nuts::nut00 as cdk00;
+    use std::str::FromStr;
+
+    // run with a reachable PostgreSQL, e.g.
+    // WILDCAT_TEST_POSTGRES=postgres://localhost/wildcat cargo test -- --ignored
+    async fn init_db() -> KeysDB {
+        let url = std::env::var("WILDCAT_TEST_POSTGRES").expect("WILDCAT_TEST_POSTGRES not set");
+        let pool = PgPoolOptions::new().connect(&url).await.unwrap();
+        let table = format!("keys_{}", uuid::Uuid::new_v4().simple());
+        let db = KeysDB { pool, table };
+        db.migrate().await.unwrap();
+        db
+    }
+
+    fn generate_entry(path: &str, active: bool, valid_from: u64) -> keys::KeysetEntry {
+        let path = DerivationPath::from_str(path).unwrap();
+        let keyset = cdk02::MintKeySet::generate_from_seed(
+            &bitcoin::secp256k1::Secp256k1::new(),
+            &[],
+            4,
+            cdk00::CurrencyUnit::Sat,
+            path.clone(),
+        );
+        let info = cdk::mint::MintKeySetInfo {
+            id: keyset.id,
+            unit: keyset.unit.clone(),
+            active,
+            valid_from,
+            valid_to: None,
+            derivation_path: path,
+            derivation_path_index: None,
+            max_order: 4,
+            input_fee_ppk: 0,
+        };
+        (info, keyset)
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running PostgreSQL"]
+    async fn test_store_and_load() {
+        let db = init_db().await;
+        let (info, keyset) = generate_entry("m/0'/0'", false, 0);
+        let kid = keys::KeysetID::from(keyset.id);
+        db.store(keyset.clone(), info.clone()).await.unwrap();
+
+        let (linfo, lkeyset) = db.load(&kid).await.unwrap().unwrap();
+        assert_eq!(linfo, info);
+        assert_eq!(lkeyset, keyset);
+        let missing = keys_test::generate_random_keysetid();
+        assert!(db.info(&missing).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running PostgreSQL"]
+    async fn test_store_flips_active() {
+        let db = init_db().await;
+        assert!(db.info_active().await.unwrap().is_none());
+
+        let (info, keyset) = generate_entry("m/0'/0'", true, 0);
+        db.store(keyset, info.clone()).await.unwrap();
+        assert_eq!(db.info_active().await.unwrap(), Some(info.clone()));
+
+        let (inactive, keyset) = generate_entry("m/0'/1'", false, 1);
+        db.store(keyset, inactive).await.unwrap();
+        assert_eq!(db.info_active().await.unwrap(), Some(info));
+
+        let (info, keyset) = generate_entry("m/0'/2'", true, 2);
+        db.store(keyset.clone(), info.clone()).await.unwrap();
+        assert_eq!(db.info_active().await.unwrap(), Some(info));
+        assert_eq!(db.keyset_active().await.unwrap(), Some(keyset));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running PostgreSQL"]
+    async fn test_store_replaces() {
+        let db = init_db().await;
+        let (mut info, keyset) = generate_entry("m/0'/0'", true, 0);
+        let kid = keys::KeysetID::from(keyset.id);
+        db.store(keyset.clone(), info.clone()).await.unwrap();
+
+        info.active = false;
+        db.store(keyset, info.clone()).await.unwrap();
+        assert_eq!(db.info(&kid).await.unwrap(), Some(info));
+        assert!(db.info_active().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running PostgreSQL"]
+    async fn test_set_active() {
+        let db = init_db().await;
+        let (old, keyset) = generate_entry("m/0'/0'", true, 0);
+        db.store(keyset, old.clone()).await.unwrap();
+        let (new, keyset) = generate_entry("m/0'/1'", true, 1);
+        db.store(keyset, new.clone()).await.unwrap();
+        assert_eq!(db.info_active().await.unwrap(), Some(new));
+
+        db.set_active(keys::KeysetID::from(old.id)).await.unwrap();
+        assert_eq!(db.info_active().await.unwrap(), Some(old));
+        let unknown = keys_test::generate_random_keysetid();
+        assert!(db.set_active(unknown).await.is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running PostgreSQL"]
+    async fn test_active_for_maturity() {
+        let db = init_db().await;
+        let maturity = chrono::DateTime::parse_from_rfc3339("2030-06-15T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        for (idx, active) in [(0, false), (1, true)] {
+            let kid = keys::generate_keyset_id_from_date(maturity, idx).unwrap();
+            let (mut info, mut keyset) = generate_entry("m/0'/0'", active, 0);
+            keyset.id = kid.into();
+            info.id = kid.into();
+            info.derivation_path_index = Some(idx);
+            db.store(keyset, info).await.unwrap();
+        }
+
+        let expected = keys::generate_keyset_id_from_date(maturity, 1).unwrap();
+        assert_eq!(
+            db.active_for_maturity(maturity, keys::MAX_ROTATION_WALK)
+                .await
+                .unwrap(),
+            Some(expected)
+        );
+        let next_day = maturity + chrono::Duration::days(1);
+        assert!(db.active_for_maturity(maturity, 0).await.is_err());
+        assert_eq!(
+            db.active_for_maturity(next_day, keys::MAX_ROTATION_WALK)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running PostgreSQL"]
+    async fn test_list_info() {
+        let db = init_db().await;
+        assert!(db.list_info().await.unwrap().is_empty());
+        let (first, keyset) = generate_entry("m/0'/0'", true, 0);
+        db.store(keyset, first.clone()).await.unwrap();
+        let (second, keyset) = generate_entry("m/0'/1'", false, 1);
+        db.store(keyset, second.clone()).await.unwrap();
+
+        let mut infos = db.list_info().await.unwrap();
+        infos.sort_by_key(|info| info.valid_from);
+        assert_eq!(infos, vec![first, second]);
+    }
+}
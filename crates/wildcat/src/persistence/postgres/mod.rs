@@ -0,0 +1,11 @@
+// ----- standard library imports
+// ----- extra library imports
+// ----- local modules
+pub mod keysets;
+// ----- local imports
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct ConnectionConfig {
+    pub connection: String,
+    pub table: String,
+}
@@ -0,0 +1,300 @@
+#![allow(dead_code)]
+// ----- standard library imports
+// ----- extra library imports
+use anyhow::Result as AnyResult;
+use async_trait::async_trait;
+use cdk::nuts::nut00 as cdk00;
+use cdk::nuts::nut01 as cdk01;
+use cdk::nuts::nut07 as cdk07;
+use redis::aio::MultiplexedConnection;
+use redis::AsyncCommands;
+// ----- local modules
+// ----- local imports
+use crate::health::HealthCheck;
+use crate::persistence::redis::ConnectionConfig;
+use crate::swap;
+
+// ----- proof repository
+// spent and pending proofs are kept as the hex of their Y in two sets,
+// `<key>:spent` and `<key>:pending`. unknown proofs are unspent.
+// issued outputs are kept as json in the `<key>:issued` hash, by the hex of their B_
+#[derive(Clone)]
+pub struct RedisProofRepository {
+    conn: MultiplexedConnection,
+    spent: String,
+    pending: String,
+    issued: String,
+}
+
+// all or nothing: 1 if any proof is spent, 2 if any is pending, 0 once all are reserved
+const RESERVE_SCRIPT: &str = r"
+for _, y in ipairs(ARGV) do
+    if redis.call('SISMEMBER', KEYS[1], y) == 1 then return 1 end
+end
+for _, y in ipairs(ARGV) do
+    if redis.call('SISMEMBER', KEYS[2], y) == 1 then return 2 end
+end
+for _, y in ipairs(ARGV) do
+    redis.call('SADD', KEYS[2], y)
+end
+return 0
+";
+
+// all or nothing: 1 if any proof is spent or repeated, 0 once all are spent and
+// no longer pending
+const SPEND_SCRIPT: &str = r"
+local seen = {}
+for _, y in ipairs(ARGV) do
+    if seen[y] or redis.call('SISMEMBER', KEYS[1], y) == 1 then return 1 end
+    seen[y] = true
+end
+for _, y in ipairs(ARGV) do
+    redis.call('SADD', KEYS[1], y)
+    redis.call('SREM', KEYS[2], y)
+end
+return 0
+";
+
+impl RedisProofRepository {
+    pub async fn new(cfg: ConnectionConfig) -> redis::RedisResult<Self> {
+        let client = redis::Client::open(cfg.connection)?;
+        let conn = client.get_multiplexed_async_connection().await?;
+        Ok(Self {
+            conn,
+            spent: format!("{}:spent", cfg.key),
+            pending: format!("{}:pending", cfg.key),
+            issued: format!("{}:issued", cfg.key),
+        })
+    }
+
+    fn hex_ys(tokens: &[cdk00::Proof]) -> AnyResult<Vec<String>> {
+        tokens
+            .iter()
+            .map(|tk| {
+                let y = cdk::dhke::hash_to_curve(&tk.secret.to_bytes())?;
+                Ok(y.to_hex())
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl HealthCheck for RedisProofRepository {
+    async fn ready(&self) -> bool {
+        let mut conn = self.conn.clone();
+        redis::cmd("PING")
+            .query_async::<String>(&mut conn)
+            .await
+            .is_ok()
+    }
+}
+
+#[async_trait]
+impl swap::ProofRepository for RedisProofRepository {
+    async fn spend(&self, tokens: &[cdk00::Proof]) -> AnyResult<()> {
+        let ys = Self::hex_ys(tokens)?;
+        if ys.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.conn.clone();
+        let outcome: u8 = redis::Script::new(SPEND_SCRIPT)
+            .key(&self.spent)
+            .key(&self.pending)
+            .arg(ys)
+            .invoke_async(&mut conn)
+            .await?;
+        match outcome {
+            0 => Ok(()),
+            _ => Err(swap::Error::ProofsAlreadySpent.into()),
+        }
+    }
+
+    async fn get_state(&self, tokens: &[cdk00::Proof]) -> AnyResult<Vec<cdk07::State>> {
+        let ys = Self::hex_ys(tokens)?;
+        if ys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut conn = self.conn.clone();
+        let mut pipe = redis::pipe();
+        for y in &ys {
+            pipe.sismember(&self.spent, y).sismember(&self.pending, y);
+        }
+        let members: Vec<bool> = pipe.query_async(&mut conn).await?;
+        let states = members
+            .chunks(2)
+            .map(|flags| match flags {
+                [true, _] => cdk07::State::Spent,
+                [false, true] => cdk07::State::Pending,
+                _ => cdk07::State::Unspent,
+            })
+            .collect();
+        Ok(states)
+    }
+
+    async fn reserve(&self, tokens: &[cdk00::Proof]) -> AnyResult<()> {
+        let ys = Self::hex_ys(tokens)?;
+        if ys.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.conn.clone();
+        let outcome: u8 = redis::Script::new(RESERVE_SCRIPT)
+            .key(&self.spent)
+            .key(&self.pending)
+            .arg(ys)
+            .invoke_async(&mut conn)
+            .await?;
+        match outcome {
+            0 => Ok(()),
+            1 => Err(swap::Error::ProofsAlreadySpent.into()),
+            _ => Err(swap::Error::ProofsPending.into()),
+        }
+    }
+
+    async fn release(&self, tokens: &[cdk00::Proof]) -> AnyResult<()> {
+        let ys = Self::hex_ys(tokens)?;
+        if ys.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.conn.clone();
+        let _: () = conn.srem(&self.pending, ys).await?;
+        Ok(())
+    }
+
+    async fn record_issued(&self, outputs: &[cdk00::BlindedMessage]) -> AnyResult<()> {
+        if outputs.is_empty() {
+            return Ok(());
+        }
+        let mut entries: Vec<(String, String)> = Vec::with_capacity(outputs.len());
+        for output in outputs {
+            entries.push((
+                output.blinded_secret.to_hex(),
+                serde_json::to_string(output)?,
+            ));
+        }
+        let mut conn = self.conn.clone();
+        let _: () = conn.hset_multiple(&self.issued, &entries).await?;
+        Ok(())
+    }
+
+    async fn issued(
+        &self,
+        blinded_secrets: &[cdk01::PublicKey],
+    ) -> AnyResult<Vec<Option<cdk00::BlindedMessage>>> {
+        if blinded_secrets.is_empty() {
+            return Ok(Vec::new());
+        }
+        let fields: Vec<String> = blinded_secrets.iter().map(|b| b.to_hex()).collect();
+        let mut conn = self.conn.clone();
+        let mut pipe = redis::pipe();
+        for field in &fields {
+            pipe.hget(&self.issued, field);
+        }
+        let values: Vec<Option<String>> = pipe.query_async(&mut conn).await?;
+        values
+            .into_iter()
+            .map(|value| {
+                value
+                    .map(|json| serde_json::from_str(&json))
+                    .transpose()
+                    .map_err(Into::into)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::test_utils as keys_test;
+    use crate::swap::ProofRepository;
+    use crate::utils::tests as utils;
+
+    // run with a reachable Redis, e.g.
+    // WILDCAT_TEST_REDIS=redis://127.0.0.1 cargo test --features redis -- --ignored
+    async fn init_repo() -> RedisProofRepository {
+        let url = std::env::var("WILDCAT_TEST_REDIS").expect("WILDCAT_TEST_REDIS not set");
+        let cfg = ConnectionConfig {
+            connection: url,
+            key: format!("proofs_{}", uuid::Uuid::new_v4().simple()),
+        };
+        RedisProofRepository::new(cfg).await.unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Redis"]
+    async fn test_spend_and_get_state() {
+        let repo = init_repo().await;
+        let keyset = keys_test::generate_keyset();
+        let proofs = utils::generate_proofs(
+            &keyset,
+            &[cdk::Amount::from(8_u64), cdk::Amount::from(4_u64)],
+        );
+        repo.spend(&proofs[0..1]).await.unwrap();
+
+        let states = repo.get_state(&proofs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Spent, cdk07::State::Unspent]);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Redis"]
+    async fn test_spend_rejects_already_spent() {
+        let repo = init_repo().await;
+        let keyset = keys_test::generate_keyset();
+        let proofs = utils::generate_proofs(
+            &keyset,
+            &[cdk::Amount::from(8_u64), cdk::Amount::from(4_u64)],
+        );
+        repo.spend(&proofs[0..1]).await.unwrap();
+
+        let e = repo.spend(&proofs).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<swap::Error>(),
+            Some(swap::Error::ProofsAlreadySpent)
+        ));
+        // the batch is checked before any proof is added
+        let states = repo.get_state(&proofs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Spent, cdk07::State::Unspent]);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Redis"]
+    async fn test_reserve_release() {
+        let repo = init_repo().await;
+        let keyset = keys_test::generate_keyset();
+        let proofs = utils::generate_proofs(
+            &keyset,
+            &[cdk::Amount::from(8_u64), cdk::Amount::from(4_u64)],
+        );
+        repo.reserve(&proofs[0..1]).await.unwrap();
+        let e = repo.reserve(&proofs).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<swap::Error>(),
+            Some(swap::Error::ProofsPending)
+        ));
+        let states = repo.get_state(&proofs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Pending, cdk07::State::Unspent]);
+
+        repo.release(&proofs).await.unwrap();
+        repo.reserve(&proofs).await.unwrap();
+        repo.spend(&proofs).await.unwrap();
+        let states = repo.get_state(&proofs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Spent, cdk07::State::Spent]);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Redis"]
+    async fn test_spend_rejected_keeps_reserved_pending() {
+        let repo = init_repo().await;
+        let keyset = keys_test::generate_keyset();
+        let proofs = utils::generate_proofs(
+            &keyset,
+            &[cdk::Amount::from(8_u64), cdk::Amount::from(4_u64)],
+        );
+        repo.spend(&proofs[0..1]).await.unwrap();
+        repo.reserve(&proofs[1..2]).await.unwrap();
+
+        assert!(repo.spend(&proofs).await.is_err());
+        let states = repo.get_state(&proofs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Spent, cdk07::State::Pending]);
+    }
+}
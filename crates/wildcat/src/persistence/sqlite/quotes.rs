@@ -0,0 +1,914 @@
+#![allow(dead_code)]
+// ----- standard library imports
+use std::str::FromStr;
+// ----- extra library imports
+use anyhow::{anyhow, Error as AnyError, Result as AnyResult};
+use async_trait::async_trait;
+use cdk::nuts::nut00 as cdk00;
+use cdk::nuts::nut01 as cdk01;
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions, SqliteRow};
+use sqlx::Row;
+use uuid::Uuid;
+// ----- local modules
+// ----- local imports
+use crate::credit::quotes;
+use crate::persistence::sqlite::ConnectionConfig;
+use crate::TStamp;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumString)]
+enum DBQuoteStatus {
+    Pending,
+    Declined,
+    Cancelled,
+    Offered,
+    Accepted,
+    Expired,
+}
+impl From<&quotes::QuoteStatus> for DBQuoteStatus {
+    fn from(value: &quotes::QuoteStatus) -> Self {
+        match value {
+            quotes::QuoteStatus::Pending { .. } => Self::Pending,
+            quotes::QuoteStatus::Declined => Self::Declined,
+            quotes::QuoteStatus::Cancelled => Self::Cancelled,
+            quotes::QuoteStatus::Offered { .. } => Self::Offered,
+            quotes::QuoteStatus::Accepted { .. } => Self::Accepted,
+            quotes::QuoteStatus::Expired => Self::Expired,
+        }
+    }
+}
+
+// timestamps are stored as microseconds from unix epoch, so that sorting/filtering happens in SQL
+#[derive(Debug, Clone)]
+struct DBQuote {
+    quote_id: String,
+    bill: String,
+    endorser: String,
+    submitted: i64,
+    status: String,
+    blinds: Option<String>,
+    signatures: Option<String>,
+    // offered quotes keep their expiry in ttl
+    ttl: Option<i64>,
+    discount: Option<i64>,
+    fee: Option<i64>,
+    maturity: Option<i64>,
+    // face value of the bill
+    amount: Option<i64>,
+    // hex encoded
+    locked_to: Option<String>,
+}
+
+impl TryFrom<quotes::Quote> for DBQuote {
+    type Error = AnyError;
+    fn try_from(q: quotes::Quote) -> Result<Self, Self::Error> {
+        let status = DBQuoteStatus::from(&q.status).to_string();
+        let (blinds, signatures, ttl, discount, fee) = match q.status {
+            quotes::QuoteStatus::Pending { blinds } => (
+                Some(serde_json::to_string(&blinds)?),
+                None,
+                None,
+                None,
+                None,
+            ),
+            quotes::QuoteStatus::Declined
+            | quotes::QuoteStatus::Cancelled
+            | quotes::QuoteStatus::Expired => (None, None, None, None, None),
+            quotes::QuoteStatus::Offered {
+                blinds,
+                discount,
+                expiry,
+            } => (
+                Some(serde_json::to_string(&blinds)?),
+                None,
+                Some(expiry.timestamp_micros()),
+                Some(i64::try_from(u64::from(discount))?),
+                None,
+            ),
+            quotes::QuoteStatus::Accepted {
+                signatures,
+                ttl,
+                fee,
+            } => (
+                None,
+                Some(serde_json::to_string(&signatures)?),
+                Some(ttl.timestamp_micros()),
+                None,
+                Some(i64::try_from(u64::from(fee))?),
+            ),
+        };
+        Ok(Self {
+            quote_id: q.id.to_string(),
+            bill: q.bill,
+            endorser: q.endorser,
+            submitted: q.submitted.timestamp_micros(),
+            status,
+            blinds,
+            signatures,
+            ttl,
+            discount,
+            fee,
+            maturity: q.maturity.map(|m| m.timestamp_micros()),
+            amount: q
+                .amount
+                .map(|amount| i64::try_from(u64::from(amount)))
+                .transpose()?,
+            locked_to: q.locked_to.map(|key| key.to_hex()),
+        })
+    }
+}
+
+impl TryFrom<DBQuote> for quotes::Quote {
+    type Error = AnyError;
+    fn try_from(dbq: DBQuote) -> Result<Self, Self::Error> {
+        let status = match DBQuoteStatus::from_str(&dbq.status)? {
+            DBQuoteStatus::Pending => {
+                let blinds = dbq.blinds.ok_or_else(|| anyhow!("missing blinds"))?;
+                quotes::QuoteStatus::Pending {
+                    blinds: serde_json::from_str::<Vec<cdk00::BlindedMessage>>(&blinds)?,
+                }
+            }
+            DBQuoteStatus::Declined => quotes::QuoteStatus::Declined,
+            DBQuoteStatus::Cancelled => quotes::QuoteStatus::Cancelled,
+            DBQuoteStatus::Expired => quotes::QuoteStatus::Expired,
+            DBQuoteStatus::Offered => {
+                let blinds = dbq.blinds.ok_or_else(|| anyhow!("missing blinds"))?;
+                let discount = dbq.discount.ok_or_else(|| anyhow!("missing discount"))?;
+                let expiry = dbq.ttl.ok_or_else(|| anyhow!("missing expiry"))?;
+                quotes::QuoteStatus::Offered {
+                    blinds: serde_json::from_str::<Vec<cdk00::BlindedMessage>>(&blinds)?,
+                    discount: cdk::Amount::from(u64::try_from(discount)?),
+                    expiry: TStamp::from_timestamp_micros(expiry)
+                        .ok_or_else(|| anyhow!("invalid expiry"))?,
+                }
+            }
+            DBQuoteStatus::Accepted => {
+                let signatures = dbq
+                    .signatures
+                    .ok_or_else(|| anyhow!("missing signatures"))?;
+                let ttl = dbq.ttl.ok_or_else(|| anyhow!("missing ttl"))?;
+                // quotes accepted before fees were recorded have none
+                let fee = u64::try_from(dbq.fee.unwrap_or_default())?;
+                quotes::QuoteStatus::Accepted {
+                    signatures: serde_json::from_str::<Vec<cdk00::BlindSignature>>(&signatures)?,
+                    ttl: TStamp::from_timestamp_micros(ttl)
+                        .ok_or_else(|| anyhow!("invalid ttl"))?,
+                    fee: cdk::Amount::from(fee),
+                }
+            }
+        };
+        Ok(Self {
+            id: Uuid::from_str(&dbq.quote_id)?,
+            bill: dbq.bill,
+            endorser: dbq.endorser,
+            submitted: TStamp::from_timestamp_micros(dbq.submitted)
+                .ok_or_else(|| anyhow!("invalid submitted"))?,
+            status,
+            maturity: dbq
+                .maturity
+                .map(|m| {
+                    TStamp::from_timestamp_micros(m).ok_or_else(|| anyhow!("invalid maturity"))
+                })
+                .transpose()?,
+            amount: dbq
+                .amount
+                .map(|amount| u64::try_from(amount).map(cdk::Amount::from))
+                .transpose()?,
+            locked_to: dbq.locked_to.map(cdk01::PublicKey::from_hex).transpose()?,
+        })
+    }
+}
+
+impl TryFrom<SqliteRow> for DBQuote {
+    type Error = sqlx::Error;
+    fn try_from(row: SqliteRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            quote_id: row.try_get("quote_id")?,
+            bill: row.try_get("bill")?,
+            endorser: row.try_get("endorser")?,
+            submitted: row.try_get("submitted")?,
+            status: row.try_get("status")?,
+            blinds: row.try_get("blinds")?,
+            signatures: row.try_get("signatures")?,
+            ttl: row.try_get("ttl")?,
+            discount: row.try_get("discount")?,
+            fee: row.try_get("fee")?,
+            maturity: row.try_get("maturity")?,
+            amount: row.try_get("amount")?,
+            locked_to: row.try_get("locked_to")?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DB {
+    pool: SqlitePool,
+    table: String,
+}
+
+impl DB {
+    pub async fn new(cfg: ConnectionConfig) -> sqlx::Result<Self> {
+        let pool = SqlitePoolOptions::new().connect(&cfg.connection).await?;
+        let db = Self {
+            pool,
+            table: cfg.table,
+        };
+        db.migrate().await?;
+        Ok(db)
+    }
+
+    async fn migrate(&self) -> sqlx::Result<()> {
+        let table = &self.table;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {table} (
+                quote_id TEXT PRIMARY KEY NOT NULL,
+                bill TEXT NOT NULL,
+                endorser TEXT NOT NULL,
+                submitted INTEGER NOT NULL,
+                status TEXT NOT NULL,
+                blinds TEXT,
+                signatures TEXT,
+                ttl INTEGER,
+                discount INTEGER,
+                fee INTEGER,
+                maturity INTEGER,
+                amount INTEGER,
+                locked_to TEXT
+            )"
+        ))
+        .execute(&self.pool)
+        .await?;
+        // tables created before bills carried their maturity date, or quotes their lock
+        self.add_column_if_missing("maturity", "INTEGER").await?;
+        self.add_column_if_missing("locked_to", "TEXT").await?;
+        self.add_column_if_missing("amount", "INTEGER").await?;
+        // accepted quotes used to keep their fee in discount
+        if self.add_column_if_missing("fee", "INTEGER").await? {
+            sqlx::query(&format!(
+                "UPDATE {table} SET fee = discount, discount = NULL WHERE status = ?"
+            ))
+            .bind(DBQuoteStatus::Accepted.to_string())
+            .execute(&self.pool)
+            .await?;
+        }
+        sqlx::query(&format!(
+            "CREATE INDEX IF NOT EXISTS {table}_bill_endorser ON {table} (bill, endorser)"
+        ))
+        .execute(&self.pool)
+        .await?;
+        sqlx::query(&format!(
+            "CREATE INDEX IF NOT EXISTS {table}_endorser ON {table} (endorser, submitted)"
+        ))
+        .execute(&self.pool)
+        .await?;
+        sqlx::query(&format!(
+            "CREATE INDEX IF NOT EXISTS {table}_status ON {table} (status, submitted)"
+        ))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // true if the column was added
+    async fn add_column_if_missing(&self, column: &str, kind: &str) -> sqlx::Result<bool> {
+        let table = &self.table;
+        let existing: Option<String> = sqlx::query_scalar(&format!(
+            "SELECT name FROM pragma_table_info('{table}') WHERE name = ?"
+        ))
+        .bind(column)
+        .fetch_optional(&self.pool)
+        .await?;
+        if existing.is_none() {
+            sqlx::query(&format!("ALTER TABLE {table} ADD COLUMN {column} {kind}"))
+                .execute(&self.pool)
+                .await?;
+        }
+        Ok(existing.is_none())
+    }
+
+    async fn load(&self, qid: Uuid) -> sqlx::Result<Option<DBQuote>> {
+        sqlx::query(&format!("SELECT * FROM {} WHERE quote_id = ?", self.table))
+            .bind(qid.to_string())
+            .fetch_optional(&self.pool)
+            .await?
+            .map(DBQuote::try_from)
+            .transpose()
+    }
+
+    async fn store(&self, quote: DBQuote) -> sqlx::Result<()> {
+        sqlx::query(&format!(
+            "INSERT INTO {} (quote_id, bill, endorser, submitted, status, blinds, signatures, ttl, discount, fee, maturity, amount, locked_to)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            self.table
+        ))
+        .bind(quote.quote_id)
+        .bind(quote.bill)
+        .bind(quote.endorser)
+        .bind(quote.submitted)
+        .bind(quote.status)
+        .bind(quote.blinds)
+        .bind(quote.signatures)
+        .bind(quote.ttl)
+        .bind(quote.discount)
+        .bind(quote.fee)
+        .bind(quote.maturity)
+        .bind(quote.amount)
+        .bind(quote.locked_to)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn update_if_status(&self, quote: DBQuote, status: DBQuoteStatus) -> sqlx::Result<()> {
+        sqlx::query(&format!(
+            "UPDATE {} SET status = ?, blinds = ?, signatures = ?, ttl = ?, discount = ?, fee = ?, locked_to = ?
+            WHERE quote_id = ? AND status = ?",
+            self.table
+        ))
+        .bind(quote.status)
+        .bind(quote.blinds)
+        .bind(quote.signatures)
+        .bind(quote.ttl)
+        .bind(quote.discount)
+        .bind(quote.fee)
+        .bind(quote.locked_to)
+        .bind(quote.quote_id)
+        .bind(status.to_string())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // newest first, ties broken by id so that pages are stable across calls
+    async fn list_by_status(
+        &self,
+        status: DBQuoteStatus,
+        since: Option<TStamp>,
+        page: quotes::Page,
+    ) -> sqlx::Result<Vec<String>> {
+        let since = since.map(|s| s.timestamp_micros()).unwrap_or(i64::MIN);
+        sqlx::query(&format!(
+            "SELECT quote_id FROM {} WHERE status = ? AND submitted >= ?
+            ORDER BY submitted DESC, quote_id ASC LIMIT ? OFFSET ?",
+            self.table
+        ))
+        .bind(status.to_string())
+        .bind(since)
+        .bind(i64::try_from(page.limit).unwrap_or(i64::MAX))
+        .bind(i64::try_from(page.offset).unwrap_or(i64::MAX))
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| row.try_get("quote_id"))
+        .collect()
+    }
+
+    async fn purge_expired(&self, before: TStamp) -> sqlx::Result<u64> {
+        let before = before.timestamp_micros();
+        let result = sqlx::query(&format!(
+            "DELETE FROM {} WHERE (status IN (?, ?, ?) AND submitted < ?)
+            OR (status = ? AND ttl < ?)",
+            self.table
+        ))
+        .bind(DBQuoteStatus::Declined.to_string())
+        .bind(DBQuoteStatus::Cancelled.to_string())
+        .bind(DBQuoteStatus::Expired.to_string())
+        .bind(before)
+        .bind(DBQuoteStatus::Accepted.to_string())
+        .bind(before)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn search_by_bill(&self, bill: &str, endorser: &str) -> sqlx::Result<Option<DBQuote>> {
+        sqlx::query(&format!(
+            "SELECT * FROM {} WHERE bill = ? AND endorser = ? ORDER BY submitted DESC LIMIT 1",
+            self.table
+        ))
+        .bind(bill)
+        .bind(endorser)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(DBQuote::try_from)
+        .transpose()
+    }
+
+    async fn search_by_endorser(&self, endorser: &str) -> sqlx::Result<Vec<DBQuote>> {
+        sqlx::query(&format!(
+            "SELECT * FROM {} WHERE endorser = ? ORDER BY submitted DESC, quote_id ASC",
+            self.table
+        ))
+        .bind(endorser)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(DBQuote::try_from)
+        .collect()
+    }
+}
+
+#[async_trait]
+impl quotes::Repository for DB {
+    async fn load(&self, qid: uuid::Uuid) -> AnyResult<Option<quotes::Quote>> {
+        self.load(qid)
+            .await?
+            .map(std::convert::TryInto::try_into)
+            .transpose()
+    }
+
+    async fn update_if_pending(&self, new: quotes::Quote) -> AnyResult<()> {
+        if matches!(new.status, quotes::QuoteStatus::Pending { .. }) {
+            return Err(anyhow!("cannot update to pending"));
+        }
+        self.update_if_status(new.try_into()?, DBQuoteStatus::Pending)
+            .await?;
+        Ok(())
+    }
+
+    async fn update_if_offered(&self, new: quotes::Quote) -> AnyResult<()> {
+        if matches!(
+            new.status,
+            quotes::QuoteStatus::Pending { .. } | quotes::QuoteStatus::Offered { .. }
+        ) {
+            return Err(anyhow!("cannot update to pending or offered"));
+        }
+        self.update_if_status(new.try_into()?, DBQuoteStatus::Offered)
+            .await?;
+        Ok(())
+    }
+
+    async fn update_if_accepted(&self, new: quotes::Quote) -> AnyResult<()> {
+        if !matches!(new.status, quotes::QuoteStatus::Expired) {
+            return Err(anyhow!("accepted quotes can only expire"));
+        }
+        self.update_if_status(new.try_into()?, DBQuoteStatus::Accepted)
+            .await?;
+        Ok(())
+    }
+
+    async fn list_pendings(
+        &self,
+        since: Option<TStamp>,
+        page: quotes::Page,
+    ) -> AnyResult<Vec<Uuid>> {
+        self.list_by_status(DBQuoteStatus::Pending, since, page)
+            .await?
+            .iter()
+            .map(|qid| Uuid::from_str(qid).map_err(Into::into))
+            .collect()
+    }
+
+    async fn list_offered(
+        &self,
+        since: Option<TStamp>,
+        page: quotes::Page,
+    ) -> AnyResult<Vec<Uuid>> {
+        self.list_by_status(DBQuoteStatus::Offered, since, page)
+            .await?
+            .iter()
+            .map(|qid| Uuid::from_str(qid).map_err(Into::into))
+            .collect()
+    }
+
+    async fn list_accepteds(
+        &self,
+        since: Option<TStamp>,
+        page: quotes::Page,
+    ) -> AnyResult<Vec<Uuid>> {
+        self.list_by_status(DBQuoteStatus::Accepted, since, page)
+            .await?
+            .iter()
+            .map(|qid| Uuid::from_str(qid).map_err(Into::into))
+            .collect()
+    }
+
+    async fn search_by_bill(&self, bill: &str, endorser: &str) -> AnyResult<Option<quotes::Quote>> {
+        self.search_by_bill(bill, endorser)
+            .await?
+            .map(std::convert::TryInto::try_into)
+            .transpose()
+    }
+
+    async fn search_by_endorser(&self, endorser: &str) -> AnyResult<Vec<quotes::Quote>> {
+        self.search_by_endorser(endorser)
+            .await?
+            .into_iter()
+            .map(std::convert::TryInto::try_into)
+            .collect()
+    }
+
+    async fn store(&self, quote: quotes::Quote) -> AnyResult<()> {
+        self.store(quote.try_into()?).await?;
+        Ok(())
+    }
+
+    async fn purge_expired(&self, before: TStamp) -> AnyResult<usize> {
+        let purged = self.purge_expired(before).await?;
+        Ok(usize::try_from(purged)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credit::quotes::Repository;
+    use crate::keys::test_utils as keys_test;
+    use crate::utils::tests as utils;
+
+    async fn init_mem_db() -> DB {
+        // a single connection, otherwise every pooled connection opens its own in-memory DB
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let db = DB {
+            pool,
+            table: String::from("quotes"),
+        };
+        db.migrate().await.unwrap();
+        db
+    }
+
+    fn new_quote(bill: &str, endorser: &str, submitted: TStamp) -> quotes::Quote {
+        let keys = keys_test::generate_keyset();
+        let blinds = utils::generate_blinds(
+            &keys,
+            &[cdk::Amount::from(16_u64), cdk::Amount::from(8_u64)],
+        )
+        .into_iter()
+        .map(|b| b.0)
+        .collect();
+        quotes::Quote::new(
+            String::from(bill),
+            String::from(endorser),
+            blinds,
+            submitted,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_store_and_load() {
+        let db = init_mem_db().await;
+        let quote = new_quote("bill", "endorser", chrono::Utc::now());
+        let qid = quote.id;
+        Repository::store(&db, quote.clone()).await.unwrap();
+
+        let loaded = Repository::load(&db, qid).await.unwrap().unwrap();
+        assert_eq!(loaded.id, qid);
+        assert_eq!(loaded.bill, quote.bill);
+        assert_eq!(
+            loaded.submitted.timestamp_micros(),
+            quote.submitted.timestamp_micros()
+        );
+        let quotes::QuoteStatus::Pending { blinds } = loaded.status else {
+            panic!("quote is not pending");
+        };
+        let quotes::QuoteStatus::Pending { blinds: original } = quote.status else {
+            panic!("quote is not pending");
+        };
+        assert_eq!(blinds, original);
+    }
+
+    #[tokio::test]
+    async fn test_store_and_load_bill_maturity() {
+        let db = init_mem_db().await;
+        let now = chrono::Utc::now();
+        let bill = quotes::DecryptedBill {
+            id: String::from("bill"),
+            maturity: now + chrono::Duration::days(90),
+            amount: cdk::Amount::from(1000_u64),
+        };
+        let quote = quotes::Quote::from_bill(bill.clone(), String::from("endorser"), vec![], now);
+        Repository::store(&db, quote.clone()).await.unwrap();
+        let plain = new_quote("other", "endorser", now);
+        Repository::store(&db, plain.clone()).await.unwrap();
+
+        let loaded = Repository::load(&db, quote.id).await.unwrap().unwrap();
+        assert_eq!(
+            loaded.maturity.map(|m| m.timestamp_micros()),
+            Some(bill.maturity.timestamp_micros())
+        );
+        assert_eq!(loaded.amount, Some(bill.amount));
+        let loaded = Repository::load(&db, plain.id).await.unwrap().unwrap();
+        assert!(loaded.maturity.is_none());
+        assert!(loaded.amount.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_store_and_load_locked_to() {
+        let db = init_mem_db().await;
+        let mut quote = new_quote("bill", "endorser", chrono::Utc::now());
+        let key = cdk01::SecretKey::generate().public_key();
+        quote.lock_to(key).unwrap();
+        Repository::store(&db, quote.clone()).await.unwrap();
+
+        let loaded = Repository::load(&db, quote.id).await.unwrap().unwrap();
+        assert_eq!(loaded.locked_to, Some(key));
+    }
+
+    #[tokio::test]
+    async fn test_update_if_pending() {
+        let db = init_mem_db().await;
+        let mut quote = new_quote("bill", "endorser", chrono::Utc::now());
+        let qid = quote.id;
+        Repository::store(&db, quote.clone()).await.unwrap();
+
+        let ttl = chrono::Utc::now();
+        quote.accept(vec![], ttl, cdk::Amount::ZERO).unwrap();
+        Repository::update_if_pending(&db, quote.clone())
+            .await
+            .unwrap();
+        let loaded = Repository::load(&db, qid).await.unwrap().unwrap();
+        assert!(matches!(
+            loaded.status,
+            quotes::QuoteStatus::Accepted { ttl: t, .. } if t.timestamp_micros() == ttl.timestamp_micros()
+        ));
+
+        // already resolved, no further updates
+        quote.status = quotes::QuoteStatus::Declined;
+        Repository::update_if_pending(&db, quote).await.unwrap();
+        let loaded = Repository::load(&db, qid).await.unwrap().unwrap();
+        assert!(matches!(
+            loaded.status,
+            quotes::QuoteStatus::Accepted { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_search_by_bill() {
+        let db = init_mem_db().await;
+        let now = chrono::Utc::now();
+        let old = new_quote("bill", "endorser", now - chrono::Duration::days(1));
+        let new = new_quote("bill", "endorser", now);
+        let other = new_quote("bill", "other", now);
+        Repository::store(&db, old).await.unwrap();
+        Repository::store(&db, new.clone()).await.unwrap();
+        Repository::store(&db, other).await.unwrap();
+
+        let found = Repository::search_by_bill(&db, "bill", "endorser")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.id, new.id);
+        let found = Repository::search_by_bill(&db, "bill", "nobody")
+            .await
+            .unwrap();
+        assert!(found.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_search_by_endorser() {
+        let db = init_mem_db().await;
+        let now = chrono::Utc::now();
+        let old = new_quote("bill1", "endorser", now - chrono::Duration::days(1));
+        let new = new_quote("bill2", "endorser", now);
+        let other = new_quote("bill1", "other", now);
+        Repository::store(&db, old.clone()).await.unwrap();
+        Repository::store(&db, new.clone()).await.unwrap();
+        Repository::store(&db, other).await.unwrap();
+
+        let found = Repository::search_by_endorser(&db, "endorser")
+            .await
+            .unwrap();
+        let ids: Vec<_> = found.iter().map(|q| q.id).collect();
+        assert_eq!(ids, vec![new.id, old.id]);
+        let found = Repository::search_by_endorser(&db, "nobody").await.unwrap();
+        assert!(found.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_list_pendings_since() {
+        let db = init_mem_db().await;
+        let now = chrono::Utc::now();
+        let old = new_quote("bill1", "endorser", now - chrono::Duration::days(2));
+        let new = new_quote("bill2", "endorser", now);
+        let mut accepted = new_quote("bill3", "endorser", now);
+        accepted.accept(vec![], now, cdk::Amount::ZERO).unwrap();
+        Repository::store(&db, old.clone()).await.unwrap();
+        Repository::store(&db, new.clone()).await.unwrap();
+        Repository::store(&db, accepted.clone()).await.unwrap();
+
+        let pendings = Repository::list_pendings(&db, None, quotes::Page::default())
+            .await
+            .unwrap();
+        assert_eq!(pendings, vec![new.id, old.id]);
+        let since = now - chrono::Duration::days(1);
+        let pendings = Repository::list_pendings(&db, Some(since), quotes::Page::default())
+            .await
+            .unwrap();
+        assert_eq!(pendings, vec![new.id]);
+        let accepteds = Repository::list_accepteds(&db, None, quotes::Page::default())
+            .await
+            .unwrap();
+        assert_eq!(accepteds, vec![accepted.id]);
+    }
+
+    #[tokio::test]
+    async fn test_list_pendings_paginated() {
+        let db = init_mem_db().await;
+        let now = chrono::Utc::now();
+        let mut expected = Vec::new();
+        for i in 0..5 {
+            let quote = new_quote(
+                &format!("bill{i}"),
+                "endorser",
+                now - chrono::Duration::hours(i),
+            );
+            expected.push(quote.id);
+            Repository::store(&db, quote).await.unwrap();
+        }
+
+        let page = quotes::Page {
+            offset: 0,
+            limit: 2,
+        };
+        let first = Repository::list_pendings(&db, None, page).await.unwrap();
+        assert_eq!(first, expected[0..2]);
+        let page = quotes::Page {
+            offset: 2,
+            limit: 2,
+        };
+        let second = Repository::list_pendings(&db, None, page).await.unwrap();
+        assert_eq!(second, expected[2..4]);
+        let page = quotes::Page {
+            offset: 4,
+            limit: 2,
+        };
+        let last = Repository::list_pendings(&db, None, page).await.unwrap();
+        assert_eq!(last, expected[4..]);
+        let page = quotes::Page {
+            offset: 10,
+            limit: 2,
+        };
+        let past_end = Repository::list_pendings(&db, None, page).await.unwrap();
+        assert!(past_end.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_purge_expired() {
+        let db = init_mem_db().await;
+        let now = chrono::Utc::now();
+        let old = now - chrono::Duration::days(30);
+        let pending = new_quote("bill1", "endorser", old);
+        let mut declined = new_quote("bill2", "endorser", old);
+        declined.decline().unwrap();
+        let mut accepted = new_quote("bill3", "endorser", old);
+        accepted.accept(vec![], now, cdk::Amount::ZERO).unwrap();
+        for quote in [&pending, &declined, &accepted] {
+            Repository::store(&db, quote.clone()).await.unwrap();
+        }
+
+        let purged = Repository::purge_expired(&db, now - chrono::Duration::days(1))
+            .await
+            .unwrap();
+        assert_eq!(purged, 1);
+        assert!(Repository::load(&db, declined.id).await.unwrap().is_none());
+        assert!(Repository::load(&db, pending.id).await.unwrap().is_some());
+        assert!(Repository::load(&db, accepted.id).await.unwrap().is_some());
+
+        let purged = Repository::purge_expired(&db, now + chrono::Duration::days(1))
+            .await
+            .unwrap();
+        assert_eq!(purged, 1);
+        assert!(Repository::load(&db, pending.id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_offer_and_accept() {
+        let db = init_mem_db().await;
+        let mut quote = new_quote("bill", "endorser", chrono::Utc::now());
+        let qid = quote.id;
+        Repository::store(&db, quote.clone()).await.unwrap();
+
+        let expiry = chrono::Utc::now();
+        quote.offer(cdk::Amount::from(20_u64), expiry).unwrap();
+        Repository::update_if_pending(&db, quote.clone())
+            .await
+            .unwrap();
+        let loaded = Repository::load(&db, qid).await.unwrap().unwrap();
+        assert!(matches!(
+            loaded.status,
+            quotes::QuoteStatus::Offered { ref blinds, discount, expiry: e }
+                if blinds.len() == 2 && discount == cdk::Amount::from(20_u64)
+                    && e.timestamp_micros() == expiry.timestamp_micros()
+        ));
+        assert_eq!(
+            Repository::list_offered(&db, None, quotes::Page::default())
+                .await
+                .unwrap(),
+            vec![qid]
+        );
+        assert!(
+            Repository::list_pendings(&db, None, quotes::Page::default())
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        quote
+            .accept_offer(
+                vec![],
+                chrono::Utc::now(),
+                &quotes::TtlPolicy::default(),
+                cdk::Amount::ZERO,
+            )
+            .unwrap();
+        Repository::update_if_offered(&db, quote).await.unwrap();
+        let loaded = Repository::load(&db, qid).await.unwrap().unwrap();
+        assert!(matches!(
+            loaded.status,
+            quotes::QuoteStatus::Accepted { .. }
+        ));
+        let fee: Option<i64> = sqlx::query_scalar("SELECT fee FROM quotes WHERE quote_id = ?")
+            .bind(qid.to_string())
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(fee, Some(0));
+        assert!(Repository::list_offered(&db, None, quotes::Page::default())
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            Repository::list_accepteds(&db, None, quotes::Page::default())
+                .await
+                .unwrap(),
+            vec![qid]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migrate_moves_fee_out_of_discount() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        // the schema before fees had a column of their own
+        sqlx::query(
+            "CREATE TABLE quotes (
+                quote_id TEXT PRIMARY KEY NOT NULL,
+                bill TEXT NOT NULL,
+                endorser TEXT NOT NULL,
+                submitted INTEGER NOT NULL,
+                status TEXT NOT NULL,
+                blinds TEXT,
+                signatures TEXT,
+                ttl INTEGER,
+                discount INTEGER,
+                maturity INTEGER,
+                locked_to TEXT
+            )",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let now = chrono::Utc::now();
+        let accepted = Uuid::new_v4();
+        let offered = Uuid::new_v4();
+        for (qid, status, blinds, signatures) in [
+            (accepted, "Accepted", None, Some("[]")),
+            (offered, "Offered", Some("[]"), None),
+        ] {
+            sqlx::query(
+                "INSERT INTO quotes (quote_id, bill, endorser, submitted, status, blinds, signatures, ttl, discount)
+                VALUES (?, 'bill', 'endorser', ?, ?, ?, ?, ?, 5)",
+            )
+            .bind(qid.to_string())
+            .bind(now.timestamp_micros())
+            .bind(status)
+            .bind(blinds)
+            .bind(signatures)
+            .bind(now.timestamp_micros())
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        let db = DB {
+            pool,
+            table: String::from("quotes"),
+        };
+        db.migrate().await.unwrap();
+        // a second run finds the column and leaves the rows alone
+        db.migrate().await.unwrap();
+
+        let loaded = Repository::load(&db, accepted).await.unwrap().unwrap();
+        assert!(matches!(
+            loaded.status,
+            quotes::QuoteStatus::Accepted { fee, .. } if fee == cdk::Amount::from(5_u64)
+        ));
+        let discount: Option<i64> =
+            sqlx::query_scalar("SELECT discount FROM quotes WHERE quote_id = ?")
+                .bind(accepted.to_string())
+                .fetch_one(&db.pool)
+                .await
+                .unwrap();
+        assert!(discount.is_none());
+        let loaded = Repository::load(&db, offered).await.unwrap().unwrap();
+        assert!(matches!(
+            loaded.status,
+            quotes::QuoteStatus::Offered { discount, .. } if discount == cdk::Amount::from(5_u64)
+        ));
+    }
+}
@@ -2,7 +2,7 @@ use std::collections::BTreeMap;
 // ----- standard library imports
 use std::collections::HashMap;
 // ----- extra library imports
-use anyhow::Result as AnyResult;
+use anyhow::{anyhow, Result as AnyResult};
 use async_trait::async_trait;
 use cdk::nuts::nut00 as cdk00;
 use cdk::nuts::nut01 as cdk01;
@@ -14,8 +14,10 @@ use uuid::Uuid;
 // ----- local modules
 // ----- local imports
 use crate::credit::keys as creditkeys;
+use crate::health::HealthCheck;
 use crate::keys;
 use crate::persistence::surreal::ConnectionConfig;
+use crate::TStamp;
 
 // ----- keys repository
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -83,7 +85,8 @@ impl KeysDB {
     async fn store(&self, keys: keys::KeysetEntry) -> AnyResult<()> {
         let dbkeys = DBKeys::from(keys);
         let rid = RecordId::from_table_key(self.table.clone(), dbkeys.info.id.to_string());
-        let _resp: Option<DBKeys> = self.db.insert(rid).content(dbkeys).await?;
+        // upsert: re-storing a keyset replaces it, e.g. to deactivate it on rotation
+        let _resp: Option<DBKeys> = self.db.upsert(rid).content(dbkeys).await?;
         Ok(())
     }
 
@@ -94,6 +97,13 @@ impl KeysDB {
     }
 }
 
+#[async_trait]
+impl HealthCheck for KeysDB {
+    async fn ready(&self) -> bool {
+        self.db.health().await.is_ok()
+    }
+}
+
 #[async_trait]
 impl keys::Repository for KeysDB {
     async fn info(&self, kid: &keys::KeysetID) -> AnyResult<Option<cdk::mint::MintKeySetInfo>> {
@@ -103,7 +113,7 @@ impl keys::Repository for KeysDB {
             .query("SELECT info FROM $rid")
             .bind(("rid", rid))
             .await?
-            .take(0)?;
+            .take((0, "info"))?;
         Ok(result)
     }
 
@@ -124,6 +134,57 @@ impl keys::Repository for KeysDB {
     ) -> AnyResult<()> {
         self.store((info, keyset)).await
     }
+
+    async fn list_info(&self) -> AnyResult<Vec<cdk::mint::MintKeySetInfo>> {
+        let result: Vec<cdk::mint::MintKeySetInfo> = self
+            .db
+            .query("SELECT VALUE info FROM type::table($table)")
+            .bind(("table", self.table.clone()))
+            .await?
+            .take(0)?;
+        Ok(result)
+    }
+}
+
+// issued amounts live next to the keysets, in `<table>_issued`, so that re-storing a keyset
+// (e.g. on rotation) leaves them untouched
+#[async_trait]
+impl creditkeys::MaturityKeyRepository for KeysDB {
+    async fn record_issued(&self, kid: &keys::KeysetID, amount: cdk::Amount) -> AnyResult<()> {
+        let rid = RecordId::from_table_key(format!("{}_issued", self.table), kid.to_string());
+        self.db
+            .query("UPSERT $rid SET amount += $amount")
+            .bind(("rid", rid))
+            .bind(("amount", u64::from(amount)))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn issued(&self, kid: &keys::KeysetID) -> AnyResult<cdk::Amount> {
+        let rid = RecordId::from_table_key(format!("{}_issued", self.table), kid.to_string());
+        let issued: Option<u64> = self
+            .db
+            .query("SELECT VALUE amount FROM $rid")
+            .bind(("rid", rid))
+            .await?
+            .take(0)?;
+        Ok(cdk::Amount::from(issued.unwrap_or_default()))
+    }
+
+    // maturity keysets are identified by the day of their maturity, as their ids
+    async fn current_rotation(&self, maturity_date: TStamp) -> AnyResult<Option<u32>> {
+        let day = maturity_date.timestamp().div_euclid(86400) * 86400;
+        let result: Option<u32> = self
+            .db
+            .query("RETURN math::max(SELECT VALUE info.derivation_path_index FROM type::table($table) WHERE info.valid_to >= $from AND info.valid_to < $to AND info.derivation_path_index != NONE)")
+            .bind(("table", self.table.clone()))
+            .bind(("from", day))
+            .bind(("to", day + 86400))
+            .await?
+            .take(0)?;
+        Ok(result)
+    }
 }
 
 // ----- quote-based keys repository
@@ -133,6 +194,13 @@ struct DBQuoteKeys {
     data: DBKeys,
 }
 
+// who a quote keyset id was generated for, in `<table>_owner` keyed by the id
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct DBOwner {
+    bill: String,
+    endorser: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct QuoteKeysDB {
     db: Surreal<surrealdb::engine::any::Any>,
@@ -159,15 +227,52 @@ impl creditkeys::QuoteBasedRepository for QuoteKeysDB {
         Ok(res.map(|dbqk| dbqk.data.into()))
     }
 
+    async fn search_by_kid(&self, kid: &keys::KeysetID) -> AnyResult<Option<keys::KeysetEntry>> {
+        let res: Option<DBQuoteKeys> = self
+            .db
+            .query("SELECT * FROM type::table($table) WHERE data.info.id == $kid LIMIT 1")
+            .bind(("table", self.table.clone()))
+            .bind(("kid", cdk02::Id::from(*kid)))
+            .await?
+            .take(0)?;
+        Ok(res.map(|dbqk| dbqk.data.into()))
+    }
+
+    async fn load_by_bill(&self, bill: &str, node: &str) -> AnyResult<Vec<keys::KeysetEntry>> {
+        let kid = keys::credit::generate_keyset_id_from_bill(bill, node);
+        let res: Vec<DBQuoteKeys> = self
+            .db
+            .query("SELECT * FROM type::table($table) WHERE data.info.id == $kid")
+            .bind(("table", self.table.clone()))
+            .bind(("kid", cdk02::Id::from(kid)))
+            .await?
+            .take(0)?;
+        Ok(res.into_iter().map(|dbqk| dbqk.data.into()).collect())
+    }
+
+    // records are keyed by quote id, a quote holds one keyset at most
+    async fn load_by_quote(&self, qid: Uuid) -> AnyResult<Vec<keys::KeysetEntry>> {
+        let res: Option<DBQuoteKeys> = self.db.select((self.table.clone(), qid)).await?;
+        Ok(res.into_iter().map(|dbqk| dbqk.data.into()).collect())
+    }
+
     async fn store(
         &self,
         qid: Uuid,
         keyset: cdk02::MintKeySet,
         info: cdk::mint::MintKeySetInfo,
     ) -> AnyResult<()> {
+        let kid = keys::KeysetID::from(keyset.id);
+        let entry = (info, keyset);
+        if let Some(existing) = creditkeys::QuoteBasedRepository::load(self, &kid, qid).await? {
+            if existing != entry {
+                return Err(anyhow!("keyset {} for quote {} already stored", kid, qid));
+            }
+            return Ok(());
+        }
         let dbqk = DBQuoteKeys {
             qid,
-            data: DBKeys::from((info, keyset)),
+            data: DBKeys::from(entry),
         };
         let _: Option<DBQuoteKeys> = self
             .db
@@ -176,6 +281,22 @@ impl creditkeys::QuoteBasedRepository for QuoteKeysDB {
             .await?;
         Ok(())
     }
+
+    async fn owner(&self, kid: &keys::KeysetID) -> AnyResult<Option<(String, String)>> {
+        let rid = RecordId::from_table_key(format!("{}_owner", self.table), kid.to_string());
+        let owner: Option<DBOwner> = self.db.select(rid).await?;
+        Ok(owner.map(|owner| (owner.bill, owner.endorser)))
+    }
+
+    async fn set_owner(&self, kid: &keys::KeysetID, bill: &str, endorser: &str) -> AnyResult<()> {
+        let rid = RecordId::from_table_key(format!("{}_owner", self.table), kid.to_string());
+        let owner = DBOwner {
+            bill: String::from(bill),
+            endorser: String::from(endorser),
+        };
+        let _: Option<DBOwner> = self.db.upsert(rid).content(owner).await?;
+        Ok(())
+    }
 }
 
 // ----- keys repository with active keyset reference
@@ -183,12 +304,13 @@ impl creditkeys::QuoteBasedRepository for QuoteKeysDB {
 #[async_trait]
 impl keys::ActiveRepository for KeysDB {
     async fn info_active(&self) -> AnyResult<Option<cdk::mint::MintKeySetInfo>> {
+        // ORDER BY needs the sorting field among the selected ones
         let result: Option<cdk::mint::MintKeySetInfo> = self
             .db
-            .query("SELECT info FROM type::table($table) WHERE info.active is true ORDER BY info.valid_from DESC")
+            .query("SELECT info, info.valid_from AS valid_from FROM type::table($table) WHERE info.active is true ORDER BY valid_from DESC LIMIT 1")
             .bind(("table", self.table.clone()))
             .await?
-            .take(0)?;
+            .take((0, "info"))?;
         Ok(result)
     }
     async fn keyset_active(&self) -> AnyResult<Option<cdk02::MintKeySet>> {
@@ -202,4 +324,264 @@ impl keys::ActiveRepository for KeysDB {
             .map(keys::KeysetEntry::from)
             .map(|(_, keyset)| keyset))
     }
+
+    async fn set_active(&self, kid: keys::KeysetID) -> AnyResult<()> {
+        if self.load(&kid).await?.is_none() {
+            return Err(anyhow!("unknown keyset {}", kid));
+        }
+        // the active keyset is the only one flagged as such
+        let rid = RecordId::from_table_key(self.table.clone(), kid.to_string());
+        self.db
+            .query("BEGIN TRANSACTION")
+            .query("UPDATE type::table($table) SET info.active = false WHERE info.active IS true AND id != $rid")
+            .query("UPDATE $rid SET info.active = true")
+            .query("COMMIT TRANSACTION")
+            .bind(("table", self.table.clone()))
+            .bind(("rid", rid))
+            .await?
+            .check()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::test_utils as keys_test;
+    use crate::keys::ActiveRepository;
+    use std::str::FromStr;
+
+    async fn init_mem_db() -> KeysDB {
+        let sdb = Surreal::<Any>::init();
+        sdb.connect("mem://").await.unwrap();
+        sdb.use_ns("test").await.unwrap();
+        sdb.use_db("test").await.unwrap();
+        KeysDB {
+            db: sdb,
+            table: "test".to_string(),
+        }
+    }
+
+    fn generate_entry(path: &str, valid_from: u64) -> keys::KeysetEntry {
+        let path = bitcoin::bip32::DerivationPath::from_str(path).unwrap();
+        let keyset = cdk02::MintKeySet::generate_from_seed(
+            &bitcoin::secp256k1::Secp256k1::new(),
+            &[],
+            4,
+            cdk00::CurrencyUnit::Sat,
+            path.clone(),
+        );
+        let info = cdk::mint::MintKeySetInfo {
+            id: keyset.id,
+            unit: keyset.unit.clone(),
+            active: true,
+            valid_from,
+            valid_to: None,
+            derivation_path: path,
+            derivation_path_index: None,
+            max_order: 4,
+            input_fee_ppk: 0,
+        };
+        (info, keyset)
+    }
+
+    #[tokio::test]
+    async fn test_set_active() {
+        let db = init_mem_db().await;
+        let (old_info, old_keyset) = generate_entry("m/0'/0'", 1);
+        let (new_info, new_keyset) = generate_entry("m/0'/1'", 2);
+        db.store((old_info.clone(), old_keyset)).await.unwrap();
+        db.store((new_info.clone(), new_keyset)).await.unwrap();
+        let active = db.info_active().await.unwrap().unwrap();
+        assert_eq!(active.id, new_info.id);
+
+        db.set_active(keys::KeysetID::from(old_info.id))
+            .await
+            .unwrap();
+        let active = db.info_active().await.unwrap().unwrap();
+        assert_eq!(active.id, old_info.id);
+        let active = db.keyset_active().await.unwrap().unwrap();
+        assert_eq!(active.id, old_info.id);
+        let info = keys::Repository::info(&db, &keys::KeysetID::from(new_info.id))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!info.active);
+
+        let unknown = keys_test::generate_random_keysetid();
+        assert!(db.set_active(unknown).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_quote_keys_search_by_kid() {
+        let sdb = Surreal::<Any>::init();
+        sdb.connect("mem://").await.unwrap();
+        sdb.use_ns("test").await.unwrap();
+        sdb.use_db("test").await.unwrap();
+        let db = QuoteKeysDB {
+            db: sdb,
+            table: "test".to_string(),
+        };
+        let (info, keyset) = generate_entry("m/0'/0'", 1);
+        let kid = keys::KeysetID::from(keyset.id);
+        let qid = Uuid::new_v4();
+        creditkeys::QuoteBasedRepository::store(&db, qid, keyset.clone(), info.clone())
+            .await
+            .unwrap();
+
+        let found = creditkeys::QuoteBasedRepository::search_by_kid(&db, &kid)
+            .await
+            .unwrap();
+        assert_eq!(found, Some((info.clone(), keyset.clone())));
+        let missing = keys_test::generate_random_keysetid();
+        let found = creditkeys::QuoteBasedRepository::search_by_kid(&db, &missing)
+            .await
+            .unwrap();
+        assert!(found.is_none());
+
+        let mut other = info;
+        other.valid_from = 2;
+        let r = creditkeys::QuoteBasedRepository::store(&db, qid, keyset, other).await;
+        assert!(r.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_quote_keys_load_by_bill() {
+        let sdb = Surreal::<Any>::init();
+        sdb.connect("mem://").await.unwrap();
+        sdb.use_ns("test").await.unwrap();
+        sdb.use_db("test").await.unwrap();
+        let db = QuoteKeysDB {
+            db: sdb,
+            table: "test".to_string(),
+        };
+        let kid = keys::credit::generate_keyset_id_from_bill("billID", "endorserID");
+        let mut stored = Vec::new();
+        for path in ["m/0'/0'", "m/0'/1'"] {
+            let (mut info, mut keyset) = generate_entry(path, 1);
+            info.id = kid.into();
+            keyset.id = kid.into();
+            creditkeys::QuoteBasedRepository::store(
+                &db,
+                Uuid::new_v4(),
+                keyset.clone(),
+                info.clone(),
+            )
+            .await
+            .unwrap();
+            stored.push((info, keyset));
+        }
+
+        let mut found = creditkeys::QuoteBasedRepository::load_by_bill(&db, "billID", "endorserID")
+            .await
+            .unwrap();
+        found.sort_by(|a, b| a.0.derivation_path.cmp(&b.0.derivation_path));
+        assert_eq!(found, stored);
+        let found = creditkeys::QuoteBasedRepository::load_by_bill(&db, "billID", "otherID")
+            .await
+            .unwrap();
+        assert!(found.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_quote_keys_load_by_quote() {
+        let sdb = Surreal::<Any>::init();
+        sdb.connect("mem://").await.unwrap();
+        sdb.use_ns("test").await.unwrap();
+        sdb.use_db("test").await.unwrap();
+        let db = QuoteKeysDB {
+            db: sdb,
+            table: "test".to_string(),
+        };
+        let (info, keyset) = generate_entry("m/0'/0'", 1);
+        let qid = Uuid::new_v4();
+        creditkeys::QuoteBasedRepository::store(&db, qid, keyset.clone(), info.clone())
+            .await
+            .unwrap();
+
+        let found = creditkeys::QuoteBasedRepository::load_by_quote(&db, qid)
+            .await
+            .unwrap();
+        assert_eq!(found, vec![(info, keyset)]);
+        let found = creditkeys::QuoteBasedRepository::load_by_quote(&db, Uuid::new_v4())
+            .await
+            .unwrap();
+        assert!(found.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_list_info() {
+        let db = init_mem_db().await;
+        assert!(keys::Repository::list_info(&db).await.unwrap().is_empty());
+        let (first, keyset) = generate_entry("m/0'/0'", 1);
+        db.store((first.clone(), keyset)).await.unwrap();
+        let (second, keyset) = generate_entry("m/0'/1'", 2);
+        db.store((second.clone(), keyset)).await.unwrap();
+
+        let mut infos = keys::Repository::list_info(&db).await.unwrap();
+        infos.sort_by_key(|info| info.valid_from);
+        assert_eq!(infos, vec![first, second]);
+    }
+
+    #[tokio::test]
+    async fn test_issued() {
+        let db = init_mem_db().await;
+        let (info, keyset) = generate_entry("m/0'/0'", 1);
+        let kid = keys::KeysetID::from(info.id);
+        db.store((info.clone(), keyset.clone())).await.unwrap();
+        assert_eq!(
+            creditkeys::MaturityKeyRepository::issued(&db, &kid)
+                .await
+                .unwrap(),
+            cdk::Amount::ZERO
+        );
+
+        creditkeys::MaturityKeyRepository::record_issued(&db, &kid, cdk::Amount::from(8_u64))
+            .await
+            .unwrap();
+        creditkeys::MaturityKeyRepository::record_issued(&db, &kid, cdk::Amount::from(2_u64))
+            .await
+            .unwrap();
+        // re-storing the keyset keeps the issued amount
+        db.store((info, keyset)).await.unwrap();
+        assert_eq!(
+            creditkeys::MaturityKeyRepository::issued(&db, &kid)
+                .await
+                .unwrap(),
+            cdk::Amount::from(10_u64)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_current_rotation() {
+        let db = init_mem_db().await;
+        let maturity = chrono::DateTime::parse_from_rfc3339("2021-01-01T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        assert_eq!(
+            creditkeys::MaturityKeyRepository::current_rotation(&db, maturity)
+                .await
+                .unwrap(),
+            None
+        );
+
+        // rotation 1 is missing, the next day has a higher one
+        let rotations = [
+            (maturity, 0),
+            (maturity, 2),
+            (maturity + chrono::Duration::days(1), 5),
+        ];
+        for (idx, (date, rotation_idx)) in rotations.into_iter().enumerate() {
+            let (mut info, keyset) = generate_entry(&format!("m/0'/{idx}'"), 1);
+            info.valid_to = Some(date.timestamp() as u64);
+            info.derivation_path_index = Some(rotation_idx);
+            db.store((info, keyset)).await.unwrap();
+        }
+        assert_eq!(
+            creditkeys::MaturityKeyRepository::current_rotation(&db, maturity)
+                .await
+                .unwrap(),
+            Some(2)
+        );
+    }
 }
@@ -10,6 +10,8 @@ use surrealdb::Result as SurrealResult;
 use surrealdb::{engine::any::Any, Surreal};
 // ----- local modules
 // ----- local imports
+use crate::debit;
+use crate::health::HealthCheck;
 use crate::persistence::surreal::ConnectionConfig;
 use crate::swap;
 
@@ -20,6 +22,16 @@ pub struct DBProof {
     state: cdk07::State,
 }
 
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct DBIssued {
+    output: cdk00::BlindedMessage,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct DBPaymentToken {
+    token: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct DB {
     db: Surreal<surrealdb::engine::any::Any>,
@@ -39,31 +51,96 @@ impl DB {
     }
 }
 
-#[async_trait]
-impl swap::ProofRepository for DB {
-    async fn spend(&self, tokens: &[cdk00::Proof]) -> AnyResult<()> {
+impl DB {
+    fn record_ids(&self, tokens: &[cdk00::Proof]) -> AnyResult<Vec<RecordId>> {
+        tokens
+            .iter()
+            .map(|tk| {
+                let y = cdk::dhke::hash_to_curve(&tk.secret.to_bytes())?;
+                Ok(RecordId::from_table_key(&self.table, y.to_string()))
+            })
+            .collect()
+    }
+
+    fn issued_record_id(&self, blinded_secret: &cdk01::PublicKey) -> RecordId {
+        RecordId::from_table_key(format!("{}_issued", self.table), blinded_secret.to_string())
+    }
+
+    fn token_record_id(&self, token: &str) -> RecordId {
+        RecordId::from_table_key(format!("{}_tokens", self.table), token)
+    }
+
+    fn entries(&self, tokens: &[cdk00::Proof], state: cdk07::State) -> AnyResult<Vec<DBProof>> {
         let mut entries: Vec<DBProof> = Vec::with_capacity(tokens.len());
         for tk in tokens {
             let y = cdk::dhke::hash_to_curve(&tk.secret.to_bytes())?;
             let rid = RecordId::from_table_key(&self.table, y.to_string());
-            entries.push(DBProof {
-                id: rid,
-                y,
-                state: cdk07::State::Spent,
-            });
+            entries.push(DBProof { id: rid, y, state });
         }
-        let _: Vec<DBProof> = self.db.insert(&self.table).content(entries).await?;
-        Ok(())
+        Ok(entries)
+    }
+}
+
+const THROW_SPENT: &str = "proofs already spent";
+const THROW_PENDING: &str = "proofs pending";
+
+// in a failed transaction every statement reports an error, the THROWn one tells why:
+// map it back to swap::Error
+fn check_thrown(mut response: surrealdb::Response) -> AnyResult<()> {
+    let mut errors: Vec<_> = response.take_errors().into_iter().collect();
+    errors.sort_by_key(|(idx, _)| *idx);
+    let mut first = None;
+    for (_, e) in errors {
+        match e {
+            surrealdb::Error::Db(surrealdb::error::Db::Thrown(msg)) if msg == THROW_SPENT => {
+                return Err(swap::Error::ProofsAlreadySpent.into());
+            }
+            surrealdb::Error::Db(surrealdb::error::Db::Thrown(msg)) if msg == THROW_PENDING => {
+                return Err(swap::Error::ProofsPending.into());
+            }
+            e => {
+                first.get_or_insert(e);
+            }
+        }
+    }
+    match first {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+#[async_trait]
+impl HealthCheck for DB {
+    async fn ready(&self) -> bool {
+        self.db.health().await.is_ok()
+    }
+}
+
+#[async_trait]
+impl swap::ProofRepository for DB {
+    async fn spend(&self, tokens: &[cdk00::Proof]) -> AnyResult<()> {
+        let rids = self.record_ids(tokens)?;
+        let entries = self.entries(tokens, cdk07::State::Spent)?;
+        let query = format!(
+            "BEGIN TRANSACTION;
+            IF (SELECT VALUE state FROM $rids) CONTAINS $spent {{ THROW $throw_spent }};
+            INSERT INTO {} $entries ON DUPLICATE KEY UPDATE state = $spent;
+            COMMIT TRANSACTION;",
+            self.table
+        );
+        let response = self
+            .db
+            .query(query)
+            .bind(("rids", rids))
+            .bind(("entries", entries))
+            .bind(("spent", cdk07::State::Spent))
+            .bind(("throw_spent", THROW_SPENT))
+            .await?;
+        check_thrown(response)
     }
 
     async fn get_state(&self, tokens: &[cdk00::Proof]) -> AnyResult<Vec<cdk07::State>> {
-        let rids: Vec<_> = tokens
-            .iter()
-            .map(|tk| {
-                let y = cdk::dhke::hash_to_curve(&tk.secret.to_bytes())?;
-                Ok(RecordId::from_table_key(&self.table, y.to_string()))
-            })
-            .collect::<AnyResult<_>>()?;
+        let rids = self.record_ids(tokens)?;
 
         let resp: Vec<DBProof> = self
             .db
@@ -83,6 +160,90 @@ impl swap::ProofRepository for DB {
         }
         Ok(states)
     }
+
+    async fn reserve(&self, tokens: &[cdk00::Proof]) -> AnyResult<()> {
+        let rids = self.record_ids(tokens)?;
+        let entries = self.entries(tokens, cdk07::State::Pending)?;
+        let query = format!(
+            "BEGIN TRANSACTION;
+            LET $states = SELECT VALUE state FROM $rids;
+            IF $states CONTAINS $spent {{ THROW $throw_spent }};
+            IF $states CONTAINS $pending {{ THROW $throw_pending }};
+            INSERT INTO {} $entries;
+            COMMIT TRANSACTION;",
+            self.table
+        );
+        let response = self
+            .db
+            .query(query)
+            .bind(("rids", rids))
+            .bind(("entries", entries))
+            .bind(("spent", cdk07::State::Spent))
+            .bind(("pending", cdk07::State::Pending))
+            .bind(("throw_spent", THROW_SPENT))
+            .bind(("throw_pending", THROW_PENDING))
+            .await?;
+        check_thrown(response)
+    }
+
+    async fn release(&self, tokens: &[cdk00::Proof]) -> AnyResult<()> {
+        let rids = self.record_ids(tokens)?;
+        self.db
+            .query("DELETE $rids WHERE state = $pending")
+            .bind(("rids", rids))
+            .bind(("pending", cdk07::State::Pending))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    // issued outputs live in `<table>_issued`, keyed by their B_
+    async fn record_issued(&self, outputs: &[cdk00::BlindedMessage]) -> AnyResult<()> {
+        for output in outputs {
+            let rid = self.issued_record_id(&output.blinded_secret);
+            let _: Option<DBIssued> = self
+                .db
+                .upsert(rid)
+                .content(DBIssued {
+                    output: output.clone(),
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn issued(
+        &self,
+        blinded_secrets: &[cdk01::PublicKey],
+    ) -> AnyResult<Vec<Option<cdk00::BlindedMessage>>> {
+        let mut issued = Vec::with_capacity(blinded_secrets.len());
+        for blinded_secret in blinded_secrets {
+            let rid = self.issued_record_id(blinded_secret);
+            let entry: Option<DBIssued> = self.db.select(rid).await?;
+            issued.push(entry.map(|entry| entry.output));
+        }
+        Ok(issued)
+    }
+}
+
+// redeemed payment tokens live in `<table>_tokens`, keyed by the token: creating a
+// record that already exists fails, whoever comes second
+#[async_trait]
+impl debit::mint::PaymentTokenRepository for DB {
+    async fn consume(&self, token: &str) -> AnyResult<bool> {
+        let created: SurrealResult<Option<DBPaymentToken>> = self
+            .db
+            .create(self.token_record_id(token))
+            .content(DBPaymentToken {
+                token: token.to_owned(),
+            })
+            .await;
+        match created {
+            Ok(_) => Ok(true),
+            Err(surrealdb::Error::Db(surrealdb::error::Db::RecordExists { .. })) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -172,4 +333,76 @@ mod tests {
         assert_eq!(res[1], cdk07::State::Spent);
         assert_eq!(res[2], cdk07::State::Unspent);
     }
+
+    #[tokio::test]
+    async fn test_spend_already_spent() {
+        let db = init_mem_db().await;
+        let mintkeys = &keys_test::generate_keyset();
+        let proofs = utils::generate_proofs(
+            mintkeys,
+            &[cdk::Amount::from(16_u64), cdk::Amount::from(8_u64)],
+        );
+        db.spend(&proofs[0..1]).await.unwrap();
+
+        let e = db.spend(&proofs).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<swap::Error>(),
+            Some(swap::Error::ProofsAlreadySpent)
+        ));
+        let res = db.get_state(&proofs).await.unwrap();
+        assert_eq!(res, vec![cdk07::State::Spent, cdk07::State::Unspent]);
+    }
+
+    #[tokio::test]
+    async fn test_record_issued() {
+        let db = init_mem_db().await;
+        let mintkeys = &keys_test::generate_keyset();
+        let blinds = utils::generate_blinds(
+            mintkeys,
+            &[cdk::Amount::from(16_u64), cdk::Amount::from(8_u64)],
+        );
+        db.record_issued(&[blinds[0].0.clone()]).await.unwrap();
+
+        let secrets = [blinds[0].0.blinded_secret, blinds[1].0.blinded_secret];
+        let res = db.issued(&secrets).await.unwrap();
+        assert_eq!(res, vec![Some(blinds[0].0.clone()), None]);
+    }
+
+    #[tokio::test]
+    async fn test_consume_payment_token() {
+        use crate::debit::mint::PaymentTokenRepository;
+
+        let db = init_mem_db().await;
+        assert!(db.consume("token").await.unwrap());
+        assert!(!db.consume("token").await.unwrap());
+        assert!(db.consume("other").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_reserve_release() {
+        let db = init_mem_db().await;
+        let mintkeys = &keys_test::generate_keyset();
+        let proofs = utils::generate_proofs(
+            mintkeys,
+            &[cdk::Amount::from(16_u64), cdk::Amount::from(8_u64)],
+        );
+        db.reserve(&proofs[0..1]).await.unwrap();
+        let e = db.reserve(&proofs).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<swap::Error>(),
+            Some(swap::Error::ProofsPending)
+        ));
+        let res = db.get_state(&proofs).await.unwrap();
+        assert_eq!(res, vec![cdk07::State::Pending, cdk07::State::Unspent]);
+
+        db.release(&proofs).await.unwrap();
+        let res = db.get_state(&proofs).await.unwrap();
+        assert_eq!(res, vec![cdk07::State::Unspent, cdk07::State::Unspent]);
+
+        db.reserve(&proofs).await.unwrap();
+        db.spend(&proofs).await.unwrap();
+        db.release(&proofs).await.unwrap();
+        let res = db.get_state(&proofs).await.unwrap();
+        assert_eq!(res, vec![cdk07::State::Spent, cdk07::State::Spent]);
+    }
 }
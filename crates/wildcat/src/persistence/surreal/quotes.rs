@@ -3,6 +3,7 @@
 use anyhow::{anyhow, Error as AnyError, Result as AnyResult};
 use async_trait::async_trait;
 use cdk::nuts::nut00 as cdk00;
+use cdk::nuts::nut01 as cdk01;
 use surrealdb::Result as SurrealResult;
 use surrealdb::{engine::any::Any, Surreal};
 use uuid::Uuid;
@@ -16,14 +17,20 @@ use crate::TStamp;
 enum DBQuoteStatus {
     Pending,
     Declined,
+    Cancelled,
+    Offered,
     Accepted,
+    Expired,
 }
 impl From<&quotes::QuoteStatus> for DBQuoteStatus {
     fn from(value: &quotes::QuoteStatus) -> Self {
         match value {
             quotes::QuoteStatus::Pending { .. } => Self::Pending,
             quotes::QuoteStatus::Declined => Self::Declined,
+            quotes::QuoteStatus::Cancelled => Self::Cancelled,
+            quotes::QuoteStatus::Offered { .. } => Self::Offered,
             quotes::QuoteStatus::Accepted { .. } => Self::Accepted,
+            quotes::QuoteStatus::Expired => Self::Expired,
         }
     }
 }
@@ -37,7 +44,18 @@ struct DBQuote {
     status: DBQuoteStatus,
     blinds: Option<Vec<cdk00::BlindedMessage>>,
     signatures: Option<Vec<cdk00::BlindSignature>>,
+    // offered quotes keep their expiry in ttl
     ttl: Option<TStamp>,
+    discount: Option<cdk::Amount>,
+    #[serde(default)]
+    fee: Option<cdk::Amount>,
+    #[serde(default)]
+    maturity: Option<TStamp>,
+    // face value of the bill
+    #[serde(default)]
+    amount: Option<cdk::Amount>,
+    #[serde(default)]
+    locked_to: Option<cdk01::PublicKey>,
 }
 
 impl From<quotes::Quote> for DBQuote {
@@ -48,30 +66,98 @@ impl From<quotes::Quote> for DBQuote {
                 bill: q.bill,
                 endorser: q.endorser,
                 submitted: q.submitted,
+                maturity: q.maturity,
+                amount: q.amount,
+                locked_to: q.locked_to,
                 status: DBQuoteStatus::Pending,
                 blinds: Some(blinds),
                 signatures: None,
                 ttl: None,
+                discount: None,
+                fee: None,
             },
             quotes::QuoteStatus::Declined => Self {
                 quote_id: q.id,
                 bill: q.bill,
                 endorser: q.endorser,
                 submitted: q.submitted,
+                maturity: q.maturity,
+                amount: q.amount,
+                locked_to: q.locked_to,
                 status: DBQuoteStatus::Declined,
                 blinds: None,
                 signatures: None,
                 ttl: None,
+                discount: None,
+                fee: None,
             },
-            quotes::QuoteStatus::Accepted { signatures, ttl } => Self {
+            quotes::QuoteStatus::Cancelled => Self {
                 quote_id: q.id,
                 bill: q.bill,
                 endorser: q.endorser,
                 submitted: q.submitted,
+                maturity: q.maturity,
+                amount: q.amount,
+                locked_to: q.locked_to,
+                status: DBQuoteStatus::Cancelled,
+                blinds: None,
+                signatures: None,
+                ttl: None,
+                discount: None,
+                fee: None,
+            },
+            quotes::QuoteStatus::Offered {
+                blinds,
+                discount,
+                expiry,
+            } => Self {
+                quote_id: q.id,
+                bill: q.bill,
+                endorser: q.endorser,
+                submitted: q.submitted,
+                maturity: q.maturity,
+                amount: q.amount,
+                locked_to: q.locked_to,
+                status: DBQuoteStatus::Offered,
+                blinds: Some(blinds),
+                signatures: None,
+                ttl: Some(expiry),
+                discount: Some(discount),
+                fee: None,
+            },
+            quotes::QuoteStatus::Accepted {
+                signatures,
+                ttl,
+                fee,
+            } => Self {
+                quote_id: q.id,
+                bill: q.bill,
+                endorser: q.endorser,
+                submitted: q.submitted,
+                maturity: q.maturity,
+                amount: q.amount,
+                locked_to: q.locked_to,
                 status: DBQuoteStatus::Accepted,
                 blinds: None,
                 signatures: Some(signatures),
                 ttl: Some(ttl),
+                discount: None,
+                fee: Some(fee),
+            },
+            quotes::QuoteStatus::Expired => Self {
+                quote_id: q.id,
+                bill: q.bill,
+                endorser: q.endorser,
+                submitted: q.submitted,
+                maturity: q.maturity,
+                amount: q.amount,
+                locked_to: q.locked_to,
+                status: DBQuoteStatus::Expired,
+                blinds: None,
+                signatures: None,
+                ttl: None,
+                discount: None,
+                fee: None,
             },
         }
     }
@@ -86,6 +172,9 @@ impl TryFrom<DBQuote> for quotes::Quote {
                 bill: dbq.bill,
                 endorser: dbq.endorser,
                 submitted: dbq.submitted,
+                maturity: dbq.maturity,
+                amount: dbq.amount,
+                locked_to: dbq.locked_to,
                 status: quotes::QuoteStatus::Pending {
                     blinds: dbq.blinds.ok_or_else(|| anyhow!("missing blinds"))?,
                 },
@@ -95,20 +184,62 @@ impl TryFrom<DBQuote> for quotes::Quote {
                 bill: dbq.bill,
                 endorser: dbq.endorser,
                 submitted: dbq.submitted,
+                maturity: dbq.maturity,
+                amount: dbq.amount,
+                locked_to: dbq.locked_to,
                 status: quotes::QuoteStatus::Declined,
             }),
+            DBQuoteStatus::Cancelled => Ok(Self {
+                id: dbq.quote_id,
+                bill: dbq.bill,
+                endorser: dbq.endorser,
+                submitted: dbq.submitted,
+                maturity: dbq.maturity,
+                amount: dbq.amount,
+                locked_to: dbq.locked_to,
+                status: quotes::QuoteStatus::Cancelled,
+            }),
+            DBQuoteStatus::Offered => Ok(Self {
+                id: dbq.quote_id,
+                bill: dbq.bill,
+                endorser: dbq.endorser,
+                submitted: dbq.submitted,
+                maturity: dbq.maturity,
+                amount: dbq.amount,
+                locked_to: dbq.locked_to,
+                status: quotes::QuoteStatus::Offered {
+                    blinds: dbq.blinds.ok_or_else(|| anyhow!("missing blinds"))?,
+                    discount: dbq.discount.ok_or_else(|| anyhow!("missing discount"))?,
+                    expiry: dbq.ttl.ok_or_else(|| anyhow!("missing expiry"))?,
+                },
+            }),
             DBQuoteStatus::Accepted => Ok(Self {
                 id: dbq.quote_id,
                 bill: dbq.bill,
                 endorser: dbq.endorser,
                 submitted: dbq.submitted,
+                maturity: dbq.maturity,
+                amount: dbq.amount,
+                locked_to: dbq.locked_to,
                 status: quotes::QuoteStatus::Accepted {
                     signatures: dbq
                         .signatures
                         .ok_or_else(|| anyhow!("missing signatures"))?,
                     ttl: dbq.ttl.ok_or_else(|| anyhow!("missing ttl"))?,
+                    // quotes accepted before fees were recorded have none
+                    fee: dbq.fee.unwrap_or_default(),
                 },
             }),
+            DBQuoteStatus::Expired => Ok(Self {
+                id: dbq.quote_id,
+                bill: dbq.bill,
+                endorser: dbq.endorser,
+                submitted: dbq.submitted,
+                maturity: dbq.maturity,
+                amount: dbq.amount,
+                locked_to: dbq.locked_to,
+                status: quotes::QuoteStatus::Expired,
+            }),
         }
     }
 }
@@ -125,10 +256,27 @@ impl DB {
         db_connection.connect(cfg.connection).await?;
         db_connection.use_ns(cfg.namespace).await?;
         db_connection.use_db(cfg.database).await?;
-        Ok(Self {
+        let db = Self {
             db: db_connection,
             table: cfg.table,
-        })
+        };
+        db.migrate().await?;
+        Ok(db)
+    }
+
+    // accepted quotes used to keep their fee in discount. new ones always have a fee,
+    // running it again is a no-op
+    async fn migrate(&self) -> SurrealResult<()> {
+        self.db
+            .query(
+                "UPDATE type::table($table) SET fee = discount, discount = NONE
+                WHERE status == $accepted AND fee == NONE AND discount != NONE",
+            )
+            .bind(("table", self.table.clone()))
+            .bind(("accepted", DBQuoteStatus::Accepted))
+            .await?
+            .check()?;
+        Ok(())
     }
 
     async fn load(&self, qid: Uuid) -> SurrealResult<Option<DBQuote>> {
@@ -142,26 +290,74 @@ impl DB {
             .await
     }
 
+    async fn update_if_status(
+        &self,
+        new: quotes::Quote,
+        status: DBQuoteStatus,
+    ) -> SurrealResult<()> {
+        let recordid = surrealdb::RecordId::from_table_key(&self.table, new.id);
+        self.db
+            .query("UPDATE $rid CONTENT $new WHERE status == $status")
+            .bind(("rid", recordid))
+            .bind(("new", DBQuote::from(new)))
+            .bind(("status", status))
+            .await?;
+        Ok(())
+    }
+
+    // newest first, ties broken by id so that pages are stable across calls
     async fn list_by_status(
         &self,
         status: DBQuoteStatus,
         since: Option<TStamp>,
+        page: quotes::Page,
     ) -> SurrealResult<Vec<Uuid>> {
+        let mut statement =
+            String::from("SELECT * FROM type::table($table) WHERE status == $status");
+        if since.is_some() {
+            statement.push_str(" AND submitted >= $since");
+        }
+        statement.push_str(" ORDER BY submitted DESC, quote_id ASC");
+        if page.limit != usize::MAX {
+            statement.push_str(" LIMIT $limit");
+        }
+        statement.push_str(" START $offset");
         let mut query = self
             .db
-            .query(
-                "SELECT * FROM type::table($table) WHERE status == $status ORDER BY submitted DESC",
-            )
+            .query(statement)
             .bind(("table", self.table.clone()))
-            .bind(("status", status));
+            .bind(("status", status))
+            .bind(("offset", page.offset))
+            .bind(("limit", page.limit));
         if let Some(since) = since {
-            query = query
-                .query(" AND submitted >= $since")
-                .bind(("since", since));
+            query = query.bind(("since", since));
         }
         query.await?.take("quote_id")
     }
 
+    async fn purge_expired(&self, before: TStamp) -> SurrealResult<Vec<DBQuote>> {
+        self.db
+            .query(
+                "DELETE type::table($table)
+                WHERE (status IN $terminated AND submitted < $before)
+                OR (status == $accepted AND ttl < $before)
+                RETURN BEFORE",
+            )
+            .bind(("table", self.table.clone()))
+            .bind((
+                "terminated",
+                vec![
+                    DBQuoteStatus::Declined,
+                    DBQuoteStatus::Cancelled,
+                    DBQuoteStatus::Expired,
+                ],
+            ))
+            .bind(("accepted", DBQuoteStatus::Accepted))
+            .bind(("before", before))
+            .await?
+            .take(0)
+    }
+
     async fn search_by_bill(&self, bill: &str, endorser: &str) -> SurrealResult<Option<DBQuote>> {
         let results: Vec<DBQuote> = self.db
             .query("SELECT * FROM type::table($table) WHERE bill == $bill AND endorser == $endorser ORDER BY submitted DESC")
@@ -170,6 +366,15 @@ impl DB {
             .bind(("endorser", endorser.to_owned())).await?.take(0)?;
         Ok(results.first().cloned())
     }
+
+    async fn search_by_endorser(&self, endorser: &str) -> SurrealResult<Vec<DBQuote>> {
+        self.db
+            .query("SELECT * FROM type::table($table) WHERE endorser == $endorser ORDER BY submitted DESC, quote_id ASC")
+            .bind(("table", self.table.clone()))
+            .bind(("endorser", endorser.to_owned()))
+            .await?
+            .take(0)
+    }
 }
 
 #[async_trait]
@@ -185,24 +390,55 @@ impl quotes::Repository for DB {
         if matches!(new.status, quotes::QuoteStatus::Pending { .. }) {
             return Err(anyhow!("cannot update to pending"));
         }
-        let recordid = surrealdb::RecordId::from_table_key(&self.table, new.id);
-        self.db
-            .query("UPDATE $rid CONTENT $new WHERE status == $status")
-            .bind(("rid", recordid))
-            .bind(("new", DBQuote::from(new)))
-            .bind(("status", DBQuoteStatus::Pending))
-            .await?;
+        self.update_if_status(new, DBQuoteStatus::Pending).await?;
+        Ok(())
+    }
+
+    async fn update_if_offered(&self, new: quotes::Quote) -> AnyResult<()> {
+        if matches!(
+            new.status,
+            quotes::QuoteStatus::Pending { .. } | quotes::QuoteStatus::Offered { .. }
+        ) {
+            return Err(anyhow!("cannot update to pending or offered"));
+        }
+        self.update_if_status(new, DBQuoteStatus::Offered).await?;
+        Ok(())
+    }
+
+    async fn update_if_accepted(&self, new: quotes::Quote) -> AnyResult<()> {
+        if !matches!(new.status, quotes::QuoteStatus::Expired) {
+            return Err(anyhow!("accepted quotes can only expire"));
+        }
+        self.update_if_status(new, DBQuoteStatus::Accepted).await?;
         Ok(())
     }
 
-    async fn list_pendings(&self, since: Option<TStamp>) -> AnyResult<Vec<Uuid>> {
-        self.list_by_status(DBQuoteStatus::Pending, since)
+    async fn list_pendings(
+        &self,
+        since: Option<TStamp>,
+        page: quotes::Page,
+    ) -> AnyResult<Vec<Uuid>> {
+        self.list_by_status(DBQuoteStatus::Pending, since, page)
             .await
             .map_err(Into::into)
     }
 
-    async fn list_accepteds(&self, since: Option<TStamp>) -> AnyResult<Vec<Uuid>> {
-        self.list_by_status(DBQuoteStatus::Accepted, since)
+    async fn list_offered(
+        &self,
+        since: Option<TStamp>,
+        page: quotes::Page,
+    ) -> AnyResult<Vec<Uuid>> {
+        self.list_by_status(DBQuoteStatus::Offered, since, page)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn list_accepteds(
+        &self,
+        since: Option<TStamp>,
+        page: quotes::Page,
+    ) -> AnyResult<Vec<Uuid>> {
+        self.list_by_status(DBQuoteStatus::Accepted, since, page)
             .await
             .map_err(Into::into)
     }
@@ -214,8 +450,89 @@ impl quotes::Repository for DB {
             .transpose()
     }
 
+    async fn search_by_endorser(&self, endorser: &str) -> AnyResult<Vec<quotes::Quote>> {
+        self.search_by_endorser(endorser)
+            .await?
+            .into_iter()
+            .map(std::convert::TryInto::try_into)
+            .collect()
+    }
+
     async fn store(&self, quote: quotes::Quote) -> AnyResult<()> {
         self.store(quote.into()).await?;
         Ok(())
     }
+
+    async fn purge_expired(&self, before: TStamp) -> AnyResult<usize> {
+        let purged = self.purge_expired(before).await?;
+        Ok(purged.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credit::quotes::Repository;
+
+    async fn init_mem_db() -> DB {
+        let sdb = Surreal::<Any>::init();
+        sdb.connect("mem://").await.unwrap();
+        sdb.use_ns("test").await.unwrap();
+        sdb.use_db("test").await.unwrap();
+        DB {
+            db: sdb,
+            table: "test".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_accepted_fee_and_bill_amount() {
+        let db = init_mem_db().await;
+        let now = chrono::Utc::now();
+        let bill = quotes::DecryptedBill {
+            id: String::from("bill"),
+            maturity: now + chrono::Duration::days(90),
+            amount: cdk::Amount::from(1000_u64),
+        };
+        let mut quote = quotes::Quote::from_bill(bill, String::from("endorser"), vec![], now);
+        Repository::store(&db, quote.clone()).await.unwrap();
+        quote.accept(vec![], now, cdk::Amount::from(7_u64)).unwrap();
+        Repository::update_if_pending(&db, quote.clone())
+            .await
+            .unwrap();
+
+        let stored = db.load(quote.id).await.unwrap().unwrap();
+        assert_eq!(stored.fee, Some(cdk::Amount::from(7_u64)));
+        assert!(stored.discount.is_none());
+        let loaded = Repository::load(&db, quote.id).await.unwrap().unwrap();
+        assert_eq!(loaded.amount, Some(cdk::Amount::from(1000_u64)));
+        assert!(matches!(
+            loaded.status,
+            quotes::QuoteStatus::Accepted { fee, .. } if fee == cdk::Amount::from(7_u64)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_migrate_moves_fee_out_of_discount() {
+        let db = init_mem_db().await;
+        let now = chrono::Utc::now();
+        let mut quote =
+            quotes::Quote::new(String::from("bill"), String::from("endorser"), vec![], now);
+        quote.accept(vec![], now, cdk::Amount::ZERO).unwrap();
+        // as stored before fees had a field of their own
+        let mut legacy = DBQuote::from(quote.clone());
+        legacy.discount = Some(cdk::Amount::from(5_u64));
+        legacy.fee = None;
+        db.store(legacy).await.unwrap();
+
+        db.migrate().await.unwrap();
+        db.migrate().await.unwrap();
+        let loaded = Repository::load(&db, quote.id).await.unwrap().unwrap();
+        assert!(matches!(
+            loaded.status,
+            quotes::QuoteStatus::Accepted { fee, .. } if fee == cdk::Amount::from(5_u64)
+        ));
+        let stored = db.load(quote.id).await.unwrap().unwrap();
+        assert!(stored.discount.is_none());
+    }
 }
@@ -0,0 +1,112 @@
+#![allow(dead_code)]
+// ----- standard library imports
+// ----- extra library imports
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+// ----- local imports
+
+/// format version of the entries serialized from now on
+pub const CURRENT_VERSION: u16 = 1;
+
+pub type Result<T> = std::result::Result<T, Error>;
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("stored entry version {0} is unknown, latest supported is {CURRENT_VERSION}")]
+    UnknownVersion(u16),
+    #[error("serde_json error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+// ----- versioned entry
+// serialized backends store `inner` tagged with the format version it was written in,
+// so that a loader can tell old rows apart and upgrade them.
+// rows written before versioning carry no tag, they are read as they are
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionedEntry<T> {
+    pub version: u16,
+    pub inner: T,
+}
+
+impl<T> VersionedEntry<T> {
+    pub fn current(inner: T) -> Self {
+        Self {
+            version: CURRENT_VERSION,
+            inner,
+        }
+    }
+}
+
+/// json of `inner` at the current version
+pub fn to_json<T: Serialize>(inner: &T) -> Result<String> {
+    Ok(serde_json::to_string(&VersionedEntry::current(inner))?)
+}
+
+/// parses an entry serialized by `to_json`, or an untagged one stored before versioning
+pub fn from_json<T: DeserializeOwned>(json: &str) -> Result<T> {
+    let value: serde_json::Value = serde_json::from_str(json)?;
+    if value.get("version").is_none() {
+        return Ok(serde_json::from_value(value)?);
+    }
+    // inner is parsed only once the version is known: a future format may not fit T
+    let entry: VersionedEntry<serde_json::Value> = serde_json::from_value(value)?;
+    match entry.version {
+        CURRENT_VERSION => Ok(serde_json::from_value(entry.inner)?),
+        version => Err(Error::UnknownVersion(version)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::test_utils as keys_test;
+    use cdk::nuts::nut02 as cdk02;
+
+    fn info(keyset: &cdk02::MintKeySet) -> cdk::mint::MintKeySetInfo {
+        cdk::mint::MintKeySetInfo {
+            id: keyset.id,
+            unit: keyset.unit.clone(),
+            active: true,
+            valid_from: 1,
+            valid_to: Some(2),
+            derivation_path: Default::default(),
+            derivation_path_index: Some(0),
+            max_order: 4,
+            input_fee_ppk: 0,
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_current_version() {
+        let keyset = keys_test::generate_keyset();
+        let info = info(&keyset);
+
+        let json = to_json(&keyset).unwrap();
+        let tagged: VersionedEntry<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        assert_eq!(tagged.version, CURRENT_VERSION);
+        assert_eq!(from_json::<cdk02::MintKeySet>(&json).unwrap(), keyset);
+        let json = to_json(&info).unwrap();
+        assert_eq!(from_json::<cdk::mint::MintKeySetInfo>(&json).unwrap(), info);
+    }
+
+    #[test]
+    fn test_untagged_row() {
+        let keyset = keys_test::generate_keyset();
+        let info = info(&keyset);
+
+        let json = serde_json::to_string(&info).unwrap();
+        assert_eq!(from_json::<cdk::mint::MintKeySetInfo>(&json).unwrap(), info);
+    }
+
+    #[test]
+    fn test_future_version_rejected() {
+        // a future format may not fit the current type at all
+        let json = serde_json::to_string(&VersionedEntry {
+            version: CURRENT_VERSION + 1,
+            inner: serde_json::json!({"keys": "reshaped"}),
+        })
+        .unwrap();
+
+        let e = from_json::<cdk02::MintKeySet>(&json).unwrap_err();
+        assert!(matches!(e, Error::UnknownVersion(v) if v == CURRENT_VERSION + 1));
+    }
+}
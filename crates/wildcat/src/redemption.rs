@@ -0,0 +1,420 @@
+// ----- standard library imports
+use std::collections::HashSet;
+// ----- extra library imports
+use axum::extract::{Json, State};
+use cdk::nuts::nut00 as cdk00;
+use cdk::nuts::nut03 as cdk03;
+use thiserror::Error;
+// ----- local imports
+use crate::credit::keys::{maturity_of, SwapRepository};
+use crate::keys::{self, KeysetID};
+use crate::swap;
+use crate::TStamp;
+
+// ----- error
+pub type Result<T> = std::result::Result<T, Error>;
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Swap error: {0}")]
+    Swap(#[from] swap::Error),
+
+    #[error("Keyset {0} is not a maturity keyset")]
+    NotMaturityKeyset(KeysetID),
+    #[error("Keyset {0} matures on {1}")]
+    NotMatured(KeysetID, TStamp),
+    #[error("No active debit keyset")]
+    NoActiveDebitKeyset,
+    #[error("Output for keyset {0}, expected the debit keyset {1}")]
+    NotDebitKeyset(KeysetID, KeysetID),
+}
+
+impl axum::response::IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        self.to_string().into_response()
+    }
+}
+
+// ---------- Service
+/// redemption: credit eCash of matured keysets is exchanged 1:1 for debit eCash
+#[derive(Clone)]
+pub struct RedemptionService<KeysRepo, ActiveRepo, ProofRepo> {
+    pub swap: swap::Service<SwapRepository<KeysRepo, ActiveRepo>, ProofRepo>,
+}
+
+impl<KeysRepo, ActiveRepo, ProofRepo> RedemptionService<KeysRepo, ActiveRepo, ProofRepo>
+where
+    KeysRepo: keys::Repository,
+    ActiveRepo: keys::ActiveRepository,
+    ProofRepo: swap::ProofRepository,
+{
+    // every input must come from a maturity keyset whose maturity date is past
+    async fn verify_matured(&self, inputs: &[cdk00::Proof], now: TStamp) -> Result<()> {
+        let ids = inputs
+            .iter()
+            .map(|proof| swap::try_keyset_id(proof.keyset_id))
+            .collect::<std::result::Result<HashSet<KeysetID>, _>>()?;
+        for kid in ids {
+            let info = self
+                .swap
+                .keys
+                .maturity_keys
+                .info(&kid)
+                .await
+                .map_err(|e| swap::Error::KeysetRepository(e.into()))?
+                .ok_or(Error::NotMaturityKeyset(kid))?;
+            let maturity = maturity_of(&info).map_err(swap::Error::KeysetRepository)?;
+            if maturity >= now {
+                return Err(Error::NotMatured(kid, maturity));
+            }
+        }
+        Ok(())
+    }
+
+    async fn debit_keyset(&self) -> Result<KeysetID> {
+        let info = self
+            .swap
+            .keys
+            .debit_keys
+            .info_active()
+            .await
+            .map_err(|e| swap::Error::KeysetRepository(e.into()))?
+            .ok_or(Error::NoActiveDebitKeyset)?;
+        Ok(KeysetID::from(info.id))
+    }
+
+    async fn redeem_reserved(
+        &self,
+        inputs: &[cdk00::Proof],
+        outputs: &[cdk00::BlindedMessage],
+        debit: &KeysetID,
+    ) -> std::result::Result<Vec<cdk00::BlindSignature>, swap::Error> {
+        // matured keysets are past valid_to by definition
+        let verified = self
+            .swap
+            .verify_proofs_signatures(inputs, swap::KeysetValidity::Ignore)
+            .await?;
+        if !verified {
+            return Err(swap::Error::UnknownProofs);
+        }
+        self.swap.sign_outputs(debit, outputs).await
+    }
+
+    /// spends `inputs` and signs `outputs`, of the same total amount, with the active
+    /// debit keyset
+    pub async fn redeem(
+        &self,
+        inputs: &[cdk00::Proof],
+        outputs: &[cdk00::BlindedMessage],
+        now: TStamp,
+    ) -> Result<Vec<cdk00::BlindSignature>> {
+        // the swap checks, plus: redemption is 1:1, no fee is charged
+        self.swap.verify_counts(inputs.len(), outputs.len())?;
+        let (total_input, total_output) = swap::verify_swap_request(inputs, outputs)?;
+        if total_input != total_output {
+            return Err(swap::Error::UnmatchingAmount(total_input, total_output).into());
+        }
+        self.verify_matured(inputs, now).await?;
+        self.swap.verify_input_amounts(inputs).await?;
+        let debit = self.debit_keyset().await?;
+        for output in outputs {
+            let kid = swap::try_keyset_id(output.keyset_id)?;
+            if kid != debit {
+                return Err(Error::NotDebitKeyset(kid, debit));
+            }
+        }
+
+        self.swap.verify_fresh_outputs(outputs.iter()).await?;
+        self.swap
+            .proofs
+            .reserve(inputs)
+            .await
+            .map_err(swap::proof_repository_error)?;
+        let result = self.redeem_reserved(inputs, outputs, &debit).await;
+        let signatures = self.swap.settle(inputs, result).await?;
+        self.swap.record_issued(outputs.iter(), &signatures).await;
+        Ok(signatures)
+    }
+}
+
+// ---------- web
+pub async fn redeem_tokens<KeysRepo, ActiveRepo, ProofRepo>(
+    State(ctrl): State<RedemptionService<KeysRepo, ActiveRepo, ProofRepo>>,
+    Json(request): Json<cdk03::SwapRequest>,
+) -> Result<Json<cdk03::SwapResponse>>
+where
+    KeysRepo: keys::Repository,
+    ActiveRepo: keys::ActiveRepository,
+    ProofRepo: swap::ProofRepository,
+{
+    let now = ctrl.swap.clock.now();
+    let signatures = ctrl.redeem(&request.inputs, &request.outputs, now).await?;
+    Ok(Json(cdk03::SwapResponse { signatures }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::inmemory;
+    use crate::utils::tests as utils;
+    use bcr_wdc_keys::test_utils as keys_test;
+    use bitcoin::bip32::DerivationPath;
+    use cdk::mint::MintKeySetInfo;
+    use cdk::nuts::nut02 as cdk02;
+    use cdk::nuts::nut07 as cdk07;
+    use cdk::Amount;
+    use std::str::FromStr;
+
+    type TestService = RedemptionService<
+        inmemory::KeysetIDEntryMap,
+        inmemory::KeysetIDEntryMapWithActive,
+        inmemory::ProofMap,
+    >;
+
+    fn keyset_info(keyset: &cdk02::MintKeySet, valid_to: Option<u64>) -> MintKeySetInfo {
+        MintKeySetInfo {
+            id: keyset.id,
+            unit: keyset.unit.clone(),
+            active: true,
+            valid_from: 0,
+            valid_to,
+            derivation_path: Default::default(),
+            derivation_path_index: None,
+            max_order: keyset.keys.len() as u8,
+            input_fee_ppk: 0,
+        }
+    }
+
+    // a maturity keyset maturing at `maturity` and an active debit keyset
+    async fn service(maturity: TStamp) -> (TestService, cdk02::MintKeySet, cdk02::MintKeySet) {
+        let maturity_keyset = keys_test::generate_keyset();
+        let maturity_info = keyset_info(&maturity_keyset, Some(maturity.timestamp() as u64));
+        let debit_keyset =
+            keys_test::generate_keyset_with_path(DerivationPath::from_str("m/0'/1'").unwrap());
+        let debit_info = keyset_info(&debit_keyset, None);
+        let keys = SwapRepository {
+            endorsed_keys: inmemory::KeysetIDEntryMap::default(),
+            maturity_keys: inmemory::KeysetIDEntryMap::default(),
+            debit_keys: inmemory::KeysetIDEntryMapWithActive::default(),
+            max_rotation_walk: keys::MAX_ROTATION_WALK,
+        };
+        keys::Repository::store(&keys.maturity_keys, maturity_keyset.clone(), maturity_info)
+            .await
+            .unwrap();
+        keys::Repository::store(&keys.debit_keys, debit_keyset.clone(), debit_info)
+            .await
+            .unwrap();
+        let service = RedemptionService {
+            swap: swap::Service {
+                keys,
+                proofs: inmemory::ProofMap::default(),
+                stats: Default::default(),
+                limits: Default::default(),
+                clock: std::sync::Arc::new(crate::clock::SystemClock),
+            },
+        };
+        (service, maturity_keyset, debit_keyset)
+    }
+
+    #[tokio::test]
+    async fn test_redeem_matured_proofs() {
+        let now = chrono::Utc::now();
+        let (service, maturity_keyset, debit_keyset) =
+            service(now - chrono::Duration::days(1)).await;
+        let amounts = [Amount::from(8), Amount::from(4)];
+        let inputs = utils::generate_proofs(&maturity_keyset, &amounts);
+        let outputs: Vec<_> = utils::generate_blinds(&debit_keyset, &amounts)
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+
+        let signatures = service.redeem(&inputs, &outputs, now).await.unwrap();
+        assert_eq!(signatures.len(), 2);
+        assert!(signatures.iter().all(|s| s.keyset_id == debit_keyset.id));
+        for (output, signature) in outputs.iter().zip(&signatures) {
+            assert!(utils::verify_dleq(&debit_keyset, output, signature));
+        }
+        let states = swap::ProofRepository::get_state(&service.swap.proofs, &inputs)
+            .await
+            .unwrap();
+        assert!(states.iter().all(|s| *s == cdk07::State::Spent));
+    }
+
+    #[tokio::test]
+    async fn test_redeem_tokens_route() {
+        use axum::body::Body;
+        use axum::http::{header, Request, StatusCode};
+        use tower::ServiceExt;
+
+        let now = chrono::Utc::now();
+        let (service, maturity_keyset, debit_keyset) =
+            service(now - chrono::Duration::days(1)).await;
+        let inputs = utils::generate_proofs(&maturity_keyset, &[Amount::from(8)]);
+        let outputs: Vec<_> = utils::generate_blinds(&debit_keyset, &[Amount::from(8)])
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+        let router = axum::Router::new()
+            .route("/redeem", axum::routing::post(redeem_tokens))
+            .with_state(service);
+
+        let body = serde_json::json!({"inputs": inputs, "outputs": outputs});
+        let request = Request::post("/redeem")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let reply: cdk03::SwapResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply.signatures.len(), 1);
+        assert_eq!(reply.signatures[0].keyset_id, debit_keyset.id);
+    }
+
+    #[tokio::test]
+    async fn test_redeem_immature_proofs_rejected() {
+        let now = chrono::Utc::now();
+        let (service, maturity_keyset, debit_keyset) =
+            service(now + chrono::Duration::days(1)).await;
+        let inputs = utils::generate_proofs(&maturity_keyset, &[Amount::from(8)]);
+        let outputs: Vec<_> = utils::generate_blinds(&debit_keyset, &[Amount::from(8)])
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+
+        let r = service.redeem(&inputs, &outputs, now).await;
+        assert!(
+            matches!(r, Err(Error::NotMatured(kid, _)) if kid == KeysetID::from(maturity_keyset.id))
+        );
+        let states = swap::ProofRepository::get_state(&service.swap.proofs, &inputs)
+            .await
+            .unwrap();
+        assert!(states.iter().all(|s| *s == cdk07::State::Unspent));
+    }
+    #[tokio::test]
+    async fn test_redeem_malformed_secret_rejected() {
+        let now = chrono::Utc::now();
+        let (service, maturity_keyset, debit_keyset) =
+            service(now - chrono::Duration::days(1)).await;
+        let mut inputs = utils::generate_proofs(&maturity_keyset, &[Amount::from(8)]);
+        inputs[0].secret = cdk::secret::Secret::new("a".repeat(4096));
+        let outputs: Vec<_> = utils::generate_blinds(&debit_keyset, &[Amount::from(8)])
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+
+        let r = service.redeem(&inputs, &outputs, now).await;
+        assert!(matches!(
+            r,
+            Err(Error::Swap(swap::Error::MalformedSecret("too long")))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_redeem_duplicate_inputs_rejected() {
+        let now = chrono::Utc::now();
+        let (service, maturity_keyset, debit_keyset) =
+            service(now - chrono::Duration::days(1)).await;
+        let proof = utils::generate_proofs(&maturity_keyset, &[Amount::from(8)]).remove(0);
+        let inputs = vec![proof.clone(), proof];
+        let outputs: Vec<_> = utils::generate_blinds(&debit_keyset, &[Amount::from(16)])
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+
+        let r = service.redeem(&inputs, &outputs, now).await;
+        assert!(matches!(r, Err(Error::Swap(swap::Error::DuplicateInputs))));
+        let states = swap::ProofRepository::get_state(&service.swap.proofs, &inputs)
+            .await
+            .unwrap();
+        assert!(states.iter().all(|s| *s == cdk07::State::Unspent));
+    }
+
+    #[tokio::test]
+    async fn test_redeem_zero_amount_output_rejected() {
+        let now = chrono::Utc::now();
+        let (service, maturity_keyset, debit_keyset) =
+            service(now - chrono::Duration::days(1)).await;
+        let inputs = utils::generate_proofs(&maturity_keyset, &[Amount::from(8)]);
+        let mut outputs: Vec<_> = utils::generate_blinds(&debit_keyset, &[Amount::from(8)])
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+        let mut zero = outputs[0].clone();
+        zero.amount = Amount::ZERO;
+        outputs.push(zero);
+
+        let r = service.redeem(&inputs, &outputs, now).await;
+        assert!(matches!(r, Err(Error::Swap(swap::Error::ZeroAmount))));
+    }
+
+    #[tokio::test]
+    async fn test_redeem_twice_rejected_as_spent() {
+        let now = chrono::Utc::now();
+        let (service, maturity_keyset, debit_keyset) =
+            service(now - chrono::Duration::days(1)).await;
+        let inputs = utils::generate_proofs(&maturity_keyset, &[Amount::from(8)]);
+        let blinds = |amount| {
+            utils::generate_blinds(&debit_keyset, &[amount])
+                .into_iter()
+                .map(|b| b.0)
+                .collect::<Vec<_>>()
+        };
+        service
+            .redeem(&inputs, &blinds(Amount::from(8)), now)
+            .await
+            .unwrap();
+
+        let r = service.redeem(&inputs, &blinds(Amount::from(8)), now).await;
+        assert!(matches!(
+            r,
+            Err(Error::Swap(swap::Error::ProofsAlreadySpent))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_redeem_reused_output_rejected() {
+        let now = chrono::Utc::now();
+        let (service, maturity_keyset, debit_keyset) =
+            service(now - chrono::Duration::days(1)).await;
+        let outputs: Vec<_> = utils::generate_blinds(&debit_keyset, &[Amount::from(8)])
+            .into_iter()
+            .map(|b| b.0)
+            .collect();
+        let inputs = utils::generate_proofs(&maturity_keyset, &[Amount::from(8)]);
+        service.redeem(&inputs, &outputs, now).await.unwrap();
+
+        let inputs = utils::generate_proofs(&maturity_keyset, &[Amount::from(8)]);
+        let r = service.redeem(&inputs, &outputs, now).await;
+        assert!(matches!(
+            r,
+            Err(Error::Swap(swap::Error::BlindedMessageReused))
+        ));
+        let states = swap::ProofRepository::get_state(&service.swap.proofs, &inputs)
+            .await
+            .unwrap();
+        assert!(states.iter().all(|s| *s == cdk07::State::Unspent));
+    }
+
+    #[tokio::test]
+    async fn test_redeem_too_many_outputs_rejected() {
+        let now = chrono::Utc::now();
+        let (mut service, maturity_keyset, debit_keyset) =
+            service(now - chrono::Duration::days(1)).await;
+        service.swap.limits = swap::SwapLimits {
+            max_inputs: 10,
+            max_outputs: 1,
+        };
+        let inputs = utils::generate_proofs(&maturity_keyset, &[Amount::from(8)]);
+        let outputs: Vec<_> =
+            utils::generate_blinds(&debit_keyset, &[Amount::from(4), Amount::from(4)])
+                .into_iter()
+                .map(|b| b.0)
+                .collect();
+
+        let r = service.redeem(&inputs, &outputs, now).await;
+        assert!(matches!(
+            r,
+            Err(Error::Swap(swap::Error::TooManyOutputs(2, 1)))
+        ));
+    }
+}
@@ -0,0 +1,30 @@
+// ----- standard library imports
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+// ----- extra library imports
+use cdk::nuts::nut00 as cdk00;
+use cdk::Amount;
+// ----- local imports
+
+/// how many signatures of each denomination have been issued, shared between clones
+#[derive(Debug, Default, Clone)]
+pub struct DenominationStats {
+    counts: Arc<Mutex<BTreeMap<Amount, u64>>>,
+}
+
+impl DenominationStats {
+    pub fn record(&self, signatures: &[cdk00::BlindSignature]) {
+        let mut counts = self.counts.lock().unwrap();
+        for signature in signatures {
+            *counts.entry(signature.amount).or_default() += 1;
+        }
+    }
+
+    pub fn histogram(&self) -> BTreeMap<Amount, u64> {
+        self.counts.lock().unwrap().clone()
+    }
+
+    pub fn reset(&self) {
+        self.counts.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,256 @@
+#![allow(dead_code)]
+// ----- standard library imports
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+// ----- extra library imports
+use async_trait::async_trait;
+use cdk::mint::MintKeySetInfo;
+use cdk::nuts::nut02 as cdk02;
+// ----- local imports
+use crate::health::HealthCheck;
+use crate::keys::KeysetID;
+use crate::swap::{KeysRepoResult, KeysRepository};
+
+// ----- caching keys repository
+// keysets never change once created, they are kept for good.
+// infos and replacements depend on which keysets are active, they expire after `ttl`
+#[derive(Clone)]
+pub struct CachingKeysRepository<Repo> {
+    repo: Repo,
+    ttl: Duration,
+    keysets: Arc<RwLock<HashMap<KeysetID, cdk02::MintKeySet>>>,
+    infos: Arc<RwLock<HashMap<KeysetID, (Instant, MintKeySetInfo)>>>,
+    replacements: Arc<RwLock<HashMap<KeysetID, (Instant, KeysetID)>>>,
+}
+
+impl<Repo> CachingKeysRepository<Repo> {
+    pub fn new(repo: Repo, ttl: Duration) -> Self {
+        Self {
+            repo,
+            ttl,
+            keysets: Default::default(),
+            infos: Default::default(),
+            replacements: Default::default(),
+        }
+    }
+
+    fn fresh<T: Clone>(
+        &self,
+        cache: &RwLock<HashMap<KeysetID, (Instant, T)>>,
+        id: &KeysetID,
+    ) -> Option<T> {
+        cache
+            .read()
+            .unwrap()
+            .get(id)
+            .filter(|(cached_at, _)| cached_at.elapsed() < self.ttl)
+            .map(|(_, value)| value.clone())
+    }
+}
+
+#[async_trait]
+impl<Repo> HealthCheck for CachingKeysRepository<Repo>
+where
+    Repo: HealthCheck,
+{
+    async fn ready(&self) -> bool {
+        self.repo.ready().await
+    }
+}
+
+#[async_trait]
+impl<Repo> KeysRepository for CachingKeysRepository<Repo>
+where
+    Repo: KeysRepository,
+{
+    async fn keyset(&self, id: &KeysetID) -> KeysRepoResult<Option<cdk02::MintKeySet>> {
+        if let Some(keyset) = self.keysets.read().unwrap().get(id) {
+            return Ok(Some(keyset.clone()));
+        }
+        let keyset = self.repo.keyset(id).await?;
+        if let Some(keyset) = &keyset {
+            self.keysets.write().unwrap().insert(*id, keyset.clone());
+        }
+        Ok(keyset)
+    }
+
+    async fn info(&self, id: &KeysetID) -> KeysRepoResult<Option<MintKeySetInfo>> {
+        if let Some(info) = self.fresh(&self.infos, id) {
+            return Ok(Some(info));
+        }
+        let info = self.repo.info(id).await?;
+        if let Some(info) = &info {
+            let entry = (Instant::now(), info.clone());
+            self.infos.write().unwrap().insert(*id, entry);
+        }
+        Ok(info)
+    }
+
+    // enabling a keyset must take effect at once, never cached
+    async fn spendable(&self, id: &KeysetID) -> KeysRepoResult<bool> {
+        self.repo.spendable(id).await
+    }
+
+    async fn replacing_id(&self, id: &KeysetID) -> KeysRepoResult<Option<KeysetID>> {
+        if let Some(replacement) = self.fresh(&self.replacements, id) {
+            return Ok(Some(replacement));
+        }
+        let replacement = self.repo.replacing_id(id).await?;
+        if let Some(replacement) = replacement {
+            if replacement != *id {
+                // `id` is being replaced, its info (e.g. the active flag) may be stale
+                self.infos.write().unwrap().remove(id);
+            }
+            let entry = (Instant::now(), replacement);
+            self.replacements.write().unwrap().insert(*id, entry);
+        }
+        Ok(replacement)
+    }
+
+    async fn list_info(&self) -> KeysRepoResult<Vec<MintKeySetInfo>> {
+        self.repo.list_info().await
+    }
+
+    async fn list_info_filtered(&self, only_enabled: bool) -> KeysRepoResult<Vec<MintKeySetInfo>> {
+        self.repo.list_info_filtered(only_enabled).await
+    }
+
+    async fn load_many(
+        &self,
+        ids: &[KeysetID],
+    ) -> KeysRepoResult<HashMap<KeysetID, cdk02::MintKeySet>> {
+        let mut keysets = HashMap::with_capacity(ids.len());
+        let mut missing = Vec::new();
+        {
+            let cached = self.keysets.read().unwrap();
+            for id in ids {
+                match cached.get(id) {
+                    Some(keyset) => {
+                        keysets.insert(*id, keyset.clone());
+                    }
+                    None => missing.push(*id),
+                }
+            }
+        }
+        if missing.is_empty() {
+            return Ok(keysets);
+        }
+        let loaded = self.repo.load_many(&missing).await?;
+        self.keysets
+            .write()
+            .unwrap()
+            .extend(loaded.iter().map(|(id, keyset)| (*id, keyset.clone())));
+        keysets.extend(loaded);
+        Ok(keysets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::test_utils as keys_test;
+    use crate::swap::service::MockKeysRepository;
+    use mockall::predicate::*;
+
+    fn keyset_info(keyset: &cdk02::MintKeySet, active: bool) -> MintKeySetInfo {
+        MintKeySetInfo {
+            id: keyset.id,
+            unit: keyset.unit.clone(),
+            active,
+            valid_from: 0,
+            valid_to: None,
+            derivation_path: Default::default(),
+            derivation_path_index: None,
+            max_order: Default::default(),
+            input_fee_ppk: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keyset_hits_cache() {
+        let keyset = keys_test::generate_keyset();
+        let kid = KeysetID::from(keyset.id);
+        let mut repo = MockKeysRepository::new();
+        let ex_keyset = keyset.clone();
+        repo.expect_keyset()
+            .with(eq(kid))
+            .times(1)
+            .returning(move |_| Ok(Some(ex_keyset.clone())));
+        let cache = CachingKeysRepository::new(repo, Duration::from_secs(60));
+
+        assert_eq!(cache.keyset(&kid).await.unwrap(), Some(keyset.clone()));
+        assert_eq!(cache.keyset(&kid).await.unwrap(), Some(keyset.clone()));
+        let many = cache.load_many(&[kid]).await.unwrap();
+        assert_eq!(many.get(&kid), Some(&keyset));
+    }
+
+    #[tokio::test]
+    async fn test_load_many_fetches_only_missing() {
+        let keyset = keys_test::generate_keyset();
+        let kid = KeysetID::from(keyset.id);
+        let unknown = keys_test::generate_random_keysetid();
+        let mut repo = MockKeysRepository::new();
+        let ex_keyset = keyset.clone();
+        repo.expect_load_many()
+            .times(1)
+            .returning(move |_| Ok(HashMap::from([(kid, ex_keyset.clone())])));
+        repo.expect_load_many()
+            .with(eq(vec![unknown]))
+            .times(1)
+            .returning(|_| Ok(HashMap::new()));
+        let cache = CachingKeysRepository::new(repo, Duration::from_secs(60));
+
+        cache.load_many(&[kid]).await.unwrap();
+        let many = cache.load_many(&[kid, unknown]).await.unwrap();
+        assert_eq!(many.len(), 1);
+        assert_eq!(many.get(&kid), Some(&keyset));
+    }
+
+    #[tokio::test]
+    async fn test_info_expires() {
+        let keyset = keys_test::generate_keyset();
+        let kid = KeysetID::from(keyset.id);
+        let info = keyset_info(&keyset, true);
+        let mut repo = MockKeysRepository::new();
+        repo.expect_info()
+            .times(2)
+            .returning(move |_| Ok(Some(info.clone())));
+
+        let cache = CachingKeysRepository::new(repo, Duration::from_secs(60));
+        cache.info(&kid).await.unwrap();
+        cache.info(&kid).await.unwrap();
+        // a zero ttl never hits
+        let cache = CachingKeysRepository {
+            ttl: Duration::ZERO,
+            ..cache
+        };
+        cache.info(&kid).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replacing_id_invalidates_info() {
+        let keyset = keys_test::generate_keyset();
+        let kid = KeysetID::from(keyset.id);
+        let replacement = keys_test::generate_random_keysetid();
+        let mut repo = MockKeysRepository::new();
+        let active = keyset_info(&keyset, true);
+        repo.expect_info()
+            .times(1)
+            .returning(move |_| Ok(Some(active.clone())));
+        let inactive = keyset_info(&keyset, false);
+        repo.expect_info()
+            .times(1)
+            .returning(move |_| Ok(Some(inactive.clone())));
+        repo.expect_replacing_id()
+            .with(eq(kid))
+            .times(1)
+            .returning(move |_| Ok(Some(replacement)));
+        let cache = CachingKeysRepository::new(repo, Duration::from_secs(60));
+
+        assert!(cache.info(&kid).await.unwrap().unwrap().active);
+        assert_eq!(cache.replacing_id(&kid).await.unwrap(), Some(replacement));
+        assert_eq!(cache.replacing_id(&kid).await.unwrap(), Some(replacement));
+        assert!(!cache.info(&kid).await.unwrap().unwrap().active);
+    }
+}
@@ -1,18 +1,21 @@
 #![allow(dead_code)]
 // ----- standard library imports
 // ----- extra library imports
+use cdk::nuts::nut00 as cdk00;
 use cdk::Amount;
 use thiserror::Error;
 // ----- local imports
 use crate::keys::KeysetID;
+use crate::TStamp;
 
 pub type Result<T> = std::result::Result<T, Error>;
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Proof Repository error: {0}")]
     ProofRepository(#[from] anyhow::Error),
     #[error("Keyset Repository error: {0}")]
-    KeysetRepository(anyhow::Error),
+    KeysetRepository(KeysRepoError),
 
     #[error("DHKE error: {0}")]
     CdkDhke(#[from] cdk::dhke::Error),
@@ -21,20 +24,88 @@ pub enum Error {
 
     #[error("Already spent proofs")]
     ProofsAlreadySpent,
+    #[error("Proofs are pending in another operation")]
+    ProofsPending,
     #[error("Unknown proofs")]
     UnknownProofs,
     #[error("proofs cannot be merged together")]
     UnmergeableProofs,
+    #[error("Outputs pinned to keyset {0}, inputs are replaced by keyset {1}")]
+    UnexpectedOutputKeyset(KeysetID, KeysetID),
+    #[error("proofs from keysets of different units: {0} and {1}")]
+    MixedUnits(cdk00::CurrencyUnit, cdk00::CurrencyUnit),
 
     #[error("Unknown keyset {0}")]
     UnknownKeyset(KeysetID),
+    #[error("Unsupported keyset id {0}")]
+    UnsupportedKeysetID(cdk::nuts::nut02::Id),
+    #[error("Keyset {0} is disabled")]
+    DisabledKeyset(KeysetID),
+    #[error("No active keyset replaces keyset {0}")]
+    NoReplacementKeyset(KeysetID),
+    #[error("Proofs of keyset {0} cannot be spent")]
+    UnspendableKeyset(KeysetID),
+    #[error("Keyset {0} is past its validity window")]
+    KeysetExpired(KeysetID),
     #[error("Unknown amount {1} for keyset {0}")]
     UnknownAmountForKeyset(KeysetID, Amount),
+    #[error("Spending conditions unmet: {0}")]
+    SpendingConditionUnmet(cdk::nuts::nut11::Error),
+    #[error("Proofs of keyset {0} must be locked to and signed by {1}")]
+    LockedToKey(KeysetID, cdk::nuts::nut01::PublicKey),
+    #[error("HTLC preimage does not match the hash lock")]
+    HtlcPreimageMismatch,
+    #[error("HTLC locktime {0} not reached, refund not allowed yet")]
+    HtlcLocktimeNotMet(u64),
 
     #[error("Zero amount is not allowed")]
     ZeroAmount,
+    #[error("Too many inputs: {0} > {1}")]
+    TooManyInputs(usize, usize),
+    #[error("Too many outputs: {0} > {1}")]
+    TooManyOutputs(usize, usize),
+    #[error("No outputs, use melt to redeem proofs without new ones")]
+    NoOutputs,
+    #[error("Amount {0} needs denominations above max order {1}")]
+    AmountExceedsMaxOrder(Amount, u8),
+    #[error("Malformed proof secret: {0}")]
+    MalformedSecret(&'static str),
+    #[error("Duplicate input proofs")]
+    DuplicateInputs,
+    #[error("Duplicate blinded messages in outputs")]
+    DuplicateOutputs,
+    #[error("Blinded message already signed, use restore to recover its signature")]
+    BlindedMessageReused,
+    #[error("Amounts overflow")]
+    AmountOverflow,
     #[error("Unmatching amount: input {0} != output {1}")]
     UnmatchingAmount(Amount, Amount),
+    #[error("Insufficient melt inputs: input {0} < melt {1}")]
+    MeltError(Amount, Amount),
+    #[error("Unmatching change: change {0} != change outputs {1}")]
+    UnmatchingChange(Amount, Amount),
+    #[error("Excessive fee return: surplus {0} < fee outputs {1}")]
+    ExcessiveFeeReturn(Amount, Amount),
+    #[error("Insufficient fee covered: fee {0} > paid {1}")]
+    InsufficientFeeCovered(Amount, Amount),
+}
+
+pub type KeysRepoResult<T> = std::result::Result<T, KeysRepoError>;
+/// failures of a swap::KeysRepository. NotFound, Disabled, Corrupt and RotationWalkExceeded
+/// are about the keysets themselves and definitive, Backend errors come from the storage (e.g. a lost
+/// DB connection) and may succeed on retry
+#[derive(Debug, Error)]
+pub enum KeysRepoError {
+    #[error("keyset {0} not found")]
+    NotFound(KeysetID),
+    #[error("keyset {0} is disabled")]
+    Disabled(KeysetID),
+    #[error("keyset {0} is corrupted: {1}")]
+    Corrupt(KeysetID, String),
+    #[error("no active keyset for maturity {0} within {1} rotations")]
+    RotationWalkExceeded(TStamp, u32),
+    #[error("keys backend error: {0}")]
+    Backend(#[from] anyhow::Error),
 }
 
 impl axum::response::IntoResponse for Error {
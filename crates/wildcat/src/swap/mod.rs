@@ -1,10 +1,20 @@
 // ----- standard library imports
 // ----- extra library imports
 // ----- local modules
+mod cache;
 mod error;
+mod retry;
 mod service;
 pub mod web;
 // ----- local imports
+pub use error::{Error, KeysRepoError, KeysRepoResult};
+pub use service::issued_outputs;
+pub(crate) use service::proof_repository_error;
+pub use service::try_keyset_id;
+pub use service::verify_secret;
+pub(crate) use service::verify_swap_request;
 pub use service::KeysRepository;
+pub use service::KeysetValidity;
 pub use service::ProofRepository;
 pub use service::Service;
+pub use service::SwapLimits;
@@ -0,0 +1,307 @@
+#![allow(dead_code)]
+// ----- standard library imports
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+// ----- extra library imports
+use anyhow::Result as AnyResult;
+use async_trait::async_trait;
+use cdk::mint::MintKeySetInfo;
+use cdk::nuts::nut00 as cdk00;
+use cdk::nuts::nut01 as cdk01;
+use cdk::nuts::nut02 as cdk02;
+use cdk::nuts::nut07 as cdk07;
+// ----- local imports
+use crate::health::HealthCheck;
+use crate::keys::KeysetID;
+use crate::swap::{Error, KeysRepoError, KeysRepoResult, KeysRepository, ProofRepository};
+
+type Retryable = Arc<dyn Fn(&anyhow::Error) -> bool + Send + Sync>;
+
+/// how a RetryingRepository retries: up to `max_attempts` calls in total, waiting
+/// `base_delay` after the first failure and doubling it after each following one.
+/// `retryable` tells transient backend failures (e.g. a lost connection) apart
+#[derive(Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: usize,
+    pub base_delay: Duration,
+    pub retryable: Retryable,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(50),
+            retryable: Arc::new(|_| true),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn with_retryable<F>(mut self, retryable: F) -> Self
+    where
+        F: Fn(&anyhow::Error) -> bool + Send + Sync + 'static,
+    {
+        self.retryable = Arc::new(retryable);
+        self
+    }
+
+    // logical failures (swap::Error, definitive KeysRepoError) are never retried,
+    // whatever `retryable` says
+    fn is_transient(&self, e: &anyhow::Error) -> bool {
+        e.downcast_ref::<Error>().is_none() && (self.retryable)(e)
+    }
+}
+
+// ----- retrying repository
+// wraps a swap::KeysRepository or swap::ProofRepository, retrying failed calls
+// according to `policy`. on exhaustion the last error is returned
+#[derive(Clone)]
+pub struct RetryingRepository<Repo> {
+    repo: Repo,
+    policy: RetryPolicy,
+}
+
+impl<Repo> RetryingRepository<Repo> {
+    pub fn new(repo: Repo, policy: RetryPolicy) -> Self {
+        Self { repo, policy }
+    }
+
+    async fn run<T, E, F, Fut>(&self, transient: impl Fn(&E) -> bool, mut op: F) -> Result<T, E>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut delay = self.policy.base_delay;
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Err(e) if attempt < self.policy.max_attempts && transient(&e) => {
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn transient_keys_error(&self, e: &KeysRepoError) -> bool {
+        matches!(e, KeysRepoError::Backend(inner) if self.policy.is_transient(inner))
+    }
+}
+
+#[async_trait]
+impl<Repo> HealthCheck for RetryingRepository<Repo>
+where
+    Repo: HealthCheck,
+{
+    async fn ready(&self) -> bool {
+        self.repo.ready().await
+    }
+}
+
+#[async_trait]
+impl<Repo> KeysRepository for RetryingRepository<Repo>
+where
+    Repo: KeysRepository,
+{
+    async fn keyset(&self, id: &KeysetID) -> KeysRepoResult<Option<cdk02::MintKeySet>> {
+        self.run(|e| self.transient_keys_error(e), || self.repo.keyset(id))
+            .await
+    }
+
+    async fn info(&self, id: &KeysetID) -> KeysRepoResult<Option<MintKeySetInfo>> {
+        self.run(|e| self.transient_keys_error(e), || self.repo.info(id))
+            .await
+    }
+
+    async fn spendable(&self, id: &KeysetID) -> KeysRepoResult<bool> {
+        self.run(|e| self.transient_keys_error(e), || self.repo.spendable(id))
+            .await
+    }
+
+    async fn replacing_id(&self, id: &KeysetID) -> KeysRepoResult<Option<KeysetID>> {
+        self.run(
+            |e| self.transient_keys_error(e),
+            || self.repo.replacing_id(id),
+        )
+        .await
+    }
+
+    async fn list_info(&self) -> KeysRepoResult<Vec<MintKeySetInfo>> {
+        self.run(|e| self.transient_keys_error(e), || self.repo.list_info())
+            .await
+    }
+
+    async fn list_info_filtered(&self, only_enabled: bool) -> KeysRepoResult<Vec<MintKeySetInfo>> {
+        self.run(
+            |e| self.transient_keys_error(e),
+            || self.repo.list_info_filtered(only_enabled),
+        )
+        .await
+    }
+
+    async fn locked_to(&self, id: &KeysetID) -> KeysRepoResult<Option<cdk01::PublicKey>> {
+        self.run(|e| self.transient_keys_error(e), || self.repo.locked_to(id))
+            .await
+    }
+
+    async fn load_many(
+        &self,
+        ids: &[KeysetID],
+    ) -> KeysRepoResult<HashMap<KeysetID, cdk02::MintKeySet>> {
+        self.run(
+            |e| self.transient_keys_error(e),
+            || self.repo.load_many(ids),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl<Repo> ProofRepository for RetryingRepository<Repo>
+where
+    Repo: ProofRepository,
+{
+    async fn spend(&self, tokens: &[cdk00::Proof]) -> AnyResult<()> {
+        self.run(|e| self.policy.is_transient(e), || self.repo.spend(tokens))
+            .await
+    }
+
+    async fn get_state(&self, tokens: &[cdk00::Proof]) -> AnyResult<Vec<cdk07::State>> {
+        self.run(
+            |e| self.policy.is_transient(e),
+            || self.repo.get_state(tokens),
+        )
+        .await
+    }
+
+    async fn reserve(&self, tokens: &[cdk00::Proof]) -> AnyResult<()> {
+        self.run(
+            |e| self.policy.is_transient(e),
+            || self.repo.reserve(tokens),
+        )
+        .await
+    }
+
+    async fn release(&self, tokens: &[cdk00::Proof]) -> AnyResult<()> {
+        self.run(
+            |e| self.policy.is_transient(e),
+            || self.repo.release(tokens),
+        )
+        .await
+    }
+
+    async fn record_issued(&self, outputs: &[cdk00::BlindedMessage]) -> AnyResult<()> {
+        self.run(
+            |e| self.policy.is_transient(e),
+            || self.repo.record_issued(outputs),
+        )
+        .await
+    }
+
+    async fn issued(
+        &self,
+        blinded_secrets: &[cdk01::PublicKey],
+    ) -> AnyResult<Vec<Option<cdk00::BlindedMessage>>> {
+        self.run(
+            |e| self.policy.is_transient(e),
+            || self.repo.issued(blinded_secrets),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::test_utils as keys_test;
+    use crate::swap::service::{MockKeysRepository, MockProofRepository};
+    use anyhow::anyhow;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            base_delay: Duration::ZERO,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spend_succeeds_after_transient_failures() {
+        let mut repo = MockProofRepository::new();
+        repo.expect_spend()
+            .times(2)
+            .returning(|_| Err(anyhow!("connection reset")));
+        repo.expect_spend().times(1).returning(|_| Ok(()));
+        let retrying = RetryingRepository::new(repo, policy());
+
+        retrying.spend(&[]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_spend_logical_error_not_retried() {
+        let mut repo = MockProofRepository::new();
+        repo.expect_spend()
+            .times(1)
+            .returning(|_| Err(Error::ProofsAlreadySpent.into()));
+        let retrying = RetryingRepository::new(repo, policy());
+
+        let e = retrying.spend(&[]).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<Error>(),
+            Some(Error::ProofsAlreadySpent)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_spend_gives_up_with_last_error() {
+        let mut repo = MockProofRepository::new();
+        repo.expect_spend()
+            .times(2)
+            .returning(|_| Err(anyhow!("connection reset")));
+        repo.expect_spend()
+            .times(1)
+            .returning(|_| Err(anyhow!("connection refused")));
+        let retrying = RetryingRepository::new(repo, policy());
+
+        let e = retrying.spend(&[]).await.unwrap_err();
+        assert_eq!(e.to_string(), "connection refused");
+    }
+
+    #[tokio::test]
+    async fn test_keyset_retries_backend_errors_only() {
+        let keyset = keys_test::generate_keyset();
+        let kid = KeysetID::from(keyset.id);
+        let mut repo = MockKeysRepository::new();
+        repo.expect_keyset()
+            .times(2)
+            .returning(|_| Err(KeysRepoError::Backend(anyhow!("connection reset"))));
+        let ex_keyset = keyset.clone();
+        repo.expect_keyset()
+            .times(1)
+            .returning(move |_| Ok(Some(ex_keyset.clone())));
+        repo.expect_info()
+            .times(1)
+            .returning(|kid| Err(KeysRepoError::NotFound(*kid)));
+        let retrying = RetryingRepository::new(repo, policy());
+
+        assert_eq!(retrying.keyset(&kid).await.unwrap(), Some(keyset));
+        let e = retrying.info(&kid).await.unwrap_err();
+        assert!(matches!(e, KeysRepoError::NotFound(id) if id == kid));
+    }
+
+    #[tokio::test]
+    async fn test_retryable_predicate() {
+        let mut repo = MockProofRepository::new();
+        repo.expect_reserve()
+            .times(1)
+            .returning(|_| Err(anyhow!("constraint violation")));
+        let policy = policy().with_retryable(|e| e.to_string().contains("connection"));
+        let retrying = RetryingRepository::new(repo, policy);
+
+        assert!(retrying.reserve(&[]).await.is_err());
+    }
+}
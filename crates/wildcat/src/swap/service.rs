@@ -1,37 +1,416 @@
 #![allow(dead_code)]
 // ----- standard library imports
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
 // ----- extra library imports
 use anyhow::Result as AnyResult;
 use async_trait::async_trait;
+use bitcoin::hashes::sha256::Hash as Sha256;
+use bitcoin::hashes::Hash;
 use cdk::mint::MintKeySetInfo;
 use cdk::nuts::nut00 as cdk00;
+use cdk::nuts::nut01 as cdk01;
 use cdk::nuts::nut02 as cdk02;
 use cdk::nuts::nut07 as cdk07;
+use cdk::nuts::nut10 as cdk10;
+use cdk::nuts::nut11 as cdk11;
 use cdk::Amount;
 // ----- local imports
+use crate::clock::Clock;
+use crate::health::{HealthCheck, HealthReport};
 use crate::keys::KeysetID;
-use crate::swap::error::{Error, Result};
+use crate::stats::DenominationStats;
+use crate::swap::error::{Error, KeysRepoError, KeysRepoResult, Result};
 
 #[cfg_attr(test, mockall::automock)]
 #[async_trait]
-pub trait KeysRepository {
-    async fn keyset(&self, id: &KeysetID) -> AnyResult<Option<cdk02::MintKeySet>>;
-    async fn info(&self, id: &KeysetID) -> AnyResult<Option<MintKeySetInfo>>;
-    // in case keyset id is inactive, returns the proper replacement for it
-    async fn replacing_id(&self, id: &KeysetID) -> AnyResult<Option<KeysetID>>;
+pub trait KeysRepository: Send + Sync {
+    async fn keyset(&self, id: &KeysetID) -> KeysRepoResult<Option<cdk02::MintKeySet>>;
+    async fn info(&self, id: &KeysetID) -> KeysRepoResult<Option<MintKeySetInfo>>;
+    /// whether proofs of keyset `id` may be spent. unlike `active`, which only tells
+    /// if new outputs are signed with it, e.g. a disabled quote keyset is not spendable
+    /// while an inactive maturity keyset is
+    async fn spendable(&self, id: &KeysetID) -> KeysRepoResult<bool>;
+    // in case keyset id is inactive, returns the proper replacement for it.
+    // reports a disabled keyset as KeysRepoError::Disabled
+    async fn replacing_id(&self, id: &KeysetID) -> KeysRepoResult<Option<KeysetID>>;
+    /// every known keyset, as listed by NUT-02, sorted by (unit, valid_from, id)
+    /// so that repeated calls and replicas agree
+    async fn list_info(&self) -> KeysRepoResult<Vec<MintKeySetInfo>>;
+    /// as `list_info`, restricted to the spendable keysets if `only_enabled`
+    async fn list_info_filtered(&self, only_enabled: bool) -> KeysRepoResult<Vec<MintKeySetInfo>> {
+        let infos = self.list_info().await?;
+        if !only_enabled {
+            return Ok(infos);
+        }
+        let mut enabled = Vec::with_capacity(infos.len());
+        for info in infos {
+            if self.spendable(&KeysetID::from(info.id)).await? {
+                enabled.push(info);
+            }
+        }
+        Ok(enabled)
+    }
+    /// the key proofs of keyset `id` must be P2PK-locked to, if any
+    async fn locked_to(&self, _id: &KeysetID) -> KeysRepoResult<Option<cdk01::PublicKey>> {
+        Ok(None)
+    }
+    /// batch version of `keyset`, unknown ids are left out of the result.
+    /// backends able to fetch several keysets in one round-trip should override it
+    async fn load_many(
+        &self,
+        ids: &[KeysetID],
+    ) -> KeysRepoResult<HashMap<KeysetID, cdk02::MintKeySet>> {
+        let mut keysets = HashMap::with_capacity(ids.len());
+        for id in ids {
+            if let Some(keyset) = self.keyset(id).await? {
+                keysets.insert(*id, keyset);
+            }
+        }
+        Ok(keysets)
+    }
 }
 
 #[cfg_attr(test, mockall::automock)]
 #[async_trait]
-pub trait ProofRepository {
+pub trait ProofRepository: Send + Sync {
+    /// marks the proofs as spent, fails if any of them is already spent
     async fn spend(&self, tokens: &[cdk00::Proof]) -> AnyResult<()>;
     async fn get_state(&self, tokens: &[cdk00::Proof]) -> AnyResult<Vec<cdk07::State>>;
+    /// marks the proofs as pending, all or nothing: fails if any of them is not unspent
+    async fn reserve(&self, tokens: &[cdk00::Proof]) -> AnyResult<()>;
+    /// clears the pending mark, proofs in other states are left untouched
+    async fn release(&self, tokens: &[cdk00::Proof]) -> AnyResult<()>;
+    /// remembers the signed outputs, with the keyset and amount they were signed for
+    async fn record_issued(&self, outputs: &[cdk00::BlindedMessage]) -> AnyResult<()>;
+    /// the recorded output of each blinded secret, in input order
+    async fn issued(
+        &self,
+        blinded_secrets: &[cdk01::PublicKey],
+    ) -> AnyResult<Vec<Option<cdk00::BlindedMessage>>>;
+}
+
+// repositories may report logical failures (e.g. a double spend) as a swap::Error
+pub(crate) fn proof_repository_error(e: anyhow::Error) -> Error {
+    match e.downcast::<Error>() {
+        Ok(err) => err,
+        Err(e) => Error::ProofRepository(e),
+    }
+}
+
+// unknown and disabled keysets are swap failures, not repository ones
+fn keys_repository_error(e: KeysRepoError) -> Error {
+    match e {
+        KeysRepoError::NotFound(id) => Error::UnknownKeyset(id),
+        KeysRepoError::Disabled(id) => Error::DisabledKeyset(id),
+        e => Error::KeysetRepository(e),
+    }
+}
+
+/// wallet-supplied ids are converted with this, never with `KeysetID::from`
+pub fn try_keyset_id(id: cdk02::Id) -> Result<KeysetID> {
+    KeysetID::try_from_id(id).map_err(|_| Error::UnsupportedKeysetID(id))
+}
+
+// distinct keyset ids of the proofs, in order of first appearance
+fn keyset_ids(proofs: &[cdk00::Proof]) -> Result<Vec<KeysetID>> {
+    let mut ids: Vec<KeysetID> = Vec::new();
+    for proof in proofs {
+        let id = try_keyset_id(proof.keyset_id)?;
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    Ok(ids)
+}
+
+// rejects proofs whose keyset id `KeysetID` cannot represent
+fn verify_keyset_ids(proofs: &[cdk00::Proof]) -> Result<()> {
+    proofs
+        .iter()
+        .try_for_each(|proof| try_keyset_id(proof.keyset_id).map(|_| ()))
+}
+
+// checks needing no repository: non-empty, no zero amounts, well-formed secrets,
+// no duplicates and enough inputs. returns the input and output totals
+pub(crate) fn verify_swap_request(
+    inputs: &[cdk00::Proof],
+    outputs: &[cdk00::BlindedMessage],
+) -> Result<(Amount, Amount)> {
+    if inputs.is_empty() {
+        return Err(Error::ZeroAmount);
+    }
+    // swapping into nothing would burn the inputs, only melt may do that
+    if outputs.is_empty() {
+        return Err(Error::NoOutputs);
+    }
+    let no_zero_amount = outputs.iter().all(|output| output.amount != Amount::ZERO);
+    if !no_zero_amount {
+        return Err(Error::ZeroAmount);
+    }
+    verify_keyset_ids(inputs)?;
+    verify_secrets(inputs)?;
+    if has_duplicate_inputs(inputs) {
+        return Err(Error::DuplicateInputs);
+    }
+    if has_duplicate_outputs(outputs.iter()) {
+        return Err(Error::DuplicateOutputs);
+    }
+    let total_input = total_amount(inputs.iter().map(|proof| proof.amount))?;
+    let total_output = total_amount(outputs.iter().map(|output| output.amount))?;
+    if total_input < total_output {
+        return Err(Error::UnmatchingAmount(total_input, total_output));
+    }
+    Ok((total_input, total_output))
+}
+
+/// longest proof secret accepted, in bytes. NUT-10 secrets with a handful of tags
+/// stay well below it
+pub const MAX_SECRET_LENGTH: usize = 1024;
+
+/// the outputs as actually signed, which may differ in keyset from the requested ones.
+/// every mint path records these in the issued ledger of the `ProofRepository`
+pub fn issued_outputs<'a>(
+    outputs: impl Iterator<Item = &'a cdk00::BlindedMessage>,
+    signatures: &[cdk00::BlindSignature],
+) -> Vec<cdk00::BlindedMessage> {
+    outputs
+        .zip(signatures)
+        .map(|(output, signature)| {
+            cdk00::BlindedMessage::new(signature.amount, signature.keyset_id, output.blinded_secret)
+        })
+        .collect()
+}
+
+/// rejects secrets not worth hashing to the curve: empty or longer than
+/// MAX_SECRET_LENGTH
+pub fn verify_secret(secret: &cdk::secret::Secret) -> Result<()> {
+    let length = secret.as_bytes().len();
+    if length == 0 {
+        return Err(Error::MalformedSecret("empty"));
+    }
+    if length > MAX_SECRET_LENGTH {
+        return Err(Error::MalformedSecret("too long"));
+    }
+    Ok(())
+}
+
+/// `verify_secret` over every proof, before anything gets hashed to the curve
+pub fn verify_secrets(proofs: &[cdk00::Proof]) -> Result<()> {
+    proofs
+        .iter()
+        .try_for_each(|proof| verify_secret(&proof.secret))
+}
+
+fn has_duplicate_inputs(inputs: &[cdk00::Proof]) -> bool {
+    let mut secrets = HashSet::with_capacity(inputs.len());
+    !inputs.iter().all(|proof| secrets.insert(&proof.secret))
+}
+
+fn has_duplicate_outputs<'a>(outputs: impl Iterator<Item = &'a cdk00::BlindedMessage>) -> bool {
+    let mut secrets = HashSet::new();
+    !outputs
+        .into_iter()
+        .all(|output| secrets.insert(output.blinded_secret))
+}
+
+// cdk::Amount addition panics on overflow, adversarial amounts must not get there
+fn total_amount(mut amounts: impl Iterator<Item = Amount>) -> Result<Amount> {
+    amounts
+        .try_fold(Amount::ZERO, |total, amount| total.checked_add(amount))
+        .ok_or(Error::AmountOverflow)
+}
+
+/// canonical binary decomposition of `amount`, largest denomination first.
+/// a keyset generated with `max_order` has denominations 2^0 .. 2^(max_order - 1)
+pub fn split_amount(amount: Amount, max_order: u8) -> Result<Vec<Amount>> {
+    let limit = 1_u64
+        .checked_shl(u32::from(max_order))
+        .map_or(Amount::from(u64::MAX), Amount::from);
+    let parts = amount.split();
+    if parts.first().is_some_and(|largest| *largest >= limit) {
+        return Err(Error::AmountExceedsMaxOrder(amount, max_order));
+    }
+    Ok(parts)
+}
+
+/// NUT-10 well-known secrets lock the proof, plain secrets carry no conditions
+fn verify_spending_conditions(proof: &cdk00::Proof, now: u64) -> Result<()> {
+    let Ok(secret) = cdk10::Secret::try_from(&proof.secret) else {
+        return Ok(());
+    };
+    match secret.kind {
+        // NUT-11: signatures by the locking key(s), or by a refund key after locktime
+        cdk10::Kind::P2PK => proof.verify_p2pk().map_err(Error::SpendingConditionUnmet),
+        cdk10::Kind::HTLC => verify_htlc(proof, &secret, now),
+    }
+}
+
+// proofs of locked keysets: a P2PK secret on `lock` itself, signed by it. other
+// signers the secret may allow through its tags do not count
+fn verify_lock(proof: &cdk00::Proof, id: KeysetID, lock: &cdk01::PublicKey) -> Result<()> {
+    let locked = cdk10::Secret::try_from(&proof.secret).is_ok_and(|secret| {
+        secret.kind == cdk10::Kind::P2PK
+            && cdk01::PublicKey::from_str(&secret.secret_data.data).is_ok_and(|key| key == *lock)
+    });
+    if !locked || witness_signatures(proof, &[*lock])? < 1 {
+        return Err(Error::LockedToKey(id, *lock));
+    }
+    Ok(())
+}
+
+// valid signatures of the witness over the proof secret, by any of `pubkeys`
+fn witness_signatures(proof: &cdk00::Proof, pubkeys: &[cdk01::PublicKey]) -> Result<u64> {
+    let signatures = proof
+        .witness
+        .as_ref()
+        .and_then(cdk00::Witness::signatures)
+        .unwrap_or_default()
+        .iter()
+        .map(|signature| bitcoin::secp256k1::schnorr::Signature::from_str(signature))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| Error::SpendingConditionUnmet(cdk11::Error::InvalidSignature))?;
+    Ok(cdk11::valid_signatures(
+        proof.secret.as_bytes(),
+        pubkeys,
+        &signatures,
+    ))
+}
+
+/// NUT-14: the witness preimage must hash to the lock, plus the signatures required by
+/// the `pubkeys` tag. Past locktime, a refund key signature (anyone, without refund keys)
+/// unlocks the proof as well
+fn verify_htlc(proof: &cdk00::Proof, secret: &cdk10::Secret, now: u64) -> Result<()> {
+    let conditions = match &secret.secret_data.tags {
+        Some(tags) => {
+            cdk11::Conditions::try_from(tags.clone()).map_err(Error::SpendingConditionUnmet)?
+        }
+        None => cdk11::Conditions::default(),
+    };
+    let preimage = proof.witness.as_ref().and_then(cdk00::Witness::preimage);
+    let hash_lock = Sha256::from_str(&secret.secret_data.data).ok();
+    let unlocked = match (preimage, hash_lock) {
+        (Some(preimage), Some(hash_lock)) => {
+            hex::decode(preimage).is_ok_and(|preimage| Sha256::hash(&preimage) == hash_lock)
+        }
+        _ => false,
+    };
+    if unlocked {
+        if let Some(pubkeys) = &conditions.pubkeys {
+            let required = conditions.num_sigs.unwrap_or(1);
+            if witness_signatures(proof, pubkeys)? < required {
+                return Err(Error::SpendingConditionUnmet(
+                    cdk11::Error::SpendConditionsNotMet,
+                ));
+            }
+        }
+        return Ok(());
+    }
+    let Some(locktime) = conditions.locktime else {
+        return Err(Error::HtlcPreimageMismatch);
+    };
+    let refund_keys = conditions.refund_keys.unwrap_or_default();
+    let refund_signed = !refund_keys.is_empty() && witness_signatures(proof, &refund_keys)? > 0;
+    if locktime >= now && refund_signed {
+        return Err(Error::HtlcLocktimeNotMet(locktime));
+    }
+    if locktime >= now {
+        return Err(Error::HtlcPreimageMismatch);
+    }
+    if refund_keys.is_empty() || refund_signed {
+        return Ok(());
+    }
+    Err(Error::SpendingConditionUnmet(
+        cdk11::Error::SpendConditionsNotMet,
+    ))
+}
+
+fn sign_output(
+    keys: &cdk02::MintKeySet,
+    output: &cdk00::BlindedMessage,
+) -> Result<cdk00::BlindSignature> {
+    let keypair = keys
+        .keys
+        .get(&output.amount)
+        .ok_or(Error::UnknownAmountForKeyset(
+            KeysetID::from(keys.id),
+            output.amount,
+        ))?;
+    let c = cdk::dhke::sign_message(&keypair.secret_key, &output.blinded_secret)?;
+    let signature = cdk00::BlindSignature::new(
+        output.amount,
+        c,
+        keys.id,
+        &output.blinded_secret,
+        keypair.secret_key.clone(),
+    )?;
+    Ok(signature)
+}
+
+/// whether proofs of keysets past their `valid_to` are accepted. matured credit
+/// keysets are meant to be redeemed, not swapped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeysetValidity {
+    Ignore,
+    Enforce,
 }
 
 #[derive(Clone)]
 pub struct Service<KeysRepo, ProofRepo> {
     pub keys: KeysRepo,
     pub proofs: ProofRepo,
+    pub stats: DenominationStats,
+    pub limits: SwapLimits,
+    pub clock: Arc<dyn Clock>,
+}
+
+/// caps the proofs and blinded messages a single request may carry, every one of them
+/// costs an EC operation
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+pub struct SwapLimits {
+    pub max_inputs: usize,
+    pub max_outputs: usize,
+}
+
+/// what a swap did: the signed outputs, the input fee kept by the mint and the keyset
+/// that signed the outputs
+#[derive(Debug, Clone)]
+pub struct SwapOutcome {
+    pub signatures: Vec<cdk00::BlindSignature>,
+    pub fee_paid: Amount,
+    pub keyset: KeysetID,
+}
+
+/// an output the client still has to blind: face value and keyset to sign it with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputTemplate {
+    pub amount: Amount,
+    pub keyset_id: cdk02::Id,
+}
+
+impl Default for SwapLimits {
+    fn default() -> Self {
+        Self {
+            max_inputs: 1000,
+            max_outputs: 1000,
+        }
+    }
+}
+
+impl<KeysRepo, ProofRepo> Service<KeysRepo, ProofRepo>
+where
+    KeysRepo: HealthCheck,
+    ProofRepo: HealthCheck,
+{
+    /// readiness of the keys and proofs repositories
+    pub async fn health(&self) -> HealthReport {
+        let (keys, proofs) = tokio::join!(self.keys.ready(), self.proofs.ready());
+        let mut report = HealthReport::default();
+        report.dependencies.insert(String::from("keys"), keys);
+        report.dependencies.insert(String::from("proofs"), proofs);
+        report
+    }
 }
 
 impl<KeysRepo, ProofRepo> Service<KeysRepo, ProofRepo>
@@ -39,324 +418,3296 @@ where
     KeysRepo: KeysRepository,
     ProofRepo: ProofRepository,
 {
-    async fn verify_proofs_are_unspent(&self, proofs: &[cdk00::Proof]) -> Result<bool> {
-        let result = self
-            .proofs
-            .get_state(proofs)
+    #[tracing::instrument(skip_all, fields(proofs = proofs.len()))]
+    pub(crate) async fn verify_proofs_signatures(
+        &self,
+        proofs: &[cdk00::Proof],
+        validity: KeysetValidity,
+    ) -> Result<bool> {
+        let now = self.clock.now().timestamp() as u64;
+        let ids = keyset_ids(proofs)?;
+        let keysets = self
+            .keys
+            .load_many(&ids)
             .await
-            .map_err(Error::ProofRepository)?
-            .into_iter()
-            .all(|state| state == cdk07::State::Unspent);
-        Ok(result)
-    }
-
-    async fn verify_proofs_signatures(&self, proofs: &[cdk00::Proof]) -> Result<bool> {
-        for proof in proofs {
-            let id = proof.keyset_id;
-            let keyset = self
+            .map_err(keys_repository_error)?;
+        tracing::debug!(keysets = ?ids, found = keysets.len(), "keysets loaded");
+        let mut locks = HashMap::new();
+        for id in &ids {
+            if !keysets.contains_key(id) {
+                return Err(Error::UnknownKeyset(*id));
+            }
+            let spendable = self
+                .keys
+                .spendable(id)
+                .await
+                .map_err(keys_repository_error)?;
+            if !spendable {
+                return Err(Error::UnspendableKeyset(*id));
+            }
+            if validity == KeysetValidity::Enforce {
+                let info = self
+                    .keys
+                    .info(id)
+                    .await
+                    .map_err(keys_repository_error)?
+                    .ok_or(Error::UnknownKeyset(*id))?;
+                if matches!(info.valid_to, Some(valid_to) if valid_to < now) {
+                    return Err(Error::KeysetExpired(*id));
+                }
+            }
+            let lock = self
                 .keys
-                .keyset(&id.into())
+                .locked_to(id)
                 .await
-                .map_err(Error::KeysetRepository)?
-                .ok_or_else(|| Error::UnknownKeyset(id.into()))?;
+                .map_err(keys_repository_error)?;
+            if let Some(lock) = lock {
+                locks.insert(*id, lock);
+            }
+        }
+        for proof in proofs {
+            let id = try_keyset_id(proof.keyset_id)?;
+            let keyset = keysets.get(&id).ok_or(Error::UnknownKeyset(id))?;
             let key = keyset
                 .keys
                 .get(&proof.amount)
-                .ok_or_else(|| Error::UnknownAmountForKeyset(id.into(), proof.amount))?;
+                .ok_or(Error::UnknownAmountForKeyset(id, proof.amount))?;
             let ok = cdk::dhke::verify_message(&key.secret_key, proof.c, proof.secret.as_bytes());
             if ok.is_err() {
+                tracing::debug!(keyset = %id, amount = %proof.amount, "invalid proof signature");
                 return Ok(false);
             }
+            verify_spending_conditions(proof, now)?;
+            if let Some(lock) = locks.get(&id) {
+                verify_lock(proof, id, lock)?;
+            }
         }
         Ok(true)
     }
 
+    /// NUT-02 input fee: sum of input_fee_ppk over all inputs, rounded up to the unit.
+    /// inputs must all come from keysets of the same unit
+    async fn inputs_fee(&self, inputs: &[cdk00::Proof]) -> Result<Amount> {
+        let mut fees_ppk: HashMap<KeysetID, u64> = HashMap::new();
+        let mut unit: Option<cdk00::CurrencyUnit> = None;
+        for id in keyset_ids(inputs)? {
+            let info = self
+                .keys
+                .info(&id)
+                .await
+                .map_err(keys_repository_error)?
+                .ok_or(Error::UnknownKeyset(id))?;
+            match &unit {
+                Some(unit) if *unit != info.unit => {
+                    return Err(Error::MixedUnits(unit.clone(), info.unit));
+                }
+                Some(_) => {}
+                None => unit = Some(info.unit.clone()),
+            }
+            fees_ppk.insert(id, info.input_fee_ppk);
+        }
+        let mut sum_fee_ppk: u64 = 0;
+        for proof in inputs {
+            sum_fee_ppk += fees_ppk[&try_keyset_id(proof.keyset_id)?];
+        }
+        Ok(Amount::from(sum_fee_ppk.div_ceil(1000)))
+    }
+
+    async fn replacing_keyset(&self, inputs: &[cdk00::Proof]) -> Result<KeysetID> {
+        let mut ids: Vec<KeysetID> = Vec::new();
+        for id in keyset_ids(inputs)? {
+            let o = self
+                .keys
+                .replacing_id(&id)
+                .await
+                .map_err(keys_repository_error)?
+                // the keyset is known, the mint has nothing active to replace it with
+                .ok_or(Error::NoReplacementKeyset(id))?;
+            ids.push(o);
+        }
+        let first = ids.first().expect("first is None");
+        if ids.iter().any(|id| *id != *first) {
+            return Err(Error::UnmergeableProofs);
+        }
+        Ok(*first)
+    }
+
+    /// every returned signature carries a NUT-12 DLEQ proof
+    pub(crate) async fn sign_outputs(
+        &self,
+        kid: &KeysetID,
+        outputs: &[cdk00::BlindedMessage],
+    ) -> Result<Vec<cdk00::BlindSignature>> {
+        let keys = self
+            .keys
+            .keyset(kid)
+            .await
+            .map_err(keys_repository_error)?
+            .ok_or(Error::UnknownKeyset(*kid))?;
+        // every amount is checked before computing any signature
+        if let Some(output) = outputs
+            .iter()
+            .find(|output| !keys.keys.contains_key(&output.amount))
+        {
+            return Err(Error::UnknownAmountForKeyset(*kid, output.amount));
+        }
+        outputs
+            .iter()
+            .map(|output| sign_output(&keys, output))
+            .collect()
+    }
+
+    // the inputs are already spent: a failure here only costs the ability to restore
+    pub(crate) async fn record_issued<'a>(
+        &self,
+        outputs: impl Iterator<Item = &'a cdk00::BlindedMessage>,
+        signatures: &[cdk00::BlindSignature],
+    ) {
+        self.stats.record(signatures);
+        let issued = issued_outputs(outputs, signatures);
+        if let Err(e) = self.proofs.record_issued(&issued).await {
+            log::error!("failed to record {} issued outputs: {}", issued.len(), e);
+        }
+    }
+
+    pub(crate) fn verify_counts(&self, inputs: usize, outputs: usize) -> Result<()> {
+        if inputs > self.limits.max_inputs {
+            return Err(Error::TooManyInputs(inputs, self.limits.max_inputs));
+        }
+        if outputs > self.limits.max_outputs {
+            return Err(Error::TooManyOutputs(outputs, self.limits.max_outputs));
+        }
+        Ok(())
+    }
+
+    // outputs signed in an earlier swap are a client bug, or a restore in disguise.
+    // concurrent requests with the same output may still both go through
+    pub(crate) async fn verify_fresh_outputs<'a>(
+        &self,
+        outputs: impl Iterator<Item = &'a cdk00::BlindedMessage>,
+    ) -> Result<()> {
+        let secrets: Vec<cdk01::PublicKey> = outputs.map(|output| output.blinded_secret).collect();
+        let issued = self
+            .proofs
+            .issued(&secrets)
+            .await
+            .map_err(proof_repository_error)?;
+        if issued.iter().any(Option::is_some) {
+            return Err(Error::BlindedMessageReused);
+        }
+        Ok(())
+    }
+
+    // keysets only sign powers of two below 2^max_order, anything else cannot verify:
+    // rejected before the inputs are reserved and their signatures checked.
+    // unknown keysets are left to the signature verification
+    pub(crate) async fn verify_input_amounts(&self, inputs: &[cdk00::Proof]) -> Result<()> {
+        if let Some(proof) = inputs
+            .iter()
+            .find(|proof| !u64::from(proof.amount).is_power_of_two())
+        {
+            let id = try_keyset_id(proof.keyset_id)?;
+            return Err(Error::UnknownAmountForKeyset(id, proof.amount));
+        }
+        for id in keyset_ids(inputs)? {
+            let Some(info) = self.keys.info(&id).await.map_err(keys_repository_error)? else {
+                continue;
+            };
+            let max = 1_u64
+                .checked_shl(u32::from(info.max_order))
+                .unwrap_or(u64::MAX);
+            if let Some(proof) = inputs
+                .iter()
+                .find(|proof| id == proof.keyset_id && u64::from(proof.amount) >= max)
+            {
+                return Err(Error::UnknownAmountForKeyset(id, proof.amount));
+            }
+        }
+        Ok(())
+    }
+
+    /// commits the reserved inputs to spent if `result` is ok, releases them otherwise
+    pub(crate) async fn settle<T>(&self, inputs: &[cdk00::Proof], result: Result<T>) -> Result<T> {
+        let result = match result {
+            Ok(value) => self
+                .proofs
+                .spend(inputs)
+                .await
+                .map(|_| value)
+                .map_err(proof_repository_error),
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            if let Err(e) = self.proofs.release(inputs).await {
+                log::error!("failed to release {} reserved proofs: {}", inputs.len(), e);
+            }
+        }
+        result
+    }
+
+    /// `output_keyset` lets the client pin the keyset signing the outputs, the swap fails
+    /// unless it is the one replacing the inputs
+    #[tracing::instrument(
+        skip_all,
+        fields(inputs = inputs.len(), outputs = outputs.len()),
+        err(level = "warn", Display)
+    )]
     pub async fn swap(
         &self,
         inputs: &[cdk00::Proof],
         outputs: &[cdk00::BlindedMessage],
+        output_keyset: Option<KeysetID>,
+    ) -> Result<Vec<cdk00::BlindSignature>> {
+        let outcome = self.swap_detailed(inputs, outputs, output_keyset).await?;
+        Ok(outcome.signatures)
+    }
+
+    /// as `swap`, reporting the fee paid and the keyset that signed the outputs
+    pub async fn swap_detailed(
+        &self,
+        inputs: &[cdk00::Proof],
+        outputs: &[cdk00::BlindedMessage],
+        output_keyset: Option<KeysetID>,
+    ) -> Result<SwapOutcome> {
+        // first step: zero-cost verifications
+        self.verify_counts(inputs.len(), outputs.len())?;
+        let (total_input, total_output) = verify_swap_request(inputs, outputs)?;
+        tracing::debug!(%total_input, %total_output, "swap request received");
+        self.verify_fresh_outputs(outputs.iter()).await?;
+        self.verify_input_amounts(inputs).await?;
+        // second step: costly verifications, inputs are held pending meanwhile
+        self.proofs
+            .reserve(inputs)
+            .await
+            .map_err(proof_repository_error)?;
+        tracing::debug!("inputs reserved");
+        let result = self
+            .swap_reserved(inputs, outputs, output_keyset, total_input, total_output)
+            .await;
+        let outcome = self.settle(inputs, result).await?;
+        self.record_issued(outputs.iter(), &outcome.signatures)
+            .await;
+        Ok(outcome)
+    }
+
+    async fn swap_reserved(
+        &self,
+        inputs: &[cdk00::Proof],
+        outputs: &[cdk00::BlindedMessage],
+        output_keyset: Option<KeysetID>,
+        total_input: Amount,
+        total_output: Amount,
+    ) -> Result<SwapOutcome> {
+        let proofs_signatures_are_ok = self
+            .verify_proofs_signatures(inputs, KeysetValidity::Ignore)
+            .await?;
+        if !proofs_signatures_are_ok {
+            return Err(Error::UnknownProofs);
+        }
+        let fee = self.inputs_fee(inputs).await?;
+        let paid = total_input - total_output;
+        tracing::debug!(%fee, %paid, "input fee computed");
+        if paid < fee {
+            return Err(Error::InsufficientFeeCovered(fee, paid));
+        }
+        if paid > fee {
+            return Err(Error::UnmatchingAmount(total_input, total_output + fee));
+        }
+
+        let kid = self.replacing_keyset(inputs).await?;
+        if let Some(pinned) = output_keyset {
+            if pinned != kid {
+                return Err(Error::UnexpectedOutputKeyset(pinned, kid));
+            }
+        }
+        tracing::debug!(keyset = %kid, "signing outputs");
+        let signatures = self.sign_outputs(&kid, outputs).await?;
+        Ok(SwapOutcome {
+            signatures,
+            fee_paid: fee,
+            keyset: kid,
+        })
+    }
+
+    /// output templates swapping `inputs` into the face values picked by the client,
+    /// so thin clients need not work out the fee and the replacing keyset. nothing is
+    /// spent: the client blinds a secret per template, then swaps as usual.
+    /// `target_amounts` must be denominations of the replacing keyset and sum to the
+    /// inputs minus their fee
+    pub async fn swap_auto(
+        &self,
+        inputs: &[cdk00::Proof],
+        target_amounts: &[Amount],
+    ) -> Result<Vec<OutputTemplate>> {
+        self.verify_counts(inputs.len(), target_amounts.len())?;
+        if inputs.is_empty() || target_amounts.contains(&Amount::ZERO) {
+            return Err(Error::ZeroAmount);
+        }
+        if target_amounts.is_empty() {
+            return Err(Error::NoOutputs);
+        }
+        verify_keyset_ids(inputs)?;
+        verify_secrets(inputs)?;
+        let total_input = total_amount(inputs.iter().map(|proof| proof.amount))?;
+        let total_output = total_amount(target_amounts.iter().copied())?;
+        if total_input < total_output {
+            return Err(Error::UnmatchingAmount(total_input, total_output));
+        }
+        let fee = self.inputs_fee(inputs).await?;
+        let paid = total_input - total_output;
+        if paid < fee {
+            return Err(Error::InsufficientFeeCovered(fee, paid));
+        }
+        if paid > fee {
+            return Err(Error::UnmatchingAmount(total_input, total_output + fee));
+        }
+
+        let kid = self.replacing_keyset(inputs).await?;
+        let keyset = self
+            .keys
+            .keyset(&kid)
+            .await
+            .map_err(keys_repository_error)?
+            .ok_or(Error::UnknownKeyset(kid))?;
+        target_amounts
+            .iter()
+            .map(|amount| {
+                if keyset.keys.get(amount).is_none() {
+                    return Err(Error::UnknownAmountForKeyset(kid, *amount));
+                }
+                Ok(OutputTemplate {
+                    amount: *amount,
+                    keyset_id: keyset.id,
+                })
+            })
+            .collect()
+    }
+
+    /// swaps several input/output groups at once, each one verified and signed as if
+    /// swapped alone (e.g. proofs of different maturity dates, replaced by different
+    /// keysets). all or nothing: the inputs of every group are spent together or not at all.
+    /// signatures are returned per group, in request order
+    pub async fn swap_multi(
+        &self,
+        groups: Vec<(Vec<cdk00::Proof>, Vec<cdk00::BlindedMessage>)>,
+    ) -> Result<Vec<Vec<cdk00::BlindSignature>>> {
+        if groups.is_empty() {
+            return Err(Error::ZeroAmount);
+        }
+        // first step: zero-cost verifications, per group and across groups
+        self.verify_counts(
+            groups.iter().map(|(inputs, _)| inputs.len()).sum(),
+            groups.iter().map(|(_, outputs)| outputs.len()).sum(),
+        )?;
+        let mut totals = Vec::with_capacity(groups.len());
+        for (inputs, outputs) in &groups {
+            totals.push(verify_swap_request(inputs, outputs)?);
+        }
+        let inputs: Vec<cdk00::Proof> = groups
+            .iter()
+            .flat_map(|(inputs, _)| inputs.iter().cloned())
+            .collect();
+        if has_duplicate_inputs(&inputs) {
+            return Err(Error::DuplicateInputs);
+        }
+        if has_duplicate_outputs(groups.iter().flat_map(|(_, outputs)| outputs.iter())) {
+            return Err(Error::DuplicateOutputs);
+        }
+        self.verify_fresh_outputs(groups.iter().flat_map(|(_, outputs)| outputs.iter()))
+            .await?;
+        self.verify_input_amounts(&inputs).await?;
+        // second step: costly verifications, all inputs are held pending meanwhile
+        self.proofs
+            .reserve(&inputs)
+            .await
+            .map_err(proof_repository_error)?;
+        let result = self.swap_multi_reserved(&groups, totals).await;
+        let signatures = self.settle(&inputs, result).await?;
+        self.record_issued(
+            groups.iter().flat_map(|(_, outputs)| outputs.iter()),
+            &signatures.concat(),
+        )
+        .await;
+        Ok(signatures)
+    }
+
+    async fn swap_multi_reserved(
+        &self,
+        groups: &[(Vec<cdk00::Proof>, Vec<cdk00::BlindedMessage>)],
+        totals: Vec<(Amount, Amount)>,
+    ) -> Result<Vec<Vec<cdk00::BlindSignature>>> {
+        let mut signatures = Vec::with_capacity(groups.len());
+        for ((inputs, outputs), (total_input, total_output)) in groups.iter().zip(totals) {
+            let outcome = self
+                .swap_reserved(inputs, outputs, None, total_input, total_output)
+                .await?;
+            signatures.push(outcome.signatures);
+        }
+        Ok(signatures)
+    }
+
+    /// NUT-08 style swap: inputs may exceed outputs, the surplus can be returned
+    /// (fully or partially) through `fee_outputs`, signed after the main outputs
+    pub async fn swap_with_fee_return(
+        &self,
+        inputs: &[cdk00::Proof],
+        outputs: &[cdk00::BlindedMessage],
+        fee_outputs: &[cdk00::BlindedMessage],
     ) -> Result<Vec<cdk00::BlindSignature>> {
         if inputs.is_empty() {
             return Err(Error::ZeroAmount);
         }
+        if outputs.is_empty() && fee_outputs.is_empty() {
+            return Err(Error::NoOutputs);
+        }
         // first step: zero-cost verifications
-        let no_zero_amount = outputs.iter().all(|output| output.amount != Amount::ZERO);
+        self.verify_counts(inputs.len(), outputs.len() + fee_outputs.len())?;
+        let no_zero_amount = outputs
+            .iter()
+            .chain(fee_outputs.iter())
+            .all(|output| output.amount != Amount::ZERO);
         if !no_zero_amount {
             return Err(Error::ZeroAmount);
         }
-        let total_input: Amount = inputs
-            .iter()
-            .fold(Amount::ZERO, |total, proof| total + proof.amount);
-        let total_output: Amount = outputs
-            .iter()
-            .fold(Amount::ZERO, |total, output| total + output.amount);
+        verify_keyset_ids(inputs)?;
+        verify_secrets(inputs)?;
+        if has_duplicate_inputs(inputs) {
+            return Err(Error::DuplicateInputs);
+        }
+        if has_duplicate_outputs(outputs.iter().chain(fee_outputs.iter())) {
+            return Err(Error::DuplicateOutputs);
+        }
+        let total_input = total_amount(inputs.iter().map(|proof| proof.amount))?;
+        let total_output = total_amount(outputs.iter().map(|output| output.amount))?;
+        let total_fee_return = total_amount(fee_outputs.iter().map(|output| output.amount))?;
         log::debug!(
-            "Received swap request: {} inputs totaling {}, {} outputs totaling {}",
+            "Received swap request: {} inputs totaling {}, {} outputs totaling {}, {} fee outputs totaling {}",
             inputs.len(),
             total_input,
             outputs.len(),
-            total_output
+            total_output,
+            fee_outputs.len(),
+            total_fee_return,
         );
-        if total_input != total_output {
+        if total_input < total_output {
             return Err(Error::UnmatchingAmount(total_input, total_output));
         }
-        // second step: costly verifications
-        let proofs_are_unspent = self.verify_proofs_are_unspent(inputs).await?;
-        if !proofs_are_unspent {
-            return Err(Error::ProofsAlreadySpent);
+        let surplus = total_input - total_output;
+        if total_fee_return > surplus {
+            return Err(Error::ExcessiveFeeReturn(surplus, total_fee_return));
         }
-        let proofs_signatures_are_ok = self.verify_proofs_signatures(inputs).await?;
+        self.verify_fresh_outputs(outputs.iter().chain(fee_outputs.iter()))
+            .await?;
+        self.verify_input_amounts(inputs).await?;
+        // second step: costly verifications, inputs are held pending meanwhile
+        self.proofs
+            .reserve(inputs)
+            .await
+            .map_err(proof_repository_error)?;
+        let result = self
+            .swap_with_fee_return_reserved(inputs, outputs, fee_outputs, surplus, total_fee_return)
+            .await;
+        let signatures = self.settle(inputs, result).await?;
+        self.record_issued(outputs.iter().chain(fee_outputs.iter()), &signatures)
+            .await;
+        Ok(signatures)
+    }
+
+    async fn swap_with_fee_return_reserved(
+        &self,
+        inputs: &[cdk00::Proof],
+        outputs: &[cdk00::BlindedMessage],
+        fee_outputs: &[cdk00::BlindedMessage],
+        surplus: Amount,
+        total_fee_return: Amount,
+    ) -> Result<Vec<cdk00::BlindSignature>> {
+        let proofs_signatures_are_ok = self
+            .verify_proofs_signatures(inputs, KeysetValidity::Ignore)
+            .await?;
         if !proofs_signatures_are_ok {
             return Err(Error::UnknownProofs);
         }
+        let fee = self.inputs_fee(inputs).await?;
+        if surplus < fee {
+            return Err(Error::InsufficientFeeCovered(fee, surplus));
+        }
+        if total_fee_return > surplus - fee {
+            return Err(Error::ExcessiveFeeReturn(surplus - fee, total_fee_return));
+        }
 
-        let mut ids: Vec<KeysetID> = Vec::new();
-        for i in inputs {
-            let o = self
-                .keys
-                .replacing_id(&i.keyset_id.into())
-                .await
-                .map_err(Error::KeysetRepository)?
-                .ok_or(Error::UnknownKeyset(i.keyset_id.into()))?;
-            ids.push(o);
+        let kid = self.replacing_keyset(inputs).await?;
+        let mut signatures = self.sign_outputs(&kid, outputs).await?;
+        let fee_signatures = self.sign_outputs(&kid, fee_outputs).await?;
+        signatures.extend(fee_signatures);
+        Ok(signatures)
+    }
+
+    /// NUT-07 state of each proof, in input order; never-seen proofs are unspent
+    pub async fn check_state(&self, proofs: &[cdk00::Proof]) -> Result<Vec<cdk07::ProofState>> {
+        verify_keyset_ids(proofs)?;
+        verify_secrets(proofs)?;
+        let states = self
+            .proofs
+            .get_state(proofs)
+            .await
+            .map_err(proof_repository_error)?;
+        let mut response = Vec::with_capacity(proofs.len());
+        for (proof, state) in proofs.iter().zip(states) {
+            let y = cdk::dhke::hash_to_curve(&proof.secret.to_bytes())?;
+            response.push(cdk07::ProofState {
+                y,
+                state,
+                witness: None,
+            });
         }
-        let first = ids.first().expect("first is None");
-        if ids.iter().any(|id| *id != *first) {
-            return Err(Error::UnmergeableProofs);
+        Ok(response)
+    }
+
+    /// burns the inputs against a payout of `amount`, returns the fee (inputs - amount)
+    pub async fn melt(&self, inputs: &[cdk00::Proof], amount: Amount) -> Result<Amount> {
+        if inputs.is_empty() || amount == Amount::ZERO {
+            return Err(Error::ZeroAmount);
+        }
+        // first step: zero-cost verifications
+        self.verify_counts(inputs.len(), 0)?;
+        verify_keyset_ids(inputs)?;
+        verify_secrets(inputs)?;
+        if has_duplicate_inputs(inputs) {
+            return Err(Error::DuplicateInputs);
+        }
+        let total_input = total_amount(inputs.iter().map(|proof| proof.amount))?;
+        log::debug!(
+            "Received melt request: {} inputs totaling {}, melt amount {}",
+            inputs.len(),
+            total_input,
+            amount
+        );
+        if total_input < amount {
+            return Err(Error::MeltError(total_input, amount));
+        }
+        self.verify_input_amounts(inputs).await?;
+        // second step: costly verifications, inputs are held pending meanwhile
+        self.proofs
+            .reserve(inputs)
+            .await
+            .map_err(proof_repository_error)?;
+        let result = self.melt_reserved(inputs, total_input, amount).await;
+        self.settle(inputs, result).await
+    }
+
+    async fn melt_reserved(
+        &self,
+        inputs: &[cdk00::Proof],
+        total_input: Amount,
+        amount: Amount,
+    ) -> Result<Amount> {
+        let proofs_signatures_are_ok = self
+            .verify_proofs_signatures(inputs, KeysetValidity::Ignore)
+            .await?;
+        if !proofs_signatures_are_ok {
+            return Err(Error::UnknownProofs);
+        }
+        Ok(total_input - amount)
+    }
+
+    /// burns the inputs against a payout of `melt_amount`, the change left after fees is
+    /// returned signed on `change_outputs`, which must match it exactly.
+    /// without change outputs the surplus is kept by the mint
+    pub async fn melt_with_change(
+        &self,
+        inputs: &[cdk00::Proof],
+        melt_amount: Amount,
+        change_outputs: &[cdk00::BlindedMessage],
+    ) -> Result<Vec<cdk00::BlindSignature>> {
+        if inputs.is_empty() || melt_amount == Amount::ZERO {
+            return Err(Error::ZeroAmount);
+        }
+        // first step: zero-cost verifications
+        self.verify_counts(inputs.len(), change_outputs.len())?;
+        let no_zero_amount = change_outputs
+            .iter()
+            .all(|output| output.amount != Amount::ZERO);
+        if !no_zero_amount {
+            return Err(Error::ZeroAmount);
+        }
+        verify_keyset_ids(inputs)?;
+        verify_secrets(inputs)?;
+        if has_duplicate_inputs(inputs) {
+            return Err(Error::DuplicateInputs);
+        }
+        if has_duplicate_outputs(change_outputs.iter()) {
+            return Err(Error::DuplicateOutputs);
+        }
+        let total_input = total_amount(inputs.iter().map(|proof| proof.amount))?;
+        let total_change = total_amount(change_outputs.iter().map(|output| output.amount))?;
+        log::debug!(
+            "Received melt request: {} inputs totaling {}, melt amount {}, {} change outputs totaling {}",
+            inputs.len(),
+            total_input,
+            melt_amount,
+            change_outputs.len(),
+            total_change,
+        );
+        if total_input < melt_amount {
+            return Err(Error::MeltError(total_input, melt_amount));
+        }
+        self.verify_input_amounts(inputs).await?;
+        self.verify_fresh_outputs(change_outputs.iter()).await?;
+        // second step: costly verifications, inputs are held pending meanwhile
+        self.proofs
+            .reserve(inputs)
+            .await
+            .map_err(proof_repository_error)?;
+        let result = self
+            .melt_with_change_reserved(
+                inputs,
+                total_input,
+                melt_amount,
+                change_outputs,
+                total_change,
+            )
+            .await;
+        let signatures = self.settle(inputs, result).await?;
+        if !signatures.is_empty() {
+            self.record_issued(change_outputs.iter(), &signatures).await;
+        }
+        Ok(signatures)
+    }
+
+    async fn melt_with_change_reserved(
+        &self,
+        inputs: &[cdk00::Proof],
+        total_input: Amount,
+        melt_amount: Amount,
+        change_outputs: &[cdk00::BlindedMessage],
+        total_change: Amount,
+    ) -> Result<Vec<cdk00::BlindSignature>> {
+        let proofs_signatures_are_ok = self
+            .verify_proofs_signatures(inputs, KeysetValidity::Ignore)
+            .await?;
+        if !proofs_signatures_are_ok {
+            return Err(Error::UnknownProofs);
+        }
+        let fee = self.inputs_fee(inputs).await?;
+        let surplus = total_input - melt_amount;
+        if surplus < fee {
+            return Err(Error::InsufficientFeeCovered(fee, surplus));
+        }
+        if change_outputs.is_empty() {
+            return Ok(Vec::new());
+        }
+        let change = surplus - fee;
+        if total_change != change {
+            return Err(Error::UnmatchingChange(change, total_change));
+        }
+
+        let kid = self.replacing_keyset(inputs).await?;
+        self.sign_outputs(&kid, change_outputs).await
+    }
+
+    /// denominations a wallet should blind to swap `inputs` into as few proofs as
+    /// possible on `output_keyset`, their total is the inputs' one minus fees.
+    /// blinding is left to the wallet: the mint must never see the secrets
+    pub async fn consolidate(
+        &self,
+        inputs: &[cdk00::Proof],
+        output_keyset: &KeysetID,
+    ) -> Result<Vec<Amount>> {
+        let info = self
+            .keys
+            .info(output_keyset)
+            .await
+            .map_err(keys_repository_error)?
+            .ok_or(Error::UnknownKeyset(*output_keyset))?;
+        verify_keyset_ids(inputs)?;
+        verify_secrets(inputs)?;
+        let total = total_amount(inputs.iter().map(|proof| proof.amount))?;
+        let fee = self.inputs_fee(inputs).await?;
+        if total < fee {
+            return Err(Error::InsufficientFeeCovered(fee, total));
+        }
+        split_amount(total - fee, info.max_order)
+    }
+
+    /// NUT-09: signs again the outputs issued in the past, signing being deterministic.
+    /// outputs never issued are left out of the response
+    pub async fn restore(
+        &self,
+        outputs: &[cdk00::BlindedMessage],
+    ) -> Result<Vec<(cdk00::BlindedMessage, cdk00::BlindSignature)>> {
+        // each output is a lookup and possibly a signature, as in a swap
+        self.verify_counts(0, outputs.len())?;
+        let blinded_secrets: Vec<cdk01::PublicKey> =
+            outputs.iter().map(|output| output.blinded_secret).collect();
+        let issued: Vec<cdk00::BlindedMessage> = self
+            .proofs
+            .issued(&blinded_secrets)
+            .await
+            .map_err(proof_repository_error)?
+            .into_iter()
+            .flatten()
+            .collect();
+        let mut ids: Vec<KeysetID> = Vec::new();
+        for output in &issued {
+            let id = KeysetID::from(output.keyset_id);
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        let keysets = self
+            .keys
+            .load_many(&ids)
+            .await
+            .map_err(keys_repository_error)?;
+        // recorded keyset and amount prevail over the requested ones
+        let mut restored = Vec::with_capacity(issued.len());
+        for output in issued {
+            let kid = KeysetID::from(output.keyset_id);
+            let keys = keysets.get(&kid).ok_or(Error::UnknownKeyset(kid))?;
+            let signature = sign_output(keys, &output)?;
+            restored.push((output, signature));
+        }
+        Ok(restored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::MockHealthCheck;
+    use crate::keys::test_utils as keys_test;
+    use crate::utils::tests as utils;
+    use mockall::predicate::*;
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+
+    fn keyset_info(keyset: &cdk02::MintKeySet, input_fee_ppk: u64) -> MintKeySetInfo {
+        MintKeySetInfo {
+            id: keyset.id,
+            unit: keyset.unit.clone(),
+            active: true,
+            valid_from: 0,
+            valid_to: None,
+            derivation_path: Default::default(),
+            derivation_path_index: None,
+            max_order: keyset.keys.len() as u8,
+            input_fee_ppk,
+        }
+    }
+
+    #[test]
+    fn test_split_amount() {
+        assert!(split_amount(Amount::ZERO, 20).unwrap().is_empty());
+        assert_eq!(
+            split_amount(Amount::from(1), 20).unwrap(),
+            vec![Amount::from(1)]
+        );
+        assert_eq!(
+            split_amount(Amount::from(23), 20).unwrap(),
+            vec![
+                Amount::from(16),
+                Amount::from(4),
+                Amount::from(2),
+                Amount::from(1)
+            ]
+        );
+        // largest denomination for max order 4 is 8
+        assert_eq!(
+            split_amount(Amount::from(15), 4).unwrap(),
+            vec![
+                Amount::from(8),
+                Amount::from(4),
+                Amount::from(2),
+                Amount::from(1)
+            ]
+        );
+        let r = split_amount(Amount::from(17), 4);
+        assert!(matches!(r.unwrap_err(), Error::AmountExceedsMaxOrder(_, 4)));
+        assert!(split_amount(Amount::from(u64::MAX), 64).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_swap_spent_proofs() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
+        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+
+        let mut keyrepo = MockKeysRepository::new();
+        let info = keyset_info(&keys, 0);
+        keyrepo
+            .expect_info()
+            .returning(move |_| Ok(Some(info.clone())));
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo
+            .expect_issued()
+            .returning(|secrets| Ok(vec![None; secrets.len()]));
+        proofrepo
+            .expect_reserve()
+            .returning(|_| Err(Error::ProofsAlreadySpent.into()));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(r.is_err());
+        let e = r.unwrap_err();
+        assert!(matches!(e, Error::ProofsAlreadySpent));
+    }
+
+    #[tokio::test]
+    async fn test_swap_rejects_malformed_input_amounts_early() {
+        let keys = keys_test::generate_keyset();
+        let kid = KeysetID::from(keys.id);
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(2)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let mut keyrepo = MockKeysRepository::new();
+        let info = keyset_info(&keys, 0);
+        keyrepo
+            .expect_info()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(info.clone())));
+        // nothing is reserved
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo
+            .expect_issued()
+            .returning(|secrets| Ok(vec![None; secrets.len()]));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let mut inputs = utils::generate_proofs(&keys, &[Amount::from(2)]);
+        inputs[0].amount = Amount::from(3);
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(matches!(
+            r,
+            Err(Error::UnknownAmountForKeyset(id, amount)) if id == kid && amount == Amount::from(3)
+        ));
+        // a power of two above the largest denomination of the keyset
+        let largest = *keys.keys.keys().last().unwrap();
+        inputs[0].amount = largest + largest;
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(matches!(
+            r,
+            Err(Error::UnknownAmountForKeyset(id, amount)) if id == kid && amount == largest + largest
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_swap_unknown_keysetid() {
+        let kid = keys_test::generate_random_keysetid();
+        let id = kid.into();
+
+        let keys = keys_test::generate_keyset();
+        let mut inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
+        inputs.get_mut(0).unwrap().keyset_id = id;
+        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        keyrepo.expect_info().returning(|_| Ok(None));
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo
+            .expect_issued()
+            .returning(|secrets| Ok(vec![None; secrets.len()]));
+        proofrepo.expect_reserve().returning(|_| Ok(()));
+        keyrepo.expect_load_many().returning(|_| Ok(HashMap::new()));
+        proofrepo
+            .expect_release()
+            .with(eq(inputs.clone()))
+            .times(1)
+            .returning(|_| Ok(()));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(r.is_err());
+        let e = r.unwrap_err();
+        assert!(matches!(e, Error::UnknownKeyset(_)));
+    }
+
+    #[tokio::test]
+    async fn test_swap_keys_backend_error() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
+        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+
+        let mut keyrepo = MockKeysRepository::new();
+        let info = keyset_info(&keys, 0);
+        keyrepo
+            .expect_info()
+            .returning(move |_| Ok(Some(info.clone())));
+        keyrepo
+            .expect_load_many()
+            .returning(|_| Err(anyhow::anyhow!("connection lost").into()));
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo
+            .expect_issued()
+            .returning(|secrets| Ok(vec![None; secrets.len()]));
+        proofrepo.expect_reserve().returning(|_| Ok(()));
+        proofrepo
+            .expect_release()
+            .with(eq(inputs.clone()))
+            .times(1)
+            .returning(|_| Ok(()));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        // unlike an unknown keyset, the failure is reported as the repository's
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(matches!(
+            r.unwrap_err(),
+            Error::KeysetRepository(KeysRepoError::Backend(_))
+        ));
+    }
+
+    #[test]
+    fn test_keys_repository_error() {
+        let kid = keys_test::generate_random_keysetid();
+        let e = keys_repository_error(KeysRepoError::NotFound(kid));
+        assert!(matches!(e, Error::UnknownKeyset(id) if id == kid));
+        let e = keys_repository_error(KeysRepoError::Disabled(kid));
+        assert!(matches!(e, Error::DisabledKeyset(id) if id == kid));
+        let e = keys_repository_error(KeysRepoError::Corrupt(kid, String::from("no valid_to")));
+        assert!(matches!(
+            e,
+            Error::KeysetRepository(KeysRepoError::Corrupt(id, _)) if id == kid
+        ));
+        let e = keys_repository_error(anyhow::anyhow!("connection lost").into());
+        assert!(matches!(
+            e,
+            Error::KeysetRepository(KeysRepoError::Backend(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_swap_wrong_signatures() {
+        let keys = keys_test::generate_keyset();
+        let mut inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
+        inputs.get_mut(0).unwrap().c = utils::publics()[0];
+        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let info = keyset_info(&keys, 0);
+        keyrepo
+            .expect_info()
+            .returning(move |_| Ok(Some(info.clone())));
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo
+            .expect_issued()
+            .returning(|secrets| Ok(vec![None; secrets.len()]));
+        proofrepo.expect_reserve().returning(|_| Ok(()));
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        proofrepo
+            .expect_release()
+            .with(eq(inputs.clone()))
+            .times(1)
+            .returning(|_| Ok(()));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(r.is_err());
+        let e = r.unwrap_err();
+        assert!(matches!(e, Error::UnknownProofs));
+    }
+
+    #[tokio::test]
+    async fn test_swap_unmatched_amounts() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
+        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(16)].as_slice())
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo
+            .expect_issued()
+            .returning(|secrets| Ok(vec![None; secrets.len()]));
+        proofrepo.expect_reserve().returning(|_| Ok(()));
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(r.is_err());
+        let e = r.unwrap_err();
+        assert!(matches!(e, Error::UnmatchingAmount(_, _)));
+    }
+
+    #[tokio::test]
+    async fn test_swap_split_tokens_ok() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
+        let outputs: Vec<_> =
+            utils::generate_blinds(&keys, vec![Amount::from(4), Amount::from(4)].as_slice())
+                .into_iter()
+                .map(|a| a.0)
+                .collect();
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo.expect_record_issued().returning(|_| Ok(()));
+        proofrepo
+            .expect_issued()
+            .returning(|secrets| Ok(vec![None; secrets.len()]));
+        proofrepo.expect_reserve().returning(|_| Ok(()));
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        let ex_keys = keys.clone();
+        keyrepo
+            .expect_keyset()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(ex_keys.clone())));
+        let info = keyset_info(&keys, 0);
+        keyrepo
+            .expect_info()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(info.clone())));
+        keyrepo
+            .expect_replacing_id()
+            .returning(move |_| Ok(Some(kid)));
+        proofrepo
+            .expect_spend()
+            .with(eq(inputs.clone()))
+            .returning(|_| Ok(()));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(r.is_ok());
+        let bs = r.unwrap();
+        for (blind, signature) in outputs.iter().zip(bs.iter()) {
+            assert!(utils::verify_dleq(&keys, blind, signature));
+        }
+        assert!(utils::verify_signatures_data(
+            &keys,
+            outputs.into_iter().zip(bs.into_iter())
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_swap_unknown_output_amount() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(
+            &keys,
+            vec![Amount::from(8), Amount::from(2), Amount::from(1)].as_slice(),
+        );
+        let mut outputs: Vec<_> =
+            utils::generate_blinds(&keys, vec![Amount::from(8), Amount::from(2)].as_slice())
+                .into_iter()
+                .map(|a| a.0)
+                .collect();
+        // valid first output, second one of no denomination
+        outputs[1].amount = Amount::from(3);
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        let ex_keys = keys.clone();
+        keyrepo
+            .expect_keyset()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(ex_keys.clone())));
+        let info = keyset_info(&keys, 0);
+        keyrepo
+            .expect_info()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(info.clone())));
+        keyrepo
+            .expect_replacing_id()
+            .returning(move |_| Ok(Some(kid)));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: crate::persistence::inmemory::ProofMap::default(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(matches!(
+            r.unwrap_err(),
+            Error::UnknownAmountForKeyset(id, amount) if id == kid && amount == Amount::from(3)
+        ));
+        // nothing spent nor left pending
+        let states = swaps.proofs.get_state(&inputs).await.unwrap();
+        assert!(states.iter().all(|state| *state == cdk07::State::Unspent));
+    }
+
+    fn restore_service(
+        keys: &cdk02::MintKeySet,
+    ) -> Service<MockKeysRepository, crate::persistence::inmemory::ProofMap> {
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        let ex_keys = keys.clone();
+        keyrepo
+            .expect_keyset()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(ex_keys.clone())));
+        let info = keyset_info(keys, 0);
+        keyrepo
+            .expect_info()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(info.clone())));
+        keyrepo
+            .expect_replacing_id()
+            .returning(move |_| Ok(Some(kid)));
+        Service {
+            keys: keyrepo,
+            proofs: crate::persistence::inmemory::ProofMap::default(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_swap_denomination_stats() {
+        let keys = keys_test::generate_keyset();
+        let swaps = restore_service(&keys);
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(16)]);
+        let amounts = [
+            Amount::from(8),
+            Amount::from(4),
+            Amount::from(2),
+            Amount::from(2),
+        ];
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &amounts)
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        swaps.swap(&inputs, &outputs, None).await.unwrap();
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(8)]);
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(4), Amount::from(4)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        swaps.swap(&inputs, &outputs, None).await.unwrap();
+
+        let expected = BTreeMap::from([
+            (Amount::from(2), 2),
+            (Amount::from(4), 3),
+            (Amount::from(8), 1),
+        ]);
+        assert_eq!(swaps.stats.histogram(), expected);
+        // clones share the counters
+        let stats = swaps.stats.clone();
+        stats.reset();
+        assert!(swaps.stats.histogram().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_swap_rejects_reused_blinded_message() {
+        let keys = keys_test::generate_keyset();
+        let swaps = restore_service(&keys);
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(4), Amount::from(4)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(8)]);
+        swaps.swap(&inputs, &outputs, None).await.unwrap();
+
+        // fresh inputs, one output already signed
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(8)]);
+        let mut reused = utils::generate_blinds(&keys, &[Amount::from(4)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect::<Vec<_>>();
+        reused.push(outputs[1].clone());
+        let r = swaps.swap(&inputs, &reused, None).await;
+        assert!(matches!(r.unwrap_err(), Error::BlindedMessageReused));
+        let states = swaps.proofs.get_state(&inputs).await.unwrap();
+        assert!(states.iter().all(|state| *state == cdk07::State::Unspent));
+        // the restore path still hands the signature back
+        let restored = swaps.restore(&outputs[1..]).await.unwrap();
+        assert_eq!(restored.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_swap_pinned_output_keyset() {
+        let keys = keys_test::generate_keyset();
+        let kid = KeysetID::from(keys.id);
+        let swaps = restore_service(&keys);
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(8)]);
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(4), Amount::from(4)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let signatures = swaps.swap(&inputs, &outputs, Some(kid)).await.unwrap();
+        assert!(signatures
+            .iter()
+            .all(|s| KeysetID::from(s.keyset_id) == kid));
+    }
+
+    #[tokio::test]
+    async fn test_swap_pinned_output_keyset_mismatch() {
+        let keys = keys_test::generate_keyset();
+        let kid = KeysetID::from(keys.id);
+        let swaps = restore_service(&keys);
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(8)]);
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(4), Amount::from(4)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let pinned = keys_test::generate_random_keysetid();
+        let r = swaps.swap(&inputs, &outputs, Some(pinned)).await;
+        assert!(matches!(
+            r,
+            Err(Error::UnexpectedOutputKeyset(p, k)) if p == pinned && k == kid
+        ));
+        // the inputs are released, not burnt
+        let states = swaps.proofs.get_state(&inputs).await.unwrap();
+        assert!(states.iter().all(|state| *state == cdk07::State::Unspent));
+    }
+
+    #[tokio::test]
+    async fn test_restore_issued_outputs() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(8)]);
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(4), Amount::from(4)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let unissued = utils::generate_blinds(&keys, &[Amount::from(2)])[0]
+            .0
+            .clone();
+        let swaps = restore_service(&keys);
+        let signatures = swaps.swap(&inputs, &outputs, None).await.unwrap();
+
+        let request = vec![outputs[1].clone(), unissued, outputs[0].clone()];
+        let restored = swaps.restore(&request).await.unwrap();
+        // DLEQ proofs are randomized, the signatures themselves are not
+        let restored: Vec<_> = restored
+            .into_iter()
+            .map(|(output, signature)| (output, signature.c))
+            .collect();
+        let expected = vec![
+            (outputs[1].clone(), signatures[1].c),
+            (outputs[0].clone(), signatures[0].c),
+        ];
+        assert_eq!(restored, expected);
+    }
+
+    #[tokio::test]
+    async fn test_restore_uses_issued_amount() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(2)]);
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(2)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let swaps = restore_service(&keys);
+        let signatures = swaps.swap(&inputs, &outputs, None).await.unwrap();
+
+        // asking for a larger denomination does not yield a larger signature
+        let mut forged = outputs[0].clone();
+        forged.amount = Amount::from(8);
+        let restored = swaps.restore(&[forged]).await.unwrap();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].0, outputs[0]);
+        assert_eq!(restored[0].1.amount, Amount::from(2));
+        assert_eq!(restored[0].1.c, signatures[0].c);
+    }
+
+    #[tokio::test]
+    async fn test_restore_too_many_outputs() {
+        let keys = keys_test::generate_keyset();
+        let mut swaps = restore_service(&keys);
+        swaps.limits = SwapLimits {
+            max_inputs: 10,
+            max_outputs: 1,
+        };
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(1), Amount::from(2)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+
+        let r = swaps.restore(&outputs).await;
+        assert!(matches!(r, Err(Error::TooManyOutputs(2, 1))));
+        assert!(swaps.restore(&outputs[..1]).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_swap_amount_overflow() {
+        let keys = keys_test::generate_keyset();
+        let mut inputs = utils::generate_proofs(&keys, &[Amount::from(1), Amount::from(2)]);
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(1), Amount::from(2)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo.expect_reserve().never();
+        let swaps = Service {
+            keys: MockKeysRepository::new(),
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        // u64::MAX + 2 would wrap to 1
+        inputs[0].amount = Amount::from(u64::MAX);
+        let mut overflowing_outputs = outputs.clone();
+        overflowing_outputs[0].amount = Amount::from(u64::MAX);
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(matches!(r, Err(Error::AmountOverflow)));
+        inputs[0].amount = Amount::from(1);
+        let r = swaps.swap(&inputs, &overflowing_outputs, None).await;
+        assert!(matches!(r, Err(Error::AmountOverflow)));
+    }
+
+    #[tokio::test]
+    async fn test_swap_merge_tokens_ok() {
+        let keys = keys_test::generate_keyset();
+        let inputs =
+            utils::generate_proofs(&keys, vec![Amount::from(4), Amount::from(4)].as_slice());
+        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo.expect_record_issued().returning(|_| Ok(()));
+        proofrepo
+            .expect_issued()
+            .returning(|secrets| Ok(vec![None; secrets.len()]));
+        proofrepo.expect_reserve().returning(|_| Ok(()));
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        let ex_keys = keys.clone();
+        keyrepo
+            .expect_keyset()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(ex_keys.clone())));
+        let info = keyset_info(&keys, 0);
+        keyrepo
+            .expect_info()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(info.clone())));
+        keyrepo
+            .expect_replacing_id()
+            .returning(move |_| Ok(Some(kid)));
+        proofrepo
+            .expect_spend()
+            .with(eq(inputs.clone()))
+            .returning(|_| Ok(()));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(r.is_ok());
+        let bs = r.unwrap();
+        for (blind, signature) in outputs.iter().zip(bs.iter()) {
+            assert!(utils::verify_dleq(&keys, blind, signature));
+        }
+        assert!(utils::verify_signatures_data(
+            &keys,
+            outputs.into_iter().zip(bs.into_iter())
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_melt_exact_amount() {
+        let keys = keys_test::generate_keyset();
+        let inputs =
+            utils::generate_proofs(&keys, vec![Amount::from(8), Amount::from(4)].as_slice());
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let info = keyset_info(&keys, 0);
+        keyrepo
+            .expect_info()
+            .returning(move |_| Ok(Some(info.clone())));
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo
+            .expect_issued()
+            .returning(|secrets| Ok(vec![None; secrets.len()]));
+        proofrepo.expect_reserve().returning(|_| Ok(()));
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        proofrepo
+            .expect_spend()
+            .with(eq(inputs.clone()))
+            .times(1)
+            .returning(|_| Ok(()));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let fee = swaps.melt(&inputs, Amount::from(12)).await.unwrap();
+        assert_eq!(fee, Amount::ZERO);
+    }
+
+    // mocks for a melt_with_change over inputs of `keys`, either spending or releasing them
+    fn melt_with_change_service(
+        keys: &cdk02::MintKeySet,
+        input_fee_ppk: u64,
+        succeeds: bool,
+    ) -> Service<MockKeysRepository, MockProofRepository> {
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo
+            .expect_issued()
+            .returning(|secrets| Ok(vec![None; secrets.len()]));
+        proofrepo.expect_reserve().returning(|_| Ok(()));
+        if succeeds {
+            proofrepo.expect_spend().times(1).returning(|_| Ok(()));
+            proofrepo.expect_record_issued().returning(|_| Ok(()));
+        } else {
+            proofrepo.expect_release().times(1).returning(|_| Ok(()));
+        }
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        let info = keyset_info(keys, input_fee_ppk);
+        keyrepo
+            .expect_info()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(info.clone())));
+        let ex_keys = keys.clone();
+        keyrepo
+            .expect_keyset()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(ex_keys.clone())));
+        keyrepo
+            .expect_replacing_id()
+            .returning(move |_| Ok(Some(kid)));
+        Service {
+            keys: keyrepo,
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_melt_with_change_ok() {
+        let keys = keys_test::generate_keyset();
+        let inputs =
+            utils::generate_proofs(&keys, vec![Amount::from(16), Amount::from(4)].as_slice());
+        // 2 inputs at 500 ppk: fee is 1, change is 20 - 12 - 1 = 7
+        let change_outputs: Vec<_> = utils::generate_blinds(
+            &keys,
+            vec![Amount::from(4), Amount::from(2), Amount::from(1)].as_slice(),
+        )
+        .into_iter()
+        .map(|a| a.0)
+        .collect();
+        let swaps = melt_with_change_service(&keys, 500, true);
+
+        let signatures = swaps
+            .melt_with_change(&inputs, Amount::from(12), &change_outputs)
+            .await
+            .unwrap();
+        assert_eq!(signatures.len(), 3);
+        let amounts: Vec<_> = signatures.iter().map(|s| s.amount).collect();
+        assert_eq!(
+            amounts,
+            vec![Amount::from(4), Amount::from(2), Amount::from(1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_melt_with_change_rejects_reused_blinded_message() {
+        let keys = keys_test::generate_keyset();
+        let swaps = restore_service(&keys);
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(4), Amount::from(4)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(8)]);
+        swaps.swap(&inputs, &outputs, None).await.unwrap();
+
+        // fresh inputs, the change output already signed
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(16)]);
+        let r = swaps
+            .melt_with_change(&inputs, Amount::from(12), &outputs[1..])
+            .await;
+        assert!(matches!(r.unwrap_err(), Error::BlindedMessageReused));
+        let states = swaps.proofs.get_state(&inputs).await.unwrap();
+        assert!(states.iter().all(|state| *state == cdk07::State::Unspent));
+    }
+
+    #[tokio::test]
+    async fn test_melt_with_change_unmatching_change() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(16)].as_slice());
+        let change_outputs: Vec<_> =
+            utils::generate_blinds(&keys, vec![Amount::from(2)].as_slice())
+                .into_iter()
+                .map(|a| a.0)
+                .collect();
+        let swaps = melt_with_change_service(&keys, 0, false);
+
+        let r = swaps
+            .melt_with_change(&inputs, Amount::from(12), &change_outputs)
+            .await;
+        assert!(matches!(
+            r.unwrap_err(),
+            Error::UnmatchingChange(change, outputs)
+                if change == Amount::from(4) && outputs == Amount::from(2)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_melt_with_change_outputs_without_change() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(16)].as_slice());
+        let change_outputs: Vec<_> =
+            utils::generate_blinds(&keys, vec![Amount::from(1)].as_slice())
+                .into_iter()
+                .map(|a| a.0)
+                .collect();
+        let swaps = melt_with_change_service(&keys, 0, false);
+
+        let r = swaps
+            .melt_with_change(&inputs, Amount::from(16), &change_outputs)
+            .await;
+        assert!(matches!(
+            r.unwrap_err(),
+            Error::UnmatchingChange(change, _) if change == Amount::ZERO
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_melt_with_change_no_outputs_keeps_surplus() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(16)].as_slice());
+        let swaps = melt_with_change_service(&keys, 0, true);
+
+        let signatures = swaps
+            .melt_with_change(&inputs, Amount::from(12), &[])
+            .await
+            .unwrap();
+        assert!(signatures.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_melt_overfunded() {
+        let keys = keys_test::generate_keyset();
+        let inputs =
+            utils::generate_proofs(&keys, vec![Amount::from(8), Amount::from(4)].as_slice());
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let info = keyset_info(&keys, 0);
+        keyrepo
+            .expect_info()
+            .returning(move |_| Ok(Some(info.clone())));
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo
+            .expect_issued()
+            .returning(|secrets| Ok(vec![None; secrets.len()]));
+        proofrepo.expect_reserve().returning(|_| Ok(()));
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        proofrepo
+            .expect_spend()
+            .with(eq(inputs.clone()))
+            .times(1)
+            .returning(|_| Ok(()));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let fee = swaps.melt(&inputs, Amount::from(10)).await.unwrap();
+        assert_eq!(fee, Amount::from(2));
+    }
+
+    #[tokio::test]
+    async fn test_melt_insufficient_inputs() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
+        let swaps = Service {
+            keys: MockKeysRepository::new(),
+            proofs: MockProofRepository::new(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let r = swaps.melt(&inputs, Amount::from(16)).await;
+        assert!(matches!(r.unwrap_err(), Error::MeltError(_, _)));
+    }
+
+    #[tokio::test]
+    async fn test_melt_zero_amount() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
+        let swaps = Service {
+            keys: MockKeysRepository::new(),
+            proofs: MockProofRepository::new(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let r = swaps.melt(&inputs, Amount::ZERO).await;
+        assert!(matches!(r.unwrap_err(), Error::ZeroAmount));
+    }
+
+    #[tokio::test]
+    async fn test_swap_traces() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
+        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo.expect_record_issued().returning(|_| Ok(()));
+        proofrepo
+            .expect_issued()
+            .returning(|secrets| Ok(vec![None; secrets.len()]));
+        proofrepo.expect_reserve().returning(|_| Ok(()));
+        proofrepo.expect_spend().returning(|_| Ok(()));
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        let info = keyset_info(&keys, 0);
+        keyrepo
+            .expect_info()
+            .returning(move |_| Ok(Some(info.clone())));
+        let ex_keys = keys.clone();
+        keyrepo
+            .expect_keyset()
+            .returning(move |_| Ok(Some(ex_keys.clone())));
+        keyrepo
+            .expect_replacing_id()
+            .returning(move |_| Ok(Some(kid)));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let capture = utils::TraceCapture::default();
+        let _guard = tracing::subscriber::set_default(capture.clone());
+        swaps.swap(&inputs, &outputs, None).await.unwrap();
+        assert_eq!(
+            *capture.spans.lock().unwrap(),
+            vec!["swap", "verify_proofs_signatures"]
+        );
+        {
+            let events = capture.events.lock().unwrap();
+            assert!(events
+                .iter()
+                .all(|(level, _)| *level > tracing::Level::INFO));
+            assert!(events
+                .iter()
+                .any(|(_, fields)| fields.contains(&kid.to_string())));
+            // no secret material ends up in the traces
+            let blinded = outputs[0].blinded_secret.to_hex();
+            assert!(events.iter().all(|(_, fields)| !fields.contains(&blinded)));
+        }
+
+        // rejected before any costly verification
+        capture.spans.lock().unwrap().clear();
+        let r = swaps
+            .swap(&inputs, &[outputs[0].clone(), outputs[0].clone()], None)
+            .await;
+        assert!(r.is_err());
+        assert_eq!(*capture.spans.lock().unwrap(), vec!["swap"]);
+        let events = capture.events.lock().unwrap();
+        let (level, fields) = events.last().unwrap();
+        assert_eq!(*level, tracing::Level::WARN);
+        assert!(fields.contains(&Error::DuplicateOutputs.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_swap_duplicate_outputs() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(16)].as_slice());
+        let output = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
+            .into_iter()
+            .map(|a| a.0)
+            .next()
+            .unwrap();
+        let outputs = vec![output.clone(), output];
+        // no expectations: rejected before touching any repository
+        let swaps = Service {
+            keys: MockKeysRepository::new(),
+            proofs: MockProofRepository::new(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(matches!(r.unwrap_err(), Error::DuplicateOutputs));
+    }
+
+    #[tokio::test]
+    async fn test_swap_duplicate_inputs() {
+        let keys = keys_test::generate_keyset();
+        let input = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice())
+            .pop()
+            .unwrap();
+        let inputs = vec![input.clone(), input];
+        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(16)].as_slice())
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let swaps = Service {
+            keys: MockKeysRepository::new(),
+            proofs: MockProofRepository::new(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(matches!(r.unwrap_err(), Error::DuplicateInputs));
+    }
+
+    #[tokio::test]
+    async fn test_swap_malformed_secret() {
+        let keys = keys_test::generate_keyset();
+        let mut inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
+        inputs[0].secret = cdk::secret::Secret::new("");
+        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        // no expectations: rejected before touching any repository
+        let swaps = Service {
+            keys: MockKeysRepository::new(),
+            proofs: MockProofRepository::new(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(matches!(r.unwrap_err(), Error::MalformedSecret("empty")));
+        let r = swaps.swap_with_fee_return(&inputs, &outputs, &[]).await;
+        assert!(matches!(r.unwrap_err(), Error::MalformedSecret("empty")));
+        let r = swaps.check_state(&inputs).await;
+        assert!(matches!(r.unwrap_err(), Error::MalformedSecret("empty")));
+        let r = swaps.melt(&inputs, Amount::from(8)).await;
+        assert!(matches!(r.unwrap_err(), Error::MalformedSecret("empty")));
+        let r = swaps.melt_with_change(&inputs, Amount::from(4), &[]).await;
+        assert!(matches!(r.unwrap_err(), Error::MalformedSecret("empty")));
+        let r = swaps.swap_auto(&inputs, &[Amount::from(8)]).await;
+        assert!(matches!(r.unwrap_err(), Error::MalformedSecret("empty")));
+    }
+
+    #[tokio::test]
+    async fn test_swap_no_outputs() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo.expect_reserve().never();
+        proofrepo.expect_spend().never();
+        let swaps = Service {
+            keys: MockKeysRepository::new(),
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let r = swaps.swap(&inputs, &[], None).await;
+        assert!(matches!(r.unwrap_err(), Error::NoOutputs));
+        let r = swaps.swap_with_fee_return(&inputs, &[], &[]).await;
+        assert!(matches!(r.unwrap_err(), Error::NoOutputs));
+    }
+
+    #[tokio::test]
+    async fn test_swap_with_fee_return_duplicate_fee_output() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(16)].as_slice());
+        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        // the same blinded message as a regular and as a fee output
+        let fee_outputs = outputs.clone();
+        let swaps = Service {
+            keys: MockKeysRepository::new(),
+            proofs: MockProofRepository::new(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let r = swaps
+            .swap_with_fee_return(&inputs, &outputs, &fee_outputs)
+            .await;
+        assert!(matches!(r.unwrap_err(), Error::DuplicateOutputs));
+    }
+
+    #[tokio::test]
+    async fn test_melt_duplicate_inputs() {
+        let keys = keys_test::generate_keyset();
+        let input = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice())
+            .pop()
+            .unwrap();
+        let inputs = vec![input.clone(), input];
+        let swaps = Service {
+            keys: MockKeysRepository::new(),
+            proofs: MockProofRepository::new(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let r = swaps.melt(&inputs, Amount::from(16)).await;
+        assert!(matches!(r.unwrap_err(), Error::DuplicateInputs));
+    }
+
+    #[tokio::test]
+    async fn test_swap_with_fee_return_ok() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(16)].as_slice());
+        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let fee_outputs: Vec<_> =
+            utils::generate_blinds(&keys, vec![Amount::from(4), Amount::from(2)].as_slice())
+                .into_iter()
+                .map(|a| a.0)
+                .collect();
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo.expect_record_issued().returning(|_| Ok(()));
+        proofrepo
+            .expect_issued()
+            .returning(|secrets| Ok(vec![None; secrets.len()]));
+        proofrepo.expect_reserve().returning(|_| Ok(()));
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        let ex_keys = keys.clone();
+        keyrepo
+            .expect_keyset()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(ex_keys.clone())));
+        let info = keyset_info(&keys, 0);
+        keyrepo
+            .expect_info()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(info.clone())));
+        keyrepo
+            .expect_replacing_id()
+            .returning(move |_| Ok(Some(kid)));
+        proofrepo
+            .expect_spend()
+            .with(eq(inputs.clone()))
+            .returning(|_| Ok(()));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let bs = swaps
+            .swap_with_fee_return(&inputs, &outputs, &fee_outputs)
+            .await
+            .unwrap();
+        assert_eq!(bs.len(), 3);
+        for (blind, signature) in outputs.iter().chain(fee_outputs.iter()).zip(bs.iter()) {
+            assert!(utils::verify_dleq(&keys, blind, signature));
+        }
+        assert!(utils::verify_signatures_data(
+            &keys,
+            outputs
+                .into_iter()
+                .chain(fee_outputs.into_iter())
+                .zip(bs.into_iter())
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_swap_with_fee_return_excessive() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(16)].as_slice());
+        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let fee_outputs: Vec<_> =
+            utils::generate_blinds(&keys, vec![Amount::from(8), Amount::from(2)].as_slice())
+                .into_iter()
+                .map(|a| a.0)
+                .collect();
+        let swaps = Service {
+            keys: MockKeysRepository::new(),
+            proofs: MockProofRepository::new(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let r = swaps
+            .swap_with_fee_return(&inputs, &outputs, &fee_outputs)
+            .await;
+        assert!(matches!(r.unwrap_err(), Error::ExcessiveFeeReturn(_, _)));
+    }
+
+    #[tokio::test]
+    async fn test_swap_with_fee_return_keeps_surplus() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(16)].as_slice());
+        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo.expect_record_issued().returning(|_| Ok(()));
+        proofrepo
+            .expect_issued()
+            .returning(|secrets| Ok(vec![None; secrets.len()]));
+        proofrepo.expect_reserve().returning(|_| Ok(()));
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        let ex_keys = keys.clone();
+        keyrepo
+            .expect_keyset()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(ex_keys.clone())));
+        let info = keyset_info(&keys, 0);
+        keyrepo
+            .expect_info()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(info.clone())));
+        keyrepo
+            .expect_replacing_id()
+            .returning(move |_| Ok(Some(kid)));
+        proofrepo
+            .expect_spend()
+            .with(eq(inputs.clone()))
+            .returning(|_| Ok(()));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let bs = swaps
+            .swap_with_fee_return(&inputs, &outputs, &[])
+            .await
+            .unwrap();
+        assert_eq!(bs.len(), 1);
+        assert_eq!(bs[0].amount, Amount::from(8));
+    }
+
+    #[tokio::test]
+    async fn test_swap_fee_ppk_many_inputs() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(
+            &keys,
+            vec![Amount::from(8), Amount::from(4), Amount::from(4)].as_slice(),
+        );
+        let outputs: Vec<_> = utils::generate_blinds(
+            &keys,
+            vec![
+                Amount::from(8),
+                Amount::from(4),
+                Amount::from(2),
+                Amount::from(1),
+            ]
+            .as_slice(),
+        )
+        .into_iter()
+        .map(|a| a.0)
+        .collect();
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo.expect_record_issued().returning(|_| Ok(()));
+        proofrepo
+            .expect_issued()
+            .returning(|secrets| Ok(vec![None; secrets.len()]));
+        proofrepo.expect_reserve().returning(|_| Ok(()));
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        let ex_keys = keys.clone();
+        keyrepo
+            .expect_keyset()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(ex_keys.clone())));
+        let info = keyset_info(&keys, 1);
+        keyrepo
+            .expect_info()
+            .with(eq(kid))
+            .times(2)
+            .returning(move |_| Ok(Some(info.clone())));
+        keyrepo
+            .expect_replacing_id()
+            .returning(move |_| Ok(Some(kid)));
+        proofrepo
+            .expect_spend()
+            .with(eq(inputs.clone()))
+            .returning(|_| Ok(()));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let bs = swaps.swap(&inputs, &outputs, None).await.unwrap();
+        assert!(utils::verify_signatures_data(
+            &keys,
+            outputs.into_iter().zip(bs.into_iter())
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_swap_fee_ppk_not_covered() {
+        let keys = keys_test::generate_keyset();
+        let inputs =
+            utils::generate_proofs(&keys, vec![Amount::from(4), Amount::from(4)].as_slice());
+        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo
+            .expect_issued()
+            .returning(|secrets| Ok(vec![None; secrets.len()]));
+        proofrepo.expect_reserve().returning(|_| Ok(()));
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        let ex_keys = keys.clone();
+        keyrepo
+            .expect_keyset()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(ex_keys.clone())));
+        let info = keyset_info(&keys, 1);
+        keyrepo
+            .expect_info()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(info.clone())));
+        proofrepo
+            .expect_release()
+            .with(eq(inputs.clone()))
+            .times(1)
+            .returning(|_| Ok(()));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(matches!(
+            r.unwrap_err(),
+            Error::InsufficientFeeCovered(fee, paid) if fee == Amount::from(1) && paid == Amount::ZERO
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_consolidate_ones() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(1); 4]);
+        let kid = KeysetID::from(keys.id);
+        let mut info = keyset_info(&keys, 0);
+        info.max_order = 10;
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo
+            .expect_info()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(info.clone())));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: MockProofRepository::new(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let amounts = swaps.consolidate(&inputs, &kid).await.unwrap();
+        assert_eq!(amounts, vec![Amount::from(4)]);
+    }
+
+    #[tokio::test]
+    async fn test_consolidate_preserves_total_minus_fee() {
+        let keys = keys_test::generate_keyset();
+        let input_amounts = [1, 1, 2, 2, 4, 8, 8, 1].map(Amount::from);
+        let inputs = utils::generate_proofs(&keys, &input_amounts);
+        let kid = KeysetID::from(keys.id);
+        let mut info = keyset_info(&keys, 300);
+        info.max_order = 10;
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo
+            .expect_info()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(info.clone())));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: MockProofRepository::new(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let amounts = swaps.consolidate(&inputs, &kid).await.unwrap();
+        // 8 inputs at 300 ppk: a fee of 3
+        assert_eq!(
+            total_amount(amounts.iter().copied()).unwrap(),
+            Amount::from(27 - 3)
+        );
+        assert_eq!(amounts, vec![Amount::from(16), Amount::from(8)]);
+    }
+
+    #[tokio::test]
+    async fn test_swap_fee_mixed_keysets() {
+        let keys = keys_test::generate_keyset();
+        let path = bitcoin::bip32::DerivationPath::from_str("m/0'/1").unwrap();
+        let other_keys = cdk02::MintKeySet::generate_from_seed(
+            &bitcoin::secp256k1::Secp256k1::new(),
+            &[],
+            10,
+            cdk00::CurrencyUnit::Sat,
+            path,
+        );
+        let mut inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
+        inputs.extend(utils::generate_proofs(
+            &other_keys,
+            vec![Amount::from(8)].as_slice(),
+        ));
+        // (600 + 500 + 999) / 1000 = 2
+        let outputs: Vec<_> = utils::generate_blinds(
+            &keys,
+            vec![Amount::from(8), Amount::from(4), Amount::from(2)].as_slice(),
+        )
+        .into_iter()
+        .map(|a| a.0)
+        .collect();
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo.expect_record_issued().returning(|_| Ok(()));
+        proofrepo
+            .expect_issued()
+            .returning(|secrets| Ok(vec![None; secrets.len()]));
+        proofrepo.expect_reserve().returning(|_| Ok(()));
+        let kid = KeysetID::from(keys.id);
+        let other_kid = KeysetID::from(other_keys.id);
+        let keysets = HashMap::from([(kid, keys.clone()), (other_kid, other_keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        let ex_keys = keys.clone();
+        keyrepo
+            .expect_keyset()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(ex_keys.clone())));
+        let info = keyset_info(&keys, 600);
+        keyrepo
+            .expect_info()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(info.clone())));
+        let info = keyset_info(&other_keys, 500);
+        keyrepo
+            .expect_info()
+            .with(eq(other_kid))
+            .returning(move |_| Ok(Some(info.clone())));
+        keyrepo
+            .expect_replacing_id()
+            .returning(move |_| Ok(Some(kid)));
+        proofrepo
+            .expect_spend()
+            .with(eq(inputs.clone()))
+            .returning(|_| Ok(()));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let bs = swaps.swap(&inputs, &outputs, None).await.unwrap();
+        assert_eq!(bs.len(), 3);
+    }
+
+    // 8 from `keys` (sat) and 8 from a second keyset of `other_unit`, swapped into `keys`
+    fn two_keysets_swap(
+        other_unit: cdk00::CurrencyUnit,
+    ) -> (
+        Service<MockKeysRepository, MockProofRepository>,
+        Vec<cdk00::Proof>,
+        Vec<cdk00::BlindedMessage>,
+    ) {
+        let keys = keys_test::generate_keyset();
+        let path = bitcoin::bip32::DerivationPath::from_str("m/0'/1").unwrap();
+        let other_keys = cdk02::MintKeySet::generate_from_seed(
+            &bitcoin::secp256k1::Secp256k1::new(),
+            &[],
+            10,
+            other_unit,
+            path,
+        );
+        let mut inputs = utils::generate_proofs(&keys, &[Amount::from(8)]);
+        inputs.extend(utils::generate_proofs(&other_keys, &[Amount::from(8)]));
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(16)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let mut proofrepo = MockProofRepository::new();
+        proofrepo
+            .expect_issued()
+            .returning(|secrets| Ok(vec![None; secrets.len()]));
+        proofrepo.expect_reserve().returning(|_| Ok(()));
+        proofrepo.expect_release().returning(|_| Ok(()));
+        proofrepo.expect_spend().returning(|_| Ok(()));
+        proofrepo.expect_record_issued().returning(|_| Ok(()));
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([
+            (kid, keys.clone()),
+            (KeysetID::from(other_keys.id), other_keys.clone()),
+        ]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        let ex_keys = keys.clone();
+        keyrepo
+            .expect_keyset()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(ex_keys.clone())));
+        let infos = HashMap::from([
+            (kid, keyset_info(&keys, 0)),
+            (KeysetID::from(other_keys.id), keyset_info(&other_keys, 0)),
+        ]);
+        keyrepo
+            .expect_info()
+            .returning(move |id| Ok(infos.get(id).cloned()));
+        keyrepo
+            .expect_replacing_id()
+            .returning(move |_| Ok(Some(kid)));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: proofrepo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+        (swaps, inputs, outputs)
+    }
+
+    #[tokio::test]
+    async fn test_swap_same_unit_keysets_ok() {
+        let (swaps, inputs, outputs) = two_keysets_swap(cdk00::CurrencyUnit::Sat);
+
+        let bs = swaps.swap(&inputs, &outputs, None).await.unwrap();
+        assert_eq!(bs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_swap_mixed_units() {
+        let usd = cdk00::CurrencyUnit::Custom(String::from("crusd"));
+        let (swaps, inputs, outputs) = two_keysets_swap(usd.clone());
+
+        let e = swaps.swap(&inputs, &outputs, None).await.unwrap_err();
+        assert!(matches!(
+            e,
+            Error::MixedUnits(ref a, ref b)
+                if (*a == cdk00::CurrencyUnit::Sat && *b == usd)
+                    || (*a == usd && *b == cdk00::CurrencyUnit::Sat)
+        ));
+    }
+
+    // swaps between proofs and outputs of `keys`, spent proofs are tracked for real
+    fn single_keyset_swap(
+        keys: &cdk02::MintKeySet,
+    ) -> Service<MockKeysRepository, crate::persistence::inmemory::ProofMap> {
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        let ex_keys = keys.clone();
+        keyrepo
+            .expect_keyset()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(ex_keys.clone())));
+        let info = keyset_info(keys, 0);
+        keyrepo
+            .expect_info()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(info.clone())));
+        keyrepo
+            .expect_replacing_id()
+            .returning(move |_| Ok(Some(kid)));
+        Service {
+            keys: keyrepo,
+            proofs: crate::persistence::inmemory::ProofMap::default(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        }
+    }
+
+    fn locked_proof(
+        keys: &cdk02::MintKeySet,
+        conditions: cdk::nuts::SpendingConditions,
+    ) -> cdk00::Proof {
+        let secret = cdk10::Secret::from(conditions).try_into().unwrap();
+        utils::generate_proof_with_secret(keys, Amount::from(8), secret)
+    }
+
+    #[tokio::test]
+    async fn test_swap_p2pk_locked_proof() {
+        let keys = keys_test::generate_keyset();
+        let owner = cdk01::SecretKey::generate();
+        let conditions = cdk::nuts::SpendingConditions::new_p2pk(owner.public_key(), None);
+        let mut proof = locked_proof(&keys, conditions);
+        proof.sign_p2pk(owner).unwrap();
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(8)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let swaps = single_keyset_swap(&keys);
+
+        let bs = swaps.swap(&[proof], &outputs, None).await.unwrap();
+        assert_eq!(bs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_swap_p2pk_missing_witness() {
+        let keys = keys_test::generate_keyset();
+        let owner = cdk01::SecretKey::generate();
+        let conditions = cdk::nuts::SpendingConditions::new_p2pk(owner.public_key(), None);
+        let proof = locked_proof(&keys, conditions);
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(8)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let swaps = single_keyset_swap(&keys);
+
+        let inputs = [proof];
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(matches!(r, Err(Error::SpendingConditionUnmet(_))));
+        let states = swaps.proofs.get_state(&inputs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Unspent]);
+    }
+
+    #[tokio::test]
+    async fn test_swap_p2pk_wrong_key() {
+        let keys = keys_test::generate_keyset();
+        let owner = cdk01::SecretKey::generate();
+        let conditions = cdk::nuts::SpendingConditions::new_p2pk(owner.public_key(), None);
+        let mut proof = locked_proof(&keys, conditions);
+        proof.sign_p2pk(cdk01::SecretKey::generate()).unwrap();
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(8)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let swaps = single_keyset_swap(&keys);
+
+        let r = swaps.swap(&[proof], &outputs, None).await;
+        assert!(matches!(r, Err(Error::SpendingConditionUnmet(_))));
+    }
+
+    const HTLC_PREIMAGE: &str = "0000000000000000000000000000000000000000000000000000000000000001";
+
+    fn htlc_witness(proof: &mut cdk00::Proof, preimage: &str, signer: Option<cdk01::SecretKey>) {
+        let signatures = signer.map(|key| {
+            let signature = key.sign(proof.secret.as_bytes()).unwrap();
+            vec![signature.to_string()]
+        });
+        proof.witness = Some(cdk00::Witness::HTLCWitness(cdk::nuts::nut14::HTLCWitness {
+            preimage: String::from(preimage),
+            signatures,
+        }));
+    }
+
+    fn htlc_refund_conditions(locktime: u64, refund: &cdk01::SecretKey) -> cdk11::Conditions {
+        cdk11::Conditions {
+            locktime: Some(locktime),
+            refund_keys: Some(vec![refund.public_key()]),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_swap_htlc_preimage() {
+        let keys = keys_test::generate_keyset();
+        let conditions =
+            cdk::nuts::SpendingConditions::new_htlc(String::from(HTLC_PREIMAGE), None).unwrap();
+        let mut proof = locked_proof(&keys, conditions);
+        htlc_witness(&mut proof, HTLC_PREIMAGE, None);
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(8)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let swaps = single_keyset_swap(&keys);
+
+        let bs = swaps.swap(&[proof], &outputs, None).await.unwrap();
+        assert_eq!(bs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_swap_htlc_wrong_preimage() {
+        let keys = keys_test::generate_keyset();
+        let conditions =
+            cdk::nuts::SpendingConditions::new_htlc(String::from(HTLC_PREIMAGE), None).unwrap();
+        let mut proof = locked_proof(&keys, conditions);
+        let wrong = "0000000000000000000000000000000000000000000000000000000000000002";
+        htlc_witness(&mut proof, wrong, None);
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(8)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let swaps = single_keyset_swap(&keys);
+
+        let r = swaps.swap(&[proof], &outputs, None).await;
+        assert!(matches!(r, Err(Error::HtlcPreimageMismatch)));
+    }
+
+    #[tokio::test]
+    async fn test_swap_htlc_refund_after_locktime() {
+        let keys = keys_test::generate_keyset();
+        let refund = cdk01::SecretKey::generate();
+        let locktime = chrono::Utc::now().timestamp() as u64 - 3600;
+        let conditions = cdk::nuts::SpendingConditions::new_htlc(
+            String::from(HTLC_PREIMAGE),
+            Some(htlc_refund_conditions(locktime, &refund)),
+        )
+        .unwrap();
+        let mut proof = locked_proof(&keys, conditions);
+        htlc_witness(&mut proof, "", Some(refund));
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(8)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let swaps = single_keyset_swap(&keys);
+
+        let bs = swaps.swap(&[proof], &outputs, None).await.unwrap();
+        assert_eq!(bs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_swap_htlc_refund_before_locktime() {
+        let keys = keys_test::generate_keyset();
+        let refund = cdk01::SecretKey::generate();
+        let locktime = chrono::Utc::now().timestamp() as u64 + 3600;
+        let conditions = cdk::nuts::SpendingConditions::new_htlc(
+            String::from(HTLC_PREIMAGE),
+            Some(htlc_refund_conditions(locktime, &refund)),
+        )
+        .unwrap();
+        let mut proof = locked_proof(&keys, conditions);
+        htlc_witness(&mut proof, "", Some(refund));
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(8)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let swaps = single_keyset_swap(&keys);
+
+        let r = swaps.swap(&[proof], &outputs, None).await;
+        assert!(matches!(r, Err(Error::HtlcLocktimeNotMet(lt)) if lt == locktime));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_swap_concurrent_double_spend() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        let ex_keys = keys.clone();
+        keyrepo
+            .expect_keyset()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(ex_keys.clone())));
+        let info = keyset_info(&keys, 0);
+        keyrepo
+            .expect_info()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(info.clone())));
+        keyrepo
+            .expect_replacing_id()
+            .returning(move |_| Ok(Some(kid)));
+        let swaps = Arc::new(Service {
+            keys: keyrepo,
+            proofs: crate::persistence::inmemory::ProofMap::default(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        });
+
+        let mut handles = Vec::new();
+        for _ in 0..2 {
+            let swaps = swaps.clone();
+            let inputs = inputs.clone();
+            let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
+                .into_iter()
+                .map(|a| a.0)
+                .collect();
+            handles.push(tokio::spawn(async move {
+                swaps.swap(&inputs, &outputs, None).await
+            }));
         }
-
-        let keys = self
-            .keys
-            .keyset(first)
-            .await
-            .map_err(Error::KeysetRepository)?
-            .expect("Keyset from first not found");
-        let mut signatures = Vec::new();
-        for output in outputs {
-            let keypair = keys
-                .keys
-                .get(&output.amount)
-                .ok_or(Error::UnknownAmountForKeyset(*first, output.amount))?;
-            let c = cdk::dhke::sign_message(&keypair.secret_key, &output.blinded_secret)?;
-            let signature = cdk00::BlindSignature::new(
-                output.amount,
-                c,
-                keys.id,
-                &output.blinded_secret,
-                keypair.secret_key.clone(),
-            )?;
-            signatures.push(signature);
+        let mut results = Vec::new();
+        for handle in handles {
+            results.push(handle.await.unwrap());
         }
-        self.proofs
-            .spend(inputs)
-            .await
-            .map_err(Error::ProofRepository)?;
-        Ok(signatures)
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        // the loser either finds the inputs still reserved or already spent
+        assert!(results
+            .iter()
+            .any(|r| matches!(r, Err(Error::ProofsAlreadySpent | Error::ProofsPending))));
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::keys::test_utils as keys_test;
-    use crate::utils::tests as utils;
-    use mockall::predicate::*;
 
     #[tokio::test]
-    async fn test_swap_spent_proofs() {
+    async fn test_swap_reserved_proofs() {
         let keys = keys_test::generate_keyset();
         let inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
         let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
             .into_iter()
             .map(|a| a.0)
             .collect();
-
-        let keyrepo = MockKeysRepository::new();
-        let mut proofrepo = MockProofRepository::new();
-        proofrepo
-            .expect_get_state()
-            .returning(|_| Ok(vec![cdk07::State::Spent]));
+        let proofs = crate::persistence::inmemory::ProofMap::default();
+        // e.g. a melt in flight
+        proofs.reserve(&inputs).await.unwrap();
+        let mut keyrepo = MockKeysRepository::new();
+        let info = keyset_info(&keys, 0);
+        keyrepo
+            .expect_info()
+            .returning(move |_| Ok(Some(info.clone())));
         let swaps = Service {
             keys: keyrepo,
-            proofs: proofrepo,
+            proofs,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
         };
-        let r = swaps.swap(&inputs, &outputs).await;
-        assert!(r.is_err());
-        let e = r.unwrap_err();
-        assert!(matches!(e, Error::ProofsAlreadySpent));
+
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(matches!(r.unwrap_err(), Error::ProofsPending));
+        let states = swaps.proofs.get_state(&inputs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Pending]);
     }
 
     #[tokio::test]
-    async fn test_swap_unknown_keysetid() {
-        let kid = keys_test::generate_random_keysetid();
-        let id = kid.into();
-
+    async fn test_check_state() {
         let keys = keys_test::generate_keyset();
-        let mut inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
-        inputs.get_mut(0).unwrap().keyset_id = id;
-        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
-            .into_iter()
-            .map(|a| a.0)
-            .collect();
-
-        let mut keyrepo = MockKeysRepository::new();
-        let mut proofrepo = MockProofRepository::new();
-        proofrepo
-            .expect_get_state()
-            .returning(|_| Ok(vec![cdk07::State::Unspent]));
-        keyrepo
-            .expect_keyset()
-            .with(eq(kid))
-            .returning(|_| Ok(None));
+        let proofs = utils::generate_proofs(
+            &keys,
+            vec![Amount::from(8), Amount::from(4), Amount::from(2)].as_slice(),
+        );
         let swaps = Service {
-            keys: keyrepo,
-            proofs: proofrepo,
+            keys: MockKeysRepository::new(),
+            proofs: crate::persistence::inmemory::ProofMap::default(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
         };
+        swaps.proofs.spend(&proofs[0..1]).await.unwrap();
+        swaps.proofs.reserve(&proofs[1..2]).await.unwrap();
 
-        let r = swaps.swap(&inputs, &outputs).await;
-        assert!(r.is_err());
-        let e = r.unwrap_err();
-        assert!(matches!(e, Error::UnknownKeyset(_)));
+        let states = swaps.check_state(&proofs).await.unwrap();
+        let expected = [
+            cdk07::State::Spent,
+            cdk07::State::Pending,
+            cdk07::State::Unspent,
+        ];
+        assert_eq!(states.len(), proofs.len());
+        for ((state, proof), expected) in states.iter().zip(&proofs).zip(expected) {
+            let y = cdk::dhke::hash_to_curve(&proof.secret.to_bytes()).unwrap();
+            assert_eq!(state.y, y);
+            assert_eq!(state.state, expected);
+        }
     }
 
     #[tokio::test]
-    async fn test_swap_wrong_signatures() {
+    async fn test_melt_failure_releases_proofs() {
         let keys = keys_test::generate_keyset();
         let mut inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
+        let c = inputs[0].c;
         inputs.get_mut(0).unwrap().c = utils::publics()[0];
-        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
-            .into_iter()
-            .map(|a| a.0)
-            .collect();
         let mut keyrepo = MockKeysRepository::new();
-        let mut proofrepo = MockProofRepository::new();
-        proofrepo
-            .expect_get_state()
-            .returning(|_| Ok(vec![cdk07::State::Unspent]));
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let info = keyset_info(&keys, 0);
+        keyrepo
+            .expect_info()
+            .returning(move |_| Ok(Some(info.clone())));
         let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
         keyrepo
-            .expect_keyset()
-            .with(eq(kid))
-            .returning(move |_| Ok(Some(keys.clone())));
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
         let swaps = Service {
             keys: keyrepo,
-            proofs: proofrepo,
+            proofs: crate::persistence::inmemory::ProofMap::default(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
         };
 
-        let r = swaps.swap(&inputs, &outputs).await;
-        assert!(r.is_err());
-        let e = r.unwrap_err();
-        assert!(matches!(e, Error::UnknownProofs));
+        let r = swaps.melt(&inputs, Amount::from(8)).await;
+        assert!(matches!(r.unwrap_err(), Error::UnknownProofs));
+        let states = swaps.proofs.get_state(&inputs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Unspent]);
+
+        inputs.get_mut(0).unwrap().c = c;
+        let fee = swaps.melt(&inputs, Amount::from(8)).await.unwrap();
+        assert_eq!(fee, Amount::ZERO);
+        let states = swaps.proofs.get_state(&inputs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Spent]);
+    }
+
+    // counts the repository round-trips, load_many falls back to the default implementation
+    struct CountingKeysRepository {
+        keyset: cdk02::MintKeySet,
+        keyset_calls: std::sync::atomic::AtomicUsize,
+        info_calls: std::sync::atomic::AtomicUsize,
+        replacing_id_calls: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait]
+    impl KeysRepository for CountingKeysRepository {
+        async fn keyset(&self, _id: &KeysetID) -> KeysRepoResult<Option<cdk02::MintKeySet>> {
+            self.keyset_calls
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(Some(self.keyset.clone()))
+        }
+        async fn info(&self, _id: &KeysetID) -> KeysRepoResult<Option<MintKeySetInfo>> {
+            self.info_calls
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(Some(keyset_info(&self.keyset, 0)))
+        }
+        async fn spendable(&self, _id: &KeysetID) -> KeysRepoResult<bool> {
+            Ok(true)
+        }
+        async fn replacing_id(&self, id: &KeysetID) -> KeysRepoResult<Option<KeysetID>> {
+            self.replacing_id_calls
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(Some(*id))
+        }
+        async fn list_info(&self) -> KeysRepoResult<Vec<MintKeySetInfo>> {
+            Ok(vec![keyset_info(&self.keyset, 0)])
+        }
     }
 
     #[tokio::test]
-    async fn test_swap_unmatched_amounts() {
+    async fn test_swap_keyset_calls_are_batched() {
         let keys = keys_test::generate_keyset();
-        let inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
-        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(16)].as_slice())
-            .into_iter()
-            .map(|a| a.0)
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(1); 100].as_slice());
+        let outputs: Vec<_> = utils::generate_blinds(
+            &keys,
+            vec![Amount::from(64), Amount::from(32), Amount::from(4)].as_slice(),
+        )
+        .into_iter()
+        .map(|a| a.0)
+        .collect();
+        let swaps = Service {
+            keys: CountingKeysRepository {
+                keyset: keys.clone(),
+                keyset_calls: Default::default(),
+                info_calls: Default::default(),
+                replacing_id_calls: Default::default(),
+            },
+            proofs: crate::persistence::inmemory::ProofMap::default(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        swaps.swap(&inputs, &outputs, None).await.unwrap();
+        let load = |c: &std::sync::atomic::AtomicUsize| c.load(std::sync::atomic::Ordering::SeqCst);
+        // one for verifying the 100 inputs, one for signing the outputs
+        assert_eq!(load(&swaps.keys.keyset_calls), 2);
+        // one for the input amounts, one for the fee
+        assert_eq!(load(&swaps.keys.info_calls), 2);
+        assert_eq!(load(&swaps.keys.replacing_id_calls), 1);
+    }
+
+    // every keyset is known, active, charges `input_fee_ppk` and is replaced by itself
+    fn multi_keyset_swaps(
+        keysets: &[cdk02::MintKeySet],
+        input_fee_ppk: u64,
+    ) -> Service<MockKeysRepository, crate::persistence::inmemory::ProofMap> {
+        let keysets: HashMap<KeysetID, cdk02::MintKeySet> = keysets
+            .iter()
+            .map(|keys| (KeysetID::from(keys.id), keys.clone()))
             .collect();
         let mut keyrepo = MockKeysRepository::new();
-        let mut proofrepo = MockProofRepository::new();
-        proofrepo
-            .expect_get_state()
-            .returning(|_| Ok(vec![cdk07::State::Unspent]));
-        let kid = KeysetID::from(keys.id);
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let all = keysets.clone();
+        keyrepo.expect_load_many().returning(move |ids| {
+            Ok(ids
+                .iter()
+                .filter_map(|id| all.get(id).map(|keys| (*id, keys.clone())))
+                .collect())
+        });
+        let all = keysets.clone();
         keyrepo
             .expect_keyset()
-            .with(eq(kid))
-            .returning(move |_| Ok(Some(keys.clone())));
-        let swaps = Service {
+            .returning(move |id| Ok(all.get(id).cloned()));
+        keyrepo
+            .expect_info()
+            .returning(move |id| Ok(keysets.get(id).map(|keys| keyset_info(keys, input_fee_ppk))));
+        keyrepo.expect_replacing_id().returning(|id| Ok(Some(*id)));
+        Service {
             keys: keyrepo,
-            proofs: proofrepo,
+            proofs: crate::persistence::inmemory::ProofMap::default(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_swap_limits() {
+        let keys = keys_test::generate_keyset();
+        let mut swaps = multi_keyset_swaps(std::slice::from_ref(&keys), 0);
+        swaps.limits = SwapLimits {
+            max_inputs: 2,
+            max_outputs: 3,
+        };
+        let blinds = |amounts: &[u64]| -> Vec<cdk00::BlindedMessage> {
+            let amounts: Vec<Amount> = amounts.iter().copied().map(Amount::from).collect();
+            utils::generate_blinds(&keys, &amounts)
+                .into_iter()
+                .map(|a| a.0)
+                .collect()
         };
 
-        let r = swaps.swap(&inputs, &outputs).await;
-        assert!(r.is_err());
-        let e = r.unwrap_err();
-        assert!(matches!(e, Error::UnmatchingAmount(_, _)));
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(4), Amount::from(4)]);
+        let r = swaps.swap(&inputs, &blinds(&[4, 2, 2]), None).await;
+        assert_eq!(r.unwrap().len(), 3);
+
+        let inputs =
+            utils::generate_proofs(&keys, &[Amount::from(4), Amount::from(2), Amount::from(2)]);
+        let r = swaps.swap(&inputs, &blinds(&[8]), None).await;
+        assert!(matches!(r, Err(Error::TooManyInputs(3, 2))));
+
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(8)]);
+        let r = swaps.swap(&inputs, &blinds(&[2, 2, 2, 2]), None).await;
+        assert!(matches!(r, Err(Error::TooManyOutputs(4, 3))));
+        let states = swaps.proofs.get_state(&inputs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Unspent]);
     }
 
     #[tokio::test]
-    async fn test_swap_split_tokens_ok() {
+    async fn test_swap_detailed_fee_paid() {
         let keys = keys_test::generate_keyset();
-        let inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
+        let kid = KeysetID::from(keys.id);
+        let inputs =
+            utils::generate_proofs(&keys, &[Amount::from(8), Amount::from(4), Amount::from(4)]);
         let outputs: Vec<_> =
-            utils::generate_blinds(&keys, vec![Amount::from(4), Amount::from(4)].as_slice())
+            utils::generate_blinds(&keys, &[Amount::from(8), Amount::from(4), Amount::from(2)])
+                .into_iter()
+                .map(|a| a.0)
+                .collect();
+        // 3 inputs at 400 ppk: 1200 ppk, rounded up to 2
+        let swaps = multi_keyset_swaps(std::slice::from_ref(&keys), 400);
+
+        let outcome = swaps.swap_detailed(&inputs, &outputs, None).await.unwrap();
+        assert_eq!(outcome.fee_paid, Amount::from(2));
+        assert_eq!(outcome.keyset, kid);
+        assert_eq!(outcome.signatures.len(), 3);
+
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(8)]);
+        let outputs: Vec<_> = utils::generate_blinds(&keys, &[Amount::from(8)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let swaps = multi_keyset_swaps(std::slice::from_ref(&keys), 0);
+        let outcome = swaps.swap_detailed(&inputs, &outputs, None).await.unwrap();
+        assert_eq!(outcome.fee_paid, Amount::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_swap_auto() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(8), Amount::from(8)]);
+        // 2 inputs at 500 ppk: a fee of 1
+        let swaps = multi_keyset_swaps(std::slice::from_ref(&keys), 500);
+
+        let targets = [4, 4, 4, 2, 1].map(Amount::from);
+        let templates = swaps.swap_auto(&inputs, &targets).await.unwrap();
+        let amounts: Vec<_> = templates.iter().map(|template| template.amount).collect();
+        assert_eq!(amounts, targets);
+        assert!(templates
+            .iter()
+            .all(|template| template.keyset_id == keys.id));
+        // nothing is spent
+        let states = swaps.proofs.get_state(&inputs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Unspent; 2]);
+    }
+
+    #[tokio::test]
+    async fn test_swap_auto_unmatching_targets() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(8), Amount::from(8)]);
+        let swaps = multi_keyset_swaps(std::slice::from_ref(&keys), 500);
+
+        let over = [8, 8].map(Amount::from);
+        let r = swaps.swap_auto(&inputs, &over).await;
+        assert!(matches!(
+            r.unwrap_err(),
+            Error::InsufficientFeeCovered(fee, paid) if fee == Amount::from(1) && paid == Amount::ZERO
+        ));
+        let under = [8, 4].map(Amount::from);
+        let r = swaps.swap_auto(&inputs, &under).await;
+        assert!(matches!(r.unwrap_err(), Error::UnmatchingAmount(_, _)));
+        let beyond_inputs = [16, 8].map(Amount::from);
+        let r = swaps.swap_auto(&inputs, &beyond_inputs).await;
+        assert!(matches!(r.unwrap_err(), Error::UnmatchingAmount(_, _)));
+        // 3 is no denomination of the keyset
+        let odd = [8, 3, 4].map(Amount::from);
+        let r = swaps.swap_auto(&inputs, &odd).await;
+        assert!(
+            matches!(r.unwrap_err(), Error::UnknownAmountForKeyset(_, amount) if amount == Amount::from(3))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_swap_multi_two_groups() {
+        let first_keys = keys_test::generate_keyset();
+        let second_keys = keys_test::generate_keyset();
+        let first_inputs = utils::generate_proofs(&first_keys, &[Amount::from(8)]);
+        let first_outputs: Vec<_> =
+            utils::generate_blinds(&first_keys, &[Amount::from(4), Amount::from(4)])
                 .into_iter()
                 .map(|a| a.0)
                 .collect();
+        let second_inputs = utils::generate_proofs(&second_keys, &[Amount::from(2)]);
+        let second_outputs: Vec<_> = utils::generate_blinds(&second_keys, &[Amount::from(2)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let swaps = multi_keyset_swaps(&[first_keys.clone(), second_keys.clone()], 0);
+
+        let signatures = swaps
+            .swap_multi(vec![
+                (first_inputs.clone(), first_outputs),
+                (second_inputs.clone(), second_outputs),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(signatures.len(), 2);
+        assert_eq!(signatures[0].len(), 2);
+        assert!(signatures[0].iter().all(|bs| bs.keyset_id == first_keys.id));
+        assert_eq!(signatures[1].len(), 1);
+        assert_eq!(signatures[1][0].keyset_id, second_keys.id);
+        let inputs = [first_inputs, second_inputs].concat();
+        let states = swaps.proofs.get_state(&inputs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Spent, cdk07::State::Spent]);
+    }
+
+    #[tokio::test]
+    async fn test_swap_multi_failing_group_aborts_batch() {
+        let first_keys = keys_test::generate_keyset();
+        let second_keys = keys_test::generate_keyset();
+        let first_inputs = utils::generate_proofs(&first_keys, &[Amount::from(8)]);
+        let first_outputs: Vec<_> = utils::generate_blinds(&first_keys, &[Amount::from(8)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let mut second_inputs = utils::generate_proofs(&second_keys, &[Amount::from(2)]);
+        // not signed by the keyset
+        second_inputs[0].c = utils::publics()[0];
+        let second_outputs: Vec<_> = utils::generate_blinds(&second_keys, &[Amount::from(2)])
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let swaps = multi_keyset_swaps(&[first_keys, second_keys], 0);
+
+        let r = swaps
+            .swap_multi(vec![
+                (first_inputs.clone(), first_outputs),
+                (second_inputs.clone(), second_outputs),
+            ])
+            .await;
+        assert!(matches!(r, Err(Error::UnknownProofs)));
+        let inputs = [first_inputs, second_inputs].concat();
+        let states = swaps.proofs.get_state(&inputs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Unspent, cdk07::State::Unspent]);
+    }
+
+    #[tokio::test]
+    async fn test_swap_dleq_detects_tampering() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
+        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
         let mut keyrepo = MockKeysRepository::new();
-        let mut proofrepo = MockProofRepository::new();
-        proofrepo
-            .expect_get_state()
-            .returning(|_| Ok(vec![cdk07::State::Unspent]));
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
         let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
         let ex_keys = keys.clone();
         keyrepo
             .expect_keyset()
             .with(eq(kid))
             .returning(move |_| Ok(Some(ex_keys.clone())));
+        let info = keyset_info(&keys, 0);
+        keyrepo
+            .expect_info()
+            .returning(move |_| Ok(Some(info.clone())));
         keyrepo
             .expect_replacing_id()
             .returning(move |_| Ok(Some(kid)));
-        proofrepo
-            .expect_spend()
-            .with(eq(inputs.clone()))
-            .returning(|_| Ok(()));
         let swaps = Service {
             keys: keyrepo,
-            proofs: proofrepo,
+            proofs: crate::persistence::inmemory::ProofMap::default(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
         };
 
-        let r = swaps.swap(&inputs, &outputs).await;
-        assert!(r.is_ok());
-        let bs = r.unwrap();
-        assert!(utils::verify_signatures_data(
-            &keys,
-            outputs.into_iter().zip(bs.into_iter())
-        ));
+        let mut bs = swaps.swap(&inputs, &outputs, None).await.unwrap();
+        assert!(bs[0].dleq.is_some());
+        assert!(utils::verify_dleq(&keys, &outputs[0], &bs[0]));
+        // a signature from another key does not match the proof
+        bs[0].c = utils::publics()[0];
+        assert!(!utils::verify_dleq(&keys, &outputs[0], &bs[0]));
     }
 
     #[tokio::test]
-    async fn test_swap_merge_tokens_ok() {
+    async fn test_swap_unknown_target_keyset() {
         let keys = keys_test::generate_keyset();
-        let inputs =
-            utils::generate_proofs(&keys, vec![Amount::from(4), Amount::from(4)].as_slice());
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
         let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
             .into_iter()
             .map(|a| a.0)
             .collect();
         let mut keyrepo = MockKeysRepository::new();
-        let mut proofrepo = MockProofRepository::new();
-        proofrepo
-            .expect_get_state()
-            .returning(|_| Ok(vec![cdk07::State::Unspent]));
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
         let kid = KeysetID::from(keys.id);
-        let ex_keys = keys.clone();
+        let target = keys_test::generate_random_keysetid();
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
         keyrepo
             .expect_keyset()
-            .with(eq(kid))
-            .returning(move |_| Ok(Some(ex_keys.clone())));
+            .with(eq(target))
+            .returning(|_| Ok(None));
+        let info = keyset_info(&keys, 0);
+        keyrepo
+            .expect_info()
+            .returning(move |_| Ok(Some(info.clone())));
         keyrepo
             .expect_replacing_id()
-            .returning(move |_| Ok(Some(kid)));
-        proofrepo
-            .expect_spend()
-            .with(eq(inputs.clone()))
-            .returning(|_| Ok(()));
+            .returning(move |_| Ok(Some(target)));
         let swaps = Service {
             keys: keyrepo,
-            proofs: proofrepo,
+            proofs: crate::persistence::inmemory::ProofMap::default(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
         };
 
-        let r = swaps.swap(&inputs, &outputs).await;
-        assert!(r.is_ok());
-        let bs = r.unwrap();
-        assert!(utils::verify_signatures_data(
-            &keys,
-            outputs.into_iter().zip(bs.into_iter())
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(matches!(r.unwrap_err(), Error::UnknownKeyset(id) if id == target));
+        let states = swaps.proofs.get_state(&inputs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Unspent]);
+    }
+
+    #[tokio::test]
+    async fn test_swap_no_replacement_keyset() {
+        let keys = keys_test::generate_keyset();
+        let inputs = utils::generate_proofs(&keys, vec![Amount::from(8)].as_slice());
+        let outputs: Vec<_> = utils::generate_blinds(&keys, vec![Amount::from(8)].as_slice())
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        let info = keyset_info(&keys, 0);
+        keyrepo
+            .expect_info()
+            .returning(move |_| Ok(Some(info.clone())));
+        keyrepo.expect_replacing_id().returning(|_| Ok(None));
+        let swaps = Service {
+            keys: keyrepo,
+            proofs: crate::persistence::inmemory::ProofMap::default(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        };
+
+        let r = swaps.swap(&inputs, &outputs, None).await;
+        assert!(matches!(r.unwrap_err(), Error::NoReplacementKeyset(id) if id == kid));
+        let states = swaps.proofs.get_state(&inputs).await.unwrap();
+        assert_eq!(states, vec![cdk07::State::Unspent]);
+    }
+
+    // a single keyset valid until `valid_to`
+    fn windowed_keyset_swap(
+        keys: &cdk02::MintKeySet,
+        valid_to: u64,
+    ) -> Service<MockKeysRepository, crate::persistence::inmemory::ProofMap> {
+        let mut keyrepo = MockKeysRepository::new();
+        keyrepo.expect_spendable().returning(|_| Ok(true));
+        keyrepo.expect_locked_to().returning(|_| Ok(None));
+        let kid = KeysetID::from(keys.id);
+        let keysets = HashMap::from([(kid, keys.clone())]);
+        keyrepo
+            .expect_load_many()
+            .returning(move |_| Ok(keysets.clone()));
+        let mut info = keyset_info(keys, 0);
+        info.valid_to = Some(valid_to);
+        keyrepo
+            .expect_info()
+            .with(eq(kid))
+            .returning(move |_| Ok(Some(info.clone())));
+        Service {
+            keys: keyrepo,
+            proofs: crate::persistence::inmemory::ProofMap::default(),
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_proofs_within_validity_window() {
+        let keys = keys_test::generate_keyset();
+        let valid_to = (chrono::Utc::now() + chrono::Duration::days(1)).timestamp() as u64;
+        let swaps = windowed_keyset_swap(&keys, valid_to);
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(8)]);
+
+        let r = swaps
+            .verify_proofs_signatures(&inputs, KeysetValidity::Enforce)
+            .await;
+        assert!(r.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_verify_proofs_past_valid_to() {
+        let keys = keys_test::generate_keyset();
+        let kid = KeysetID::from(keys.id);
+        let valid_to = (chrono::Utc::now() - chrono::Duration::days(1)).timestamp() as u64;
+        let swaps = windowed_keyset_swap(&keys, valid_to);
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(8)]);
+
+        let r = swaps
+            .verify_proofs_signatures(&inputs, KeysetValidity::Enforce)
+            .await;
+        assert!(matches!(r, Err(Error::KeysetExpired(id)) if id == kid));
+        // not checked unless asked for
+        let r = swaps
+            .verify_proofs_signatures(&inputs, KeysetValidity::Ignore)
+            .await;
+        assert!(r.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_verify_proofs_follows_clock() {
+        let keys = keys_test::generate_keyset();
+        let kid = KeysetID::from(keys.id);
+        let valid_to = chrono::DateTime::parse_from_rfc3339("2030-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let mut swaps = windowed_keyset_swap(&keys, valid_to.timestamp() as u64);
+        let inputs = utils::generate_proofs(&keys, &[Amount::from(8)]);
+
+        swaps.clock = Arc::new(crate::clock::FixedClock(
+            valid_to - chrono::Duration::days(1),
         ));
+        let r = swaps
+            .verify_proofs_signatures(&inputs, KeysetValidity::Enforce)
+            .await;
+        assert!(r.unwrap());
+        swaps.clock = Arc::new(crate::clock::FixedClock(
+            valid_to + chrono::Duration::days(1),
+        ));
+        let r = swaps
+            .verify_proofs_signatures(&inputs, KeysetValidity::Enforce)
+            .await;
+        assert!(matches!(r, Err(Error::KeysetExpired(id)) if id == kid));
+    }
+
+    fn health_service(keys: bool, proofs: bool) -> Service<MockHealthCheck, MockHealthCheck> {
+        let mut keys_repo = MockHealthCheck::new();
+        keys_repo.expect_ready().returning(move || keys);
+        let mut proofs_repo = MockHealthCheck::new();
+        proofs_repo.expect_ready().returning(move || proofs);
+        Service {
+            keys: keys_repo,
+            proofs: proofs_repo,
+            stats: Default::default(),
+            limits: Default::default(),
+            clock: Arc::new(crate::clock::SystemClock),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_health_all_ready() {
+        let report = health_service(true, true).health().await;
+        assert!(report.ready());
+    }
+
+    #[tokio::test]
+    async fn test_health_unhealthy_dependency() {
+        let report = health_service(true, false).health().await;
+        assert!(!report.ready());
+        let expected = BTreeMap::from([
+            (String::from("keys"), true),
+            (String::from("proofs"), false),
+        ]);
+        assert_eq!(report.dependencies, expected);
     }
 }
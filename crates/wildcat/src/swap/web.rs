@@ -1,8 +1,11 @@
 // ----- standard library imports
 // ----- extra library imports
 use axum::extract::{Json, State};
+use axum::http::StatusCode;
 use cdk::nuts::nut03 as cdk03;
+use cdk::nuts::nut09 as cdk09;
 // ----- local imports
+use crate::health::{HealthCheck, HealthReport};
 use crate::swap;
 use crate::swap::error::Result;
 
@@ -14,7 +17,40 @@ where
     KR: swap::KeysRepository,
     PR: swap::ProofRepository,
 {
-    let signatures = ctrl.swap(&request.inputs, &request.outputs).await?;
+    let signatures = ctrl.swap(&request.inputs, &request.outputs, None).await?;
     let response = cdk03::SwapResponse { signatures };
     Ok(Json(response))
 }
+
+pub async fn restore<KR, PR>(
+    State(ctrl): State<swap::Service<KR, PR>>,
+    Json(request): Json<cdk09::RestoreRequest>,
+) -> Result<Json<cdk09::RestoreResponse>>
+where
+    KR: swap::KeysRepository,
+    PR: swap::ProofRepository,
+{
+    let (outputs, signatures) = ctrl.restore(&request.outputs).await?.into_iter().unzip();
+    let response = cdk09::RestoreResponse {
+        outputs,
+        signatures,
+        promises: None,
+    };
+    Ok(Json(response))
+}
+
+pub async fn ready<KR, PR>(
+    State(ctrl): State<swap::Service<KR, PR>>,
+) -> (StatusCode, Json<HealthReport>)
+where
+    KR: HealthCheck,
+    PR: HealthCheck,
+{
+    let report = ctrl.health().await;
+    let status = if report.ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
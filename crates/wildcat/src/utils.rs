@@ -55,19 +55,28 @@ pub mod tests {
         keyset: &cdk02::MintKeySet,
         amounts: &[cdk::Amount],
     ) -> Vec<cdk00::Proof> {
-        let mut proofs: Vec<cdk00::Proof> = Vec::new();
-        for amount in amounts {
-            let keypair = keyset.keys.get(amount).expect("keys for amount");
-            let secret = cdk::secret::Secret::new(rand::random::<u64>().to_string());
-            let (b_, r) = cdk::dhke::blind_message(secret.as_bytes(), None)
-                .expect("cdk::dhke::blind_message");
-            let c_ =
-                cdk::dhke::sign_message(&keypair.secret_key, &b_).expect("cdk::dhke::sign_message");
-            let c =
-                cdk::dhke::unblind_message(&c_, &r, &keypair.public_key).expect("unblind_message");
-            proofs.push(cdk00::Proof::new(*amount, keyset.id, secret, c));
-        }
-        proofs
+        amounts
+            .iter()
+            .map(|amount| {
+                let secret = cdk::secret::Secret::new(rand::random::<u64>().to_string());
+                generate_proof_with_secret(keyset, *amount, secret)
+            })
+            .collect()
+    }
+
+    /// a proof for `secret`, e.g. a NUT-10 well-known secret
+    pub fn generate_proof_with_secret(
+        keyset: &cdk02::MintKeySet,
+        amount: cdk::Amount,
+        secret: cdk::secret::Secret,
+    ) -> cdk00::Proof {
+        let keypair = keyset.keys.get(&amount).expect("keys for amount");
+        let (b_, r) =
+            cdk::dhke::blind_message(secret.as_bytes(), None).expect("cdk::dhke::blind_message");
+        let c_ =
+            cdk::dhke::sign_message(&keypair.secret_key, &b_).expect("cdk::dhke::sign_message");
+        let c = cdk::dhke::unblind_message(&c_, &r, &keypair.public_key).expect("unblind_message");
+        cdk00::Proof::new(amount, keyset.id, secret, c)
     }
 
     pub fn generate_blinds(
@@ -110,6 +119,20 @@ pub mod tests {
         true
     }
 
+    /// NUT-12: checks the DLEQ proof of `signature` against the keyset public key for its amount
+    pub fn verify_dleq(
+        keyset: &cdk02::MintKeySet,
+        blind: &cdk00::BlindedMessage,
+        signature: &cdk00::BlindSignature,
+    ) -> bool {
+        let Some(keypair) = keyset.keys.get(&signature.amount) else {
+            return false;
+        };
+        signature
+            .verify_dleq(keypair.public_key, blind.blinded_secret)
+            .is_ok()
+    }
+
     #[test]
     fn test_select_blind_signatures_no_valid_blinds() {
         let publics = publics();
@@ -275,4 +298,39 @@ pub mod tests {
         assert_eq!(selected[2].amount, cdk::Amount::from(1_u64));
         assert_eq!(selected[2].blinded_secret.to_hex(), RANDOMS[1]);
     }
+
+    /// subscriber recording span names and rendered events, for asserting on traces
+    #[derive(Default, Clone)]
+    pub struct TraceCapture {
+        pub spans: std::sync::Arc<std::sync::Mutex<Vec<&'static str>>>,
+        pub events: std::sync::Arc<std::sync::Mutex<Vec<(tracing::Level, String)>>>,
+    }
+
+    struct FieldsVisitor(String);
+    impl tracing::field::Visit for FieldsVisitor {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0 += &format!("{}={:?} ", field.name(), value);
+        }
+    }
+
+    impl tracing::Subscriber for TraceCapture {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(span.metadata().name());
+            tracing::span::Id::from_u64(spans.len() as u64)
+        }
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+        fn event(&self, event: &tracing::Event<'_>) {
+            let mut visitor = FieldsVisitor(String::new());
+            event.record(&mut visitor);
+            let level = *event.metadata().level();
+            self.events.lock().unwrap().push((level, visitor.0));
+        }
+        fn enter(&self, _: &tracing::span::Id) {}
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
 }
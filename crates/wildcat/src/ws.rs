@@ -0,0 +1,260 @@
+#![allow(dead_code)]
+// ----- standard library imports
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+// ----- extra library imports
+use anyhow::Result as AnyResult;
+use async_trait::async_trait;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::Response;
+use cdk::nuts::nut01 as cdk01;
+use cdk::nuts::nut07 as cdk07;
+use cdk::nuts::nut17 as cdk17;
+use cdk::nuts::nut17::ws as cdk17ws;
+use tokio::sync::broadcast;
+// ----- local imports
+
+// ----- required traits
+/// proof states as published to NUT-17 subscribers
+#[async_trait]
+pub trait ProofStateFeed: Send + Sync {
+    /// every state change from now on
+    fn subscribe(&self) -> broadcast::Receiver<cdk07::ProofState>;
+    /// current states, in the order of `ys`. unknown Ys are unspent
+    async fn states(&self, ys: &[cdk01::PublicKey]) -> AnyResult<Vec<cdk07::ProofState>>;
+}
+
+const INVALID_PARAMS: i32 = -32602;
+
+type Subscriptions = HashMap<cdk17::SubId, HashSet<cdk01::PublicKey>>;
+
+/// NUT-17 websocket, only the proof_state kind is supported
+pub async fn subscribe<Feed>(ws: WebSocketUpgrade, State(feed): State<Feed>) -> Response
+where
+    Feed: ProofStateFeed + Clone + 'static,
+{
+    ws.on_upgrade(move |socket| serve(socket, feed))
+}
+
+async fn serve<Feed>(mut socket: WebSocket, feed: Feed)
+where
+    Feed: ProofStateFeed,
+{
+    // listen before reading the current states, no change in between is lost
+    let mut events = feed.subscribe();
+    let mut subscriptions = Subscriptions::new();
+    loop {
+        let outgoing = tokio::select! {
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Text(text))) => handle_request(&text, &feed, &mut subscriptions).await,
+                Some(Ok(Message::Close(_))) | None => return,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    log::debug!("websocket receive failed: {e}");
+                    return;
+                }
+            },
+            event = events.recv() => match event {
+                Ok(state) => notifications(&subscriptions, state),
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    log::warn!("websocket subscriber lagging, {missed} proof states missed");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+        };
+        for msg in outgoing {
+            if socket.send(Message::Text(msg)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn notifications(subscriptions: &Subscriptions, state: cdk07::ProofState) -> Vec<String> {
+    subscriptions
+        .iter()
+        .filter(|(_, ys)| ys.contains(&state.y))
+        .map(|(sub_id, _)| notification(sub_id.clone(), state.clone()))
+        .collect()
+}
+
+fn notification(sub_id: cdk17::SubId, state: cdk07::ProofState) -> String {
+    let notification = cdk17ws::WsNotification {
+        jsonrpc: String::from(cdk17ws::JSON_RPC_VERSION),
+        method: String::from("subscribe"),
+        params: cdk17ws::NotificationInner::<String> {
+            sub_id,
+            payload: cdk17::NotificationPayload::ProofState(state),
+        },
+    };
+    serde_json::to_string(&notification).expect("notification serialization")
+}
+
+fn response(id: usize, result: cdk17ws::WsResponseResult) -> String {
+    let response = cdk17ws::WsResponse {
+        jsonrpc: String::from(cdk17ws::JSON_RPC_VERSION),
+        result,
+        id,
+    };
+    serde_json::to_string(&response).expect("response serialization")
+}
+
+fn error_response(id: usize, message: String) -> String {
+    let response = cdk17ws::WsErrorResponse {
+        jsonrpc: String::from(cdk17ws::JSON_RPC_VERSION),
+        error: cdk17ws::WsErrorBody {
+            code: INVALID_PARAMS,
+            message,
+        },
+        id,
+    };
+    serde_json::to_string(&response).expect("error response serialization")
+}
+
+async fn handle_request<Feed>(
+    text: &str,
+    feed: &Feed,
+    subscriptions: &mut Subscriptions,
+) -> Vec<String>
+where
+    Feed: ProofStateFeed,
+{
+    let request: cdk17ws::WsRequest = match serde_json::from_str(text) {
+        Ok(request) => request,
+        Err(e) => return vec![error_response(0, format!("invalid request: {e}"))],
+    };
+    match request.method {
+        cdk17ws::WsMethodRequest::Subscribe(params) => {
+            subscribe_proof_states(request.id, params, feed, subscriptions).await
+        }
+        cdk17ws::WsMethodRequest::Unsubscribe(req) => {
+            if subscriptions.remove(&req.sub_id).is_none() {
+                return vec![error_response(
+                    request.id,
+                    format!("unknown subId {}", *req.sub_id),
+                )];
+            }
+            let result = cdk17ws::WsUnsubscribeResponse {
+                status: String::from("OK"),
+                sub_id: req.sub_id,
+            };
+            vec![response(request.id, result.into())]
+        }
+    }
+}
+
+// acknowledges, then sends the current state of every Y subscribed to
+async fn subscribe_proof_states<Feed>(
+    id: usize,
+    params: cdk17::Params,
+    feed: &Feed,
+    subscriptions: &mut Subscriptions,
+) -> Vec<String>
+where
+    Feed: ProofStateFeed,
+{
+    if params.kind != cdk17::Kind::ProofState {
+        return vec![error_response(
+            id,
+            format!("unsupported kind {:?}", params.kind),
+        )];
+    }
+    let ys: Result<Vec<cdk01::PublicKey>, _> = params
+        .filters
+        .iter()
+        .map(|filter| cdk01::PublicKey::from_str(filter))
+        .collect();
+    let ys = match ys {
+        Ok(ys) => ys,
+        Err(e) => return vec![error_response(id, format!("invalid filter: {e}"))],
+    };
+    let states = match feed.states(&ys).await {
+        Ok(states) => states,
+        Err(e) => return vec![error_response(id, format!("proof states unavailable: {e}"))],
+    };
+    subscriptions.insert(params.id.clone(), ys.into_iter().collect());
+    let result = cdk17ws::WsSubscribeResponse {
+        status: String::from("OK"),
+        sub_id: params.id.clone(),
+    };
+    let mut msgs = vec![response(id, result.into())];
+    msgs.extend(
+        states
+            .into_iter()
+            .map(|state| notification(params.id.clone(), state)),
+    );
+    msgs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::inmemory;
+    use crate::swap::ProofRepository;
+    use crate::utils::tests as utils;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message as ClientMessage;
+
+    async fn server(feed: inmemory::ProofMap) -> String {
+        let router = axum::Router::new()
+            .route(
+                "/v1/ws",
+                axum::routing::get(subscribe::<inmemory::ProofMap>),
+            )
+            .with_state(feed);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service());
+        tokio::spawn(server);
+        format!("ws://{addr}/v1/ws")
+    }
+
+    async fn next_message<S>(client: &mut S) -> serde_json::Value
+    where
+        S: StreamExt<Item = Result<ClientMessage, tokio_tungstenite::tungstenite::Error>> + Unpin,
+    {
+        let msg = tokio::time::timeout(std::time::Duration::from_secs(5), client.next())
+            .await
+            .expect("message in time")
+            .unwrap()
+            .unwrap();
+        serde_json::from_str(msg.to_text().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_receives_spent_update() {
+        let proofs = inmemory::ProofMap::default();
+        let url = server(proofs.clone()).await;
+        let keyset = bcr_wdc_keys::test_utils::generate_keyset();
+        let inputs = utils::generate_proofs(&keyset, &[cdk::Amount::from(8)]);
+        let y = inputs[0].y().unwrap();
+        let (mut client, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 7,
+            "method": "subscribe",
+            "params": {"kind": "proof_state", "subId": "sub", "filters": [y.to_hex()]},
+        });
+        client
+            .send(ClientMessage::Text(request.to_string()))
+            .await
+            .unwrap();
+        let ack = next_message(&mut client).await;
+        assert_eq!(ack["id"], 7);
+        assert_eq!(ack["result"]["status"], "OK");
+        let current = next_message(&mut client).await;
+        assert_eq!(current["params"]["subId"], "sub");
+        assert_eq!(current["params"]["payload"]["state"], "UNSPENT");
+
+        proofs.spend(&inputs).await.unwrap();
+        let update = next_message(&mut client).await;
+        assert_eq!(update["method"], "subscribe");
+        assert_eq!(update["params"]["payload"]["Y"], y.to_hex());
+        assert_eq!(update["params"]["payload"]["state"], "SPENT");
+    }
+}